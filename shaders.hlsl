@@ -1,18 +1,42 @@
-cbuffer cbPerObject : register(b0)
+cbuffer cbPerFrame : register(b0)
 {
-    float4x4 model_view_projection;
+    float4x4 view_projection;
+    float time;
+};
+
+cbuffer cbPerObject : register(b1)
+{
+    float4x4 world;
     // TODO: Z Index
 };
 
+// Filled in from the material, these initializers are the defaults for
+// parameters a material leaves out
+cbuffer cbMaterial : register(b2)
+{
+    float4 base_color = float4(1.0, 1.0, 1.0, 1.0);
+    float2 uv_scale = float2(1.0, 1.0);
+};
+
+#ifdef TEXTURED
+// Multiplied into the color
+Texture2D base_color_texture : register(t0);
+SamplerState base_color_sampler : register(s0);
+#endif
+
 struct VertexIn
 {
-    float2 position : POSITION;
+    float3 position : POSITION;
+    float3 normal : NORMAL;
+    float4 tangent : TANGENT;
+    float2 uv : TEXCOORD;
     float4 color : COLOR;
 };
 
 struct VertexOut
 {
     float4 position : SV_POSITION;
+    float2 uv : TEXCOORD;
     float4 color : COLOR;
 };
 
@@ -21,13 +45,23 @@ VertexOut VSMain(VertexIn vIn)
     VertexOut result;
     
     // TODO: Z Index
-    result.position = mul(float4(vIn.position,0.0,1.0), model_view_projection);
+    float4 world_position = mul(float4(vIn.position,1.0), world);
+    result.position = mul(world_position, view_projection);
+    result.uv = vIn.uv * uv_scale;
+#ifdef VERTEX_COLORS
     result.color = vIn.color;
+#else
+    result.color = float4(1.0, 1.0, 1.0, 1.0);
+#endif
 
     return result;
 }
 
 float4 PSMain(VertexOut input) : SV_TARGET
 {
-    return input.color;
-}
\ No newline at end of file
+    float4 color = input.color * base_color;
+#ifdef TEXTURED
+    color *= base_color_texture.Sample(base_color_sampler, input.uv);
+#endif
+    return color;
+}
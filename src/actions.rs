@@ -0,0 +1,464 @@
+// Named actions and axes bound to keys, mouse buttons and mouse axes, so the
+// update code asks for "rotate" instead of checking key codes. Bindings are
+// read from and written to a small text file:
+//
+//   # comment
+//   action toggle_fullscreen = Alt+Enter
+//   axis rotate = Right, Left*-1
+//   axis zoom = WheelY*0.5
+//
+// A binding is optional modifiers joined with '+', then a key, mouse button or
+// mouse axis name. Axis bindings may be scaled with '*'. Bindings without
+// modifiers fire whatever modifiers are held; bindings with modifiers need
+// exactly those held.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::input::Input;
+use crate::platform::{Key, Modifiers, MouseButton};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseAxis {
+    // Pixels moved this frame
+    X,
+    Y,
+    // Notches scrolled this frame
+    WheelX,
+    WheelY,
+}
+
+impl MouseAxis {
+    pub const ALL: [MouseAxis; 4] = [
+        MouseAxis::X,
+        MouseAxis::Y,
+        MouseAxis::WheelX,
+        MouseAxis::WheelY,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MouseAxis::X => "MouseX",
+            MouseAxis::Y => "MouseY",
+            MouseAxis::WheelX => "WheelX",
+            MouseAxis::WheelY => "WheelY",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputSource {
+    Key(Key),
+    MouseButton(MouseButton),
+    MouseAxis(MouseAxis),
+}
+
+impl InputSource {
+    pub fn from_name(name: &str) -> Option<InputSource> {
+        if let Some(axis) = MouseAxis::ALL
+            .iter()
+            .find(|a| a.name().eq_ignore_ascii_case(name))
+        {
+            return Some(InputSource::MouseAxis(*axis));
+        }
+        if let Some(button) = MouseButton::from_name(name) {
+            return Some(InputSource::MouseButton(button));
+        }
+        Key::from_name(name).map(InputSource::Key)
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            InputSource::Key(key) => key.name(),
+            InputSource::MouseButton(button) => button.name(),
+            InputSource::MouseAxis(axis) => axis.name().to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Binding {
+    pub source: InputSource,
+    pub modifiers: Modifiers,
+    // Only used by axes. Keys and buttons contribute `scale` while held.
+    pub scale: f32,
+}
+
+impl Binding {
+    pub fn new(source: InputSource) -> Self {
+        Self {
+            source,
+            modifiers: Modifiers::default(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn key(key: Key) -> Self {
+        Self::new(InputSource::Key(key))
+    }
+
+    pub fn with_modifiers(mut self, modifiers: Modifiers) -> Self {
+        self.modifiers = modifiers;
+        self
+    }
+
+    pub fn scaled(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<Binding, Error> {
+        let (text, scale) = match text.find('*') {
+            Some(i) => {
+                let scale = text[i + 1..]
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_data(format!("Invalid scale in binding \"{}\"", text)))?;
+                (&text[..i], scale)
+            }
+            None => (text, 1.0),
+        };
+
+        let mut modifiers = Modifiers::default();
+        let mut parts: Vec<&str> = text.split('+').map(|p| p.trim()).collect();
+        let source_name = parts.pop().unwrap_or("");
+        for part in parts {
+            match part.to_lowercase().as_str() {
+                "shift" => modifiers.shift = true,
+                "ctrl" | "control" => modifiers.control = true,
+                "alt" => modifiers.alt = true,
+                _ => return Err(invalid_data(format!("Unknown modifier \"{}\"", part))),
+            }
+        }
+
+        let source = InputSource::from_name(source_name)
+            .ok_or_else(|| invalid_data(format!("Unknown input \"{}\"", source_name)))?;
+        Ok(Binding {
+            source,
+            modifiers,
+            scale,
+        })
+    }
+
+    fn modifiers_match(&self, input: &Input) -> bool {
+        self.modifiers == Modifiers::default() || self.modifiers == input.modifiers()
+    }
+
+    fn is_down(&self, input: &Input) -> bool {
+        self.modifiers_match(input)
+            && match self.source {
+                InputSource::Key(key) => input.is_key_down(key),
+                InputSource::MouseButton(button) => input.is_button_down(button),
+                InputSource::MouseAxis(_) => self.axis_value(input) != 0.0,
+            }
+    }
+
+    fn was_pressed(&self, input: &Input) -> bool {
+        self.modifiers_match(input)
+            && match self.source {
+                InputSource::Key(key) => input.was_key_pressed(key),
+                InputSource::MouseButton(button) => input.was_button_pressed(button),
+                InputSource::MouseAxis(_) => false,
+            }
+    }
+
+    fn axis_value(&self, input: &Input) -> f32 {
+        if !self.modifiers_match(input) {
+            return 0.0;
+        }
+        let value = match self.source {
+            InputSource::Key(key) => input.is_key_down(key) as i32 as f32,
+            InputSource::MouseButton(button) => input.is_button_down(button) as i32 as f32,
+            InputSource::MouseAxis(MouseAxis::X) => input.mouse_delta().0 as f32,
+            InputSource::MouseAxis(MouseAxis::Y) => input.mouse_delta().1 as f32,
+            InputSource::MouseAxis(MouseAxis::WheelX) => input.wheel_delta().0,
+            InputSource::MouseAxis(MouseAxis::WheelY) => input.wheel_delta().1,
+        };
+        value * self.scale
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.control {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.shift {
+            write!(f, "Shift+")?;
+        }
+        if self.modifiers.alt {
+            write!(f, "Alt+")?;
+        }
+        write!(f, "{}", self.source.name())?;
+        if self.scale != 1.0 {
+            write!(f, "*{}", self.scale)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub const DEFAULT_BINDINGS: &str = "\
+action toggle_fullscreen = Alt+Enter
+action next_camera = C
+action camera_look = MouseLeft
+action camera_pan = MouseRight, MouseMiddle
+axis rotate = Right, Left*-1
+axis camera_zoom = WheelY
+axis move_forward = W, S*-1
+axis move_right = D, A*-1
+axis move_up = E, Q*-1
+";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ActionMap {
+    actions: BTreeMap<String, Vec<Binding>>,
+    axes: BTreeMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_defaults() -> Self {
+        Self::parse(DEFAULT_BINDINGS).unwrap()
+    }
+
+    pub fn parse(text: &str) -> Result<ActionMap, Error> {
+        let mut map = ActionMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| invalid_data(format!("Line {}: {}", number + 1, message));
+
+            let (kind, rest) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+            let equals = rest.find('=').ok_or_else(|| error("expected '='"))?;
+            let name = rest[..equals].trim();
+            if name.is_empty() {
+                return Err(error("missing name"));
+            }
+            let bindings = rest[equals + 1..]
+                .split(',')
+                .map(|b| b.trim())
+                .filter(|b| !b.is_empty())
+                .map(Binding::parse)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| error(&e.to_string()))?;
+
+            match kind {
+                "action" => map.bind_action(name, bindings),
+                "axis" => map.bind_axis(name, bindings),
+                _ => return Err(error("expected \"action\" or \"axis\"")),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ActionMap, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Falls back to the defaults and writes them out when there is no file yet,
+    // so there is something to edit
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<ActionMap, Error> {
+        match Self::load(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let map = Self::with_defaults();
+                map.save(&path)?;
+                Ok(map)
+            }
+            result => result,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_string())
+    }
+
+    // Replaces all bindings of an action
+    pub fn bind_action(&mut self, name: &str, bindings: Vec<Binding>) {
+        self.actions.insert(name.to_string(), bindings);
+    }
+
+    pub fn bind_axis(&mut self, name: &str, bindings: Vec<Binding>) {
+        self.axes.insert(name.to_string(), bindings);
+    }
+
+    pub fn action_bindings(&self, name: &str) -> &[Binding] {
+        self.actions.get(name).map_or(&[], |b| b.as_slice())
+    }
+
+    pub fn axis_bindings(&self, name: &str) -> &[Binding] {
+        self.axes.get(name).map_or(&[], |b| b.as_slice())
+    }
+
+    // Any binding held
+    pub fn is_active(&self, input: &Input, action: &str) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|b| b.is_down(input))
+    }
+
+    // Any binding went down this frame
+    pub fn was_triggered(&self, input: &Input, action: &str) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|b| b.was_pressed(input))
+    }
+
+    // Sum of all bindings. Key only axes end up in -1..=1 when bound in pairs.
+    pub fn axis(&self, input: &Input, axis: &str) -> f32 {
+        self.axis_bindings(axis)
+            .iter()
+            .map(|b| b.axis_value(input))
+            .sum()
+    }
+}
+
+impl fmt::Display for ActionMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sections = [("action", &self.actions), ("axis", &self.axes)];
+        for (kind, entries) in sections.iter() {
+            for (name, bindings) in entries.iter() {
+                let bindings: Vec<String> = bindings.iter().map(|b| b.to_string()).collect();
+                writeln!(f, "{} {} = {}", kind, name, bindings.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Event;
+    use std::collections::HashMap;
+
+    fn press(input: &mut Input, key: Key) {
+        input.handle_event(&Event::KeyDown {
+            key,
+            modifiers: Modifiers::default(),
+            repeat: false,
+        });
+    }
+
+    #[test]
+    fn default_bindings_are_disjoint() {
+        let map = ActionMap::with_defaults();
+        let mut owners: HashMap<InputSource, &str> = HashMap::new();
+        for (name, bindings) in map.actions.iter().chain(map.axes.iter()) {
+            for binding in bindings {
+                if let Some(other) = owners.insert(binding.source, name) {
+                    panic!(
+                        "{} is bound to {} and {}",
+                        binding.source.name(),
+                        other,
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let text = "\
+# comment
+action quit = Ctrl+Shift+Alt+Escape, Alt+F4
+action fire = MouseLeft
+axis zoom = WheelY*0.5, PageUp, PageDown*-1
+axis look_x = MouseX*-0.25
+";
+        let map = ActionMap::parse(text).unwrap();
+        let written = map.to_string();
+        assert_eq!(ActionMap::parse(&written).unwrap(), map);
+        // Writing is stable, so saving twice doesn't churn the file
+        assert_eq!(ActionMap::parse(&written).unwrap().to_string(), written);
+
+        let defaults = ActionMap::with_defaults();
+        assert_eq!(ActionMap::parse(&defaults.to_string()).unwrap(), defaults);
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let binding = Binding::parse(" ctrl + shift+A * -2 ").unwrap();
+        assert_eq!(binding.source, InputSource::Key(Key::Letter('A')));
+        assert!(binding.modifiers.control && binding.modifiers.shift);
+        assert!(!binding.modifiers.alt);
+        assert_eq!(binding.scale, -2.0);
+        assert_eq!(binding.to_string(), "Ctrl+Shift+A*-2");
+        assert_eq!(
+            Binding::parse("wheely").unwrap().source,
+            InputSource::MouseAxis(MouseAxis::WheelY)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for text in &[
+            "action jump",
+            "action = Space",
+            "button jump = Space",
+            "action jump = Hyper+Space",
+            "action jump = NoSuchKey",
+            "axis zoom = WheelY*lots",
+            "action jump = Shift+",
+        ] {
+            let error = ActionMap::parse(text).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", text);
+            assert!(error.to_string().starts_with("Line 1: "), "{}", error);
+        }
+        // Line numbers count comments and blank lines
+        let error = ActionMap::parse("# c\n\naction jump").unwrap_err();
+        assert!(error.to_string().starts_with("Line 3: "), "{}", error);
+    }
+
+    #[test]
+    fn modifiers_must_match_exactly() {
+        let map = ActionMap::parse("action fullscreen = Alt+Enter\naction select = Enter").unwrap();
+
+        let mut input = Input::new();
+        press(&mut input, Key::Enter);
+        assert!(!map.was_triggered(&input, "fullscreen"));
+        assert!(map.was_triggered(&input, "select"));
+
+        // Bindings without modifiers fire whatever is held
+        let mut input = Input::new();
+        press(&mut input, Key::Alt);
+        press(&mut input, Key::Enter);
+        assert!(map.was_triggered(&input, "fullscreen"));
+        assert!(map.was_triggered(&input, "select"));
+
+        // Extra modifiers don't count as Alt+Enter
+        let mut input = Input::new();
+        press(&mut input, Key::Alt);
+        press(&mut input, Key::Shift);
+        press(&mut input, Key::Enter);
+        assert!(!map.was_triggered(&input, "fullscreen"));
+        assert!(map.is_active(&input, "select"));
+    }
+
+    #[test]
+    fn axes_sum_their_bindings() {
+        let map = ActionMap::with_defaults();
+        let mut input = Input::new();
+        assert_eq!(map.axis(&input, "rotate"), 0.0);
+        press(&mut input, Key::Right);
+        assert_eq!(map.axis(&input, "rotate"), 1.0);
+        press(&mut input, Key::Left);
+        assert_eq!(map.axis(&input, "rotate"), 0.0);
+        // Moving the camera sideways doesn't spin the quad
+        let mut input = Input::new();
+        press(&mut input, Key::Letter('D'));
+        assert_eq!(map.axis(&input, "rotate"), 0.0);
+        assert_eq!(map.axis(&input, "move_right"), 1.0);
+    }
+}
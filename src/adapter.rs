@@ -0,0 +1,463 @@
+use std::fmt;
+
+// PCI vendor ids reported in DXGI_ADAPTER_DESC1
+pub const VENDOR_AMD: u32 = 0x1002;
+pub const VENDOR_NVIDIA: u32 = 0x10DE;
+pub const VENDOR_INTEL: u32 = 0x8086;
+pub const VENDOR_MICROSOFT: u32 = 0x1414;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FeatureLevel {
+    Level9_1,
+    Level9_2,
+    Level9_3,
+    Level10_0,
+    Level10_1,
+    Level11_0,
+    Level11_1,
+}
+
+impl FeatureLevel {
+    // Highest first, the order D3D11CreateDevice expects
+    pub const ALL: [FeatureLevel; 7] = [
+        FeatureLevel::Level11_1,
+        FeatureLevel::Level11_0,
+        FeatureLevel::Level10_1,
+        FeatureLevel::Level10_0,
+        FeatureLevel::Level9_3,
+        FeatureLevel::Level9_2,
+        FeatureLevel::Level9_1,
+    ];
+
+    // Same values as D3D_FEATURE_LEVEL
+    pub fn to_raw(self) -> u32 {
+        match self {
+            FeatureLevel::Level9_1 => 0x9100,
+            FeatureLevel::Level9_2 => 0x9200,
+            FeatureLevel::Level9_3 => 0x9300,
+            FeatureLevel::Level10_0 => 0xa000,
+            FeatureLevel::Level10_1 => 0xa100,
+            FeatureLevel::Level11_0 => 0xb000,
+            FeatureLevel::Level11_1 => 0xb100,
+        }
+    }
+
+    pub fn from_raw(raw: u32) -> Option<FeatureLevel> {
+        FeatureLevel::ALL
+            .iter()
+            .copied()
+            .find(|l| l.to_raw() == raw)
+    }
+}
+
+impl fmt::Display for FeatureLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw = self.to_raw();
+        write!(f, "{}_{}", raw >> 12, (raw >> 8) & 0xf)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdapterDesc {
+    pub index: u32,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub dedicated_video_memory: usize,
+    pub shared_system_memory: usize,
+    pub is_software: bool,
+    // Highest level a device could be created with, None if creation failed
+    pub feature_level: Option<FeatureLevel>,
+}
+
+impl AdapterDesc {
+    pub fn vendor_name(&self) -> &'static str {
+        match self.vendor_id {
+            VENDOR_AMD => "AMD",
+            VENDOR_NVIDIA => "NVIDIA",
+            VENDOR_INTEL => "Intel",
+            VENDOR_MICROSOFT => "Microsoft",
+            _ => "Unknown",
+        }
+    }
+
+    // DXGI has no discrete flag. Intel parts and adapters without their own
+    // memory are treated as integrated.
+    pub fn is_discrete(&self) -> bool {
+        !self.is_software && self.vendor_id != VENDOR_INTEL && self.dedicated_video_memory > 0
+    }
+}
+
+impl fmt::Display for AdapterDesc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} ({}, {} MB dedicated",
+            self.index,
+            self.name,
+            self.vendor_name(),
+            self.dedicated_video_memory / (1024 * 1024)
+        )?;
+        match self.feature_level {
+            Some(level) => write!(f, ", feature level {})", level),
+            None => write!(f, ", unsupported)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AdapterPreference {
+    #[default]
+    PreferDiscrete,
+    ByName(String),
+    ByIndex(u32),
+    Warp,
+}
+
+impl AdapterPreference {
+    // Parses the value of the --adapter command line option:
+    // "discrete", "warp", an adapter index or part of an adapter name.
+    pub fn from_arg(arg: &str) -> Self {
+        match arg.to_lowercase().as_str() {
+            "" | "discrete" => AdapterPreference::PreferDiscrete,
+            "warp" => AdapterPreference::Warp,
+            lower => match lower.parse::<u32>() {
+                Ok(index) => AdapterPreference::ByIndex(index),
+                Err(_) => AdapterPreference::ByName(arg.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceChoice {
+    // Index into the adapter slice passed to select_adapter
+    Adapter(usize),
+    Warp,
+}
+
+// Picks the adapter to create the device on. Software adapters and adapters
+// below min_level are never picked directly; when nothing usable matches the
+// preference we fall back to the best hardware adapter and finally to WARP.
+pub fn select_adapter(
+    adapters: &[AdapterDesc],
+    preference: &AdapterPreference,
+    min_level: FeatureLevel,
+) -> DeviceChoice {
+    let usable =
+        |a: &AdapterDesc| !a.is_software && a.feature_level.is_some_and(|l| l >= min_level);
+
+    let matched = match preference {
+        AdapterPreference::Warp => return DeviceChoice::Warp,
+        AdapterPreference::PreferDiscrete => None,
+        AdapterPreference::ByIndex(index) => {
+            adapters.iter().position(|a| a.index == *index && usable(a))
+        }
+        AdapterPreference::ByName(name) => {
+            let name = name.to_lowercase();
+            adapters
+                .iter()
+                .position(|a| a.name.to_lowercase().contains(&name) && usable(a))
+        }
+    };
+
+    if let Some(i) = matched {
+        return DeviceChoice::Adapter(i);
+    }
+
+    adapters
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| usable(a))
+        .max_by_key(|(i, a)| {
+            (
+                a.is_discrete(),
+                a.dedicated_video_memory,
+                a.feature_level,
+                // Prefer the lower enumeration index on ties
+                std::cmp::Reverse(*i),
+            )
+        })
+        .map_or(DeviceChoice::Warp, |(i, _)| DeviceChoice::Adapter(i))
+}
+
+#[cfg(windows)]
+pub use self::win32::*;
+
+#[cfg(windows)]
+mod win32 {
+    use super::{AdapterDesc, FeatureLevel};
+    use std::mem;
+    use std::ptr::null_mut;
+
+    use winapi::shared::dxgi::*;
+    use winapi::shared::winerror::{FAILED, SUCCEEDED};
+    use winapi::um::d3d11::{D3D11CreateDevice, D3D11_SDK_VERSION};
+    use winapi::um::d3dcommon::D3D_DRIVER_TYPE_UNKNOWN;
+    use winapi::Interface;
+
+    pub struct Adapter {
+        pub desc: AdapterDesc,
+        pub raw: *mut IDXGIAdapter1,
+    }
+
+    impl Drop for Adapter {
+        fn drop(&mut self) {
+            unsafe {
+                self.raw.as_ref().unwrap().Release();
+            }
+        }
+    }
+
+    // Highest feature level the adapter supports, found by creating a device
+    // without asking for the device itself.
+    pub fn max_feature_level(adapter: *mut IDXGIAdapter1) -> Option<FeatureLevel> {
+        let levels: Vec<u32> = FeatureLevel::ALL.iter().map(|l| l.to_raw()).collect();
+        unsafe {
+            let mut level = 0;
+            let mut res = D3D11CreateDevice(
+                adapter as *mut IDXGIAdapter,
+                D3D_DRIVER_TYPE_UNKNOWN,
+                null_mut(),
+                0,
+                levels.as_ptr(),
+                levels.len() as u32,
+                D3D11_SDK_VERSION,
+                null_mut(),
+                &mut level,
+                null_mut(),
+            );
+            // The 11.0 runtime rejects the whole list if it contains 11_1
+            if FAILED(res) {
+                res = D3D11CreateDevice(
+                    adapter as *mut IDXGIAdapter,
+                    D3D_DRIVER_TYPE_UNKNOWN,
+                    null_mut(),
+                    0,
+                    levels[1..].as_ptr(),
+                    levels.len() as u32 - 1,
+                    D3D11_SDK_VERSION,
+                    null_mut(),
+                    &mut level,
+                    null_mut(),
+                );
+            }
+            if SUCCEEDED(res) {
+                FeatureLevel::from_raw(level)
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn enumerate_adapters() -> Vec<Adapter> {
+        let mut adapters = Vec::new();
+        unsafe {
+            let mut factory: *mut IDXGIFactory1 = null_mut();
+            let res = CreateDXGIFactory1(
+                &IDXGIFactory1::uuidof(),
+                &mut factory as *mut *mut IDXGIFactory1 as *mut *mut winapi::ctypes::c_void,
+            );
+            if FAILED(res) {
+                println!("Error creating DXGI factory: {}", res);
+                return adapters;
+            }
+
+            let mut index = 0;
+            loop {
+                let mut raw: *mut IDXGIAdapter1 = null_mut();
+                // Fails with DXGI_ERROR_NOT_FOUND past the last adapter
+                if FAILED(factory.as_ref().unwrap().EnumAdapters1(index, &mut raw)) {
+                    break;
+                }
+
+                let mut dxgi_desc: DXGI_ADAPTER_DESC1 = mem::zeroed();
+                raw.as_ref().unwrap().GetDesc1(&mut dxgi_desc);
+                let name_len = dxgi_desc
+                    .Description
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(dxgi_desc.Description.len());
+
+                let desc = AdapterDesc {
+                    index,
+                    name: String::from_utf16_lossy(&dxgi_desc.Description[..name_len]),
+                    vendor_id: dxgi_desc.VendorId,
+                    device_id: dxgi_desc.DeviceId,
+                    dedicated_video_memory: dxgi_desc.DedicatedVideoMemory,
+                    shared_system_memory: dxgi_desc.SharedSystemMemory,
+                    is_software: dxgi_desc.Flags & DXGI_ADAPTER_FLAG_SOFTWARE != 0,
+                    feature_level: max_feature_level(raw),
+                };
+                adapters.push(Adapter { desc, raw });
+                index += 1;
+            }
+
+            factory.as_ref().unwrap().Release();
+        }
+        adapters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+
+    fn adapter(index: u32, name: &str, vendor_id: u32, dedicated_mb: usize) -> AdapterDesc {
+        AdapterDesc {
+            index,
+            name: String::from(name),
+            vendor_id,
+            device_id: 0,
+            dedicated_video_memory: dedicated_mb * MB,
+            shared_system_memory: 4096 * MB,
+            is_software: false,
+            feature_level: Some(FeatureLevel::Level11_1),
+        }
+    }
+
+    // Integrated first, as laptops usually enumerate them, then a discrete
+    // card and the Basic Render Driver
+    fn laptop() -> Vec<AdapterDesc> {
+        let mut basic = adapter(2, "Microsoft Basic Render Driver", VENDOR_MICROSOFT, 0);
+        basic.is_software = true;
+        vec![
+            adapter(0, "Intel(R) UHD Graphics 630", VENDOR_INTEL, 128),
+            adapter(1, "NVIDIA GeForce RTX 3060 Laptop GPU", VENDOR_NVIDIA, 6144),
+            basic,
+        ]
+    }
+
+    fn select(adapters: &[AdapterDesc], preference: &AdapterPreference) -> DeviceChoice {
+        select_adapter(adapters, preference, FeatureLevel::Level11_0)
+    }
+
+    #[test]
+    fn parses_preferences() {
+        assert_eq!(
+            AdapterPreference::from_arg("Discrete"),
+            AdapterPreference::PreferDiscrete
+        );
+        assert_eq!(AdapterPreference::from_arg("WARP"), AdapterPreference::Warp);
+        assert_eq!(
+            AdapterPreference::from_arg("2"),
+            AdapterPreference::ByIndex(2)
+        );
+        assert_eq!(
+            AdapterPreference::from_arg("GeForce"),
+            AdapterPreference::ByName(String::from("GeForce"))
+        );
+        assert_eq!(FeatureLevel::Level10_1.to_string(), "10_1");
+        assert_eq!(
+            FeatureLevel::from_raw(0xb000),
+            Some(FeatureLevel::Level11_0)
+        );
+    }
+
+    #[test]
+    fn prefers_discrete_over_integrated() {
+        let adapters = laptop();
+        assert_eq!(
+            select(&adapters, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Adapter(1)
+        );
+        // Even when the integrated part reports more dedicated memory
+        let mut adapters = laptop();
+        adapters[0].dedicated_video_memory = 8192 * MB;
+        assert_eq!(
+            select(&adapters, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Adapter(1)
+        );
+    }
+
+    #[test]
+    fn selects_by_name_and_index() {
+        let adapters = laptop();
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByName(String::from("uhd"))),
+            DeviceChoice::Adapter(0)
+        );
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByIndex(0)),
+            DeviceChoice::Adapter(0)
+        );
+        // The DXGI index, not the position in the slice
+        let reordered: Vec<AdapterDesc> = laptop().into_iter().rev().collect();
+        assert_eq!(
+            select(&reordered, &AdapterPreference::ByIndex(0)),
+            DeviceChoice::Adapter(2)
+        );
+        // Nothing matching falls back to the best adapter
+        assert_eq!(
+            select(
+                &adapters,
+                &AdapterPreference::ByName(String::from("Radeon"))
+            ),
+            DeviceChoice::Adapter(1)
+        );
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByIndex(9)),
+            DeviceChoice::Adapter(1)
+        );
+    }
+
+    #[test]
+    fn skips_software_and_low_feature_levels() {
+        let mut adapters = laptop();
+        adapters[1].feature_level = Some(FeatureLevel::Level10_1);
+        assert_eq!(
+            select(&adapters, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Adapter(0)
+        );
+        // Asked for by name, but it can't be used
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByName(String::from("basic"))),
+            DeviceChoice::Adapter(0)
+        );
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByIndex(1)),
+            DeviceChoice::Adapter(0)
+        );
+        // Failing to create a device at all counts as unsupported
+        adapters[0].feature_level = None;
+        assert_eq!(
+            select(&adapters, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Warp
+        );
+    }
+
+    #[test]
+    fn falls_back_to_warp() {
+        assert_eq!(
+            select(&[], &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Warp
+        );
+        let software_only = vec![laptop().remove(2)];
+        assert_eq!(
+            select(&software_only, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Warp
+        );
+        assert_eq!(
+            select(&laptop(), &AdapterPreference::Warp),
+            DeviceChoice::Warp
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_index() {
+        let adapters = vec![
+            adapter(0, "Radeon RX 6800", VENDOR_AMD, 16384),
+            adapter(1, "Radeon RX 6800", VENDOR_AMD, 16384),
+        ];
+        assert_eq!(
+            select(&adapters, &AdapterPreference::PreferDiscrete),
+            DeviceChoice::Adapter(0)
+        );
+        assert_eq!(
+            select(&adapters, &AdapterPreference::ByName(String::from("6800"))),
+            DeviceChoice::Adapter(0)
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// The scene and what it does each frame, apart from drawing it. Only reads
+// input, the viewport size and the frame's delta, so it runs the same on any
+// platform and a recording can be replayed through it without a window.
+use crate::platform::{Platform, Window as _};
+use crate::{
+    actions, camera, camera_controller, display, gamepad, input, platform, replay, scene_graph,
+    transform,
+};
+
+// Key bindings, written with the defaults on first run
+pub const CONTROLS_FILE: &str = "controls.cfg";
+
+// Radians per second while a rotate key is held
+pub const ROTATION_SPEED: f64 = 5.0;
+
+// Radians per second the satellite circles the quad
+pub const ORBIT_SPEED: f64 = 1.0;
+
+// Indices into the renderer's geometry
+pub const QUAD_MESH: usize = 0;
+pub const TRIANGLE_MESH: usize = 1;
+
+// Indices into the renderer's materials, in the order they're loaded
+pub const QUAD_MATERIAL: usize = 0;
+pub const SATELLITE_MATERIAL: usize = 1;
+
+pub struct SceneObject {
+    pub node: scene_graph::NodeId,
+    // Index into the renderer's geometry
+    pub mesh: usize,
+    // Index into the renderer's materials
+    pub material: usize,
+}
+
+// Everything update_scene changes
+pub struct Scene {
+    pub rot: f64,
+    pub orbit: f64,
+    pub graph: scene_graph::SceneGraph,
+    pub quad: scene_graph::NodeId,
+    pub pivot: scene_graph::NodeId,
+    // Nodes that draw something, the rest only group
+    pub objects: Vec<SceneObject>,
+    pub camera: camera::Camera,
+    pub camera_mode: camera_controller::CameraMode,
+    pub camera_controller: Option<Box<dyn camera_controller::CameraController>>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        // The quad the sample always drew, with a smaller one circling it.
+        // The satellite hangs off an unscaled pivot so the quad's spin and
+        // scale don't carry over to it.
+        let mut graph = scene_graph::SceneGraph::new();
+        let center = graph.add(
+            "center",
+            transform::Transform::from_translation(0.25, 0.0, 0.0),
+            None,
+        );
+        let quad = graph.add(
+            "quad",
+            transform::Transform::identity().with_uniform_scale(0.25),
+            Some(center),
+        );
+        let pivot = graph.add("pivot", transform::Transform::identity(), Some(center));
+        let satellite = graph.add(
+            "satellite",
+            transform::Transform::from_translation(0.0, 0.35, 0.0).with_uniform_scale(0.08),
+            Some(pivot),
+        );
+
+        let camera_mode = camera_controller::CameraMode::default();
+        let (camera, camera_controller) = camera_mode.create(1.0);
+        Self {
+            rot: 0.0,
+            orbit: 0.0,
+            graph,
+            quad,
+            pivot,
+            objects: vec![
+                SceneObject {
+                    node: quad,
+                    mesh: QUAD_MESH,
+                    material: QUAD_MATERIAL,
+                },
+                SceneObject {
+                    node: satellite,
+                    mesh: TRIANGLE_MESH,
+                    material: SATELLITE_MATERIAL,
+                },
+            ],
+            camera,
+            camera_mode,
+            camera_controller,
+        }
+    }
+}
+
+// Everything the scene does each frame. Only reads input, the viewport size
+// and the frame's delta, so replaying a recording ends up in the same state.
+pub fn update_scene<B: gamepad::GamepadBackend>(
+    scene: &mut Scene,
+    input: &input::Input,
+    action_map: &actions::ActionMap,
+    gamepads: &gamepad::Gamepads<B>,
+    viewport: (u32, u32),
+    delta_time: f64,
+) {
+    // Keyboard and the first pad's left stick both spin the quad
+    let mut spin = action_map.axis(input, "rotate");
+    if let Some(pad) = gamepads.first_connected() {
+        spin += gamepads.left_stick(pad).0;
+    }
+    let spin = spin.clamp(-1.0, 1.0);
+    scene.rot += ROTATION_SPEED * spin as f64 * delta_time;
+    scene.orbit += ORBIT_SPEED * delta_time;
+
+    let quad = scene.graph.transform_mut(scene.quad);
+    *quad = quad.with_rotation(0.0, scene.rot as f32, 0.0);
+    let pivot = scene.graph.transform_mut(scene.pivot);
+    *pivot = pivot.with_rotation(0.0, 0.0, scene.orbit as f32);
+    scene.graph.update_world_matrices();
+
+    if action_map.was_triggered(input, "next_camera") {
+        scene.camera_mode = scene.camera_mode.next();
+        let (mut camera, controller) = scene.camera_mode.create(scene.camera.aspect);
+        camera.reverse_z = scene.camera.reverse_z;
+        scene.camera = camera;
+        scene.camera_controller = controller;
+        println!("Camera: {}", scene.camera_mode.name());
+    }
+    scene.camera.set_viewport_size(viewport.0, viewport.1);
+    if let Some(controller) = &mut scene.camera_controller {
+        let camera_input =
+            camera_controller::CameraInput::from_actions(input, action_map, viewport.1);
+        controller.update(&mut scene.camera, &camera_input, delta_time as f32);
+    }
+}
+
+// Runs a recording through the scene update without a window or device and
+// returns where the scene ended up
+pub fn replay_headless(recording: &replay::Recording, action_map: &actions::ActionMap) -> Scene {
+    let mut platform = platform::headless::HeadlessPlatform::new(recording.event_script());
+    let mut window = platform
+        .create_window(&platform::WindowDesc {
+            title: String::from("replay"),
+            rect: display::Rect::new(
+                0,
+                0,
+                recording.viewport.0 as i32,
+                recording.viewport.1 as i32,
+            ),
+            style: display::WindowStyle::Overlapped,
+        })
+        .unwrap();
+    let mut gamepads =
+        gamepad::Gamepads::new(replay::TapeBackend::<gamepad::VirtualGamepad>::replay());
+    let mut input = input::Input::new();
+    let mut events = Vec::new();
+    let mut scene = Scene::new();
+
+    for frame in &recording.frames {
+        input.begin_frame();
+        window.poll_events(&mut events);
+        for event in events.drain(..) {
+            input.handle_event(&event);
+        }
+        gamepads.backend_mut().set_frame(&frame.pads);
+        gamepads.update();
+        update_scene(
+            &mut scene,
+            &input,
+            action_map,
+            &gamepads,
+            window.size(),
+            frame.delta_time,
+        );
+    }
+    scene
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::{Event, Key, Modifiers};
+
+    fn frame(delta_time: f64, events: Vec<Event>) -> replay::Frame {
+        replay::Frame {
+            delta_time,
+            events,
+            pads: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn headless_replay_runs_scripted_events() {
+        let modifiers = Modifiers::default();
+        let recording = replay::Recording {
+            viewport: (640, 480),
+            frames: vec![
+                frame(
+                    0.25,
+                    vec![Event::KeyDown {
+                        key: Key::Right,
+                        modifiers,
+                        repeat: false,
+                    }],
+                ),
+                // Still held
+                frame(0.5, Vec::new()),
+                frame(
+                    0.125,
+                    vec![
+                        Event::KeyUp {
+                            key: Key::Right,
+                            modifiers,
+                        },
+                        Event::Resized {
+                            width: 800,
+                            height: 400,
+                        },
+                    ],
+                ),
+            ],
+        };
+
+        let scene = replay_headless(&recording, &actions::ActionMap::with_defaults());
+        assert!((scene.rot - ROTATION_SPEED * 0.75).abs() < 1e-9);
+        assert!((scene.orbit - ORBIT_SPEED * 0.875).abs() < 1e-9);
+        // The resize reached the camera through the headless window
+        assert!((scene.camera.aspect - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn headless_replay_of_nothing_leaves_scene_alone() {
+        let recording = replay::Recording {
+            viewport: (640, 480),
+            frames: Vec::new(),
+        };
+        let scene = replay_headless(&recording, &actions::ActionMap::with_defaults());
+        assert_eq!(scene.rot, 0.0);
+        assert_eq!(scene.orbit, 0.0);
+    }
+}
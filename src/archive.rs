@@ -0,0 +1,525 @@
+// Packed archives, so a shipped build reads its assets from one file instead
+// of loose files in the working directory. The layout, all little endian:
+//
+//   header     magic "RDXA", version, entry count, names size, then the
+//              offsets of the entry table and name table (u64 each)
+//   entries    one per file, sorted by name: data offset, stored size, size
+//              and hash (u64 each), then name offset (u32), name length (u16),
+//              compression (u8) and a padding byte
+//   names      the entries' names, UTF-8 with '/' separators
+//   data       each file's bytes, starting on a DATA_ALIGNMENT boundary
+//
+// Hashes are XXH3 of the uncompressed bytes and are checked on every read.
+// The reader maps the file rather than reading it, so uncompressed files can
+// be used in place.
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use memmap2::Mmap;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::file_system::{normalize, FileSystem};
+
+const MAGIC: &[u8; 4] = b"RDXA";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 32;
+const ENTRY_SIZE: usize = 40;
+// Enough for any vertex or texel format to be read straight out of the map
+pub const DATA_ALIGNMENT: usize = 16;
+
+const ZSTD_LEVEL: i32 = 19;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    // Fast to decompress, for things loaded often
+    Lz4,
+    // Smaller, slower to build
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "none" => Some(Compression::None),
+            "lz4" => Some(Compression::Lz4),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Compression> {
+        match value {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::block::compress(data)),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+        }
+    }
+
+    // The most a stored size can expand to, so a bad entry can't ask for a
+    // huge allocation. LZ4 tops out just under 255x, zstd at one 128 KiB block
+    // per 4 bytes.
+    fn max_size(self, stored_size: u64) -> u64 {
+        match self {
+            Compression::None => stored_size,
+            Compression::Lz4 => stored_size.saturating_mul(255),
+            Compression::Zstd => stored_size.saturating_mul(32 * 1024),
+        }
+    }
+
+    fn decompress(self, data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+        let decompressed = match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::block::decompress(data, size)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?,
+            Compression::Zstd => zstd::bulk::decompress(data, size)?,
+        };
+        if decompressed.len() != size {
+            return invalid_data(format!(
+                "Decompressed to {} bytes, expected {}",
+                decompressed.len(),
+                size
+            ));
+        }
+        Ok(decompressed)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    // From the start of the archive
+    pub offset: u64,
+    pub stored_size: u64,
+    pub size: u64,
+    pub hash: u64,
+    pub compression: Compression,
+}
+
+fn invalid_data<T>(message: String) -> Result<T, Error> {
+    Err(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
+
+fn align(offset: usize) -> usize {
+    offset.div_ceil(DATA_ALIGNMENT) * DATA_ALIGNMENT
+}
+
+// The name a path is stored under: normalized, relative, '/' separated.
+// None for paths that climb out of where the archive was built from.
+pub fn entry_name(path: &Path) -> Option<String> {
+    let path = normalize(path);
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+// The path itself for files, everything under it for directories, sorted
+pub fn list_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut children = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    children.sort();
+    let mut files = Vec::new();
+    for child in children {
+        files.extend(list_files(child)?);
+    }
+    Ok(files)
+}
+
+// Collects files, then compresses and lays them out in one go
+#[derive(Default)]
+pub struct ArchiveBuilder {
+    files: Vec<(String, Vec<u8>, Compression)>,
+}
+
+impl ArchiveBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adding a name twice replaces the first one
+    pub fn add<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        data: Vec<u8>,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        let name = entry_name(path.as_ref()).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Can't store {} in an archive", path.as_ref().display()),
+            )
+        })?;
+        self.files.retain(|(existing, _, _)| *existing != name);
+        self.files.push((name, data, compression));
+        Ok(())
+    }
+
+    pub fn add_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        self.add(path.as_ref(), fs::read(path.as_ref())?, compression)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Of the files before compression
+    pub fn data_size(&self) -> usize {
+        self.files.iter().map(|(_, data, _)| data.len()).sum()
+    }
+
+    // Files that don't get smaller are stored uncompressed
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut files: Vec<&(String, Vec<u8>, Compression)> = self.files.iter().collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut names = Vec::new();
+        let mut blobs = Vec::with_capacity(files.len());
+        for (name, data, compression) in &files {
+            let compressed = compression.compress(data)?;
+            let (compression, stored) = if compressed.len() < data.len() {
+                (*compression, compressed)
+            } else {
+                (Compression::None, data.clone())
+            };
+            let name_offset = names.len();
+            names.extend_from_slice(name.as_bytes());
+            blobs.push((
+                name_offset,
+                name.len(),
+                xxh3_64(data),
+                data.len(),
+                compression,
+                stored,
+            ));
+        }
+
+        let entries_offset = HEADER_SIZE;
+        let names_offset = entries_offset + files.len() * ENTRY_SIZE;
+        let mut offset = align(names_offset + names.len());
+        let mut entries = Vec::with_capacity(files.len() * ENTRY_SIZE);
+        for (name_offset, name_len, hash, size, compression, stored) in &blobs {
+            if *name_len > u16::MAX as usize {
+                return invalid_data(format!("Name too long: {} bytes", name_len));
+            }
+            entries.extend_from_slice(&(offset as u64).to_le_bytes());
+            entries.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+            entries.extend_from_slice(&(*size as u64).to_le_bytes());
+            entries.extend_from_slice(&hash.to_le_bytes());
+            entries.extend_from_slice(&(*name_offset as u32).to_le_bytes());
+            entries.extend_from_slice(&(*name_len as u16).to_le_bytes());
+            entries.push(compression.to_u8());
+            entries.push(0);
+            offset = align(offset + stored.len());
+        }
+
+        let mut bytes = Vec::with_capacity(offset);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(entries_offset as u64).to_le_bytes());
+        bytes.extend_from_slice(&(names_offset as u64).to_le_bytes());
+        bytes.extend_from_slice(&entries);
+        bytes.extend_from_slice(&names);
+        for (.., stored) in &blobs {
+            bytes.resize(align(bytes.len()), 0);
+            bytes.extend_from_slice(stored);
+        }
+        Ok(bytes)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.build()?)
+    }
+}
+
+enum Storage {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Storage {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Storage::Mapped(map) => map,
+            Storage::Owned(bytes) => bytes,
+        }
+    }
+}
+
+pub struct Archive {
+    storage: Storage,
+    // Sorted by name
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    // The file mustn't change while the archive is open
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Archive, Error> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        Self::new(Storage::Mapped(map))
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Archive, Error> {
+        Self::new(Storage::Owned(bytes))
+    }
+
+    // Checks the whole table of contents up front so lookups can't go out of
+    // bounds later
+    fn new(storage: Storage) -> Result<Archive, Error> {
+        let bytes = storage.bytes();
+        if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+            return invalid_data(String::from("Not an archive"));
+        }
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return invalid_data(format!("Unsupported archive version {}", version));
+        }
+        let count = read_u32(bytes, 8) as usize;
+        let names_size = read_u32(bytes, 12) as usize;
+        let entries_offset = read_u64(bytes, 16) as usize;
+        let names_offset = read_u64(bytes, 24) as usize;
+        let in_bounds = |offset: usize, size: usize| {
+            offset
+                .checked_add(size)
+                .is_some_and(|end| end <= bytes.len())
+        };
+        if !in_bounds(entries_offset, count.saturating_mul(ENTRY_SIZE))
+            || !in_bounds(names_offset, names_size)
+        {
+            return invalid_data(String::from("Truncated table of contents"));
+        }
+        let names = &bytes[names_offset..names_offset + names_size];
+
+        let mut entries: Vec<Entry> = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = entries_offset + i * ENTRY_SIZE;
+            let name_offset = read_u32(bytes, at + 32) as usize;
+            let name_len = read_u16(bytes, at + 36) as usize;
+            let name = names
+                .get(name_offset..name_offset + name_len)
+                .and_then(|name| std::str::from_utf8(name).ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid entry name"))?;
+            let entry = Entry {
+                name: String::from(name),
+                offset: read_u64(bytes, at),
+                stored_size: read_u64(bytes, at + 8),
+                size: read_u64(bytes, at + 16),
+                hash: read_u64(bytes, at + 24),
+                compression: Compression::from_u8(bytes[at + 38]).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unknown compression in {}", name),
+                    )
+                })?,
+            };
+            if !in_bounds(entry.offset as usize, entry.stored_size as usize) {
+                return invalid_data(format!("{} is past the end of the archive", name));
+            }
+            if entry.size > entry.compression.max_size(entry.stored_size)
+                || (entry.compression == Compression::None && entry.size != entry.stored_size)
+            {
+                return invalid_data(format!(
+                    "{} claims {} bytes from {} stored",
+                    name, entry.size, entry.stored_size
+                ));
+            }
+            if entries.last().is_some_and(|last| last.name >= entry.name) {
+                return invalid_data(format!("{} is out of order", name));
+            }
+            entries.push(entry);
+        }
+        Ok(Archive { storage, entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> Option<&Entry> {
+        let name = entry_name(path.as_ref())?;
+        self.entries
+            .binary_search_by(|entry| entry.name.as_str().cmp(&name))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    // The bytes as stored, compressed or not. Not checked against the hash.
+    pub fn raw(&self, entry: &Entry) -> &[u8] {
+        let offset = entry.offset as usize;
+        &self.storage.bytes()[offset..offset + entry.stored_size as usize]
+    }
+
+    pub fn read_entry(&self, entry: &Entry) -> Result<Vec<u8>, Error> {
+        let data = entry
+            .compression
+            .decompress(self.raw(entry), entry.size as usize)?;
+        if xxh3_64(&data) != entry.hash {
+            return invalid_data(format!("{} is corrupt", entry.name));
+        }
+        Ok(data)
+    }
+}
+
+// Paths are looked up relative to where the archive was built from
+impl FileSystem for Archive {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        match self.entry(path) {
+            Some(entry) => self.read_entry(entry),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No {} in the archive", path.display()),
+            )),
+        }
+    }
+
+    // Archives don't change while open, so the hash will do
+    fn version(&self, path: &Path) -> Option<u64> {
+        self.entry(path).map(|entry| entry.hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressible(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / 7 % 5) as u8).collect()
+    }
+
+    fn build(files: &[(&str, Vec<u8>)], compression: Compression) -> Vec<u8> {
+        let mut builder = ArchiveBuilder::new();
+        for (name, data) in files {
+            builder.add(name, data.clone(), compression).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let files = [
+            ("shaders/basic.hlsl", compressible(3000)),
+            ("a.txt", b"hi".to_vec()),
+            ("textures/./brick.dds", compressible(1000)),
+        ];
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let archive = Archive::from_bytes(build(&files, compression)).unwrap();
+            let names: Vec<&str> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+            assert_eq!(names, ["a.txt", "shaders/basic.hlsl", "textures/brick.dds"]);
+            for (name, data) in &files {
+                let entry = archive.entry(name).unwrap();
+                assert_eq!(entry.offset as usize % DATA_ALIGNMENT, 0);
+                assert_eq!(archive.read(Path::new(name)).unwrap(), *data, "{:?}", entry);
+                assert_eq!(archive.version(Path::new(name)), Some(xxh3_64(data)));
+            }
+            // Too small to shrink, so kept as is
+            assert_eq!(
+                archive.entry("a.txt").unwrap().compression,
+                Compression::None
+            );
+            let shader = archive.entry("shaders/basic.hlsl").unwrap();
+            assert_eq!(shader.compression, compression);
+            if compression != Compression::None {
+                assert!(shader.stored_size < shader.size, "{:?}", shader);
+            }
+            let missing = archive.read(Path::new("b.txt")).unwrap_err();
+            assert_eq!(missing.kind(), ErrorKind::NotFound);
+        }
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let mut bytes = build(&[("a.bin", compressible(100))], Compression::None);
+        let offset = read_u64(&bytes, HEADER_SIZE) as usize;
+        bytes[offset + 50] ^= 1;
+        let archive = Archive::from_bytes(bytes).unwrap();
+        let error = archive.read(Path::new("a.bin")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_bad_table_of_contents() {
+        let bytes = build(
+            &[("a.bin", compressible(100)), ("b.bin", compressible(200))],
+            Compression::Lz4,
+        );
+        let rejected = |bytes: Vec<u8>| {
+            Archive::from_bytes(bytes).err().map(|e| e.kind()) == Some(ErrorKind::InvalidData)
+        };
+        assert!(rejected(bytes[..HEADER_SIZE + ENTRY_SIZE].to_vec()));
+        assert!(rejected(bytes[..HEADER_SIZE - 1].to_vec()));
+
+        // An entry's data cut off
+        let end = read_u64(&bytes, HEADER_SIZE + ENTRY_SIZE) as usize;
+        assert!(rejected(bytes[..end].to_vec()));
+
+        // Sizes a stored size can't expand to
+        let mut huge = bytes.clone();
+        huge[HEADER_SIZE + 16..HEADER_SIZE + 24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(rejected(huge));
+        let mut uncompressed = bytes.clone();
+        uncompressed[HEADER_SIZE + 38] = Compression::None.to_u8();
+        assert!(rejected(uncompressed));
+
+        assert!(Archive::from_bytes(bytes).is_ok());
+    }
+}
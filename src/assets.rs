@@ -0,0 +1,951 @@
+// Meshes, textures, shaders, materials and configs loaded by path and handed
+// out as typed, reference counted handles. Loading a path that's already
+// loaded or loading returns a handle to the same asset. Loads run on a thread
+// pool and finish, running their callbacks, in update(). An asset is dropped
+// by the first update() after its last handle is.
+//
+// Every file a load reads is remembered, and reload_changed() loads again
+// the assets whose files have changed since. Handles stay valid across a
+// reload, take_reloaded() says which reloads have finished. One that failed
+// keeps the old contents and has error() set until a reload succeeds.
+use std::any;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::actions::ActionMap;
+use crate::dds;
+use crate::file_system::{normalize, DiskFileSystem, FileSystem};
+use crate::gltf_import::GltfScene;
+use crate::hot_reload::{DependencyGraph, FileWatcher};
+use crate::material::MaterialDesc;
+use crate::mesh::Mesh;
+use crate::obj::ObjModel;
+use crate::texture::{ColorSpace, Image, TextureData};
+use crate::thread_pool::ThreadPool;
+
+pub trait Asset: Sized + Send + 'static {
+    // Runs on a worker thread. Everything read through `files` is watched
+    // for changes.
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error>;
+
+    // Where the manager keeps assets of this type
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self>;
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self>;
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.iter().any(|e| extension.eq_ignore_ascii_case(e)))
+}
+
+// glTF scenes are flattened into one mesh, anything else is read as OBJ
+impl Asset for Mesh {
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error> {
+        if has_extension(path, &["gltf", "glb"]) {
+            GltfScene::load_from(files, path)?
+                .flatten()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Scene has no meshes"))
+        } else {
+            ObjModel::load_from(files, path).map(|model| model.mesh)
+        }
+    }
+
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self> {
+        &caches.meshes
+    }
+
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self> {
+        &mut caches.meshes
+    }
+}
+
+// DDS files are used as they are, other images are taken as sRGB color and
+// get a generated mip chain
+impl Asset for TextureData {
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error> {
+        if has_extension(path, &["dds"]) {
+            dds::parse(&files.read(path)?)
+        } else {
+            let image = Image::load_from(files, path)?;
+            Ok(TextureData::from_image(&image, ColorSpace::Srgb, true))
+        }
+    }
+
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self> {
+        &caches.textures
+    }
+
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self> {
+        &mut caches.textures
+    }
+}
+
+// HLSL source, compiled by whoever creates the shaders, and bytecode compiled
+// ahead of time for some of its permutations. <name>.permutations lists
+// those, a line of defines each, and the Nth one's bytecode is in
+// <name>.N.vs.cso and <name>.N.ps.cso.
+#[derive(Clone, Debug)]
+pub struct Shader {
+    pub path: PathBuf,
+    pub source: String,
+    // By the permutation's sorted defines
+    pub bytecode: BTreeMap<Vec<String>, ShaderBytecode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderBytecode {
+    pub vertex: Vec<u8>,
+    pub pixel: Vec<u8>,
+}
+
+pub fn permutations_path(path: &Path) -> PathBuf {
+    path.with_extension("permutations")
+}
+
+// Where the bytecode for one stage ("vs" or "ps") of a permutation goes
+pub fn bytecode_path(path: &Path, permutation: usize, stage: &str) -> PathBuf {
+    path.with_extension(format!("{}.{}.cso", permutation, stage))
+}
+
+impl Asset for Shader {
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error> {
+        let mut bytecode = BTreeMap::new();
+        if let Ok(permutations) = files.read_to_string(&permutations_path(path)) {
+            for (index, line) in permutations.lines().enumerate() {
+                let mut defines: Vec<String> = line.split_whitespace().map(String::from).collect();
+                defines.sort();
+                defines.dedup();
+                let code = ShaderBytecode {
+                    vertex: files.read(&bytecode_path(path, index, "vs"))?,
+                    pixel: files.read(&bytecode_path(path, index, "ps"))?,
+                };
+                bytecode.insert(defines, code);
+            }
+        }
+        Ok(Shader {
+            path: path.to_path_buf(),
+            source: files.read_to_string(path)?,
+            bytecode,
+        })
+    }
+
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self> {
+        &caches.shaders
+    }
+
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self> {
+        &mut caches.shaders
+    }
+}
+
+// Key bindings
+impl Asset for ActionMap {
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error> {
+        ActionMap::parse(&files.read_to_string(path)?)
+    }
+
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self> {
+        &caches.configs
+    }
+
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self> {
+        &mut caches.configs
+    }
+}
+
+impl Asset for MaterialDesc {
+    fn load(path: &Path, files: &dyn FileSystem) -> Result<Self, Error> {
+        MaterialDesc::parse(&files.read_to_string(path)?)
+    }
+
+    fn cache(caches: &AssetCaches) -> &AssetCache<Self> {
+        &caches.materials
+    }
+
+    fn cache_mut(caches: &mut AssetCaches) -> &mut AssetCache<Self> {
+        &mut caches.materials
+    }
+}
+
+// Files and the assets made from them
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Node {
+    File(PathBuf),
+    // The asset's type name and path
+    Asset(&'static str, PathBuf),
+}
+
+impl Node {
+    fn asset<T: Asset>(path: &Path) -> Self {
+        Node::Asset(any::type_name::<T>(), path.to_path_buf())
+    }
+}
+
+// Passes reads on, noting each file and its version before reading it
+struct RecordingFileSystem<'a> {
+    files: &'a dyn FileSystem,
+    reads: Mutex<Vec<(PathBuf, Option<u64>)>>,
+}
+
+impl FileSystem for RecordingFileSystem<'_> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let path = normalize(path);
+        let version = self.files.version(&path);
+        self.reads.lock().unwrap().push((path.clone(), version));
+        self.files.read(&path)
+    }
+
+    fn version(&self, path: &Path) -> Option<u64> {
+        self.files.version(path)
+    }
+}
+
+// Which files each asset read and the versions they were read at
+#[derive(Default)]
+struct Tracking {
+    graph: DependencyGraph<Node>,
+    watcher: FileWatcher,
+}
+
+impl Tracking {
+    fn track(&mut self, asset: &Node, reads: Vec<(PathBuf, Option<u64>)>) {
+        let previous: Vec<Node> = self.graph.dependencies(asset).cloned().collect();
+        self.graph.set_dependencies(
+            asset,
+            reads.iter().map(|(path, _)| Node::File(path.clone())),
+        );
+        for (path, version) in reads {
+            self.watcher.watch(path, version);
+        }
+        self.unwatch_unused(previous);
+    }
+
+    fn forget(&mut self, asset: &Node) {
+        let previous: Vec<Node> = self.graph.dependencies(asset).cloned().collect();
+        self.graph.remove(asset);
+        self.unwatch_unused(previous);
+    }
+
+    // Stops watching files no asset reads anymore
+    fn unwatch_unused(&mut self, files: Vec<Node>) {
+        for file in files {
+            if let Node::File(path) = &file {
+                if !self.graph.contains(&file) {
+                    self.watcher.unwatch(path);
+                }
+            }
+        }
+    }
+}
+
+// Shared by every clone of a handle. Caches only keep a Weak to it, so its
+// strong count is the number of handles.
+struct HandleInner {
+    index: usize,
+}
+
+pub struct Handle<T> {
+    inner: Arc<HandleInner>,
+    asset: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(inner: Arc<HandleInner>) -> Self {
+        Handle {
+            inner,
+            asset: PhantomData,
+        }
+    }
+
+    // Slot in the asset's cache, reused once the asset is unloaded
+    pub fn index(&self) -> usize {
+        self.inner.index
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle::new(Arc::clone(&self.inner))
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({})", self.inner.index)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+// Run on the main thread, in update(), once the load has finished
+type Callback<T> = Box<dyn FnOnce(Result<&T, &Error>)>;
+
+enum SlotState<T> {
+    Loading,
+    Loaded(T),
+    Failed(Error),
+}
+
+struct Slot<T> {
+    path: PathBuf,
+    handle: Weak<HandleInner>,
+    // Tells the load this slot is waiting for from one started for an asset
+    // that has since been unloaded
+    ticket: u64,
+    state: SlotState<T>,
+    // Loading again while the old asset stays in use
+    reloading: bool,
+    // Why the last reload failed, while the old asset is kept
+    reload_error: Option<Error>,
+    callbacks: Vec<Callback<T>>,
+}
+
+struct Completion<T> {
+    index: usize,
+    ticket: u64,
+    result: Result<T, Error>,
+    reads: Vec<(PathBuf, Option<u64>)>,
+}
+
+pub struct AssetCache<T: Asset> {
+    slots: Vec<Option<Slot<T>>>,
+    free: Vec<usize>,
+    by_path: HashMap<PathBuf, usize>,
+    next_ticket: u64,
+    // Slots whose reloads have finished since take_reloaded()
+    reloaded: Vec<usize>,
+    sender: Sender<Completion<T>>,
+    receiver: Receiver<Completion<T>>,
+}
+
+impl<T: Asset> AssetCache<T> {
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        AssetCache {
+            slots: Vec::new(),
+            free: Vec::new(),
+            by_path: HashMap::new(),
+            next_ticket: 0,
+            reloaded: Vec::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    fn slot(&self, handle: &Handle<T>) -> &Slot<T> {
+        self.slots[handle.index()]
+            .as_ref()
+            .expect("Handle outlived its asset")
+    }
+
+    // A handle to the slot, sharing the count of any handle still around
+    fn handle(&mut self, index: usize) -> Handle<T> {
+        let slot = self.slots[index].as_mut().unwrap();
+        let inner = slot.handle.upgrade().unwrap_or_else(|| {
+            let inner = Arc::new(HandleInner { index });
+            slot.handle = Arc::downgrade(&inner);
+            inner
+        });
+        Handle::new(inner)
+    }
+
+    fn add_slot(&mut self, path: PathBuf, state: SlotState<T>) -> usize {
+        let slot = Slot {
+            path: path.clone(),
+            handle: Weak::new(),
+            ticket: 0,
+            state,
+            reloading: false,
+            reload_error: None,
+            callbacks: Vec::new(),
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(slot);
+                index
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.by_path.insert(path, index);
+        index
+    }
+
+    // Leaves the slot's state alone, whatever is there stays until the load
+    // finishes
+    fn start_load(&mut self, pool: &ThreadPool, files: &Arc<dyn FileSystem>, index: usize) {
+        self.next_ticket += 1;
+        let ticket = self.next_ticket;
+        let slot = self.slots[index].as_mut().unwrap();
+        slot.ticket = ticket;
+        let path = slot.path.clone();
+        let files = Arc::clone(files);
+        let sender = self.sender.clone();
+        pool.execute(move || {
+            let recorder = RecordingFileSystem {
+                files: &*files,
+                reads: Mutex::new(Vec::new()),
+            };
+            // A panicking loader fails its asset rather than a worker
+            let result = panic::catch_unwind(AssertUnwindSafe(|| T::load(&path, &recorder)))
+                .unwrap_or_else(|_| {
+                    Err(Error::other(format!(
+                        "Loader panicked on {}",
+                        path.display()
+                    )))
+                });
+            // The cache may be gone if the app is shutting down
+            let _ = sender.send(Completion {
+                index,
+                ticket,
+                result,
+                reads: recorder.reads.into_inner().unwrap(),
+            });
+        });
+    }
+
+    // Paths that failed before are tried again
+    fn load(&mut self, pool: &ThreadPool, files: &Arc<dyn FileSystem>, path: &Path) -> Handle<T> {
+        let path = normalize(path);
+        let index = match self.by_path.get(&path) {
+            Some(&index) => {
+                let slot = self.slots[index].as_mut().unwrap();
+                if let SlotState::Failed(_) = slot.state {
+                    slot.state = SlotState::Loading;
+                    slot.reloading = false;
+                    self.start_load(pool, files, index);
+                }
+                index
+            }
+            None => {
+                let index = self.add_slot(path, SlotState::Loading);
+                self.start_load(pool, files, index);
+                index
+            }
+        };
+        self.handle(index)
+    }
+
+    // Nothing happens for paths that aren't loaded
+    fn reload(&mut self, pool: &ThreadPool, files: &Arc<dyn FileSystem>, path: &Path) {
+        if let Some(&index) = self.by_path.get(path) {
+            let slot = self.slots[index].as_mut().unwrap();
+            if let SlotState::Loading = slot.state {
+                // Not loaded yet, so there's nothing stale to replace
+                slot.reloading = false;
+            } else {
+                slot.reloading = true;
+            }
+            self.start_load(pool, files, index);
+        }
+    }
+
+    // An asset made in code, under a name other loads of the same path
+    // will find. Replaces what was there.
+    fn insert(&mut self, path: &Path, asset: T) -> Handle<T> {
+        let path = normalize(path);
+        let index = match self.by_path.get(&path) {
+            Some(&index) => {
+                let slot = self.slots[index].as_mut().unwrap();
+                // Ignore any load still running for it
+                slot.ticket = 0;
+                slot.reloading = false;
+                slot.reload_error = None;
+                slot.state = SlotState::Loaded(asset);
+                index
+            }
+            None => self.add_slot(path, SlotState::Loaded(asset)),
+        };
+        self.handle(index)
+    }
+
+    // A failed reload keeps the asset that was there and is reported like
+    // one that worked, with the error alongside
+    fn complete(&mut self, completion: Completion<T>, tracking: &mut Tracking) {
+        let slot = match self.slots[completion.index].as_mut() {
+            Some(slot) if slot.ticket == completion.ticket => slot,
+            _ => return,
+        };
+        // Even failed loads are watched, fixing the file reloads them
+        tracking.track(&Node::asset::<T>(&slot.path), completion.reads);
+        let reloading = mem::replace(&mut slot.reloading, false);
+        match completion.result {
+            Ok(asset) => {
+                slot.state = SlotState::Loaded(asset);
+                slot.reload_error = None;
+                if reloading {
+                    self.reloaded.push(completion.index);
+                }
+            }
+            Err(e) if reloading && matches!(slot.state, SlotState::Loaded(_)) => {
+                slot.reload_error = Some(e);
+                self.reloaded.push(completion.index);
+            }
+            Err(e) => slot.state = SlotState::Failed(e),
+        }
+    }
+
+    pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        match &self.slot(handle).state {
+            SlotState::Loaded(asset) => Some(asset),
+            _ => None,
+        }
+    }
+
+    // Why the load failed, or why the last reload did for a loaded asset
+    pub fn error(&self, handle: &Handle<T>) -> Option<&Error> {
+        let slot = self.slot(handle);
+        match &slot.state {
+            SlotState::Failed(e) => Some(e),
+            SlotState::Loaded(_) => slot.reload_error.as_ref(),
+            SlotState::Loading => None,
+        }
+    }
+
+    pub fn state(&self, handle: &Handle<T>) -> LoadState {
+        match self.slot(handle).state {
+            SlotState::Loading => LoadState::Loading,
+            SlotState::Loaded(_) => LoadState::Loaded,
+            SlotState::Failed(_) => LoadState::Failed,
+        }
+    }
+
+    pub fn path(&self, handle: &Handle<T>) -> &Path {
+        &self.slot(handle).path
+    }
+
+    // Assets loaded, loading or failed, including unreferenced ones update()
+    // hasn't dropped yet
+    pub fn len(&self) -> usize {
+        self.by_path.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_path.is_empty()
+    }
+
+    fn update(&mut self, tracking: &mut Tracking) {
+        while let Ok(completion) = self.receiver.try_recv() {
+            self.complete(completion, tracking);
+        }
+        // Unloading first cancels the callbacks of loads nobody holds anymore
+        for index in 0..self.slots.len() {
+            let unreferenced = self.slots[index]
+                .as_ref()
+                .is_some_and(|slot| slot.handle.strong_count() == 0);
+            if unreferenced {
+                let slot = self.slots[index].take().unwrap();
+                tracking.forget(&Node::asset::<T>(&slot.path));
+                self.by_path.remove(&slot.path);
+                self.free.push(index);
+            }
+        }
+        for slot in self.slots.iter_mut().flatten() {
+            let result = match &slot.state {
+                SlotState::Loading => continue,
+                SlotState::Loaded(asset) => Ok(asset),
+                SlotState::Failed(e) => Err(e),
+            };
+            for callback in mem::take(&mut slot.callbacks) {
+                callback(result);
+            }
+        }
+    }
+
+    // Blocks until the handle's load has finished
+    fn wait(&mut self, handle: &Handle<T>, tracking: &mut Tracking) {
+        while self.state(handle) == LoadState::Loading {
+            // The cache holds a sender itself, so this can't fail
+            let completion = self.receiver.recv().unwrap();
+            self.complete(completion, tracking);
+        }
+    }
+
+    fn take_reloaded(&mut self) -> Vec<Handle<T>> {
+        let mut reloaded = mem::take(&mut self.reloaded);
+        reloaded.sort_unstable();
+        reloaded.dedup();
+        reloaded.retain(|&index| self.slots[index].is_some());
+        reloaded
+            .into_iter()
+            .map(|index| self.handle(index))
+            .collect()
+    }
+}
+
+// One cache per asset type
+pub struct AssetCaches {
+    meshes: AssetCache<Mesh>,
+    textures: AssetCache<TextureData>,
+    shaders: AssetCache<Shader>,
+    configs: AssetCache<ActionMap>,
+    materials: AssetCache<MaterialDesc>,
+}
+
+impl AssetCaches {
+    fn update(&mut self, tracking: &mut Tracking) {
+        self.meshes.update(tracking);
+        self.textures.update(tracking);
+        self.shaders.update(tracking);
+        self.configs.update(tracking);
+        self.materials.update(tracking);
+    }
+
+    fn reload(&mut self, pool: &ThreadPool, files: &Arc<dyn FileSystem>, node: &Node) {
+        let (kind, path) = match node {
+            Node::Asset(kind, path) => (*kind, path),
+            Node::File(_) => return,
+        };
+        if kind == any::type_name::<Mesh>() {
+            self.meshes.reload(pool, files, path);
+        } else if kind == any::type_name::<TextureData>() {
+            self.textures.reload(pool, files, path);
+        } else if kind == any::type_name::<Shader>() {
+            self.shaders.reload(pool, files, path);
+        } else if kind == any::type_name::<ActionMap>() {
+            self.configs.reload(pool, files, path);
+        } else if kind == any::type_name::<MaterialDesc>() {
+            self.materials.reload(pool, files, path);
+        }
+    }
+}
+
+pub struct AssetManager {
+    pool: ThreadPool,
+    files: Arc<dyn FileSystem>,
+    caches: AssetCaches,
+    tracking: Tracking,
+}
+
+impl Default for AssetManager {
+    fn default() -> Self {
+        Self::new(
+            ThreadPool::with_available_parallelism(),
+            Arc::new(DiskFileSystem),
+        )
+    }
+}
+
+impl AssetManager {
+    pub fn new(pool: ThreadPool, files: Arc<dyn FileSystem>) -> Self {
+        AssetManager {
+            pool,
+            files,
+            caches: AssetCaches {
+                meshes: AssetCache::new(),
+                textures: AssetCache::new(),
+                shaders: AssetCache::new(),
+                configs: AssetCache::new(),
+                materials: AssetCache::new(),
+            },
+            tracking: Tracking::default(),
+        }
+    }
+
+    pub fn load<T: Asset, P: AsRef<Path>>(&mut self, path: P) -> Handle<T> {
+        T::cache_mut(&mut self.caches).load(&self.pool, &self.files, path.as_ref())
+    }
+
+    // The callback runs in the update() after the load finishes, or the next
+    // one if it already has. Dropping every handle first cancels it.
+    pub fn load_with<T, P, F>(&mut self, path: P, callback: F) -> Handle<T>
+    where
+        T: Asset,
+        P: AsRef<Path>,
+        F: FnOnce(Result<&T, &Error>) + 'static,
+    {
+        let handle = self.load(path);
+        T::cache_mut(&mut self.caches).slots[handle.index()]
+            .as_mut()
+            .unwrap()
+            .callbacks
+            .push(Box::new(callback));
+        handle
+    }
+
+    // Inserted assets read no files, so they're never reloaded
+    pub fn insert<T: Asset, P: AsRef<Path>>(&mut self, path: P, asset: T) -> Handle<T> {
+        T::cache_mut(&mut self.caches).insert(path.as_ref(), asset)
+    }
+
+    pub fn cache<T: Asset>(&self) -> &AssetCache<T> {
+        T::cache(&self.caches)
+    }
+
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T> {
+        self.cache().get(handle)
+    }
+
+    pub fn error<T: Asset>(&self, handle: &Handle<T>) -> Option<&Error> {
+        self.cache().error(handle)
+    }
+
+    pub fn state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        self.cache().state(handle)
+    }
+
+    pub fn path<T: Asset>(&self, handle: &Handle<T>) -> &Path {
+        self.cache().path(handle)
+    }
+
+    // Files the asset read the last time it was loaded
+    pub fn files_read<T: Asset>(&self, handle: &Handle<T>) -> Vec<PathBuf> {
+        let node = Node::asset::<T>(self.path(handle));
+        self.tracking
+            .graph
+            .dependencies(&node)
+            .filter_map(|file| match file {
+                Node::File(path) => Some(path.clone()),
+                Node::Asset(..) => None,
+            })
+            .collect()
+    }
+
+    // Blocks until the load finishes, then updates so callbacks run
+    pub fn wait<T: Asset>(&mut self, handle: &Handle<T>) -> Result<&T, &Error> {
+        T::cache_mut(&mut self.caches).wait(handle, &mut self.tracking);
+        self.update();
+        let cache = self.cache();
+        match cache.get(handle) {
+            Some(asset) => Ok(asset),
+            None => Err(cache.error(handle).unwrap()),
+        }
+    }
+
+    // Finishes loads, runs their callbacks and drops unreferenced assets.
+    // Call once a frame.
+    pub fn update(&mut self) {
+        self.caches.update(&mut self.tracking);
+    }
+
+    // Starts reloading every asset that read a file which has changed since.
+    // Returns the changed files.
+    pub fn reload_changed(&mut self) -> Vec<PathBuf> {
+        let changed = self.tracking.watcher.poll(&*self.files);
+        let invalidated = self
+            .tracking
+            .graph
+            .invalidated(changed.iter().cloned().map(Node::File));
+        for node in &invalidated {
+            self.caches.reload(&self.pool, &self.files, node);
+        }
+        changed
+    }
+
+    // Assets whose reloads have finished since the last call, for patching
+    // whatever was made from them. Ones with error() set failed and still
+    // have their old contents.
+    pub fn take_reloaded<T: Asset>(&mut self) -> Vec<Handle<T>> {
+        T::cache_mut(&mut self.caches).take_reloaded()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemoryFileSystem;
+
+    const BINDINGS: &str = "action fire = MouseLeft\n";
+    const OTHER_BINDINGS: &str = "action fire = Space\n";
+
+    fn manager() -> (AssetManager, Arc<MemoryFileSystem>) {
+        let files = Arc::new(MemoryFileSystem::new());
+        let manager = AssetManager::new(ThreadPool::new(1), files.clone());
+        (manager, files)
+    }
+
+    // The one worker runs jobs in order, so once this job has run every load
+    // started before it has sent its completion
+    fn finish_loads(assets: &mut AssetManager) {
+        let (sender, receiver) = channel();
+        assets.pool.execute(move || sender.send(()).unwrap());
+        receiver.recv().unwrap();
+        assets.update();
+    }
+
+    #[test]
+    fn reload_reports_changed_files() {
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let handle = assets.load::<ActionMap, _>("input.cfg");
+        assets.wait(&handle).unwrap();
+        assert_eq!(assets.files_read(&handle), [PathBuf::from("input.cfg")]);
+        assert!(assets.reload_changed().is_empty());
+
+        files.write("input.cfg", OTHER_BINDINGS);
+        assert_eq!(assets.reload_changed(), [PathBuf::from("input.cfg")]);
+        finish_loads(&mut assets);
+        assert_eq!(assets.take_reloaded::<ActionMap>(), vec![handle.clone()]);
+        assert!(assets.take_reloaded::<ActionMap>().is_empty());
+        assert_eq!(
+            *assets.get(&handle).unwrap(),
+            ActionMap::parse(OTHER_BINDINGS).unwrap()
+        );
+    }
+
+    #[test]
+    fn failed_reload_keeps_old_asset() {
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let handle = assets.load::<ActionMap, _>("input.cfg");
+        assets.wait(&handle).unwrap();
+
+        files.write("input.cfg", "action fire = NotAKey\n");
+        assets.reload_changed();
+        finish_loads(&mut assets);
+        assert_eq!(assets.take_reloaded::<ActionMap>(), vec![handle.clone()]);
+        assert_eq!(assets.state(&handle), LoadState::Loaded);
+        assert_eq!(
+            assets.error(&handle).map(|e| e.kind()),
+            Some(ErrorKind::InvalidData)
+        );
+        assert_eq!(
+            *assets.get(&handle).unwrap(),
+            ActionMap::parse(BINDINGS).unwrap()
+        );
+
+        // Still watched, so fixing the file reloads it
+        files.write("input.cfg", OTHER_BINDINGS);
+        assert_eq!(assets.reload_changed(), [PathBuf::from("input.cfg")]);
+        finish_loads(&mut assets);
+        assert_eq!(assets.take_reloaded::<ActionMap>(), vec![handle.clone()]);
+        assert!(assets.error(&handle).is_none());
+        assert_eq!(
+            *assets.get(&handle).unwrap(),
+            ActionMap::parse(OTHER_BINDINGS).unwrap()
+        );
+    }
+
+    #[test]
+    fn loads_of_one_path_share_an_asset() {
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let first = assets.load::<ActionMap, _>("input.cfg");
+        let second = assets.load::<ActionMap, _>("./configs/../input.cfg");
+        assert_eq!(first, second);
+        assert_eq!(assets.cache::<ActionMap>().len(), 1);
+        assert_eq!(assets.caches.configs.next_ticket, 1);
+        assets.wait(&second).unwrap();
+        assert_eq!(assets.state(&first), LoadState::Loaded);
+
+        // The same path as another type is a different asset
+        let material = assets.load::<MaterialDesc, _>("input.cfg");
+        assert_eq!(assets.cache::<MaterialDesc>().len(), 1);
+        assert_eq!(material.index(), first.index());
+    }
+
+    #[test]
+    fn callbacks_run_in_update() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let record = |name: &'static str| {
+            let results = Rc::clone(&results);
+            move |result: Result<&ActionMap, &Error>| {
+                results.borrow_mut().push((name, result.is_ok()))
+            }
+        };
+        let loaded = assets.load_with("input.cfg", record("loaded"));
+        let missing = assets.load_with::<ActionMap, _, _>("missing.cfg", record("missing"));
+        let dropped = assets.load_with::<ActionMap, _, _>("dropped.cfg", record("dropped"));
+        drop(dropped);
+
+        // Finished on the worker, but nothing runs until update()
+        let (sender, receiver) = channel();
+        assets.pool.execute(move || sender.send(()).unwrap());
+        receiver.recv().unwrap();
+        assert!(results.borrow().is_empty());
+        assets.update();
+        assert_eq!(*results.borrow(), [("loaded", true), ("missing", false)]);
+        assert_eq!(assets.state(&missing), LoadState::Failed);
+
+        // Already loaded, so it runs in the next update
+        assets.load_with("input.cfg", record("again"));
+        assert_eq!(results.borrow().len(), 2);
+        assets.update();
+        assert_eq!(results.borrow()[2], ("again", true));
+        assert_eq!(assets.state(&loaded), LoadState::Loaded);
+    }
+
+    #[test]
+    fn assets_unload_after_last_handle_drops() {
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let handle = assets.load::<ActionMap, _>("input.cfg");
+        assets.wait(&handle).unwrap();
+        let clone = handle.clone();
+        drop(handle);
+        assets.update();
+        assert_eq!(assets.cache::<ActionMap>().len(), 1);
+
+        drop(clone);
+        assert_eq!(assets.cache::<ActionMap>().len(), 1);
+        assets.update();
+        assert!(assets.cache::<ActionMap>().is_empty());
+        assert!(assets.tracking.watcher.is_empty());
+        files.write("input.cfg", OTHER_BINDINGS);
+        assert!(assets.reload_changed().is_empty());
+
+        // Loading again reads the file again, into the freed slot
+        let handle = assets.load::<ActionMap, _>("input.cfg");
+        assert_eq!(handle.index(), 0);
+        assert_eq!(
+            *assets.wait(&handle).unwrap(),
+            ActionMap::parse(OTHER_BINDINGS).unwrap()
+        );
+    }
+
+    #[test]
+    fn insert_ignores_load_in_flight() {
+        let (mut assets, files) = manager();
+        files.write("input.cfg", BINDINGS);
+        let loading = assets.load::<ActionMap, _>("input.cfg");
+        let inserted = assets.insert("input.cfg", ActionMap::parse(OTHER_BINDINGS).unwrap());
+        assert_eq!(loading, inserted);
+        finish_loads(&mut assets);
+        assert_eq!(
+            *assets.get(&loading).unwrap(),
+            ActionMap::parse(OTHER_BINDINGS).unwrap()
+        );
+
+        // The stale load's reads weren't tracked either
+        assert!(assets.files_read(&loading).is_empty());
+        files.write("input.cfg", "");
+        assert!(assets.reload_changed().is_empty());
+        finish_loads(&mut assets);
+        assert!(assets.take_reloaded::<ActionMap>().is_empty());
+    }
+}
@@ -0,0 +1,1588 @@
+// Block compression on the CPU: BC1, BC3, BC4, BC5 and BC7 encoders and
+// decoders working on 4x4 blocks of 8 bit RGBA. Encoders fit endpoints along
+// the principal axis of each block and refine them with least squares, the
+// quality preset decides how hard they try. Decoders follow the D3D rules, so
+// compressed textures can be read back on the CPU.
+use std::io::{Error, ErrorKind};
+
+use crate::texture::{ColorSpace, Image, TextureData, TextureFormat};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    // Endpoints straight from the principal axis
+    Fast,
+    #[default]
+    Normal,
+    // Many more refinement passes and, for BC7, every partition
+    Best,
+}
+
+impl Quality {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fast" => Some(Quality::Fast),
+            "normal" => Some(Quality::Normal),
+            "best" => Some(Quality::Best),
+            _ => None,
+        }
+    }
+
+    // Least squares passes over the endpoints
+    fn refinements(self) -> usize {
+        match self {
+            Quality::Fast => 0,
+            Quality::Normal => 2,
+            Quality::Best => 8,
+        }
+    }
+}
+
+// 16 RGBA pixels, rows top to bottom
+type Block = [[u8; 4]; 16];
+
+// Pixels as floats, so fitting doesn't round on every step
+type Color = [f32; 4];
+
+pub fn is_supported(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::Bc1
+            | TextureFormat::Bc1Srgb
+            | TextureFormat::Bc3
+            | TextureFormat::Bc3Srgb
+            | TextureFormat::Bc4
+            | TextureFormat::Bc5
+            | TextureFormat::Bc7
+            | TextureFormat::Bc7Srgb
+    )
+}
+
+fn unsupported(format: TextureFormat) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{:?} can't be compressed or decompressed", format),
+    )
+}
+
+// Channels a format keeps, from red on. What psnr should compare.
+pub fn channels(format: TextureFormat) -> usize {
+    match format {
+        TextureFormat::Bc4 | TextureFormat::R8 => 1,
+        TextureFormat::Bc5 | TextureFormat::Rg8 => 2,
+        // Alpha is only a cutout
+        TextureFormat::Bc1 | TextureFormat::Bc1Srgb => 3,
+        _ => 4,
+    }
+}
+
+// Peak signal to noise ratio in dB over the first `channels` channels.
+// Infinite for identical images.
+pub fn psnr(reference: &Image, image: &Image, channels: usize) -> f64 {
+    assert_eq!(
+        (reference.width, reference.height),
+        (image.width, image.height)
+    );
+    let mut squared_error = 0.0;
+    for (a, b) in reference.pixels.chunks(4).zip(image.pixels.chunks(4)) {
+        for c in 0..channels {
+            let difference = a[c] as f64 - b[c] as f64;
+            squared_error += difference * difference;
+        }
+    }
+    let samples = (reference.width * reference.height) as usize * channels;
+    let mse = squared_error / samples as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+// One level, edge blocks padded by repeating the last row and column
+pub fn compress(image: &Image, format: TextureFormat, quality: Quality) -> Result<Vec<u8>, Error> {
+    if !is_supported(format) {
+        return Err(unsupported(format));
+    }
+    let mut data = Vec::with_capacity(
+        format
+            .level_size(image.width, image.height)
+            .unwrap_or_default(),
+    );
+    for block_y in 0..(image.height as usize).div_ceil(4) {
+        for block_x in 0..(image.width as usize).div_ceil(4) {
+            let mut block = [[0; 4]; 16];
+            for (i, pixel) in block.iter_mut().enumerate() {
+                let x = (block_x * 4 + i % 4).min(image.width as usize - 1);
+                let y = (block_y * 4 + i / 4).min(image.height as usize - 1);
+                *pixel = image.pixel(x as u32, y as u32);
+            }
+            encode_block(format, &block, quality, &mut data);
+        }
+    }
+    Ok(data)
+}
+
+pub fn decompress(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+) -> Result<Image, Error> {
+    if !is_supported(format) {
+        return Err(unsupported(format));
+    }
+    let expected = format
+        .level_size(width, height)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Image too large"))?;
+    if data.len() != expected {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} bytes of {:?}, expected {}",
+                data.len(),
+                format,
+                expected
+            ),
+        ));
+    }
+    let block_size = format.block_size().unwrap();
+    let blocks_wide = (width as usize).div_ceil(4);
+    let mut image = Image::solid(width, height, [0, 0, 0, 0]);
+    for (b, bytes) in data.chunks(block_size).enumerate() {
+        let block = decode_block(format, bytes);
+        for (i, pixel) in block.iter().enumerate() {
+            let x = (b % blocks_wide) * 4 + i % 4;
+            let y = (b / blocks_wide) * 4 + i / 4;
+            if x < width as usize && y < height as usize {
+                let offset = (y * width as usize + x) * 4;
+                image.pixels[offset..offset + 4].copy_from_slice(pixel);
+            }
+        }
+    }
+    Ok(image)
+}
+
+// The image, and its mip chain if asked for, compressed level by level.
+// Mips are filtered in sRGB space for the sRGB formats. Fails for sizes
+// D3D can't create, such as a top level that isn't a multiple of 4.
+pub fn compress_texture(
+    image: &Image,
+    format: TextureFormat,
+    quality: Quality,
+    mipmaps: bool,
+) -> Result<TextureData, Error> {
+    let color_space = if format.is_srgb() {
+        ColorSpace::Srgb
+    } else {
+        ColorSpace::Linear
+    };
+    let levels = if mipmaps {
+        image.mip_chain(color_space)
+    } else {
+        vec![image.clone()]
+    };
+    let subresources = levels
+        .iter()
+        .map(|level| compress(level, format, quality))
+        .collect::<Result<Vec<_>, _>>()?;
+    let texture = TextureData {
+        width: image.width,
+        height: image.height,
+        format,
+        mip_levels: subresources.len() as u32,
+        array_size: 1,
+        cubemap: false,
+        subresources,
+    };
+    texture.validate()?;
+    Ok(texture)
+}
+
+fn encode_block(format: TextureFormat, block: &Block, quality: Quality, out: &mut Vec<u8>) {
+    let channel = |c: usize| {
+        let mut values = [0; 16];
+        for (value, pixel) in values.iter_mut().zip(block.iter()) {
+            *value = pixel[c];
+        }
+        values
+    };
+    match format {
+        TextureFormat::Bc1 | TextureFormat::Bc1Srgb => {
+            out.extend_from_slice(&encode_color(block, quality, true))
+        }
+        TextureFormat::Bc3 | TextureFormat::Bc3Srgb => {
+            out.extend_from_slice(&encode_bc4(&channel(3), quality));
+            out.extend_from_slice(&encode_color(block, quality, false));
+        }
+        TextureFormat::Bc4 => out.extend_from_slice(&encode_bc4(&channel(0), quality)),
+        TextureFormat::Bc5 => {
+            out.extend_from_slice(&encode_bc4(&channel(0), quality));
+            out.extend_from_slice(&encode_bc4(&channel(1), quality));
+        }
+        TextureFormat::Bc7 | TextureFormat::Bc7Srgb => {
+            out.extend_from_slice(&encode_bc7(block, quality))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn decode_block(format: TextureFormat, bytes: &[u8]) -> Block {
+    let mut block = [[0, 0, 0, 255]; 16];
+    match format {
+        TextureFormat::Bc1 | TextureFormat::Bc1Srgb => decode_color(bytes, true, &mut block),
+        TextureFormat::Bc3 | TextureFormat::Bc3Srgb => {
+            decode_bc4(&bytes[..8], 3, &mut block);
+            decode_color(&bytes[8..], false, &mut block);
+        }
+        TextureFormat::Bc4 => decode_bc4(bytes, 0, &mut block),
+        TextureFormat::Bc5 => {
+            decode_bc4(&bytes[..8], 0, &mut block);
+            decode_bc4(&bytes[8..], 1, &mut block);
+        }
+        TextureFormat::Bc7 | TextureFormat::Bc7Srgb => decode_bc7(bytes, &mut block),
+        _ => unreachable!(),
+    }
+    block
+}
+
+fn to_color(pixel: [u8; 4]) -> Color {
+    [
+        pixel[0] as f32,
+        pixel[1] as f32,
+        pixel[2] as f32,
+        pixel[3] as f32,
+    ]
+}
+
+fn distance(a: &Color, b: &Color, channels: usize) -> f32 {
+    (0..channels).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn lerp(a: &Color, b: &Color, t: f32) -> Color {
+    let mut color = [0.0; 4];
+    for c in 0..4 {
+        color[c] = a[c] + (b[c] - a[c]) * t;
+    }
+    color
+}
+
+// Ends of the line through the colors that best follows them: the principal
+// axis through their mean, cut at the outermost projections
+fn principal_endpoints(colors: &[Color], channels: usize) -> (Color, Color) {
+    let count = colors.len().max(1) as f32;
+    let mut mean = [0.0; 4];
+    for color in colors {
+        for c in 0..channels {
+            mean[c] += color[c] / count;
+        }
+    }
+    let mut covariance = [[0.0f32; 4]; 4];
+    for color in colors {
+        for i in 0..channels {
+            for j in 0..channels {
+                covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+    // Power iteration, starting from the longest diagonal of the bounds
+    let mut axis = [0.0f32; 4];
+    for c in 0..channels {
+        let (min, max) = colors
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), color| {
+                (min.min(color[c]), max.max(color[c]))
+            });
+        axis[c] = max - min;
+    }
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for i in 0..channels {
+            for j in 0..channels {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+    let length_sq: f32 = axis.iter().map(|v| v * v).sum();
+    if length_sq < 1e-12 {
+        return (mean, mean);
+    }
+
+    let project = |color: &Color| {
+        (0..channels)
+            .map(|c| (color[c] - mean[c]) * axis[c])
+            .sum::<f32>()
+    };
+    let (min, max) = colors
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(min, max), color| {
+            let t = project(color);
+            (min.min(t), max.max(t))
+        });
+    let mut low = mean;
+    let mut high = mean;
+    for c in 0..channels {
+        low[c] = (mean[c] + axis[c] * min / length_sq).clamp(0.0, 255.0);
+        high[c] = (mean[c] + axis[c] * max / length_sq).clamp(0.0, 255.0);
+    }
+    (low, high)
+}
+
+// Endpoints that best reproduce the colors given where along the line each
+// one sits (0 at `a`, 1 at `b`). None when every color sits at one spot.
+fn least_squares(colors: &[Color], positions: &[f32]) -> Option<(Color, Color)> {
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let mut ax = [0.0f32; 4];
+    let mut bx = [0.0f32; 4];
+    for (color, &t) in colors.iter().zip(positions) {
+        let s = 1.0 - t;
+        aa += s * s;
+        ab += s * t;
+        bb += t * t;
+        for c in 0..4 {
+            ax[c] += s * color[c];
+            bx[c] += t * color[c];
+        }
+    }
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+    let mut a = [0.0; 4];
+    let mut b = [0.0; 4];
+    for c in 0..4 {
+        a[c] = ((bb * ax[c] - ab * bx[c]) / determinant).clamp(0.0, 255.0);
+        b[c] = ((aa * bx[c] - ab * ax[c]) / determinant).clamp(0.0, 255.0);
+    }
+    Some((a, b))
+}
+
+// Index of the closest palette entry and its squared distance
+fn closest(color: &Color, palette: &[Color], channels: usize) -> (usize, f32) {
+    let mut best = (0, f32::MAX);
+    for (i, entry) in palette.iter().enumerate() {
+        let d = distance(color, entry, channels);
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best
+}
+
+// BC1 colors and the color half of BC3
+
+fn to_565(color: &Color) -> u16 {
+    let quantize = |v: f32, max: f32| (v / 255.0 * max).round().clamp(0.0, max) as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+fn from_565(value: u16) -> [u8; 3] {
+    let r = (value >> 11 & 31) as u8;
+    let g = (value >> 5 & 63) as u8;
+    let b = (value & 31) as u8;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+// Four colors when c0 > c1 or always (BC3), otherwise three and transparent
+// black
+fn color_palette(c0: u16, c1: u16, always_four: bool) -> [[u8; 4]; 4] {
+    let a = from_565(c0);
+    let b = from_565(c1);
+    let mix = |wa: u16, wb: u16| {
+        let mut color = [0, 0, 0, 255];
+        for c in 0..3 {
+            color[c] = ((a[c] as u16 * wa + b[c] as u16 * wb + (wa + wb) / 2) / (wa + wb)) as u8;
+        }
+        color
+    };
+    let opaque = |c: [u8; 3]| [c[0], c[1], c[2], 255];
+    if always_four || c0 > c1 {
+        [opaque(a), opaque(b), mix(2, 1), mix(1, 2)]
+    } else {
+        [opaque(a), opaque(b), mix(1, 1), [0, 0, 0, 0]]
+    }
+}
+
+fn decode_color(bytes: &[u8], bc1: bool, block: &mut Block) {
+    let c0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let c1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let indices = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    let palette = color_palette(c0, c1, !bc1);
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let color = palette[(indices >> (i * 2) & 3) as usize];
+        pixel[..3].copy_from_slice(&color[..3]);
+        if bc1 {
+            pixel[3] = color[3];
+        }
+    }
+}
+
+struct ColorFit {
+    c0: u16,
+    c1: u16,
+    indices: [u8; 16],
+    error: f32,
+}
+
+// Indices for a pair of 565 endpoints. `skip` pixels are transparent and
+// take the transparent entry of the three color palette.
+fn fit_indices(
+    block: &[Color; 16],
+    skip: &[bool; 16],
+    c0: u16,
+    c1: u16,
+    always_four: bool,
+) -> ColorFit {
+    let palette = color_palette(c0, c1, always_four).map(to_color);
+    let three_color = !always_four && c0 <= c1;
+    let choices = if three_color { 3 } else { 4 };
+    let mut fit = ColorFit {
+        c0,
+        c1,
+        indices: [0; 16],
+        error: 0.0,
+    };
+    for i in 0..16 {
+        if skip[i] {
+            fit.indices[i] = 3;
+            continue;
+        }
+        let (index, error) = closest(&block[i], &palette[..choices], 3);
+        fit.indices[i] = index as u8;
+        fit.error += error;
+    }
+    fit
+}
+
+// Endpoints in the order the mode needs: c0 > c1 for four colors, c0 <= c1
+// for three
+fn color_fit(
+    block: &[Color; 16],
+    skip: &[bool; 16],
+    a: &Color,
+    b: &Color,
+    three_color: bool,
+    always_four: bool,
+) -> ColorFit {
+    let (mut c0, mut c1) = (to_565(a), to_565(b));
+    // Equal endpoints select three colors in BC1, all of them the same one
+    if (c0 < c1) != three_color && c0 != c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    fit_indices(block, skip, c0, c1, always_four)
+}
+
+// Where each index sits between c0 and c1
+const FOUR_COLOR_POSITIONS: [f32; 4] = [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0];
+const THREE_COLOR_POSITIONS: [f32; 4] = [0.0, 1.0, 0.5, 0.0];
+
+fn fit_color_mode(
+    block: &[Color; 16],
+    skip: &[bool; 16],
+    quality: Quality,
+    three_color: bool,
+    always_four: bool,
+) -> ColorFit {
+    let opaque: Vec<Color> = (0..16).filter(|&i| !skip[i]).map(|i| block[i]).collect();
+    if opaque.is_empty() {
+        // Everything transparent
+        return ColorFit {
+            c0: 0,
+            c1: 0,
+            indices: [3; 16],
+            error: 0.0,
+        };
+    }
+    let positions = if three_color {
+        &THREE_COLOR_POSITIONS
+    } else {
+        &FOUR_COLOR_POSITIONS
+    };
+    let (mut a, mut b) = principal_endpoints(&opaque, 3);
+    let mut best = color_fit(block, skip, &a, &b, three_color, always_four);
+    for _ in 0..quality.refinements() {
+        let (colors, at): (Vec<Color>, Vec<f32>) = (0..16)
+            .filter(|&i| !skip[i])
+            .map(|i| (block[i], positions[best.indices[i] as usize]))
+            .unzip();
+        // Positions are relative to c0 and c1, which may be a and b swapped
+        match least_squares(&colors, &at) {
+            Some((c0, c1)) => {
+                a = c0;
+                b = c1;
+            }
+            None => break,
+        }
+        let fit = color_fit(block, skip, &a, &b, three_color, always_four);
+        if fit.error >= best.error {
+            break;
+        }
+        best = fit;
+    }
+    if quality == Quality::Best {
+        best = nudge_endpoints(block, skip, best, three_color, always_four);
+    }
+    best
+}
+
+// Tries moving each endpoint channel one step while that helps
+fn nudge_endpoints(
+    block: &[Color; 16],
+    skip: &[bool; 16],
+    mut best: ColorFit,
+    three_color: bool,
+    always_four: bool,
+) -> ColorFit {
+    // Red, green and blue fields of a 565 color
+    const FIELDS: [(u16, u16); 3] = [(11, 31), (5, 63), (0, 31)];
+    for _ in 0..4 {
+        let mut improved = false;
+        for endpoint in 0..2 {
+            for &(shift, max) in &FIELDS {
+                for step in [-1i32, 1] {
+                    let value = if endpoint == 0 { best.c0 } else { best.c1 };
+                    let field = (value >> shift & max) as i32 + step;
+                    if field < 0 || field > max as i32 {
+                        continue;
+                    }
+                    let changed = value & !(max << shift) | (field as u16) << shift;
+                    let (c0, c1) = if endpoint == 0 {
+                        (changed, best.c1)
+                    } else {
+                        (best.c0, changed)
+                    };
+                    // Keep the mode the endpoint order selects
+                    if !always_four && ((c0 <= c1) != three_color) {
+                        continue;
+                    }
+                    let fit = fit_indices(block, skip, c0, c1, always_four);
+                    if fit.error < best.error {
+                        best = fit;
+                        improved = true;
+                    }
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    best
+}
+
+fn encode_color(pixels: &Block, quality: Quality, bc1: bool) -> [u8; 8] {
+    let block = pixels.map(to_color);
+    // BC1 can only cut out, with the three color mode
+    let mut skip = [false; 16];
+    if bc1 {
+        for i in 0..16 {
+            skip[i] = pixels[i][3] < 128;
+        }
+    }
+    let has_transparent = skip.iter().any(|&s| s);
+    let fit = if has_transparent {
+        fit_color_mode(&block, &skip, quality, true, false)
+    } else {
+        let four = fit_color_mode(&block, &skip, quality, false, !bc1);
+        if bc1 && quality == Quality::Best {
+            let three = fit_color_mode(&block, &skip, quality, true, false);
+            if three.error < four.error {
+                three
+            } else {
+                four
+            }
+        } else {
+            four
+        }
+    };
+    let mut indices = 0u32;
+    for (i, &index) in fit.indices.iter().enumerate() {
+        indices |= (index as u32) << (i * 2);
+    }
+    let mut bytes = [0; 8];
+    bytes[..2].copy_from_slice(&fit.c0.to_le_bytes());
+    bytes[2..4].copy_from_slice(&fit.c1.to_le_bytes());
+    bytes[4..].copy_from_slice(&indices.to_le_bytes());
+    bytes
+}
+
+// BC4, and the alpha half of BC3 and both halves of BC5
+
+// Eight values when e0 > e1, otherwise six, 0 and 255
+fn bc4_palette(e0: u8, e1: u8) -> [u8; 8] {
+    let (a, b) = (e0 as u32, e1 as u32);
+    let mut palette = [e0, e1, 0, 0, 0, 0, 0, 255];
+    if e0 > e1 {
+        for k in 1..7 {
+            palette[k + 1] = (((7 - k as u32) * a + k as u32 * b + 3) / 7) as u8;
+        }
+    } else {
+        for k in 1..5 {
+            palette[k + 1] = (((5 - k as u32) * a + k as u32 * b + 2) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn decode_bc4(bytes: &[u8], channel: usize, block: &mut Block) {
+    let palette = bc4_palette(bytes[0], bytes[1]);
+    let mut indices = 0u64;
+    for (i, &byte) in bytes[2..8].iter().enumerate() {
+        indices |= (byte as u64) << (i * 8);
+    }
+    for (i, pixel) in block.iter_mut().enumerate() {
+        pixel[channel] = palette[(indices >> (i * 3) & 7) as usize];
+    }
+}
+
+// Indices and squared error for a pair of endpoints
+fn bc4_fit(values: &[u8; 16], e0: u8, e1: u8) -> (u64, u32) {
+    let palette = bc4_palette(e0, e1);
+    let mut indices = 0u64;
+    let mut error = 0;
+    for (i, &value) in values.iter().enumerate() {
+        let (index, d) = palette
+            .iter()
+            .enumerate()
+            .map(|(k, &p)| (k, (p as i32 - value as i32).pow(2) as u32))
+            .min_by_key(|&(_, d)| d)
+            .unwrap();
+        indices |= (index as u64) << (i * 3);
+        error += d;
+    }
+    (indices, error)
+}
+
+fn encode_bc4(values: &[u8; 16], quality: Quality) -> [u8; 8] {
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    // Eight values between the extremes, or six between the extremes that
+    // aren't 0 or 255 since those come free
+    let inner = values.iter().filter(|&&v| v != 0 && v != 255);
+    let inner_min = inner.clone().min().copied().unwrap_or(min);
+    let inner_max = inner.max().copied().unwrap_or(max);
+    let mut candidates = vec![(max, min)];
+    if quality != Quality::Fast {
+        candidates.push((inner_min, inner_max));
+    }
+    let radius: i32 = if quality == Quality::Best { 4 } else { 0 };
+
+    let mut best = (0, 0, 0, u32::MAX);
+    for &(e0, e1) in &candidates {
+        let six = e0 <= e1;
+        for d0 in -radius..=radius {
+            for d1 in -radius..=radius {
+                let a = (e0 as i32 + d0).clamp(0, 255) as u8;
+                let b = (e1 as i32 + d1).clamp(0, 255) as u8;
+                // Stay in the mode being searched
+                if (a <= b) != six {
+                    continue;
+                }
+                let (indices, error) = bc4_fit(values, a, b);
+                if error < best.3 {
+                    best = (a, b, indices, error);
+                }
+            }
+        }
+    }
+    let mut bytes = [0; 8];
+    bytes[0] = best.0;
+    bytes[1] = best.1;
+    bytes[2..].copy_from_slice(&best.2.to_le_bytes()[..6]);
+    bytes
+}
+
+// BC7
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    // A p-bit per endpoint, or one shared by both ends of a subset
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    // Separate alpha indices, modes 4 and 5
+    index2_bits: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn mode(
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index2_bits: u32,
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_pbits,
+        shared_pbits,
+        index_bits,
+        index2_bits,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+// Interpolation weights out of 64, by index bits
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+// Two subset partitions, bit i set when pixel i is in the second subset
+const PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+// Three subset partitions, the subset of each pixel
+const PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+// Pixel whose index is stored with one bit less, for the second subset of
+// two subset partitions
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// Same for the second and third subsets of three subset partitions
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+fn subset_of(subsets: usize, partition: usize, pixel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS_2[partition] >> pixel & 1) as usize,
+        3 => PARTITIONS_3[partition][pixel] as usize,
+        _ => 0,
+    }
+}
+
+fn is_anchor(subsets: usize, partition: usize, pixel: usize) -> bool {
+    match subsets {
+        2 => pixel == 0 || pixel == ANCHORS_2[partition] as usize,
+        3 => {
+            pixel == 0
+                || pixel == ANCHORS_3_SECOND[partition] as usize
+                || pixel == ANCHORS_3_THIRD[partition] as usize
+        }
+        _ => pixel == 0,
+    }
+}
+
+// Widens a `bits` wide value to 8 bits, repeating the high bits at the bottom
+fn expand(value: u32, bits: u32) -> u8 {
+    if bits >= 8 {
+        return value as u8;
+    }
+    (value << (8 - bits) | value >> (2 * bits - 8)) as u8
+}
+
+fn interpolate(a: u8, b: u8, weight: u32) -> u8 {
+    (((64 - weight) * a as u32 + weight * b as u32 + 32) >> 6) as u8
+}
+
+struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits & ((1u128 << count) - 1)) as u32;
+        self.bits >>= count;
+        value
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bits: u128,
+    position: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u128) << self.position;
+        self.position += count;
+    }
+}
+
+fn decode_bc7(bytes: &[u8], block: &mut Block) {
+    let mut word = [0; 16];
+    word.copy_from_slice(bytes);
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(word),
+    };
+    let mode_index = bytes[0].trailing_zeros() as usize;
+    if mode_index >= 8 {
+        // Reserved, decodes to transparent black
+        *block = [[0; 4]; 16];
+        return;
+    }
+    let mode = &BC7_MODES[mode_index];
+    reader.read(mode_index as u32 + 1);
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for c in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[c] = reader.read(mode.color_bits);
+        }
+    }
+    if mode.alpha_bits > 0 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[3] = reader.read(mode.alpha_bits);
+        }
+    }
+    let mut pbits = [0u32; 6];
+    if mode.endpoint_pbits {
+        for pbit in pbits.iter_mut().take(endpoint_count) {
+            *pbit = reader.read(1);
+        }
+    }
+    if mode.shared_pbits {
+        for subset in 0..mode.subsets {
+            let pbit = reader.read(1);
+            pbits[subset * 2] = pbit;
+            pbits[subset * 2 + 1] = pbit;
+        }
+    }
+    let has_pbits = mode.endpoint_pbits || mode.shared_pbits;
+    let mut colors = [[0u8; 4]; 6];
+    for e in 0..endpoint_count {
+        for c in 0..4 {
+            let bits = if c < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            colors[e][c] = if bits == 0 {
+                255
+            } else if has_pbits {
+                expand(endpoints[e][c] << 1 | pbits[e], bits + 1)
+            } else {
+                expand(endpoints[e][c], bits)
+            };
+        }
+    }
+
+    let mut indices = [0u32; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, i);
+        *index = reader.read(mode.index_bits - anchor as u32);
+    }
+    let mut indices2 = [0u32; 16];
+    if mode.index2_bits > 0 {
+        for (i, index) in indices2.iter_mut().enumerate() {
+            *index = reader.read(mode.index2_bits - (i == 0) as u32);
+        }
+    }
+
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let subset = subset_of(mode.subsets, partition, i);
+        let (a, b) = (colors[subset * 2], colors[subset * 2 + 1]);
+        let (color_weight, alpha_weight) = if mode.index2_bits == 0 {
+            let weight = weights(mode.index_bits)[indices[i] as usize];
+            (weight, weight)
+        } else if index_selection == 0 {
+            (
+                weights(mode.index_bits)[indices[i] as usize],
+                weights(mode.index2_bits)[indices2[i] as usize],
+            )
+        } else {
+            (
+                weights(mode.index2_bits)[indices2[i] as usize],
+                weights(mode.index_bits)[indices[i] as usize],
+            )
+        };
+        for c in 0..3 {
+            pixel[c] = interpolate(a[c], b[c], color_weight);
+        }
+        pixel[3] = interpolate(a[3], b[3], alpha_weight);
+        if rotation > 0 {
+            pixel.swap(3, rotation as usize - 1);
+        }
+    }
+}
+
+// A subset fitted for one of the single index set modes: endpoints as
+// stored (without p-bits), the p-bits and an index per pixel of the subset
+struct SubsetFit {
+    endpoints: [[u32; 4]; 2],
+    pbits: [u32; 2],
+    indices: Vec<u32>,
+    error: f32,
+}
+
+// Closest stored value for `x` with the given bits and optional p-bit
+fn quantize(x: f32, bits: u32, pbit: Option<u32>) -> (u32, u8) {
+    let max = (1u32 << bits) - 1;
+    let total = bits + pbit.is_some() as u32;
+    let scaled = x / 255.0 * ((1u32 << total) - 1) as f32;
+    let guess = match pbit {
+        Some(p) => ((scaled - p as f32) / 2.0).round(),
+        None => scaled.round(),
+    }
+    .clamp(0.0, max as f32) as u32;
+    let value = |q: u32| match pbit {
+        Some(p) => expand(q << 1 | p, total),
+        None => expand(q, total),
+    };
+    [guess.saturating_sub(1), guess, (guess + 1).min(max)]
+        .iter()
+        .map(|&q| (q, value(q)))
+        .min_by(|a, b| {
+            (a.1 as f32 - x)
+                .abs()
+                .partial_cmp(&(b.1 as f32 - x).abs())
+                .unwrap()
+        })
+        .unwrap()
+}
+
+// Quantized endpoints for the mode, picking the p-bits that land closest
+fn quantize_endpoints(mode: &Bc7Mode, ends: &[Color; 2]) -> ([[u32; 4]; 2], [u32; 2], [Color; 2]) {
+    let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+    let quantize_end = |end: &Color, pbit: Option<u32>| {
+        let mut stored = [0u32; 4];
+        let mut value: Color = [255.0; 4];
+        let mut error = 0.0;
+        for c in 0..channels {
+            let bits = if c < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            let (q, v) = quantize(end[c], bits, pbit);
+            stored[c] = q;
+            value[c] = v as f32;
+            error += (v as f32 - end[c]).powi(2);
+        }
+        (stored, value, error)
+    };
+    if mode.endpoint_pbits {
+        let mut result = ([[0; 4]; 2], [0; 2], [[0.0; 4]; 2]);
+        for (e, end) in ends.iter().enumerate() {
+            let zero = quantize_end(end, Some(0));
+            let one = quantize_end(end, Some(1));
+            let (best, pbit) = if one.2 < zero.2 { (one, 1) } else { (zero, 0) };
+            result.0[e] = best.0;
+            result.1[e] = pbit;
+            result.2[e] = best.1;
+        }
+        result
+    } else if mode.shared_pbits {
+        let candidates = [0, 1].map(|p| {
+            let a = quantize_end(&ends[0], Some(p));
+            let b = quantize_end(&ends[1], Some(p));
+            ([a.0, b.0], [p, p], [a.1, b.1], a.2 + b.2)
+        });
+        let [zero, one] = candidates;
+        let best = if one.3 < zero.3 { one } else { zero };
+        (best.0, best.1, best.2)
+    } else {
+        let a = quantize_end(&ends[0], None);
+        let b = quantize_end(&ends[1], None);
+        ([a.0, b.0], [0, 0], [a.1, b.1])
+    }
+}
+
+fn fit_subset(mode: &Bc7Mode, colors: &[Color], quality: Quality) -> SubsetFit {
+    let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+    let weights = weights(mode.index_bits);
+    let evaluate = |ends: &[Color; 2]| {
+        let (endpoints, pbits, values) = quantize_endpoints(mode, ends);
+        let palette: Vec<Color> = weights
+            .iter()
+            .map(|&w| {
+                let mut color = [0.0; 4];
+                for c in 0..4 {
+                    color[c] = interpolate(values[0][c] as u8, values[1][c] as u8, w) as f32;
+                }
+                color
+            })
+            .collect();
+        let mut fit = SubsetFit {
+            endpoints,
+            pbits,
+            indices: Vec::with_capacity(colors.len()),
+            error: 0.0,
+        };
+        for color in colors {
+            let (index, error) = closest(color, &palette, channels);
+            fit.indices.push(index as u32);
+            fit.error += error;
+        }
+        fit
+    };
+
+    let (a, b) = principal_endpoints(colors, channels);
+    let mut ends = [a, b];
+    let mut best = evaluate(&ends);
+    for _ in 0..quality.refinements() {
+        let positions: Vec<f32> = best
+            .indices
+            .iter()
+            .map(|&i| weights[i as usize] as f32 / 64.0)
+            .collect();
+        match least_squares(colors, &positions) {
+            Some((a, b)) => ends = [a, b],
+            None => break,
+        }
+        let fit = evaluate(&ends);
+        if fit.error >= best.error {
+            break;
+        }
+        best = fit;
+    }
+    best
+}
+
+struct Bc7Candidate {
+    mode: usize,
+    partition: usize,
+    subsets: Vec<SubsetFit>,
+}
+
+impl Bc7Candidate {
+    fn error(&self) -> f32 {
+        self.subsets.iter().map(|s| s.error).sum()
+    }
+
+    fn pack(mut self) -> [u8; 16] {
+        let mode = &BC7_MODES[self.mode];
+        let partition = self.partition;
+        let max_index = (1 << mode.index_bits) - 1;
+        // Anchor indices lose their top bit, so flip subsets whose anchor has
+        // it set
+        for (s, fit) in self.subsets.iter_mut().enumerate() {
+            let anchor = (0..16)
+                .filter(|&i| subset_of(mode.subsets, partition, i) == s)
+                .position(|i| is_anchor(mode.subsets, partition, i))
+                .unwrap();
+            if fit.indices[anchor] > max_index / 2 {
+                fit.endpoints.swap(0, 1);
+                fit.pbits.swap(0, 1);
+                for index in fit.indices.iter_mut() {
+                    *index = max_index - *index;
+                }
+            }
+        }
+
+        let mut writer = BitWriter::default();
+        writer.write(1 << self.mode, self.mode as u32 + 1);
+        writer.write(self.partition as u32, mode.partition_bits);
+        for c in 0..3 {
+            for fit in &self.subsets {
+                writer.write(fit.endpoints[0][c], mode.color_bits);
+                writer.write(fit.endpoints[1][c], mode.color_bits);
+            }
+        }
+        if mode.alpha_bits > 0 {
+            for fit in &self.subsets {
+                writer.write(fit.endpoints[0][3], mode.alpha_bits);
+                writer.write(fit.endpoints[1][3], mode.alpha_bits);
+            }
+        }
+        for fit in &self.subsets {
+            if mode.endpoint_pbits {
+                writer.write(fit.pbits[0], 1);
+                writer.write(fit.pbits[1], 1);
+            } else if mode.shared_pbits {
+                writer.write(fit.pbits[0], 1);
+            }
+        }
+        let mut next = vec![0; mode.subsets];
+        for i in 0..16 {
+            let subset = subset_of(mode.subsets, self.partition, i);
+            let index = self.subsets[subset].indices[next[subset]];
+            next[subset] += 1;
+            let anchor = is_anchor(mode.subsets, self.partition, i);
+            writer.write(index, mode.index_bits - anchor as u32);
+        }
+        debug_assert_eq!(writer.position, 128);
+        writer.bits.to_le_bytes()
+    }
+}
+
+fn fit_partitioned(
+    mode_index: usize,
+    partition: usize,
+    block: &[Color; 16],
+    quality: Quality,
+) -> Bc7Candidate {
+    let mode = &BC7_MODES[mode_index];
+    let subsets = (0..mode.subsets)
+        .map(|s| {
+            let colors: Vec<Color> = (0..16)
+                .filter(|&i| subset_of(mode.subsets, partition, i) == s)
+                .map(|i| block[i])
+                .collect();
+            fit_subset(mode, &colors, quality)
+        })
+        .collect();
+    Bc7Candidate {
+        mode: mode_index,
+        partition,
+        subsets,
+    }
+}
+
+// How far the colors stray from their principal axis, a cheap stand-in for
+// the error a partition will end up with
+fn line_error(colors: &[Color]) -> f32 {
+    let (a, b) = principal_endpoints(colors, 3);
+    let mut axis = [0.0; 4];
+    for c in 0..3 {
+        axis[c] = b[c] - a[c];
+    }
+    let length_sq = distance(&axis, &[0.0; 4], 3);
+    colors
+        .iter()
+        .map(|color| {
+            let t = if length_sq > 0.0 {
+                (0..3).map(|c| (color[c] - a[c]) * axis[c]).sum::<f32>() / length_sq
+            } else {
+                0.0
+            };
+            distance(color, &lerp(&a, &b, t), 3)
+        })
+        .sum()
+}
+
+// Mode 6 (one subset, RGBA) for every block. Opaque blocks also try mode 1
+// (two subsets, RGB) with the partitions that look most promising.
+fn encode_bc7(pixels: &Block, quality: Quality) -> [u8; 16] {
+    let block = pixels.map(to_color);
+    let mut best = fit_partitioned(6, 0, &block, quality);
+    let opaque = pixels.iter().all(|p| p[3] == 255);
+    let tries = match quality {
+        Quality::Fast => 0,
+        Quality::Normal => 4,
+        Quality::Best => 64,
+    };
+    if opaque && tries > 0 {
+        let mut partitions: Vec<(f32, usize)> = (0..64)
+            .map(|partition| {
+                let error = (0..2)
+                    .map(|s| {
+                        let colors: Vec<Color> = (0..16)
+                            .filter(|&i| subset_of(2, partition, i) == s)
+                            .map(|i| block[i])
+                            .collect();
+                        line_error(&colors)
+                    })
+                    .sum();
+                (error, partition)
+            })
+            .collect();
+        partitions.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        for &(_, partition) in partitions.iter().take(tries) {
+            let candidate = fit_partitioned(1, partition, &block, quality);
+            if candidate.error() < best.error() {
+                best = candidate;
+            }
+        }
+    }
+    best.pack()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Smooth ramps in every channel, what most textures look like up close
+    fn gradient(size: u32) -> Image {
+        let mut pixels = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let (u, v) = (x * 255 / (size - 1), y * 255 / (size - 1));
+                pixels.extend_from_slice(&[u as u8, v as u8, (255 - u) as u8, (u + v) as u8 / 2]);
+            }
+        }
+        Image::new(size, size, pixels)
+    }
+
+    // Same, with a little deterministic noise on top
+    fn noisy(size: u32) -> Image {
+        let mut image = gradient(size);
+        let mut state = 12345u32;
+        for value in image.pixels.iter_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (state >> 16) % 17;
+            *value = (*value as u32 + noise).saturating_sub(8).min(255) as u8;
+        }
+        image
+    }
+
+    fn opaque(mut image: Image) -> Image {
+        for pixel in image.pixels.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+        image
+    }
+
+    fn round_trip(image: &Image, format: TextureFormat, quality: Quality) -> f64 {
+        let data = compress(image, format, quality).unwrap();
+        assert_eq!(
+            Some(data.len()),
+            format.level_size(image.width, image.height)
+        );
+        let back = decompress(&data, image.width, image.height, format).unwrap();
+        psnr(image, &back, channels(format))
+    }
+
+    // Lowest PSNR in dB allowed for the gradient and the noisy image, by
+    // Fast, Normal and Best. A little under what the encoders manage, so a
+    // change that makes them worse shows up.
+    fn check_psnr(format: TextureFormat, images: &[Image], thresholds: [f64; 3]) {
+        let qualities = [Quality::Fast, Quality::Normal, Quality::Best];
+        for image in images {
+            let scores = qualities.map(|quality| round_trip(image, format, quality));
+            for (score, (threshold, quality)) in scores.iter().zip(thresholds.iter().zip(qualities))
+            {
+                assert!(
+                    score >= threshold,
+                    "{:?} {:?} {:.2} dB, expected at least {}",
+                    format,
+                    quality,
+                    score,
+                    threshold
+                );
+            }
+            // Trying harder never ends up worse
+            assert!(scores[2] >= scores[0] - 0.01, "{:?} {:?}", format, scores);
+        }
+    }
+
+    #[test]
+    fn bc1_round_trip() {
+        let images = [opaque(gradient(32)), opaque(noisy(32))];
+        check_psnr(TextureFormat::Bc1, &images, [29.5, 29.5, 31.0]);
+    }
+
+    #[test]
+    fn bc3_round_trip() {
+        check_psnr(
+            TextureFormat::Bc3,
+            &[gradient(32), noisy(32)],
+            [30.5, 30.5, 32.0],
+        );
+    }
+
+    #[test]
+    fn bc4_round_trip() {
+        check_psnr(
+            TextureFormat::Bc4,
+            &[gradient(32), noisy(32)],
+            [45.0, 45.0, 46.5],
+        );
+    }
+
+    #[test]
+    fn bc5_round_trip() {
+        check_psnr(
+            TextureFormat::Bc5,
+            &[gradient(32), noisy(32)],
+            [45.0, 45.0, 46.5],
+        );
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        check_psnr(
+            TextureFormat::Bc7,
+            &[gradient(32), noisy(32)],
+            [31.5, 31.5, 31.5],
+        );
+        // Opaque blocks also try the two subset mode
+        let images = [opaque(gradient(32)), opaque(noisy(32))];
+        check_psnr(TextureFormat::Bc7, &images, [31.0, 37.0, 37.0]);
+    }
+
+    fn bc7_block(fields: &[(u32, u32)]) -> [u8; 16] {
+        let mut writer = BitWriter::default();
+        for &(value, bits) in fields {
+            writer.write(value, bits);
+        }
+        assert_eq!(writer.position, 128);
+        writer.bits.to_le_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Block {
+        decode_block(TextureFormat::Bc7, bytes)
+    }
+
+    // The blocks below are written field by field in the order the BC7 spec
+    // lays them out, expected pixels worked out from its interpolation
+    // formula by hand
+
+    #[test]
+    fn bc7_mode_6() {
+        // Black to white, pixel i using index i
+        let mut fields = vec![(1 << 6, 7)];
+        for _ in 0..4 {
+            fields.extend([(0, 7), (127, 7)]);
+        }
+        fields.extend([(0, 1), (1, 1)]);
+        // The anchor index loses its top bit
+        fields.push((0, 3));
+        fields.extend((1..16).map(|i| (i, 4)));
+        let block = decode(&bc7_block(&fields));
+        let expected = [
+            0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+        ];
+        for (pixel, &value) in block.iter().zip(&expected) {
+            assert_eq!(*pixel, [value; 4]);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_5_rotation() {
+        let mut fields = vec![(1 << 5, 6), (1, 2)];
+        // Red, green and blue endpoints, then alpha
+        fields.extend([(127, 7), (127, 7), (0, 7), (0, 7), (64, 7), (64, 7)]);
+        fields.extend([(0, 8), (255, 8)]);
+        fields.push((0, 1));
+        fields.extend((1..16).map(|_| (0, 2)));
+        fields.push((0, 1));
+        fields.extend((1..16).map(|i| (i % 4, 2)));
+        let block = decode(&bc7_block(&fields));
+        // Rotation 1 swaps red and alpha after interpolating
+        let alpha = [0, 84, 171, 255];
+        for (i, pixel) in block.iter().enumerate() {
+            assert_eq!(*pixel, [alpha[i % 4], 0, 129, 255], "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_4_index_selection() {
+        // No rotation, index selection 1: colors take the 3 bit indices
+        let mut fields = vec![(1 << 4, 5), (0, 2), (1, 1)];
+        fields.extend([(0, 5), (31, 5), (0, 5), (0, 5), (0, 5), (0, 5)]);
+        fields.extend([(63, 6), (63, 6)]);
+        fields.push((0, 1));
+        fields.extend((1..16).map(|_| (3, 2)));
+        fields.push((0, 2));
+        fields.extend((1..16).map(|i| (i % 8, 3)));
+        let block = decode(&bc7_block(&fields));
+        let red = [0, 36, 72, 108, 147, 183, 219, 255];
+        for (i, pixel) in block.iter().enumerate() {
+            assert_eq!(*pixel, [red[i % 8], 0, 0, 255], "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_1_two_subsets() {
+        // Partition 13 puts the bottom two rows in the second subset, whose
+        // anchor is pixel 15
+        let mut fields = vec![(0b10, 2), (13, 6)];
+        // Red, green and blue of the four endpoints
+        fields.extend([(0, 6), (63, 6), (0, 6), (0, 6)]);
+        fields.extend([(0, 6), (0, 6), (32, 6), (32, 6)]);
+        fields.extend([(0, 6), (0, 6), (63, 6), (63, 6)]);
+        // One p-bit per subset
+        fields.extend([(1, 1), (0, 1)]);
+        fields.push((3, 2));
+        fields.extend((1..8).map(|i| (i, 3)));
+        fields.extend((8..15).map(|_| (5, 3)));
+        fields.push((2, 2));
+        let block = decode(&bc7_block(&fields));
+        // Red goes from 2 (0 with the p-bit set) to 255
+        let red = [109, 38, 73, 109, 148, 184, 219, 255];
+        for (i, pixel) in block.iter().enumerate() {
+            let expected = if i < 8 {
+                [red[i], 2, 2, 255]
+            } else {
+                [0, 129, 253, 255]
+            };
+            assert_eq!(*pixel, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_0_three_subsets() {
+        // Partition 8 is two rows, a row and a row, anchors 0, 8 and 15
+        let mut fields = vec![(1, 1), (8, 4)];
+        fields.extend([(15, 4), (15, 4), (0, 4), (0, 4), (0, 4), (0, 4)]);
+        fields.extend([(0, 4), (0, 4), (15, 4), (15, 4), (0, 4), (0, 4)]);
+        fields.extend([(0, 4), (0, 4), (0, 4), (0, 4), (8, 4), (8, 4)]);
+        // A p-bit per endpoint, only the last subset's are set
+        fields.extend([(0, 1), (0, 1), (0, 1), (0, 1), (1, 1), (1, 1)]);
+        for i in 0..16 {
+            let anchor = i == 0 || i == 8 || i == 15;
+            fields.push((i % 4, if anchor { 2 } else { 3 }));
+        }
+        let block = decode(&bc7_block(&fields));
+        for (i, pixel) in block.iter().enumerate() {
+            let expected = match i / 4 {
+                0 | 1 => [247, 0, 0, 255],
+                2 => [0, 247, 0, 255],
+                _ => [8, 8, 140, 255],
+            };
+            assert_eq!(*pixel, expected, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        let block = decode(&[0; 16]);
+        assert_eq!(block, [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn bc1_alpha_is_a_cutout() {
+        let mut image = gradient(8);
+        for (i, pixel) in image.pixels.chunks_mut(4).enumerate() {
+            pixel[3] = if i % 3 == 0 { 0 } else { 255 };
+        }
+        for quality in [Quality::Fast, Quality::Best] {
+            let data = compress(&image, TextureFormat::Bc1, quality).unwrap();
+            let back = decompress(&data, 8, 8, TextureFormat::Bc1).unwrap();
+            for (i, pixel) in back.pixels.chunks(4).enumerate() {
+                if i % 3 == 0 {
+                    assert_eq!(pixel, [0, 0, 0, 0]);
+                } else {
+                    assert_eq!(pixel[3], 255);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_blocks_and_bad_input() {
+        // Edge blocks are padded, the decoded image keeps its own size
+        let image = opaque(gradient(6));
+        for format in [TextureFormat::Bc1, TextureFormat::Bc4, TextureFormat::Bc7] {
+            let data = compress(&image, format, Quality::Fast).unwrap();
+            assert_eq!(data.len(), 4 * format.block_size().unwrap());
+            let back = decompress(&data, 6, 6, format).unwrap();
+            assert_eq!((back.width, back.height), (6, 6));
+        }
+
+        let data = compress(&image, TextureFormat::Bc1, Quality::Fast).unwrap();
+        let error = decompress(&data[1..], 6, 6, TextureFormat::Bc1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = compress(&image, TextureFormat::Rgba8, Quality::Fast).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        // D3D wants whole blocks at the top level
+        assert!(compress_texture(&image, TextureFormat::Bc1, Quality::Fast, false).is_err());
+        let texture =
+            compress_texture(&gradient(8), TextureFormat::Bc7Srgb, Quality::Fast, true).unwrap();
+        assert_eq!(texture.mip_levels, 4);
+    }
+}
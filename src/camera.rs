@@ -0,0 +1,265 @@
+// View and projection matrices from a position, an orientation and a lens.
+// Left handed like the rest of the renderer: +x right, +y up, +z into the
+// screen, depth 0 at the near plane (1 with reverse-Z).
+use directx_math::{
+    XMLoadFloat3, XMLoadFloat4, XMMatrixInverse, XMMatrixLookAtLH, XMMatrixLookToLH,
+    XMMatrixMultiply, XMMatrixOrthographicLH, XMMatrixPerspectiveFovLH, XMQuaternionIdentity,
+    XMQuaternionNormalize, XMQuaternionRotationMatrix, XMStoreFloat3, XMStoreFloat4,
+    XMVector3Rotate, XMVectorSet, XMFLOAT3, XMFLOAT4, XMMATRIX, XMVECTOR, XM_PIDIV4,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // Height of the view volume in world units, the width follows the aspect
+    Orthographic { height: f32 },
+    // Vertical field of view in radians
+    Perspective { fov_y: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: XMFLOAT3,
+    // Unit quaternion, identity looks down +z with +y up
+    pub orientation: XMFLOAT4,
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+    // Width / height of the viewport
+    pub aspect: f32,
+    // Near plane at depth 1 and far plane at 0. Spreads float precision much
+    // more evenly over the depth range, but needs depth cleared to 0 and a
+    // GREATER depth test.
+    pub reverse_z: bool,
+}
+
+impl Default for Camera {
+    // What the sample always drew with: a unit high orthographic view from
+    // 10 units in front of the origin
+    fn default() -> Self {
+        let mut camera = Self::orthographic(1.0, 0.01, 50.0);
+        camera.position = XMFLOAT3 {
+            x: 0.0,
+            y: 0.0,
+            z: -10.0,
+        };
+        camera
+    }
+}
+
+impl Camera {
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Self::new(Projection::Orthographic { height }, near, far)
+    }
+
+    pub fn perspective(fov_y: f32, near: f32, far: f32) -> Self {
+        Self::new(Projection::Perspective { fov_y }, near, far)
+    }
+
+    // 45 degree perspective camera, the usual starting point for 3D scenes
+    pub fn perspective_default() -> Self {
+        Self::perspective(XM_PIDIV4, 0.1, 100.0)
+    }
+
+    fn new(projection: Projection, near: f32, far: f32) -> Self {
+        let mut orientation = XMFLOAT4::default();
+        XMStoreFloat4(&mut orientation, XMQuaternionIdentity());
+        Self {
+            position: XMFLOAT3::default(),
+            orientation,
+            projection,
+            near,
+            far,
+            aspect: 1.0,
+            reverse_z: false,
+        }
+    }
+
+    // Keep the aspect in sync with the window. A minimized window reports
+    // zero, keep the old aspect then rather than dividing by zero.
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect = width as f32 / height as f32;
+        }
+    }
+
+    pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
+        self.position = XMFLOAT3 { x, y, z };
+    }
+
+    pub fn set_orientation(&mut self, orientation: XMVECTOR) {
+        XMStoreFloat4(&mut self.orientation, XMQuaternionNormalize(orientation));
+    }
+
+    // Move to `eye` and turn towards `target`, keeping `up` roughly up
+    pub fn look_at(&mut self, eye: XMVECTOR, target: XMVECTOR, up: XMVECTOR) {
+        XMStoreFloat3(&mut self.position, eye);
+        // The camera's world transform is the inverse of its view matrix
+        let world = XMMatrixInverse(None, XMMatrixLookAtLH(eye, target, up));
+        self.set_orientation(XMQuaternionRotationMatrix(world));
+    }
+
+    pub fn position_vector(&self) -> XMVECTOR {
+        XMLoadFloat3(&self.position)
+    }
+
+    pub fn orientation_vector(&self) -> XMVECTOR {
+        XMLoadFloat4(&self.orientation)
+    }
+
+    fn rotate(&self, x: f32, y: f32, z: f32) -> XMVECTOR {
+        XMVector3Rotate(XMVectorSet(x, y, z, 0.0), self.orientation_vector())
+    }
+
+    pub fn forward(&self) -> XMVECTOR {
+        self.rotate(0.0, 0.0, 1.0)
+    }
+
+    pub fn right(&self) -> XMVECTOR {
+        self.rotate(1.0, 0.0, 0.0)
+    }
+
+    pub fn up(&self) -> XMVECTOR {
+        self.rotate(0.0, 1.0, 0.0)
+    }
+
+    pub fn view_matrix(&self) -> XMMATRIX {
+        XMMatrixLookToLH(self.position_vector(), self.forward(), self.up())
+    }
+
+    pub fn projection_matrix(&self) -> XMMATRIX {
+        // Swapping the planes is all reverse-Z takes, both builders only
+        // need them to differ
+        let (near, far) = if self.reverse_z {
+            (self.far, self.near)
+        } else {
+            (self.near, self.far)
+        };
+        match self.projection {
+            Projection::Orthographic { height } => {
+                XMMatrixOrthographicLH(height * self.aspect, height, near, far)
+            }
+            Projection::Perspective { fov_y } => {
+                XMMatrixPerspectiveFovLH(fov_y, self.aspect, near, far)
+            }
+        }
+    }
+
+    // Depth of the far plane, what the depth buffer is cleared to
+    pub fn far_depth(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    // Row vector convention, world space goes in on the left
+    pub fn view_projection_matrix(&self) -> XMMATRIX {
+        XMMatrixMultiply(self.view_matrix(), &self.projection_matrix())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directx_math::{
+        XMStoreFloat4x4, XMVector3TransformCoord, XMVectorGetZ, XMFLOAT4X4, XM_PIDIV2,
+    };
+
+    fn rows(matrix: XMMATRIX) -> [[f32; 4]; 4] {
+        let mut stored = XMFLOAT4X4::default();
+        XMStoreFloat4x4(&mut stored, matrix);
+        stored.m
+    }
+
+    fn assert_rows(actual: [[f32; 4]; 4], expected: [[f32; 4]; 4]) {
+        for (a, e) in actual.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // Depth of a view space point after the perspective divide
+    fn depth(camera: &Camera, z: f32) -> f32 {
+        XMVectorGetZ(XMVector3TransformCoord(
+            XMVectorSet(0.0, 0.0, z, 1.0),
+            camera.projection_matrix(),
+        ))
+    }
+
+    #[test]
+    fn perspective_matrix() {
+        // 90 degrees so 1 / tan(fov / 2) is 1, range = far / (far - near)
+        let mut camera = Camera::perspective(XM_PIDIV2, 1.0, 3.0);
+        camera.set_viewport_size(200, 100);
+        assert_rows(
+            rows(camera.projection_matrix()),
+            [
+                [0.5, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.5, 1.0],
+                [0.0, 0.0, -1.5, 0.0],
+            ],
+        );
+        assert!((depth(&camera, 1.0) - 0.0).abs() < 1e-6);
+        assert!((depth(&camera, 3.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn orthographic_matrix() {
+        // 4 wide, 2 high, depth (z - near) / (far - near)
+        let mut camera = Camera::orthographic(2.0, 1.0, 5.0);
+        camera.set_viewport_size(200, 100);
+        assert_rows(
+            rows(camera.projection_matrix()),
+            [
+                [0.5, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.25, 0.0],
+                [0.0, 0.0, -0.25, 1.0],
+            ],
+        );
+        assert_eq!(camera.far_depth(), 1.0);
+    }
+
+    #[test]
+    fn reverse_z_swaps_near_and_far() {
+        let mut camera = Camera::perspective(XM_PIDIV2, 1.0, 3.0);
+        camera.reverse_z = true;
+        // range = near / (near - far) = -0.5, offset -range * far = 1.5
+        assert_rows(
+            rows(camera.projection_matrix()),
+            [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, -0.5, 1.0],
+                [0.0, 0.0, 1.5, 0.0],
+            ],
+        );
+        assert!((depth(&camera, 1.0) - 1.0).abs() < 1e-6);
+        assert!((depth(&camera, 3.0) - 0.0).abs() < 1e-6);
+        // Nearer is greater
+        assert!(depth(&camera, 1.5) > depth(&camera, 2.0));
+        assert_eq!(camera.far_depth(), 0.0);
+
+        let mut camera = Camera::orthographic(2.0, 1.0, 5.0);
+        camera.reverse_z = true;
+        assert!((depth(&camera, 1.0) - 1.0).abs() < 1e-6);
+        assert!((depth(&camera, 5.0) - 0.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn default_view_projection() {
+        // Unit high orthographic view from z = -10, so the origin ends up
+        // 9.99 units past the near plane of a 49.99 unit deep volume
+        let camera = Camera::default();
+        let point = XMVector3TransformCoord(
+            XMVectorSet(0.25, 0.5, 0.0, 1.0),
+            camera.view_projection_matrix(),
+        );
+        let mut projected = XMFLOAT3::default();
+        XMStoreFloat3(&mut projected, point);
+        assert!((projected.x - 0.5).abs() < 1e-5);
+        assert!((projected.y - 1.0).abs() < 1e-5);
+        assert!((projected.z - 9.99 / 49.99).abs() < 1e-5);
+    }
+}
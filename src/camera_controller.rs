@@ -0,0 +1,442 @@
+// Interchangeable ways of steering a Camera. Controllers only see a
+// CameraInput of already collected deltas, so where the deltas come from
+// (mouse, keys, a recording) doesn't matter to the math.
+use directx_math::{
+    XMQuaternionRotationRollPitchYaw, XMStoreFloat3, XMVectorAdd, XMVectorScale, XMVectorSet,
+    XMVectorSubtract, XM_PIDIV2,
+};
+
+use crate::actions::ActionMap;
+use crate::camera::{Camera, Projection};
+use crate::input::Input;
+
+// Keeps pitch away from straight up/down, where yaw stops meaning anything
+const MAX_PITCH: f32 = XM_PIDIV2 - 0.01;
+
+// Each wheel notch scales the distance / view height by this much
+const ZOOM_STEP: f32 = 1.1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CameraInput {
+    // Pixels dragged with the look button held, +y is down
+    pub look: (f32, f32),
+    // Pixels dragged with the pan button held, +y is down
+    pub pan: (f32, f32),
+    // Wheel notches, positive zooms in
+    pub zoom: f32,
+    // -1..=1 along the camera's right, up and forward axes
+    pub movement: (f32, f32, f32),
+    // Height of the viewport in pixels, to turn pixel drags into world units
+    pub viewport_height: f32,
+}
+
+impl CameraInput {
+    // Reads the camera_* and move_* bindings
+    pub fn from_actions(input: &Input, action_map: &ActionMap, viewport_height: u32) -> Self {
+        let (dx, dy) = input.mouse_delta();
+        let drag = (dx as f32, dy as f32);
+        let when = |action: &str| {
+            if action_map.is_active(input, action) {
+                drag
+            } else {
+                (0.0, 0.0)
+            }
+        };
+        Self {
+            look: when("camera_look"),
+            pan: when("camera_pan"),
+            zoom: action_map.axis(input, "camera_zoom"),
+            movement: (
+                action_map.axis(input, "move_right").clamp(-1.0, 1.0),
+                action_map.axis(input, "move_up").clamp(-1.0, 1.0),
+                action_map.axis(input, "move_forward").clamp(-1.0, 1.0),
+            ),
+            viewport_height: viewport_height as f32,
+        }
+    }
+}
+
+pub trait CameraController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput, delta_time: f32);
+}
+
+fn set_yaw_pitch(camera: &mut Camera, yaw: f32, pitch: f32) {
+    camera.set_orientation(XMQuaternionRotationRollPitchYaw(pitch, yaw, 0.0));
+}
+
+// Circles a target point. Drag to turn around it, pan drag to slide the
+// target, wheel to move closer or further away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitController {
+    pub target: (f32, f32, f32),
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    // Radians per pixel dragged
+    pub rotate_speed: f32,
+}
+
+impl OrbitController {
+    pub fn new(target: (f32, f32, f32), distance: f32) -> Self {
+        Self {
+            target,
+            distance,
+            yaw: 0.0,
+            pitch: 0.0,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+            rotate_speed: 0.005,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput, _delta_time: f32) {
+        self.yaw += input.look.0 * self.rotate_speed;
+        self.pitch = (self.pitch + input.look.1 * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+        self.distance = (self.distance * ZOOM_STEP.powf(-input.zoom))
+            .clamp(self.min_distance, self.max_distance);
+        set_yaw_pitch(camera, self.yaw, self.pitch);
+
+        // Panning moves the target in the view plane. Scaling by distance
+        // keeps the point under the cursor roughly under the cursor.
+        if input.pan != (0.0, 0.0) && input.viewport_height > 0.0 {
+            let units_per_pixel = self.distance / input.viewport_height;
+            let offset = XMVectorAdd(
+                XMVectorScale(camera.right(), -input.pan.0 * units_per_pixel),
+                XMVectorScale(camera.up(), input.pan.1 * units_per_pixel),
+            );
+            let (x, y, z) = self.target;
+            let mut target = Default::default();
+            XMStoreFloat3(&mut target, XMVectorAdd(XMVectorSet(x, y, z, 1.0), offset));
+            self.target = (target.x, target.y, target.z);
+        }
+
+        let (x, y, z) = self.target;
+        let eye = XMVectorSubtract(
+            XMVectorSet(x, y, z, 1.0),
+            XMVectorScale(camera.forward(), self.distance),
+        );
+        XMStoreFloat3(&mut camera.position, eye);
+    }
+}
+
+// First person free flight. Drag to look around, move along the camera axes,
+// wheel changes the speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlyController {
+    pub yaw: f32,
+    pub pitch: f32,
+    // Units per second
+    pub speed: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub rotate_speed: f32,
+}
+
+impl FlyController {
+    pub fn new(yaw: f32, pitch: f32) -> Self {
+        Self {
+            yaw,
+            pitch,
+            speed: 2.0,
+            min_speed: 0.01,
+            max_speed: 1000.0,
+            rotate_speed: 0.005,
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput, delta_time: f32) {
+        self.yaw += input.look.0 * self.rotate_speed;
+        self.pitch = (self.pitch + input.look.1 * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+        self.speed =
+            (self.speed * ZOOM_STEP.powf(input.zoom)).clamp(self.min_speed, self.max_speed);
+        set_yaw_pitch(camera, self.yaw, self.pitch);
+
+        let (right, up, forward) = input.movement;
+        let step = self.speed * delta_time;
+        let mut offset = XMVectorScale(camera.right(), right * step);
+        offset = XMVectorAdd(offset, XMVectorScale(camera.up(), up * step));
+        offset = XMVectorAdd(offset, XMVectorScale(camera.forward(), forward * step));
+        let position = XMVectorAdd(camera.position_vector(), offset);
+        XMStoreFloat3(&mut camera.position, position);
+    }
+}
+
+// Scrolling and zooming a flat orthographic view. Drag with either button to
+// pan, wheel to zoom, move keys to scroll.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PanZoomController {
+    pub min_height: f32,
+    pub max_height: f32,
+    // View heights per second when scrolling with keys
+    pub scroll_speed: f32,
+}
+
+impl Default for PanZoomController {
+    fn default() -> Self {
+        Self {
+            min_height: 0.01,
+            max_height: 1000.0,
+            scroll_speed: 1.0,
+        }
+    }
+}
+
+impl CameraController for PanZoomController {
+    fn update(&mut self, camera: &mut Camera, input: &CameraInput, delta_time: f32) {
+        let height = match &mut camera.projection {
+            Projection::Orthographic { height } => height,
+            // Nothing sensible to zoom, leave perspective cameras alone
+            Projection::Perspective { .. } => return,
+        };
+        *height = (*height * ZOOM_STEP.powf(-input.zoom)).clamp(self.min_height, self.max_height);
+        let height = *height;
+
+        // The content follows the cursor, so the camera moves the other way
+        let drag = (input.look.0 + input.pan.0, input.look.1 + input.pan.1);
+        let units_per_pixel = if input.viewport_height > 0.0 {
+            height / input.viewport_height
+        } else {
+            0.0
+        };
+        // Forward scrolls up as well, so WASD works on a flat view
+        let (right, up, forward) = input.movement;
+        let up = (up + forward).clamp(-1.0, 1.0);
+        let scroll = self.scroll_speed * height * delta_time;
+        camera.position.x += -drag.0 * units_per_pixel + right * scroll;
+        camera.position.y += drag.1 * units_per_pixel + up * scroll;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    // The sample's original view, no controller
+    #[default]
+    Fixed,
+    PanZoom,
+    Orbit,
+    Fly,
+}
+
+impl CameraMode {
+    pub fn next(self) -> CameraMode {
+        match self {
+            CameraMode::Fixed => CameraMode::PanZoom,
+            CameraMode::PanZoom => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Fly,
+            CameraMode::Fly => CameraMode::Fixed,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraMode::Fixed => "fixed",
+            CameraMode::PanZoom => "pan/zoom",
+            CameraMode::Orbit => "orbit",
+            CameraMode::Fly => "fly",
+        }
+    }
+
+    // Fresh camera and controller for the mode, keeping the viewport aspect
+    pub fn create(self, aspect: f32) -> (Camera, Option<Box<dyn CameraController>>) {
+        let (mut camera, controller): (Camera, Option<Box<dyn CameraController>>) = match self {
+            CameraMode::Fixed => (Camera::default(), None),
+            CameraMode::PanZoom => (
+                Camera::default(),
+                Some(Box::new(PanZoomController::default())),
+            ),
+            CameraMode::Orbit => (
+                Camera::perspective_default(),
+                Some(Box::new(OrbitController::new((0.0, 0.0, 0.0), 2.0))),
+            ),
+            CameraMode::Fly => {
+                let mut camera = Camera::perspective_default();
+                camera.set_position(0.0, 0.0, -2.0);
+                (camera, Some(Box::new(FlyController::new(0.0, 0.0))))
+            }
+        };
+        camera.aspect = aspect;
+        (camera, controller)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directx_math::{XMFLOAT3, XMVECTOR, XM_PIDIV4};
+
+    fn xyz(v: XMVECTOR) -> (f32, f32, f32) {
+        let mut stored = XMFLOAT3::default();
+        XMStoreFloat3(&mut stored, v);
+        (stored.x, stored.y, stored.z)
+    }
+
+    fn assert_near(actual: (f32, f32, f32), expected: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        assert!(
+            close(actual.0, expected.0)
+                && close(actual.1, expected.1)
+                && close(actual.2, expected.2),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn position(camera: &Camera) -> (f32, f32, f32) {
+        (camera.position.x, camera.position.y, camera.position.z)
+    }
+
+    fn input() -> CameraInput {
+        CameraInput {
+            viewport_height: 200.0,
+            ..CameraInput::default()
+        }
+    }
+
+    // Pixels to drag for `angle` radians at the default rotate speed
+    fn pixels(angle: f32) -> f32 {
+        angle / 0.005
+    }
+
+    #[test]
+    fn orbit_turns_around_the_target() {
+        let mut camera = Camera::perspective_default();
+        let mut orbit = OrbitController::new((1.0, 0.0, 0.0), 2.0);
+
+        // Starts behind the target looking down +z
+        orbit.update(&mut camera, &input(), 0.016);
+        assert_near(position(&camera), (1.0, 0.0, -2.0));
+        assert_near(xyz(camera.forward()), (0.0, 0.0, 1.0));
+
+        // Dragging right a quarter turn ends up on the target's -x side
+        let mut drag = input();
+        drag.look = (pixels(XM_PIDIV2), 0.0);
+        orbit.update(&mut camera, &drag, 0.016);
+        assert_near(position(&camera), (-1.0, 0.0, 0.0));
+        assert_near(xyz(camera.forward()), (1.0, 0.0, 0.0));
+
+        // Dragging down looks down on it from above
+        let mut orbit = OrbitController::new((0.0, 0.0, 0.0), 2.0);
+        let mut drag = input();
+        drag.look = (0.0, pixels(XM_PIDIV4));
+        orbit.update(&mut camera, &drag, 0.016);
+        let h = 2.0 * XM_PIDIV4.sin();
+        assert_near(position(&camera), (0.0, h, -h));
+        assert_near(xyz(camera.up()), (0.0, XM_PIDIV4.cos(), XM_PIDIV4.sin()));
+
+        // Never quite straight down
+        drag.look = (0.0, pixels(10.0));
+        orbit.update(&mut camera, &drag, 0.016);
+        assert_eq!(orbit.pitch, MAX_PITCH);
+    }
+
+    #[test]
+    fn orbit_zooms_and_pans() {
+        let mut camera = Camera::perspective_default();
+        let mut orbit = OrbitController::new((0.0, 0.0, 0.0), 2.0);
+        let mut zoom = input();
+        zoom.zoom = 1.0;
+        orbit.update(&mut camera, &zoom, 0.016);
+        assert!((orbit.distance - 2.0 / ZOOM_STEP).abs() < 1e-6);
+        zoom.zoom = -1000.0;
+        orbit.update(&mut camera, &zoom, 0.016);
+        assert_eq!(orbit.distance, orbit.max_distance);
+        zoom.zoom = 1000.0;
+        orbit.update(&mut camera, &zoom, 0.016);
+        assert_eq!(orbit.distance, orbit.min_distance);
+
+        // Half the viewport height to the right is half the distance, the
+        // target moves left so the scene follows the cursor
+        let mut orbit = OrbitController::new((0.0, 0.0, 0.0), 2.0);
+        let mut pan = input();
+        pan.pan = (100.0, -100.0);
+        orbit.update(&mut camera, &pan, 0.016);
+        assert_near(orbit.target, (-1.0, -1.0, 0.0));
+        assert_near(position(&camera), (-1.0, -1.0, -2.0));
+    }
+
+    #[test]
+    fn fly_moves_along_camera_axes() {
+        let mut camera = Camera::perspective_default();
+        let mut fly = FlyController::new(0.0, 0.0);
+        let mut moving = input();
+        moving.movement = (0.0, 0.0, 1.0);
+        // 2 units per second for half a second
+        fly.update(&mut camera, &moving, 0.5);
+        assert_near(position(&camera), (0.0, 0.0, 1.0));
+
+        // Turned a quarter right, forward is +x
+        moving.look = (pixels(XM_PIDIV2), 0.0);
+        fly.update(&mut camera, &moving, 0.5);
+        assert_near(position(&camera), (1.0, 0.0, 1.0));
+
+        // Right is now -z, up stays up
+        moving.look = (0.0, 0.0);
+        moving.movement = (1.0, 1.0, 0.0);
+        fly.update(&mut camera, &moving, 0.25);
+        assert_near(position(&camera), (1.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn fly_speed_is_clamped() {
+        let mut camera = Camera::perspective_default();
+        let mut fly = FlyController::new(0.0, 0.0);
+        let mut wheel = input();
+        wheel.zoom = 1.0;
+        fly.update(&mut camera, &wheel, 0.016);
+        assert!((fly.speed - 2.0 * ZOOM_STEP).abs() < 1e-6);
+        wheel.zoom = 1000.0;
+        fly.update(&mut camera, &wheel, 0.016);
+        assert_eq!(fly.speed, fly.max_speed);
+        wheel.zoom = -1000.0;
+        fly.update(&mut camera, &wheel, 0.016);
+        assert_eq!(fly.speed, fly.min_speed);
+        // Scrolling back up recovers from the minimum
+        wheel.zoom = 1.0;
+        fly.update(&mut camera, &wheel, 0.016);
+        assert!(fly.speed > fly.min_speed);
+    }
+
+    #[test]
+    fn pan_zoom_scrolls_flat_views() {
+        let mut camera = Camera::default();
+        let mut pan_zoom = PanZoomController::default();
+        let mut zoom = input();
+        zoom.zoom = 1.0;
+        pan_zoom.update(&mut camera, &zoom, 0.016);
+        let height = 1.0 / ZOOM_STEP;
+        assert_eq!(camera.projection, Projection::Orthographic { height });
+
+        // Dragging moves the view the other way, a viewport height of drag
+        // is a view height of world
+        let mut drag = input();
+        drag.look = (200.0, 100.0);
+        pan_zoom.update(&mut camera, &drag, 0.016);
+        assert_near(position(&camera), (-height, height / 2.0, -10.0));
+
+        // Keys scroll a view height per second, forward counts as up
+        let mut camera = Camera::default();
+        let mut keys = input();
+        keys.movement = (1.0, 0.0, -1.0);
+        pan_zoom.update(&mut camera, &keys, 0.5);
+        assert_near(position(&camera), (0.5, -0.5, -10.0));
+    }
+
+    #[test]
+    fn pan_zoom_leaves_perspective_alone() {
+        let mut camera = Camera::perspective_default();
+        let before = camera.projection;
+        let mut moving = input();
+        moving.zoom = 3.0;
+        moving.look = (10.0, 10.0);
+        PanZoomController::default().update(&mut camera, &moving, 0.016);
+        assert_eq!(camera.projection, before);
+        assert_near(position(&camera), (0.0, 0.0, 0.0));
+    }
+}
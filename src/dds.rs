@@ -0,0 +1,451 @@
+// DirectDraw Surface files, the container prebaked textures ship in. Reads
+// the legacy header (DXT1-5, ATI1/2, BC4/5 four CCs and plain RGBA masks) and
+// the DX10 extension header, which names a DXGI_FORMAT directly. Volume
+// textures aren't supported. Written files always use the DX10 header.
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::texture::{mip_count, TextureData, TextureFormat};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const PIXEL_FORMAT_SIZE: u32 = 32;
+const DX10_HEADER_SIZE: usize = 20;
+// Magic, header and DX10 header
+const DATA_OFFSET: usize = 4 + HEADER_SIZE + DX10_HEADER_SIZE;
+
+// DDS_HEADER flags
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDSD_LINEARSIZE: u32 = 0x8_0000;
+
+// DDS_PIXELFORMAT flags
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+// All six DDSCAPS2_CUBEMAP_POSITIVEX ... NEGATIVEZ bits
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xfc00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+// D3D10_RESOURCE_DIMENSION
+const DIMENSION_TEXTURE1D: u32 = 2;
+const DIMENSION_TEXTURE2D: u32 = 3;
+const DIMENSION_TEXTURE3D: u32 = 4;
+const RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+// D3DFMT values legacy files put in the four CC field
+const D3DFMT_A16B16G16R16F: u32 = 113;
+const D3DFMT_A32B32G32R32F: u32 = 116;
+
+fn invalid_data<T>(message: String) -> Result<T, Error> {
+    Err(Error::new(ErrorKind::InvalidData, message))
+}
+
+fn four_cc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+// Little endian u32 at `index` words into `bytes`
+fn word(bytes: &[u8], index: usize) -> u32 {
+    let i = index * 4;
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+struct PixelFormat {
+    flags: u32,
+    four_cc: u32,
+    bit_count: u32,
+    masks: [u32; 4],
+}
+
+// The format a legacy pixel format describes
+fn legacy_format(pf: &PixelFormat) -> Result<TextureFormat, Error> {
+    if pf.flags & DDPF_FOURCC != 0 {
+        let format = match pf.four_cc {
+            c if c == four_cc(b"DXT1") => TextureFormat::Bc1,
+            c if c == four_cc(b"DXT2") || c == four_cc(b"DXT3") => TextureFormat::Bc2,
+            c if c == four_cc(b"DXT4") || c == four_cc(b"DXT5") => TextureFormat::Bc3,
+            c if c == four_cc(b"ATI1") || c == four_cc(b"BC4U") => TextureFormat::Bc4,
+            c if c == four_cc(b"BC4S") => TextureFormat::Bc4Snorm,
+            c if c == four_cc(b"ATI2") || c == four_cc(b"BC5U") => TextureFormat::Bc5,
+            c if c == four_cc(b"BC5S") => TextureFormat::Bc5Snorm,
+            D3DFMT_A16B16G16R16F => TextureFormat::Rgba16Float,
+            D3DFMT_A32B32G32R32F => TextureFormat::Rgba32Float,
+            c => {
+                let text: String = c.to_le_bytes().iter().map(|&b| b as char).collect();
+                return invalid_data(format!("Unsupported four CC {:?}", text));
+            }
+        };
+        return Ok(format);
+    }
+    let alpha = if pf.flags & DDPF_ALPHAPIXELS != 0 {
+        pf.masks[3]
+    } else {
+        0
+    };
+    let rgb = [pf.masks[0], pf.masks[1], pf.masks[2]];
+    if pf.flags & DDPF_RGB != 0 && pf.bit_count == 32 {
+        match (rgb, alpha) {
+            ([0xff, 0xff00, 0xff_0000], 0xff00_0000) => return Ok(TextureFormat::Rgba8),
+            ([0xff_0000, 0xff00, 0xff], 0xff00_0000) => return Ok(TextureFormat::Bgra8),
+            _ => {}
+        }
+    }
+    // Some writers flag single channel files as RGB
+    if pf.flags & (DDPF_LUMINANCE | DDPF_RGB) != 0 && pf.bit_count == 8 && pf.masks[0] == 0xff {
+        return Ok(TextureFormat::R8);
+    }
+    invalid_data(format!(
+        "Unsupported pixel format: {} bits, masks {:x?}",
+        pf.bit_count, pf.masks
+    ))
+}
+
+// Reads a whole file. Every surface is copied out, so the result doesn't
+// borrow `bytes`.
+pub fn parse(bytes: &[u8]) -> Result<TextureData, Error> {
+    if bytes.len() < 4 + HEADER_SIZE || &bytes[..4] != MAGIC {
+        return invalid_data(String::from("Not a DDS file"));
+    }
+    let header = &bytes[4..4 + HEADER_SIZE];
+    if word(header, 0) as usize != HEADER_SIZE || word(header, 18) != PIXEL_FORMAT_SIZE {
+        return invalid_data(String::from("Bad DDS header size"));
+    }
+    let flags = word(header, 1);
+    let height = word(header, 2);
+    let width = word(header, 3);
+    let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+        word(header, 6).max(1)
+    } else {
+        1
+    };
+    let pixel_format = PixelFormat {
+        flags: word(header, 19),
+        four_cc: word(header, 20),
+        bit_count: word(header, 21),
+        masks: [
+            word(header, 22),
+            word(header, 23),
+            word(header, 24),
+            word(header, 25),
+        ],
+    };
+    let caps2 = word(header, 27);
+
+    let mut offset = 4 + HEADER_SIZE;
+    let dx10 = pixel_format.flags & DDPF_FOURCC != 0 && pixel_format.four_cc == four_cc(b"DX10");
+    let (format, array_size, cubemap) = if dx10 {
+        let extension = bytes
+            .get(offset..offset + DX10_HEADER_SIZE)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Truncated DX10 header"))?;
+        offset += DX10_HEADER_SIZE;
+        let dxgi_format = word(extension, 0);
+        let format = TextureFormat::from_dxgi(dxgi_format).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported DXGI_FORMAT {}", dxgi_format),
+            )
+        })?;
+        match word(extension, 1) {
+            DIMENSION_TEXTURE1D | DIMENSION_TEXTURE2D => {}
+            DIMENSION_TEXTURE3D => {
+                return invalid_data(String::from("Volume textures aren't supported"))
+            }
+            dimension => return invalid_data(format!("Unknown resource dimension {}", dimension)),
+        }
+        let cubemap = word(extension, 2) & RESOURCE_MISC_TEXTURECUBE != 0;
+        // Counts cubes rather than faces for cubemaps
+        let array_size = word(extension, 3)
+            .max(1)
+            .checked_mul(if cubemap { 6 } else { 1 })
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Array too large"))?;
+        (format, array_size, cubemap)
+    } else {
+        if caps2 & DDSCAPS2_VOLUME != 0 {
+            return invalid_data(String::from("Volume textures aren't supported"));
+        }
+        let cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+        if cubemap && caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES {
+            return invalid_data(String::from(
+                "Cubemaps without all six faces aren't supported",
+            ));
+        }
+        let array_size = if cubemap { 6 } else { 1 };
+        (legacy_format(&pixel_format)?, array_size, cubemap)
+    };
+
+    if width == 0 || height == 0 {
+        return invalid_data(String::from("Empty texture"));
+    }
+    if mip_levels > mip_count(width, height) {
+        return invalid_data(format!(
+            "{} mip levels for {}x{}",
+            mip_levels, width, height
+        ));
+    }
+    let mut texture = TextureData {
+        width,
+        height,
+        format,
+        mip_levels,
+        array_size,
+        cubemap,
+        subresources: Vec::new(),
+    };
+    for _ in 0..array_size {
+        for level in 0..mip_levels as usize {
+            let (w, h) = texture.level_size(level);
+            let end = format
+                .level_size(w, h)
+                .and_then(|size| offset.checked_add(size))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Texture too large"))?;
+            let data = bytes.get(offset..end).ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "File ends before the last surface")
+            })?;
+            texture.subresources.push(data.to_vec());
+            offset = end;
+        }
+    }
+    Ok(texture)
+}
+
+// A DDS file with a DX10 header for the texture
+pub fn write(texture: &TextureData) -> Result<Vec<u8>, Error> {
+    texture.validate()?;
+    let format = texture.format;
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT;
+    // validate() checked the sizes fit
+    let pitch_or_linear_size = if format.is_compressed() {
+        flags |= DDSD_LINEARSIZE;
+        format.level_size(texture.width, texture.height).unwrap()
+    } else {
+        flags |= DDSD_PITCH;
+        format.row_pitch(texture.width).unwrap()
+    };
+    let mut caps = DDSCAPS_TEXTURE;
+    if texture.mip_levels > 1 {
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    let mut caps2 = 0;
+    if texture.cubemap {
+        caps |= DDSCAPS_COMPLEX;
+        caps2 |= DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALL_FACES;
+    }
+
+    let mut header = [0u32; HEADER_SIZE / 4];
+    header[0] = HEADER_SIZE as u32;
+    header[1] = flags;
+    header[2] = texture.height;
+    header[3] = texture.width;
+    header[4] = pitch_or_linear_size as u32;
+    header[6] = texture.mip_levels;
+    header[18] = PIXEL_FORMAT_SIZE;
+    header[19] = DDPF_FOURCC;
+    header[20] = four_cc(b"DX10");
+    header[26] = caps;
+    header[27] = caps2;
+    let extension = [
+        format.to_dxgi(),
+        DIMENSION_TEXTURE2D,
+        if texture.cubemap {
+            RESOURCE_MISC_TEXTURECUBE
+        } else {
+            0
+        },
+        if texture.cubemap {
+            texture.array_size / 6
+        } else {
+            texture.array_size
+        },
+        0,
+    ];
+
+    let data_size: usize = texture.subresources.iter().map(Vec::len).sum();
+    let mut bytes = Vec::with_capacity(DATA_OFFSET + data_size);
+    bytes.extend_from_slice(MAGIC);
+    for value in header.iter().chain(extension.iter()) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for subresource in &texture.subresources {
+        bytes.extend_from_slice(subresource);
+    }
+    Ok(bytes)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<TextureData, Error> {
+    parse(&fs::read(path)?)
+}
+
+pub fn save<P: AsRef<Path>>(path: P, texture: &TextureData) -> Result<(), Error> {
+    fs::write(path, write(texture)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A texture whose every byte says which subresource and offset it's at
+    fn texture(
+        size: u32,
+        format: TextureFormat,
+        mip_levels: u32,
+        array_size: u32,
+        cubemap: bool,
+    ) -> TextureData {
+        let mut texture = TextureData {
+            width: size,
+            height: size,
+            format,
+            mip_levels,
+            array_size,
+            cubemap,
+            subresources: Vec::new(),
+        };
+        for slice in 0..array_size as usize {
+            for level in 0..mip_levels as usize {
+                let (w, h) = texture.level_size(level);
+                let size = format.level_size(w, h).unwrap();
+                let seed = slice * 31 + level * 7;
+                texture
+                    .subresources
+                    .push((0..size).map(|i| (seed + i) as u8).collect());
+            }
+        }
+        texture
+    }
+
+    fn round_trip(texture: &TextureData) {
+        let bytes = write(texture).unwrap();
+        assert_eq!(&parse(&bytes).unwrap(), texture);
+    }
+
+    // Header words in a legacy file, without the magic
+    fn legacy_header(width: u32, height: u32, pixel_format: [u32; 6], caps2: u32) -> Vec<u8> {
+        let mut header = [0u32; HEADER_SIZE / 4];
+        header[0] = HEADER_SIZE as u32;
+        header[1] = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+        header[2] = height;
+        header[3] = width;
+        header[18] = PIXEL_FORMAT_SIZE;
+        header[19..25].copy_from_slice(&pixel_format);
+        header[26] = DDSCAPS_TEXTURE;
+        header[27] = caps2;
+        let mut bytes = MAGIC.to_vec();
+        for value in header.iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trips_mip_chain() {
+        round_trip(&texture(16, TextureFormat::Rgba8, 5, 1, false));
+    }
+
+    #[test]
+    fn round_trips_array() {
+        round_trip(&texture(8, TextureFormat::Rgba16Float, 2, 3, false));
+    }
+
+    #[test]
+    fn round_trips_cubemap() {
+        let cube = texture(4, TextureFormat::Bgra8Srgb, 3, 6, true);
+        round_trip(&cube);
+        // Written as one cube, not six slices
+        let bytes = write(&cube).unwrap();
+        assert_eq!(word(&bytes[4 + HEADER_SIZE..], 3), 1);
+    }
+
+    #[test]
+    fn round_trips_block_formats() {
+        for &format in &[
+            TextureFormat::Bc1,
+            TextureFormat::Bc2Srgb,
+            TextureFormat::Bc3,
+            TextureFormat::Bc4,
+            TextureFormat::Bc5Snorm,
+            TextureFormat::Bc6hUfloat,
+            TextureFormat::Bc7Srgb,
+        ] {
+            // Mips below 4x4 still take a whole block
+            round_trip(&texture(8, format, 4, 1, false));
+        }
+    }
+
+    #[test]
+    fn reads_legacy_four_cc() {
+        let mut bytes = legacy_header(8, 4, [DDPF_FOURCC, four_cc(b"DXT1"), 0, 0, 0, 0], 0);
+        let data: Vec<u8> = (0..16).collect();
+        bytes.extend_from_slice(&data);
+        let texture = parse(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Bc1);
+        assert_eq!((texture.width, texture.height), (8, 4));
+        assert_eq!((texture.mip_levels, texture.array_size), (1, 1));
+        assert_eq!(texture.subresources, vec![data]);
+    }
+
+    #[test]
+    fn reads_legacy_masks_and_cubemaps() {
+        let pixel_format = [DDPF_RGB | DDPF_ALPHAPIXELS, 0, 32, 0xff_0000, 0xff00, 0xff];
+        let mut bytes = legacy_header(
+            1,
+            1,
+            pixel_format,
+            DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALL_FACES,
+        );
+        // legacy_header only takes the RGB masks, alpha is the next word
+        let alpha_mask = 4 + 25 * 4;
+        bytes[alpha_mask..alpha_mask + 4].copy_from_slice(&0xff00_0000u32.to_le_bytes());
+        for face in 0..6u8 {
+            bytes.extend_from_slice(&[face; 4]);
+        }
+        let texture = parse(&bytes).unwrap();
+        assert_eq!(texture.format, TextureFormat::Bgra8);
+        assert!(texture.cubemap);
+        assert_eq!(texture.array_size, 6);
+        assert_eq!(texture.subresource(5, 0), &[5; 4]);
+    }
+
+    #[test]
+    fn rejects_legacy_partial_cubemaps() {
+        let mut bytes = legacy_header(
+            1,
+            1,
+            [DDPF_FOURCC, four_cc(b"DXT5"), 0, 0, 0, 0],
+            DDSCAPS2_CUBEMAP | 0x400,
+        );
+        bytes.extend_from_slice(&[0; 16 * 6]);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let mut bytes = write(&texture(4, TextureFormat::Rgba32Float, 1, 1, false)).unwrap();
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = parse(&bytes).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Texture too large");
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = write(&texture(8, TextureFormat::Bc7, 2, 1, false)).unwrap();
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse(&bytes[..DATA_OFFSET - 1]).is_err());
+        assert!(parse(&bytes[..64]).is_err());
+        assert!(parse(b"DDX ").is_err());
+    }
+}
@@ -0,0 +1,574 @@
+// Display mode configuration. Mode selection and window placement are plain
+// rect math so they don't depend on Win32; the Win32 side only enumerates
+// monitors and applies the result.
+use std::cmp::Reverse;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn new(left: i32, top: i32, width: i32, height: i32) -> Self {
+        Self {
+            left,
+            top,
+            right: left + width,
+            bottom: top + height,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VideoMode {
+    pub width: u32,
+    pub height: u32,
+    // Hz
+    pub refresh_rate: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Monitor {
+    pub name: String,
+    pub bounds: Rect,
+    // Bounds minus the taskbar and docked toolbars
+    pub work_area: Rect,
+    pub primary: bool,
+    // Sorted and without duplicates, see sort_video_modes
+    pub modes: Vec<VideoMode>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed {
+        width: u32,
+        height: u32,
+    },
+    Borderless {
+        monitor: usize,
+    },
+    // None uses the monitor's largest mode
+    Exclusive {
+        monitor: usize,
+        mode: Option<VideoMode>,
+    },
+}
+
+impl Default for DisplayMode {
+    fn default() -> Self {
+        DisplayMode::Windowed {
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowStyle {
+    // Title bar and resizable frame
+    Overlapped,
+    // No decorations at all
+    Popup,
+}
+
+impl DisplayMode {
+    pub fn is_fullscreen(&self) -> bool {
+        !matches!(self, DisplayMode::Windowed { .. })
+    }
+
+    pub fn window_style(&self) -> WindowStyle {
+        if self.is_fullscreen() {
+            WindowStyle::Popup
+        } else {
+            WindowStyle::Overlapped
+        }
+    }
+
+    // Parses one command line argument:
+    //   --windowed=1280x720
+    //   --borderless or --borderless=<monitor>
+    //   --fullscreen, --fullscreen=1920x1080 or --fullscreen=1920x1080@60
+    //   optionally followed by :<monitor>
+    pub fn from_arg(arg: &str) -> Option<DisplayMode> {
+        let (name, value) = match arg.find('=') {
+            Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+            None => (arg, None),
+        };
+
+        match name {
+            "--windowed" => {
+                let (width, height) = parse_size(value?)?;
+                Some(DisplayMode::Windowed { width, height })
+            }
+            "--borderless" => {
+                let monitor = match value {
+                    Some(value) => value.parse().ok()?,
+                    None => 0,
+                };
+                Some(DisplayMode::Borderless { monitor })
+            }
+            "--fullscreen" => {
+                let value = value.unwrap_or("");
+                let (mode, monitor) = match value.find(':') {
+                    Some(i) => (&value[..i], value[i + 1..].parse().ok()?),
+                    None => (value, 0),
+                };
+                let mode = if mode.is_empty() {
+                    None
+                } else {
+                    let (size, refresh_rate) = match mode.find('@') {
+                        Some(i) => (&mode[..i], mode[i + 1..].parse().ok()?),
+                        None => (mode, 0),
+                    };
+                    let (width, height) = parse_size(size)?;
+                    Some(VideoMode {
+                        width,
+                        height,
+                        refresh_rate,
+                    })
+                };
+                Some(DisplayMode::Exclusive { monitor, mode })
+            }
+            _ => None,
+        }
+    }
+}
+
+// D3D11's largest texture, so also the largest swap chain
+const MAX_SIZE: u32 = 16384;
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let i = value.find('x')?;
+    let width = value[..i].parse().ok()?;
+    let height = value[i + 1..].parse().ok()?;
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return None;
+    }
+    Some((width, height))
+}
+
+// Sort modes by size then refresh rate, dropping duplicates that only differ
+// in things we don't track (bit depth, scaling)
+pub fn sort_video_modes(modes: &mut Vec<VideoMode>) {
+    modes.sort();
+    modes.dedup();
+}
+
+impl VideoMode {
+    // Widened so it can't overflow
+    pub fn pixel_count(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+// Exact size match with the requested refresh rate (0 = highest available),
+// otherwise the mode closest in pixel count with the highest refresh rate.
+pub fn select_video_mode(modes: &[VideoMode], requested: Option<VideoMode>) -> Option<VideoMode> {
+    let requested = match requested {
+        Some(requested) => requested,
+        None => {
+            return modes
+                .iter()
+                .copied()
+                .max_by_key(|m| (m.pixel_count(), m.refresh_rate))
+        }
+    };
+
+    let same_size = modes
+        .iter()
+        .filter(|m| m.width == requested.width && m.height == requested.height);
+    let exact = if requested.refresh_rate == 0 {
+        same_size.max_by_key(|m| m.refresh_rate)
+    } else {
+        same_size.min_by_key(|m| m.refresh_rate.abs_diff(requested.refresh_rate))
+    };
+    if let Some(mode) = exact {
+        return Some(*mode);
+    }
+
+    let area = requested.pixel_count();
+    modes
+        .iter()
+        .copied()
+        .min_by_key(|m| (m.pixel_count().abs_diff(area), Reverse(m.refresh_rate)))
+}
+
+// Size of the window frame around the client area, as AdjustWindowRect
+// reports it for the Overlapped style
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameInsets {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+pub fn find_monitor(monitors: &[Monitor], index: usize) -> Option<&Monitor> {
+    monitors
+        .get(index)
+        .or_else(|| monitors.iter().find(|m| m.primary))
+        .or_else(|| monitors.first())
+}
+
+// Outer window rect for a display mode. Windowed mode centers the client area
+// on the primary monitor's work area, shrinking it if it doesn't fit. Fullscreen
+// modes cover the chosen monitor; exclusive mode uses the mode's size since the
+// monitor is about to switch to it.
+pub fn window_rect(mode: &DisplayMode, monitors: &[Monitor], frame: FrameInsets) -> Rect {
+    match *mode {
+        DisplayMode::Windowed { width, height } => {
+            let work_area = match find_monitor(monitors, usize::MAX) {
+                Some(monitor) => monitor.work_area,
+                None => return Rect::new(0, 0, width as i32, height as i32),
+            };
+
+            let outer_width = (width as i32 + frame.left + frame.right).min(work_area.width());
+            let outer_height = (height as i32 + frame.top + frame.bottom).min(work_area.height());
+            let left = work_area.left + (work_area.width() - outer_width) / 2;
+            let top = work_area.top + (work_area.height() - outer_height) / 2;
+            Rect::new(left, top, outer_width, outer_height)
+        }
+        DisplayMode::Borderless { monitor } => {
+            find_monitor(monitors, monitor).map_or_else(Rect::default, |m| m.bounds)
+        }
+        DisplayMode::Exclusive { monitor, mode } => match find_monitor(monitors, monitor) {
+            Some(m) => match select_video_mode(&m.modes, mode) {
+                Some(video_mode) => Rect::new(
+                    m.bounds.left,
+                    m.bounds.top,
+                    video_mode.width as i32,
+                    video_mode.height as i32,
+                ),
+                None => m.bounds,
+            },
+            None => Rect::default(),
+        },
+    }
+}
+
+// Tracks the current mode and what Alt+Enter switches between
+pub struct DisplayState {
+    pub mode: DisplayMode,
+    last_windowed: DisplayMode,
+    last_fullscreen: DisplayMode,
+}
+
+impl DisplayState {
+    pub fn new(mode: DisplayMode) -> Self {
+        let (last_windowed, last_fullscreen) = if mode.is_fullscreen() {
+            (DisplayMode::default(), mode)
+        } else {
+            (mode, DisplayMode::Borderless { monitor: 0 })
+        };
+        Self {
+            mode,
+            last_windowed,
+            last_fullscreen,
+        }
+    }
+
+    // Remember the client size so leaving fullscreen restores it
+    pub fn windowed_resized(&mut self, width: u32, height: u32) {
+        if !self.mode.is_fullscreen() {
+            self.mode = DisplayMode::Windowed { width, height };
+            self.last_windowed = self.mode;
+        }
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> DisplayMode {
+        if self.mode.is_fullscreen() {
+            self.last_fullscreen = self.mode;
+            self.mode = self.last_windowed;
+        } else {
+            self.last_windowed = self.mode;
+            self.mode = self.last_fullscreen;
+        }
+        self.mode
+    }
+}
+
+#[cfg(windows)]
+pub use self::win32::*;
+
+#[cfg(windows)]
+mod win32 {
+    use super::{sort_video_modes, Monitor, Rect, VideoMode};
+    use std::mem;
+    use std::ptr::null_mut;
+
+    use winapi::shared::minwindef::{BOOL, LPARAM, TRUE};
+    use winapi::shared::windef::{HDC, HMONITOR, LPRECT, RECT};
+    use winapi::um::wingdi::DEVMODEW;
+    use winapi::um::winuser::*;
+
+    fn to_rect(rect: &RECT) -> Rect {
+        Rect {
+            left: rect.left,
+            top: rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+
+    unsafe extern "system" fn monitor_callback(
+        monitor: HMONITOR,
+        _hdc: HDC,
+        _rect: LPRECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data as *mut Vec<Monitor>);
+
+        let mut info: MONITORINFOEXW = mem::zeroed();
+        info.cbSize = mem::size_of::<MONITORINFOEXW>() as u32;
+        if GetMonitorInfoW(monitor, &mut info as *mut MONITORINFOEXW as LPMONITORINFO) == 0 {
+            return TRUE;
+        }
+
+        // Every display mode the monitor supports at 32 bits per pixel
+        let mut modes = Vec::new();
+        let mut dev_mode: DEVMODEW = mem::zeroed();
+        dev_mode.dmSize = mem::size_of::<DEVMODEW>() as u16;
+        let mut i = 0;
+        while EnumDisplaySettingsW(info.szDevice.as_ptr(), i, &mut dev_mode) != 0 {
+            if dev_mode.dmBitsPerPel == 32 {
+                modes.push(VideoMode {
+                    width: dev_mode.dmPelsWidth,
+                    height: dev_mode.dmPelsHeight,
+                    refresh_rate: dev_mode.dmDisplayFrequency,
+                });
+            }
+            i += 1;
+        }
+        sort_video_modes(&mut modes);
+
+        let name_len = info
+            .szDevice
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(info.szDevice.len());
+        monitors.push(Monitor {
+            name: String::from_utf16_lossy(&info.szDevice[..name_len]),
+            bounds: to_rect(&info.rcMonitor),
+            work_area: to_rect(&info.rcWork),
+            primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+            modes,
+        });
+        TRUE
+    }
+
+    pub fn enumerate_monitors() -> Vec<Monitor> {
+        let mut monitors: Vec<Monitor> = Vec::new();
+        unsafe {
+            EnumDisplayMonitors(
+                null_mut(),
+                null_mut(),
+                Some(monitor_callback),
+                &mut monitors as *mut Vec<Monitor> as LPARAM,
+            );
+        }
+        monitors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(width: u32, height: u32, refresh_rate: u32) -> VideoMode {
+        VideoMode {
+            width,
+            height,
+            refresh_rate,
+        }
+    }
+
+    fn monitor(bounds: Rect, work_area: Rect, primary: bool) -> Monitor {
+        let mut modes = vec![
+            mode(1920, 1080, 60),
+            mode(1920, 1080, 144),
+            mode(1280, 720, 60),
+            mode(2560, 1440, 60),
+        ];
+        sort_video_modes(&mut modes);
+        Monitor {
+            name: String::from("test"),
+            bounds,
+            work_area,
+            primary,
+            modes,
+        }
+    }
+
+    // A secondary monitor on the left and the primary one with a 40 pixel
+    // taskbar at the bottom
+    fn monitors() -> Vec<Monitor> {
+        vec![
+            monitor(
+                Rect::new(-1280, 0, 1280, 1024),
+                Rect::new(-1280, 0, 1280, 1024),
+                false,
+            ),
+            monitor(
+                Rect::new(0, 0, 1920, 1080),
+                Rect::new(0, 0, 1920, 1040),
+                true,
+            ),
+        ]
+    }
+
+    const FRAME: FrameInsets = FrameInsets {
+        left: 8,
+        top: 31,
+        right: 8,
+        bottom: 8,
+    };
+
+    #[test]
+    fn parses_display_args() {
+        assert_eq!(
+            DisplayMode::from_arg("--windowed=800x600"),
+            Some(DisplayMode::Windowed {
+                width: 800,
+                height: 600
+            })
+        );
+        assert_eq!(
+            DisplayMode::from_arg("--fullscreen=1920x1080@144:1"),
+            Some(DisplayMode::Exclusive {
+                monitor: 1,
+                mode: Some(mode(1920, 1080, 144))
+            })
+        );
+        assert_eq!(DisplayMode::from_arg("--windowed=0x600"), None);
+        assert_eq!(DisplayMode::from_arg("--windowed=4294967295x2"), None);
+        assert_eq!(DisplayMode::from_arg("--fullscreen=70000x70000"), None);
+    }
+
+    #[test]
+    fn selects_video_modes() {
+        let modes = &monitors()[1].modes;
+        // Largest, then fastest
+        assert_eq!(select_video_mode(modes, None), Some(mode(2560, 1440, 60)));
+        // Exact size, fastest or closest refresh rate
+        assert_eq!(
+            select_video_mode(modes, Some(mode(1920, 1080, 0))),
+            Some(mode(1920, 1080, 144))
+        );
+        assert_eq!(
+            select_video_mode(modes, Some(mode(1920, 1080, 75))),
+            Some(mode(1920, 1080, 60))
+        );
+        // Closest pixel count, fastest of those
+        assert_eq!(
+            select_video_mode(modes, Some(mode(1900, 1100, 0))),
+            Some(mode(1920, 1080, 144))
+        );
+        assert_eq!(select_video_mode(&[], None), None);
+    }
+
+    #[test]
+    fn huge_modes_do_not_overflow() {
+        let modes = [mode(u32::MAX, u32::MAX, 60), mode(640, 480, 60)];
+        assert_eq!(select_video_mode(&modes, None), Some(modes[0]));
+        assert_eq!(
+            select_video_mode(&modes, Some(mode(u32::MAX - 1, u32::MAX, 0))),
+            Some(modes[0])
+        );
+        assert_eq!(
+            select_video_mode(&modes, Some(mode(800, 600, 0))),
+            Some(modes[1])
+        );
+    }
+
+    #[test]
+    fn centers_windows_on_primary_work_area() {
+        let windowed = DisplayMode::Windowed {
+            width: 1280,
+            height: 720,
+        };
+        // 1296x759 outer, centered in 1920x1040
+        assert_eq!(
+            window_rect(&windowed, &monitors(), FRAME),
+            Rect::new(312, 140, 1296, 759)
+        );
+        // Shrunk to the work area
+        let huge = DisplayMode::Windowed {
+            width: 4000,
+            height: 3000,
+        };
+        assert_eq!(
+            window_rect(&huge, &monitors(), FRAME),
+            Rect::new(0, 0, 1920, 1040)
+        );
+        // Client sized with no monitors
+        assert_eq!(
+            window_rect(&windowed, &[], FRAME),
+            Rect::new(0, 0, 1280, 720)
+        );
+    }
+
+    #[test]
+    fn fullscreen_windows_cover_monitors() {
+        let borderless = DisplayMode::Borderless { monitor: 0 };
+        assert_eq!(
+            window_rect(&borderless, &monitors(), FRAME),
+            Rect::new(-1280, 0, 1280, 1024)
+        );
+        // Unknown monitors fall back to the primary one
+        let exclusive = DisplayMode::Exclusive {
+            monitor: 7,
+            mode: Some(mode(1280, 720, 60)),
+        };
+        assert_eq!(
+            window_rect(&exclusive, &monitors(), FRAME),
+            Rect::new(0, 0, 1280, 720)
+        );
+    }
+
+    #[test]
+    fn toggles_between_last_modes() {
+        let windowed = DisplayMode::Windowed {
+            width: 800,
+            height: 600,
+        };
+        let mut state = DisplayState::new(windowed);
+        assert_eq!(
+            state.toggle_fullscreen(),
+            DisplayMode::Borderless { monitor: 0 }
+        );
+        // Resizes only count while windowed
+        state.windowed_resized(10, 10);
+        assert_eq!(state.toggle_fullscreen(), windowed);
+        state.windowed_resized(1024, 768);
+        assert_eq!(
+            state.toggle_fullscreen(),
+            DisplayMode::Borderless { monitor: 0 }
+        );
+        assert_eq!(
+            state.toggle_fullscreen(),
+            DisplayMode::Windowed {
+                width: 1024,
+                height: 768
+            }
+        );
+
+        let exclusive = DisplayMode::Exclusive {
+            monitor: 1,
+            mode: None,
+        };
+        let mut state = DisplayState::new(exclusive);
+        assert_eq!(state.toggle_fullscreen(), DisplayMode::default());
+        assert_eq!(state.toggle_fullscreen(), exclusive);
+    }
+}
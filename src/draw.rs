@@ -0,0 +1,423 @@
+// What to draw this frame and the constants that go with it. Constants are
+// split by how often they change: once per frame (camera), once per material
+// and once per object (world matrix). Per object constants are packed one after
+// the other into a single dynamic buffer instead of mapping a tiny buffer
+// with WRITE_DISCARD for every draw.
+use directx_math::{XMMatrixTranspose, XMStoreFloat4x4, XMFLOAT4X4, XMMATRIX};
+
+use crate::pipeline::RenderState;
+
+// Constant buffer offsets have to be multiples of 16 constants of 16 bytes
+pub const CONSTANT_ALIGNMENT: usize = 256;
+
+// Ring sizes, enough for 256 draws per wrap
+pub const OBJECT_RING_SIZE: usize = 64 * 1024;
+pub const FRAME_RING_SIZE: usize = 4 * 1024;
+pub const MATERIAL_RING_SIZE: usize = 64 * 1024;
+
+// Matches cbPerFrame in shaders.hlsl
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct FrameConstants {
+    pub view_projection: XMFLOAT4X4,
+    pub time: f32,
+    pub _padding: [f32; 3],
+}
+
+impl FrameConstants {
+    pub fn new(view_projection: XMMATRIX, time: f32) -> Self {
+        Self {
+            view_projection: shader_matrix(view_projection),
+            time,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+// Matches cbPerObject in shaders.hlsl
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ObjectConstants {
+    pub world: XMFLOAT4X4,
+}
+
+// HLSL reads cbuffer matrices column major, DirectXMath keeps them row major
+// XMMatrixTranspose is very important! Read Remarks: https://learn.microsoft.com/en-us/windows/win32/api/directxmath/nf-directxmath-XMStoreFloat4x4
+pub fn shader_matrix(matrix: XMMATRIX) -> XMFLOAT4X4 {
+    let mut stored = XMFLOAT4X4::default();
+    XMStoreFloat4x4(&mut stored, XMMatrixTranspose(matrix));
+    stored
+}
+
+// Where the renderer keeps a 1x1 white texture, for untextured materials
+pub const WHITE_TEXTURE: usize = 0;
+
+// A material ready to draw with, made from a material::MaterialDesc
+#[derive(Clone, Debug, Default)]
+pub struct Material {
+    // Index into the renderer's shader permutations
+    pub permutation: usize,
+    // cbMaterial, laid out for the permutation
+    pub constants: Vec<u8>,
+    // Texture slot and index into the renderer's textures
+    pub textures: Vec<(u32, usize)>,
+    pub state: RenderState,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    // Index into the renderer's meshes
+    pub mesh: usize,
+    pub world: XMFLOAT4X4,
+    // Index into the renderer's materials
+    pub material: usize,
+    // Id in the renderer's pipeline::PipelineCache
+    pub pipeline: usize,
+}
+
+impl DrawItem {
+    pub fn constants(&self) -> ObjectConstants {
+        ObjectConstants { world: self.world }
+    }
+}
+
+#[derive(Default)]
+pub struct DrawList {
+    items: Vec<DrawItem>,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Keeps the allocation, so the list can be refilled every frame
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    pub fn push(&mut self, mesh: usize, world: XMMATRIX, material: usize, pipeline: usize) {
+        self.items.push(DrawItem {
+            mesh,
+            world: shader_matrix(world),
+            material,
+            pipeline,
+        });
+    }
+
+    // Groups draws by pipeline, then material, then mesh, so each is bound
+    // once per run of draws using it. Pipeline changes cost the most, so they
+    // go first. Stable, so otherwise equal draws keep their order.
+    pub fn sort_by_pipeline(&mut self) {
+        self.items
+            .sort_by_key(|item| (item.pipeline, item.material, item.mesh));
+    }
+
+    pub fn items(&self) -> &[DrawItem] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+pub fn align_constant_size(size: usize) -> usize {
+    size.next_multiple_of(CONSTANT_ALIGNMENT)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RingAllocation {
+    // Bytes from the start of the buffer
+    pub offset: usize,
+    // Aligned size that was reserved
+    pub size: usize,
+    // The ring wrapped, so map with WRITE_DISCARD to get fresh memory instead
+    // of overwriting what the GPU may still be reading
+    pub discard: bool,
+}
+
+// Hands out aligned ranges of a buffer front to back, starting over when the
+// end is reached. With D3D11 dynamic buffers the driver renames the buffer on
+// WRITE_DISCARD, so there's no need to track when the GPU is done with a
+// range, wrapping is enough.
+#[derive(Clone, Copy, Debug)]
+pub struct ConstantRing {
+    capacity: usize,
+    head: usize,
+}
+
+impl ConstantRing {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = align_constant_size(capacity.max(1));
+        // The first map has to discard, start out full
+        Self {
+            capacity,
+            head: capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // None if `size` doesn't fit in the whole ring
+    pub fn allocate(&mut self, size: usize) -> Option<RingAllocation> {
+        // Before aligning, which could overflow. The capacity is aligned so
+        // anything that fits still does once rounded up.
+        if size > self.capacity {
+            return None;
+        }
+        let size = align_constant_size(size);
+        let discard = self.head + size > self.capacity;
+        if discard {
+            self.head = 0;
+        }
+        let offset = self.head;
+        self.head += size;
+        Some(RingAllocation {
+            offset,
+            size,
+            discard,
+        })
+    }
+}
+
+#[cfg(windows)]
+pub use self::win32::ConstantRingBuffer;
+
+#[cfg(windows)]
+mod win32 {
+    use std::mem;
+    use std::ptr::{copy_nonoverlapping, null_mut};
+
+    use winapi::shared::winerror::FAILED;
+    use winapi::um::d3d11::*;
+    use winapi::um::d3d11_1::ID3D11DeviceContext1;
+    use winapi::um::winnt::HRESULT;
+    use winapi::Interface;
+
+    use super::{ConstantRing, RingAllocation, CONSTANT_ALIGNMENT};
+
+    // A dynamic constant buffer used as a ring. Needs D3D 11.1 to bind at an
+    // offset and to map constant buffers with NO_OVERWRITE. Without that it
+    // falls back to a single slot that's discarded on every push, which is
+    // what the renderer did before. Each kind of constants needs its own ring
+    // for the fallback to work.
+    pub struct ConstantRingBuffer {
+        buffer: *mut ID3D11Buffer,
+        context1: *mut ID3D11DeviceContext1,
+        ring: ConstantRing,
+    }
+
+    impl ConstantRingBuffer {
+        pub unsafe fn new(
+            device: &ID3D11Device,
+            context: &ID3D11DeviceContext,
+            capacity: usize,
+        ) -> Result<Self, HRESULT> {
+            let mut options: D3D11_FEATURE_DATA_D3D11_OPTIONS = mem::zeroed();
+            let res = device.CheckFeatureSupport(
+                D3D11_FEATURE_D3D11_OPTIONS,
+                &mut options as *mut _ as _,
+                mem::size_of::<D3D11_FEATURE_DATA_D3D11_OPTIONS>() as u32,
+            );
+            let supported = !FAILED(res)
+                && options.ConstantBufferOffsetting != 0
+                && options.MapNoOverwriteOnDynamicConstantBuffer != 0;
+
+            let mut context1: *mut ID3D11DeviceContext1 = null_mut();
+            if supported {
+                context.QueryInterface(
+                    &ID3D11DeviceContext1::uuidof(),
+                    &mut context1 as *mut _ as _,
+                );
+            }
+            let capacity = if context1.is_null() {
+                println!("Constant buffer offsetting not supported, discarding per draw");
+                CONSTANT_ALIGNMENT
+            } else {
+                capacity
+            };
+
+            let ring = ConstantRing::new(capacity);
+            let buffer_desc = D3D11_BUFFER_DESC {
+                Usage: D3D11_USAGE_DYNAMIC,
+                ByteWidth: ring.capacity() as u32,
+                BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                MiscFlags: 0,
+                StructureByteStride: 0,
+            };
+            let mut buffer = null_mut();
+            let res = device.CreateBuffer(&buffer_desc, null_mut(), &mut buffer);
+            if FAILED(res) {
+                if let Some(context1) = context1.as_ref() {
+                    context1.Release();
+                }
+                return Err(res);
+            }
+
+            Ok(Self {
+                buffer,
+                context1,
+                ring,
+            })
+        }
+
+        // Copies `data` into the next free range. The returned allocation
+        // goes to bind_vs/bind_ps.
+        pub unsafe fn push<T: Copy>(
+            &mut self,
+            context: &ID3D11DeviceContext,
+            data: &T,
+        ) -> Option<RingAllocation> {
+            let bytes =
+                std::slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>());
+            self.push_bytes(context, bytes)
+        }
+
+        // For constants laid out at runtime, like materials'
+        pub unsafe fn push_bytes(
+            &mut self,
+            context: &ID3D11DeviceContext,
+            data: &[u8],
+        ) -> Option<RingAllocation> {
+            let allocation = self.ring.allocate(data.len())?;
+            let map_type = if allocation.discard {
+                D3D11_MAP_WRITE_DISCARD
+            } else {
+                D3D11_MAP_WRITE_NO_OVERWRITE
+            };
+
+            let mut mapped: D3D11_MAPPED_SUBRESOURCE = mem::zeroed();
+            let res = context.Map(self.buffer as _, 0, map_type, 0, &mut mapped);
+            if FAILED(res) {
+                println!("Error mapping constant buffer: {}", res);
+                return None;
+            }
+            let destination = (mapped.pData as *mut u8).add(allocation.offset);
+            copy_nonoverlapping(data.as_ptr(), destination, data.len());
+            context.Unmap(self.buffer as _, 0);
+            Some(allocation)
+        }
+
+        pub unsafe fn bind_vs(
+            &self,
+            context: &ID3D11DeviceContext,
+            slot: u32,
+            allocation: RingAllocation,
+        ) {
+            match self.context1.as_ref() {
+                Some(context1) => {
+                    let (first, count) = constant_range(allocation);
+                    context1.VSSetConstantBuffers1(slot, 1, &self.buffer, &first, &count);
+                }
+                None => context.VSSetConstantBuffers(slot, 1, &self.buffer),
+            }
+        }
+
+        pub unsafe fn bind_ps(
+            &self,
+            context: &ID3D11DeviceContext,
+            slot: u32,
+            allocation: RingAllocation,
+        ) {
+            match self.context1.as_ref() {
+                Some(context1) => {
+                    let (first, count) = constant_range(allocation);
+                    context1.PSSetConstantBuffers1(slot, 1, &self.buffer, &first, &count);
+                }
+                None => context.PSSetConstantBuffers(slot, 1, &self.buffer),
+            }
+        }
+    }
+
+    // In 16 byte constants, as the *SetConstantBuffers1 calls want them
+    fn constant_range(allocation: RingAllocation) -> (u32, u32) {
+        (
+            (allocation.offset / 16) as u32,
+            (allocation.size / 16) as u32,
+        )
+    }
+
+    impl Drop for ConstantRingBuffer {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some(context1) = self.context1.as_ref() {
+                    context1.Release();
+                }
+                if let Some(buffer) = self.buffer.as_ref() {
+                    buffer.Release();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_allocation_discards() {
+        let mut ring = ConstantRing::new(1024);
+        let first = ring.allocate(64).unwrap();
+        assert_eq!(first.offset, 0);
+        assert!(first.discard);
+        let second = ring.allocate(64).unwrap();
+        assert_eq!(second.offset, CONSTANT_ALIGNMENT);
+        assert!(!second.discard);
+    }
+
+    #[test]
+    fn wrapping_discards() {
+        let mut ring = ConstantRing::new(1024);
+        for i in 0..4 {
+            let allocation = ring.allocate(200).unwrap();
+            assert_eq!(allocation.offset, i * CONSTANT_ALIGNMENT);
+            assert_eq!(allocation.discard, i == 0);
+        }
+        // Full, the next one starts over
+        let wrapped = ring.allocate(1).unwrap();
+        assert_eq!(wrapped.offset, 0);
+        assert!(wrapped.discard);
+        assert!(!ring.allocate(1).unwrap().discard);
+
+        // Doesn't fit in what's left, so wraps early
+        let big = ring.allocate(3 * CONSTANT_ALIGNMENT).unwrap();
+        assert_eq!(big.offset, 0);
+        assert!(big.discard);
+    }
+
+    #[test]
+    fn allocations_stay_aligned() {
+        let mut ring = ConstantRing::new(OBJECT_RING_SIZE);
+        assert_eq!(ring.capacity() % CONSTANT_ALIGNMENT, 0);
+        for size in [1, 16, 255, 256, 257, 1000, 4096, 17] {
+            let allocation = ring.allocate(size).unwrap();
+            assert_eq!(allocation.offset % CONSTANT_ALIGNMENT, 0);
+            assert_eq!(allocation.size % CONSTANT_ALIGNMENT, 0);
+            assert!(allocation.size >= size);
+            assert!(allocation.offset + allocation.size <= ring.capacity());
+        }
+        // Odd capacities round up too
+        assert_eq!(ConstantRing::new(300).capacity(), 2 * CONSTANT_ALIGNMENT);
+        assert_eq!(ConstantRing::new(0).capacity(), CONSTANT_ALIGNMENT);
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let mut ring = ConstantRing::new(1024);
+        assert_eq!(ring.allocate(1025), None);
+        assert_eq!(ring.allocate(usize::MAX - 8), None);
+        // Refusing doesn't disturb the ring
+        let allocation = ring.allocate(1024).unwrap();
+        assert_eq!(allocation.offset, 0);
+        assert!(allocation.discard);
+    }
+}
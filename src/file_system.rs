@@ -0,0 +1,105 @@
+// Where assets are read from: the disk for the app, or memory, so loading and
+// reloading can be driven without touching real files.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+pub trait FileSystem: Send + Sync {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error>;
+
+    // A stamp that changes whenever the file does, None when there's no file
+    fn version(&self, path: &Path) -> Option<u64>;
+
+    fn read_to_string(&self, path: &Path) -> Result<String, Error> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+// "a/./b/../c" and "a/c" name the same file. Done on the text alone, so it
+// works for files that don't exist.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+// Paths relative to the working directory
+pub struct DiskFileSystem;
+
+impl FileSystem for DiskFileSystem {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        fs::read(path)
+    }
+
+    // Modification time and size, since some file systems only keep the time
+    // to the second
+    fn version(&self, path: &Path) -> Option<u64> {
+        let metadata = fs::metadata(path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        metadata.modified().ok()?.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryFileSystem {
+    // Contents and version of each file, by normalized path
+    files: Mutex<HashMap<PathBuf, (Vec<u8>, u64)>>,
+    next_version: Mutex<u64>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Creates or replaces a file
+    pub fn write<P: AsRef<Path>, B: Into<Vec<u8>>>(&self, path: P, contents: B) {
+        let mut next_version = self.next_version.lock().unwrap();
+        *next_version += 1;
+        self.files
+            .lock()
+            .unwrap()
+            .insert(normalize(path.as_ref()), (contents.into(), *next_version));
+    }
+
+    pub fn remove<P: AsRef<Path>>(&self, path: P) {
+        self.files.lock().unwrap().remove(&normalize(path.as_ref()));
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        match self.files.lock().unwrap().get(&normalize(path)) {
+            Some((contents, _)) => Ok(contents.clone()),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No file {}", path.display()),
+            )),
+        }
+    }
+
+    fn version(&self, path: &Path) -> Option<u64> {
+        let files = self.files.lock().unwrap();
+        files.get(&normalize(path)).map(|(_, version)| *version)
+    }
+}
@@ -0,0 +1,442 @@
+// Gamepad state on top of a swappable device layer. XInput on Windows, a
+// scripted virtual pad anywhere else (and for tests).
+use std::collections::VecDeque;
+
+pub const MAX_GAMEPADS: usize = 4;
+
+// XInput's recommended deadzones, in raw stick/trigger units
+pub const LEFT_STICK_DEADZONE: i16 = 7849;
+pub const RIGHT_STICK_DEADZONE: i16 = 8689;
+pub const TRIGGER_THRESHOLD: u8 = 30;
+
+// Looking for new pads is slow with XInput, only check empty slots this often
+const RECONNECT_INTERVAL: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Start,
+    Back,
+    LeftThumb,
+    RightThumb,
+    LeftShoulder,
+    RightShoulder,
+    A,
+    B,
+    X,
+    Y,
+}
+
+impl GamepadButton {
+    // Bit in XINPUT_GAMEPAD.wButtons
+    pub fn mask(self) -> u16 {
+        match self {
+            GamepadButton::DPadUp => 0x0001,
+            GamepadButton::DPadDown => 0x0002,
+            GamepadButton::DPadLeft => 0x0004,
+            GamepadButton::DPadRight => 0x0008,
+            GamepadButton::Start => 0x0010,
+            GamepadButton::Back => 0x0020,
+            GamepadButton::LeftThumb => 0x0040,
+            GamepadButton::RightThumb => 0x0080,
+            GamepadButton::LeftShoulder => 0x0100,
+            GamepadButton::RightShoulder => 0x0200,
+            GamepadButton::A => 0x1000,
+            GamepadButton::B => 0x2000,
+            GamepadButton::X => 0x4000,
+            GamepadButton::Y => 0x8000,
+        }
+    }
+}
+
+// What the device reports, same layout as XINPUT_GAMEPAD
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RawGamepad {
+    pub buttons: u16,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub thumb_lx: i16,
+    pub thumb_ly: i16,
+    pub thumb_rx: i16,
+    pub thumb_ry: i16,
+}
+
+impl RawGamepad {
+    pub fn with_buttons(mut self, buttons: &[GamepadButton]) -> Self {
+        self.buttons = buttons.iter().fold(0, |mask, b| mask | b.mask());
+        self
+    }
+}
+
+pub trait GamepadBackend {
+    // Current state of the pad in a slot, None if nothing is connected
+    fn poll(&mut self, index: usize) -> Option<RawGamepad>;
+}
+
+// Radial deadzone. Inside it the stick reads zero, outside the remaining
+// range is rescaled so the output still goes smoothly from 0 to 1.
+// Returns (x, y) in -1..=1, +y is up.
+pub fn apply_stick_deadzone(x: i16, y: i16, deadzone: i16) -> (f32, f32) {
+    let (x, y) = (x as f32, y as f32);
+    let magnitude = (x * x + y * y).sqrt();
+    let deadzone = deadzone as f32;
+    if magnitude <= deadzone {
+        return (0.0, 0.0);
+    }
+
+    let max = i16::MAX as f32;
+    let scaled = ((magnitude.min(max) - deadzone) / (max - deadzone)).min(1.0);
+    (x / magnitude * scaled, y / magnitude * scaled)
+}
+
+// 0..=1, zero below the threshold
+pub fn apply_trigger_threshold(value: u8, threshold: u8) -> f32 {
+    if value <= threshold {
+        0.0
+    } else {
+        (value - threshold) as f32 / (u8::MAX - threshold) as f32
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GamepadState {
+    pub connected: bool,
+    pub buttons: u16,
+    pub left_stick: (f32, f32),
+    pub right_stick: (f32, f32),
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+impl GamepadState {
+    pub fn from_raw(raw: &RawGamepad) -> Self {
+        Self {
+            connected: true,
+            buttons: raw.buttons,
+            left_stick: apply_stick_deadzone(raw.thumb_lx, raw.thumb_ly, LEFT_STICK_DEADZONE),
+            right_stick: apply_stick_deadzone(raw.thumb_rx, raw.thumb_ry, RIGHT_STICK_DEADZONE),
+            left_trigger: apply_trigger_threshold(raw.left_trigger, TRIGGER_THRESHOLD),
+            right_trigger: apply_trigger_threshold(raw.right_trigger, TRIGGER_THRESHOLD),
+        }
+    }
+
+    pub fn is_down(&self, button: GamepadButton) -> bool {
+        self.buttons & button.mask() != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GamepadEvent {
+    Connected(usize),
+    Disconnected(usize),
+}
+
+pub struct Gamepads<B: GamepadBackend> {
+    backend: B,
+    current: [GamepadState; MAX_GAMEPADS],
+    previous: [GamepadState; MAX_GAMEPADS],
+    events: Vec<GamepadEvent>,
+    // Updates left until empty slots are polled again
+    reconnect_countdown: u32,
+}
+
+impl<B: GamepadBackend> Gamepads<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            current: [GamepadState::default(); MAX_GAMEPADS],
+            previous: [GamepadState::default(); MAX_GAMEPADS],
+            events: Vec::new(),
+            reconnect_countdown: 0,
+        }
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    // Poll every pad once per frame
+    pub fn update(&mut self) {
+        self.events.clear();
+        self.previous = self.current;
+        let look_for_new = self.reconnect_countdown == 0;
+        self.reconnect_countdown = if look_for_new {
+            RECONNECT_INTERVAL
+        } else {
+            self.reconnect_countdown - 1
+        };
+
+        for index in 0..MAX_GAMEPADS {
+            let was_connected = self.previous[index].connected;
+            if !was_connected && !look_for_new {
+                continue;
+            }
+
+            match self.backend.poll(index) {
+                Some(raw) => {
+                    self.current[index] = GamepadState::from_raw(&raw);
+                    if !was_connected {
+                        self.events.push(GamepadEvent::Connected(index));
+                    }
+                }
+                None => {
+                    self.current[index] = GamepadState::default();
+                    if was_connected {
+                        self.events.push(GamepadEvent::Disconnected(index));
+                    }
+                }
+            }
+        }
+    }
+
+    // Connects and disconnects seen by the last update
+    pub fn events(&self) -> &[GamepadEvent] {
+        &self.events
+    }
+
+    pub fn state(&self, index: usize) -> &GamepadState {
+        &self.current[index]
+    }
+
+    pub fn is_connected(&self, index: usize) -> bool {
+        self.current[index].connected
+    }
+
+    pub fn is_button_down(&self, index: usize, button: GamepadButton) -> bool {
+        self.current[index].is_down(button)
+    }
+
+    pub fn was_button_pressed(&self, index: usize, button: GamepadButton) -> bool {
+        self.current[index].is_down(button) && !self.previous[index].is_down(button)
+    }
+
+    pub fn was_button_released(&self, index: usize, button: GamepadButton) -> bool {
+        !self.current[index].is_down(button) && self.previous[index].is_down(button)
+    }
+
+    pub fn left_stick(&self, index: usize) -> (f32, f32) {
+        self.current[index].left_stick
+    }
+
+    pub fn right_stick(&self, index: usize) -> (f32, f32) {
+        self.current[index].right_stick
+    }
+
+    pub fn left_trigger(&self, index: usize) -> f32 {
+        self.current[index].left_trigger
+    }
+
+    pub fn right_trigger(&self, index: usize) -> f32 {
+        self.current[index].right_trigger
+    }
+
+    // First connected pad, handy for single player
+    pub fn first_connected(&self) -> Option<usize> {
+        (0..MAX_GAMEPADS).find(|&i| self.current[i].connected)
+    }
+}
+
+// Plays back a script of per-frame states for each slot. A slot keeps its
+// last state once its script runs out.
+#[derive(Default)]
+pub struct VirtualGamepad {
+    scripts: [VecDeque<Option<RawGamepad>>; MAX_GAMEPADS],
+    last: [Option<RawGamepad>; MAX_GAMEPADS],
+}
+
+impl VirtualGamepad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // None frames are disconnected
+    pub fn script(&mut self, index: usize, frames: Vec<Option<RawGamepad>>) {
+        self.scripts[index].extend(frames);
+    }
+
+    pub fn set(&mut self, index: usize, state: Option<RawGamepad>) {
+        self.scripts[index].clear();
+        self.last[index] = state;
+    }
+}
+
+impl GamepadBackend for VirtualGamepad {
+    fn poll(&mut self, index: usize) -> Option<RawGamepad> {
+        if let Some(frame) = self.scripts[index].pop_front() {
+            self.last[index] = frame;
+        }
+        self.last[index]
+    }
+}
+
+#[cfg(windows)]
+pub use self::xinput::XInputBackend;
+
+#[cfg(windows)]
+mod xinput {
+    use super::{GamepadBackend, RawGamepad};
+    use std::mem;
+    use winapi::shared::winerror::ERROR_SUCCESS;
+    use winapi::um::xinput::{XInputGetState, XINPUT_STATE};
+
+    pub struct XInputBackend;
+
+    impl GamepadBackend for XInputBackend {
+        fn poll(&mut self, index: usize) -> Option<RawGamepad> {
+            unsafe {
+                let mut state: XINPUT_STATE = mem::zeroed();
+                if XInputGetState(index as u32, &mut state) != ERROR_SUCCESS {
+                    return None;
+                }
+                let pad = &state.Gamepad;
+                Some(RawGamepad {
+                    buttons: pad.wButtons,
+                    left_trigger: pad.bLeftTrigger,
+                    right_trigger: pad.bRightTrigger,
+                    thumb_lx: pad.sThumbLX,
+                    thumb_ly: pad.sThumbLY,
+                    thumb_rx: pad.sThumbRX,
+                    thumb_ry: pad.sThumbRY,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    fn stick(x: i16, y: i16) -> RawGamepad {
+        RawGamepad {
+            thumb_lx: x,
+            thumb_ly: y,
+            ..RawGamepad::default()
+        }
+    }
+
+    #[test]
+    fn deadzone_is_radial() {
+        let deadzone = LEFT_STICK_DEADZONE;
+        assert_eq!(apply_stick_deadzone(0, 0, deadzone), (0.0, 0.0));
+        assert_eq!(apply_stick_deadzone(deadzone, 0, deadzone), (0.0, 0.0));
+        let inside = (deadzone as f32 * 0.7) as i16;
+        assert_eq!(apply_stick_deadzone(inside, inside, deadzone), (0.0, 0.0));
+        // Each axis alone is inside the deadzone but the length isn't, which a
+        // per axis deadzone would drop
+        let corner = (deadzone as f32 * 0.8) as i16;
+        let (x, y) = apply_stick_deadzone(corner, corner, deadzone);
+        assert!(x > 0.0 && close(x, y));
+
+        // Halfway between the deadzone and full tilt reads half
+        let half = deadzone + (i16::MAX - deadzone) / 2;
+        let (x, y) = apply_stick_deadzone(half, 0, deadzone);
+        assert!(close(x, 0.5), "{}", x);
+        assert_eq!(y, 0.0);
+
+        // Direction is kept, only the length is rescaled
+        let (x, y) = apply_stick_deadzone(-20000, 20000, deadzone);
+        assert!(close(x, -y));
+        let length = (x * x + y * y).sqrt();
+        let raw = (2.0f32).sqrt() * 20000.0;
+        let expected = (raw - deadzone as f32) / (i16::MAX as f32 - deadzone as f32);
+        assert!(close(length, expected), "{} != {}", length, expected);
+
+        // Full tilt in any direction tops out at 1, including the corners
+        assert_eq!(apply_stick_deadzone(i16::MAX, 0, deadzone), (1.0, 0.0));
+        assert_eq!(apply_stick_deadzone(0, i16::MIN, deadzone).1, -1.0);
+        let (x, y) = apply_stick_deadzone(i16::MAX, i16::MAX, deadzone);
+        assert!(close((x * x + y * y).sqrt(), 1.0));
+    }
+
+    #[test]
+    fn triggers_have_a_threshold() {
+        assert_eq!(apply_trigger_threshold(0, TRIGGER_THRESHOLD), 0.0);
+        assert_eq!(
+            apply_trigger_threshold(TRIGGER_THRESHOLD, TRIGGER_THRESHOLD),
+            0.0
+        );
+        let just_past = apply_trigger_threshold(TRIGGER_THRESHOLD + 1, TRIGGER_THRESHOLD);
+        assert!(just_past > 0.0 && just_past < 0.01);
+        assert_eq!(apply_trigger_threshold(u8::MAX, TRIGGER_THRESHOLD), 1.0);
+        assert_eq!(apply_trigger_threshold(u8::MAX, u8::MAX), 0.0);
+    }
+
+    #[test]
+    fn state_applies_deadzones_to_raw_values() {
+        let raw = RawGamepad {
+            left_trigger: TRIGGER_THRESHOLD,
+            right_trigger: u8::MAX,
+            thumb_rx: RIGHT_STICK_DEADZONE - 1,
+            ..stick(i16::MAX, 0)
+        }
+        .with_buttons(&[GamepadButton::A, GamepadButton::Start]);
+        let state = GamepadState::from_raw(&raw);
+        assert!(state.connected);
+        assert!(state.is_down(GamepadButton::A) && state.is_down(GamepadButton::Start));
+        assert!(!state.is_down(GamepadButton::B));
+        assert_eq!(state.left_stick, (1.0, 0.0));
+        assert_eq!(state.right_stick, (0.0, 0.0));
+        assert_eq!(state.left_trigger, 0.0);
+        assert_eq!(state.right_trigger, 1.0);
+    }
+
+    #[test]
+    fn connects_and_disconnects() {
+        let a = RawGamepad::default().with_buttons(&[GamepadButton::A]);
+        let mut backend = VirtualGamepad::new();
+        backend.script(2, vec![Some(RawGamepad::default()), Some(a), Some(a), None]);
+        let mut pads = Gamepads::new(backend);
+
+        pads.update();
+        assert_eq!(pads.events(), [GamepadEvent::Connected(2)]);
+        assert!(pads.is_connected(2));
+        assert_eq!(pads.first_connected(), Some(2));
+
+        pads.update();
+        assert!(pads.events().is_empty());
+        assert!(pads.was_button_pressed(2, GamepadButton::A));
+
+        pads.update();
+        assert!(pads.is_button_down(2, GamepadButton::A));
+        assert!(!pads.was_button_pressed(2, GamepadButton::A));
+
+        pads.update();
+        assert_eq!(pads.events(), [GamepadEvent::Disconnected(2)]);
+        assert!(!pads.is_connected(2));
+        assert_eq!(*pads.state(2), GamepadState::default());
+        // Dropping out releases whatever was held
+        assert!(pads.was_button_released(2, GamepadButton::A));
+        assert_eq!(pads.first_connected(), None);
+
+        pads.update();
+        assert!(pads.events().is_empty());
+    }
+
+    #[test]
+    fn empty_slots_are_polled_on_an_interval() {
+        let mut pads = Gamepads::new(VirtualGamepad::new());
+        pads.update();
+        // Plugged in right after the first look
+        pads.backend_mut().set(0, Some(RawGamepad::default()));
+        for _ in 0..RECONNECT_INTERVAL {
+            pads.update();
+            assert!(!pads.is_connected(0));
+        }
+        pads.update();
+        assert_eq!(pads.events(), [GamepadEvent::Connected(0)]);
+
+        // Connected pads are polled every frame, so a disconnect shows up
+        // straight away
+        pads.backend_mut().set(0, None);
+        pads.update();
+        assert_eq!(pads.events(), [GamepadEvent::Disconnected(0)]);
+    }
+}
@@ -0,0 +1,1235 @@
+// glTF 2.0 scenes (.gltf with external or data URI buffers, or .glb) turned
+// into the renderer's types: one Mesh per glTF mesh with a submesh per
+// primitive, materials, the node hierarchy, cameras and animations.
+//
+// glTF is right handed, the renderer left handed. Everything is mirrored on z
+// on the way in: positions, normals and tangents get z negated, rotations
+// their x and y, and triangle windings are reversed. Accessor decoding,
+// including normalized integers and sparse accessors, is left to the gltf
+// crate.
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use base64::Engine;
+use directx_math::{
+    XMLoadFloat4, XMMatrixDeterminant, XMMatrixIdentity, XMMatrixInverse, XMMatrixMultiply,
+    XMMatrixTranspose, XMQuaternionNormalize, XMQuaternionSlerp, XMStoreFloat3, XMStoreFloat4,
+    XMVector3Normalize, XMVector3TransformCoord, XMVector3TransformNormal, XMVectorGetX,
+    XMVectorHermite, XMVectorLerp, XMVectorScale, XMVectorSet, XMFLOAT3, XMFLOAT4, XMMATRIX,
+    XMVECTOR,
+};
+use gltf::animation::util::ReadOutputs;
+
+use crate::camera::{Camera, Projection};
+use crate::file_system::{DiskFileSystem, FileSystem};
+use crate::mesh::{Mesh, Submesh};
+use crate::scene_graph::{NodeId, SceneGraph};
+use crate::transform::Transform;
+use crate::vertex::Vertex;
+
+// Far plane given to infinite perspective cameras
+const INFINITE_FAR: f32 = 1000.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureRef {
+    // Index into GltfScene::images
+    pub image: usize,
+    // Which UV set, only set 0 is imported
+    pub tex_coord: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    // Cut out below the cutoff
+    Mask(f32),
+    Blend,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfMaterial {
+    pub name: String,
+    pub base_color: XMFLOAT4,
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive: XMFLOAT3,
+    pub emissive_texture: Option<TextureRef>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+impl Default for GltfMaterial {
+    // What the spec says primitives without a material look like
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            base_color: XMFLOAT4 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+                w: 1.0,
+            },
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: XMFLOAT3::default(),
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+// Still encoded, as PNG or JPEG
+#[derive(Clone, Debug)]
+pub struct GltfImage {
+    pub name: String,
+    pub mime_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: String,
+    pub transform: Transform,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub camera: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GltfCamera {
+    pub projection: Projection,
+    pub near: f32,
+    pub far: f32,
+    // None to follow the viewport
+    pub aspect: Option<f32>,
+}
+
+impl GltfCamera {
+    // A camera placed by the world matrix of the node holding it
+    pub fn to_camera(&self, world: XMMATRIX) -> Camera {
+        let mut camera = match self.projection {
+            Projection::Orthographic { height } => {
+                Camera::orthographic(height, self.near, self.far)
+            }
+            Projection::Perspective { fov_y } => Camera::perspective(fov_y, self.near, self.far),
+        };
+        if let Some(aspect) = self.aspect {
+            camera.aspect = aspect;
+        }
+        if let Some(transform) = Transform::from_matrix(world) {
+            camera.position = transform.translation;
+            camera.set_orientation(transform.rotation_vector());
+        }
+        camera
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // Every key has an in tangent, the value and an out tangent, in that
+    // order
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub struct Channel {
+    // Index into GltfScene::nodes
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    // Seconds, ascending
+    pub times: Vec<f32>,
+    // Translations and scales leave w at 0, rotations are quaternions
+    pub values: Vec<XMFLOAT4>,
+}
+
+impl Channel {
+    fn key_value(&self, key: usize) -> XMVECTOR {
+        match self.interpolation {
+            Interpolation::CubicSpline => XMLoadFloat4(&self.values[key * 3 + 1]),
+            _ => XMLoadFloat4(&self.values[key]),
+        }
+    }
+
+    // Held at the first and last key outside the keyed range
+    pub fn sample(&self, time: f32) -> XMVECTOR {
+        let keys = self.times.len();
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return self.key_value(0);
+        }
+        if next == keys {
+            return self.key_value(keys - 1);
+        }
+        let key = next - 1;
+        let duration = self.times[next] - self.times[key];
+        let s = if duration > 0.0 {
+            (time - self.times[key]) / duration
+        } else {
+            0.0
+        };
+        let rotation = self.property == Property::Rotation;
+        let (from, to) = (self.key_value(key), self.key_value(next));
+        match self.interpolation {
+            Interpolation::Step => from,
+            Interpolation::Linear if rotation => XMQuaternionSlerp(from, to, s),
+            Interpolation::Linear => XMVectorLerp(from, to, s),
+            Interpolation::CubicSpline => {
+                // Tangents are per second, Hermite wants them per key span
+                let out_tangent = XMVectorScale(XMLoadFloat4(&self.values[key * 3 + 2]), duration);
+                let in_tangent = XMVectorScale(XMLoadFloat4(&self.values[next * 3]), duration);
+                let value = XMVectorHermite(from, out_tangent, to, in_tangent, s);
+                if rotation {
+                    XMQuaternionNormalize(value)
+                } else {
+                    value
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    // Time of the last key of any channel
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|c| c.times.last())
+            .fold(0.0, |a, &b| a.max(b))
+    }
+
+    // Poses the nodes added by GltfScene::add_to_graph
+    pub fn apply(&self, time: f32, graph: &mut SceneGraph, nodes: &[Option<NodeId>]) {
+        for channel in &self.channels {
+            let id = match nodes.get(channel.node) {
+                Some(&Some(id)) => id,
+                _ => continue,
+            };
+            let value = channel.sample(time);
+            let transform = graph.transform_mut(id);
+            match channel.property {
+                Property::Translation => XMStoreFloat3(&mut transform.translation, value),
+                Property::Rotation => transform.set_rotation(value),
+                Property::Scale => XMStoreFloat3(&mut transform.scale, value),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GltfScene {
+    // Submesh::material indexes into materials
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+    pub nodes: Vec<GltfNode>,
+    // Top level nodes of the default scene
+    pub roots: Vec<usize>,
+    pub cameras: Vec<GltfCamera>,
+    pub animations: Vec<Animation>,
+}
+
+impl GltfScene {
+    // `read_file` gets the (decoded) relative URIs of external buffers and
+    // images, so the caller decides where files come from
+    pub fn from_slice<F>(bytes: &[u8], mut read_file: F) -> Result<GltfScene, Error>
+    where
+        F: FnMut(&str) -> Result<Vec<u8>, Error>,
+    {
+        let gltf = gltf::Gltf::from_slice(bytes).map_err(|e| invalid_data(e.to_string()))?;
+        let document = &gltf.document;
+
+        let mut buffers = Vec::new();
+        for buffer in document.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf
+                    .blob
+                    .clone()
+                    .ok_or_else(|| invalid_data(String::from("Missing GLB binary chunk")))?,
+                gltf::buffer::Source::Uri(uri) => read_uri(uri, &mut read_file)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(invalid_data(format!(
+                    "Buffer {} has {} bytes, expected {}",
+                    buffer.index(),
+                    data.len(),
+                    buffer.length()
+                )));
+            }
+            buffers.push(data);
+        }
+        let buffer_data = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|d| d.as_slice());
+
+        let mut scene = GltfScene::default();
+        for image in document.images() {
+            let (data, mime_type) = match image.source() {
+                gltf::image::Source::View { view, mime_type } => {
+                    let buffer = &buffers[view.buffer().index()];
+                    let range = view.offset()..view.offset() + view.length();
+                    let data = buffer
+                        .get(range)
+                        .ok_or_else(|| invalid_data(String::from("Image view out of range")))?;
+                    (data.to_vec(), Some(mime_type.to_string()))
+                }
+                gltf::image::Source::Uri { uri, mime_type } => {
+                    (read_uri(uri, &mut read_file)?, mime_type.map(String::from))
+                }
+            };
+            scene.images.push(GltfImage {
+                name: image.name().unwrap_or_default().to_string(),
+                mime_type,
+                data,
+            });
+        }
+
+        for material in document.materials() {
+            scene.materials.push(convert_material(&material));
+        }
+        // Appended when a primitive has no material
+        let mut default_material = None;
+
+        for mesh in document.meshes() {
+            let mut vertices = Vec::new();
+            let mut indices = Vec::new();
+            let mut submeshes = Vec::new();
+            for primitive in mesh.primitives() {
+                let mut part = match read_primitive(&primitive, &buffer_data)? {
+                    Some(part) => part,
+                    None => {
+                        println!(
+                            "Skipping {:?} primitive in mesh {}",
+                            primitive.mode(),
+                            mesh.index()
+                        );
+                        continue;
+                    }
+                };
+                let material = match primitive.material().index() {
+                    Some(index) => index,
+                    None => *default_material.get_or_insert_with(|| {
+                        scene.materials.push(GltfMaterial::default());
+                        scene.materials.len() - 1
+                    }),
+                };
+                submeshes.push(Submesh {
+                    name: mesh.name().unwrap_or_default().to_string(),
+                    index_start: indices.len() as u32,
+                    index_count: part.indices.len() as u32,
+                    base_vertex: vertices.len() as i32,
+                    material,
+                });
+                indices.append(&mut part.indices);
+                vertices.append(&mut part.vertices);
+            }
+            scene
+                .meshes
+                .push(Mesh::new(vertices, indices).with_submeshes(submeshes));
+        }
+
+        for camera in document.cameras() {
+            scene.cameras.push(match camera.projection() {
+                gltf::camera::Projection::Orthographic(o) => GltfCamera {
+                    projection: Projection::Orthographic {
+                        height: o.ymag() * 2.0,
+                    },
+                    near: o.znear(),
+                    far: o.zfar(),
+                    aspect: Some(o.xmag() / o.ymag()),
+                },
+                gltf::camera::Projection::Perspective(p) => GltfCamera {
+                    projection: Projection::Perspective { fov_y: p.yfov() },
+                    near: p.znear(),
+                    far: p.zfar().unwrap_or(INFINITE_FAR),
+                    aspect: p.aspect_ratio(),
+                },
+            });
+        }
+
+        for node in document.nodes() {
+            let (t, r, s) = node.transform().decomposed();
+            let mut transform = Transform::identity()
+                .with_translation(t[0], t[1], -t[2])
+                .with_scale(s[0], s[1], s[2]);
+            transform.set_rotation(XMVectorSet(-r[0], -r[1], r[2], r[3]));
+            scene.nodes.push(GltfNode {
+                name: node.name().unwrap_or_default().to_string(),
+                transform,
+                children: node.children().map(|c| c.index()).collect(),
+                mesh: node.mesh().map(|m| m.index()),
+                camera: node.camera().map(|c| c.index()),
+            });
+        }
+        if let Some(default_scene) = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            scene.roots = default_scene.nodes().map(|n| n.index()).collect();
+        }
+
+        for animation in document.animations() {
+            let mut channels = Vec::new();
+            for channel in animation.channels() {
+                let reader = channel.reader(&buffer_data);
+                let times: Vec<f32> = match reader.read_inputs() {
+                    Some(inputs) => inputs.collect(),
+                    None => continue,
+                };
+                let (property, values): (Property, Vec<XMFLOAT4>) = match reader.read_outputs() {
+                    Some(ReadOutputs::Translations(values)) => (
+                        Property::Translation,
+                        values.map(|[x, y, z]| float4(x, y, -z, 0.0)).collect(),
+                    ),
+                    Some(ReadOutputs::Rotations(values)) => (
+                        Property::Rotation,
+                        values
+                            .into_f32()
+                            .map(|[x, y, z, w]| float4(-x, -y, z, w))
+                            .collect(),
+                    ),
+                    Some(ReadOutputs::Scales(values)) => (
+                        Property::Scale,
+                        values.map(|[x, y, z]| float4(x, y, z, 0.0)).collect(),
+                    ),
+                    // Morph targets aren't supported
+                    _ => continue,
+                };
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+                let per_key = if interpolation == Interpolation::CubicSpline {
+                    3
+                } else {
+                    1
+                };
+                if times.is_empty() || values.len() != times.len() * per_key {
+                    return Err(invalid_data(format!(
+                        "Animation {} has {} keys but {} values",
+                        animation.index(),
+                        times.len(),
+                        values.len()
+                    )));
+                }
+                channels.push(Channel {
+                    node: channel.target().node().index(),
+                    property,
+                    interpolation,
+                    times,
+                    values,
+                });
+            }
+            scene.animations.push(Animation {
+                name: animation.name().unwrap_or_default().to_string(),
+                channels,
+            });
+        }
+
+        for mesh in &scene.meshes {
+            if !mesh.vertices.is_empty() {
+                mesh.validate()?;
+            }
+        }
+        Ok(scene)
+    }
+
+    // External files are looked up next to the .gltf
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GltfScene, Error> {
+        Self::load_from(&DiskFileSystem, path.as_ref())
+    }
+
+    pub fn load_from(files: &dyn FileSystem, path: &Path) -> Result<GltfScene, Error> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_slice(&files.read(path)?, |uri| files.read(&directory.join(uri)))
+    }
+
+    // World matrix of every node in the default scene, None for the others
+    pub fn world_matrices(&self) -> Vec<Option<XMMATRIX>> {
+        let mut worlds = vec![None; self.nodes.len()];
+        let mut pending: Vec<(usize, XMMATRIX)> = self
+            .roots
+            .iter()
+            .map(|&r| (r, XMMatrixIdentity()))
+            .collect();
+        while let Some((index, parent)) = pending.pop() {
+            // A node listed twice would make this loop forever
+            if index >= self.nodes.len() || worlds[index].is_some() {
+                continue;
+            }
+            let node = &self.nodes[index];
+            let world = XMMatrixMultiply(node.transform.matrix(), &parent);
+            worlds[index] = Some(world);
+            pending.extend(node.children.iter().map(|&c| (c, world)));
+        }
+        worlds
+    }
+
+    // Adds the default scene's nodes under `parent`. Returns the scene graph
+    // node of each glTF node, for Animation::apply.
+    pub fn add_to_graph(
+        &self,
+        graph: &mut SceneGraph,
+        parent: Option<NodeId>,
+    ) -> Vec<Option<NodeId>> {
+        let mut ids = vec![None; self.nodes.len()];
+        let mut pending: Vec<(usize, Option<NodeId>)> =
+            self.roots.iter().rev().map(|&r| (r, parent)).collect();
+        while let Some((index, parent)) = pending.pop() {
+            if index >= self.nodes.len() || ids[index].is_some() {
+                continue;
+            }
+            let node = &self.nodes[index];
+            let id = graph.add(&node.name, node.transform, parent);
+            ids[index] = Some(id);
+            pending.extend(node.children.iter().rev().map(|&c| (c, Some(id))));
+        }
+        ids
+    }
+
+    // Every mesh instance of the default scene baked into one mesh at its
+    // world position, a submesh per primitive. None without any meshes.
+    pub fn flatten(&self) -> Option<Mesh> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut submeshes = Vec::new();
+        for (node, world) in self.nodes.iter().zip(self.world_matrices()) {
+            let (mesh, world) = match (node.mesh.and_then(|m| self.meshes.get(m)), world) {
+                (Some(mesh), Some(world)) => (mesh, world),
+                _ => continue,
+            };
+            let normal_matrix = XMMatrixTranspose(XMMatrixInverse(None, world));
+            let mirrored = XMVectorGetX(XMMatrixDeterminant(world)) < 0.0;
+            let first_vertex = vertices.len() as i32;
+            for vertex in &mesh.vertices {
+                let mut vertex = *vertex;
+                let pos = XMVector3TransformCoord(
+                    XMVectorSet(vertex.pos.x, vertex.pos.y, vertex.pos.z, 1.0),
+                    world,
+                );
+                XMStoreFloat3(&mut vertex.pos, pos);
+                let normal = XMVector3TransformNormal(
+                    XMVectorSet(vertex.normal.x, vertex.normal.y, vertex.normal.z, 0.0),
+                    normal_matrix,
+                );
+                XMStoreFloat3(&mut vertex.normal, XMVector3Normalize(normal));
+                let w = if mirrored {
+                    -vertex.tangent.w
+                } else {
+                    vertex.tangent.w
+                };
+                let tangent = XMVector3TransformNormal(
+                    XMVectorSet(vertex.tangent.x, vertex.tangent.y, vertex.tangent.z, 0.0),
+                    world,
+                );
+                XMStoreFloat4(&mut vertex.tangent, XMVector3Normalize(tangent));
+                vertex.tangent.w = w;
+                vertices.push(vertex);
+            }
+            for submesh in &mesh.submeshes {
+                let start = submesh.index_start as usize;
+                let range = mesh
+                    .indices
+                    .iter()
+                    .skip(start)
+                    .take(submesh.index_count as usize);
+                let mut part: Vec<u32> = range.collect();
+                if mirrored {
+                    for triangle in part.chunks_exact_mut(3) {
+                        triangle.swap(1, 2);
+                    }
+                }
+                submeshes.push(Submesh {
+                    index_start: indices.len() as u32,
+                    base_vertex: submesh.base_vertex + first_vertex,
+                    ..submesh.clone()
+                });
+                indices.extend(part);
+            }
+        }
+        if indices.is_empty() {
+            return None;
+        }
+        Some(Mesh::new(vertices, indices).with_submeshes(submeshes))
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn float4(x: f32, y: f32, z: f32, w: f32) -> XMFLOAT4 {
+    XMFLOAT4 { x, y, z, w }
+}
+
+// data: URIs are decoded in place, anything else is a relative file
+fn read_uri<F>(uri: &str, read_file: &mut F) -> Result<Vec<u8>, Error>
+where
+    F: FnMut(&str) -> Result<Vec<u8>, Error>,
+{
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (header, payload) = data
+            .split_once(',')
+            .ok_or_else(|| invalid_data(String::from("Malformed data URI")))?;
+        if !header.ends_with(";base64") {
+            return Err(invalid_data(String::from(
+                "Only base64 data URIs are supported",
+            )));
+        }
+        return base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .map_err(|e| invalid_data(format!("Invalid data URI: {}", e)));
+    }
+    read_file(&percent_decode(uri))
+}
+
+// "%20" and the like in relative URIs
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn texture_ref(texture: gltf::Texture, tex_coord: u32) -> TextureRef {
+    TextureRef {
+        image: texture.source().index(),
+        tex_coord,
+    }
+}
+
+fn convert_material(material: &gltf::Material) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let [er, eg, eb] = material.emissive_factor();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+    GltfMaterial {
+        name: material.name().unwrap_or_default().to_string(),
+        base_color: float4(r, g, b, a),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|t| texture_ref(t.texture(), t.tex_coord())),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|t| texture_ref(t.texture(), t.tex_coord())),
+        normal_texture: normal
+            .as_ref()
+            .map(|t| texture_ref(t.texture(), t.tex_coord())),
+        normal_scale: normal.as_ref().map_or(1.0, |t| t.scale()),
+        occlusion_texture: occlusion
+            .as_ref()
+            .map(|t| texture_ref(t.texture(), t.tex_coord())),
+        occlusion_strength: occlusion.as_ref().map_or(1.0, |t| t.strength()),
+        emissive: XMFLOAT3 {
+            x: er,
+            y: eg,
+            z: eb,
+        },
+        emissive_texture: material
+            .emissive_texture()
+            .map(|t| texture_ref(t.texture(), t.tex_coord())),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => {
+                AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5))
+            }
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        double_sided: material.double_sided(),
+    }
+}
+
+struct Primitive {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+// Vertices and a triangle list for one primitive, None for points and lines
+fn read_primitive<'s, F>(
+    primitive: &gltf::Primitive,
+    buffer_data: &F,
+) -> Result<Option<Primitive>, Error>
+where
+    F: Clone + Fn(gltf::Buffer) -> Option<&'s [u8]>,
+{
+    let reader = primitive.reader(buffer_data.clone());
+    let positions: Vec<[f32; 3]> = match reader.read_positions() {
+        Some(positions) => positions.collect(),
+        None => return Err(invalid_data(String::from("Primitive without positions"))),
+    };
+    let count = positions.len();
+    let raw_indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..count as u32).collect(),
+    };
+    if let Some(&index) = raw_indices.iter().find(|&&i| i as usize >= count) {
+        return Err(invalid_data(format!("Index {} out of range", index)));
+    }
+
+    // To triangle lists, windings reversed for the mirror
+    let corners = |a: u32, b: u32, c: u32| [a, c, b];
+    let triangles: Vec<[u32; 3]> = match primitive.mode() {
+        gltf::mesh::Mode::Triangles => raw_indices
+            .chunks_exact(3)
+            .map(|t| corners(t[0], t[1], t[2]))
+            .collect(),
+        gltf::mesh::Mode::TriangleStrip => raw_indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, t)| {
+                if i % 2 == 0 {
+                    corners(t[0], t[1], t[2])
+                } else {
+                    corners(t[1], t[0], t[2])
+                }
+            })
+            .collect(),
+        gltf::mesh::Mode::TriangleFan => raw_indices
+            .windows(2)
+            .skip(1)
+            .map(|t| corners(raw_indices[0], t[0], t[1]))
+            .collect(),
+        _ => return Ok(None),
+    };
+
+    let mut vertices: Vec<Vertex> = positions
+        .iter()
+        .map(|&[x, y, z]| Vertex::new((x, y, -z), (0.0, 0.0, 0.0), (0.0, 0.0)))
+        .collect();
+    if let Some(uvs) = reader.read_tex_coords(0) {
+        for (vertex, [u, v]) in vertices.iter_mut().zip(uvs.into_f32()) {
+            vertex.uv.x = u;
+            vertex.uv.y = v;
+        }
+    }
+    if let Some(colors) = reader.read_colors(0) {
+        for (vertex, [r, g, b, a]) in vertices.iter_mut().zip(colors.into_rgba_f32()) {
+            *vertex = vertex.with_color(r, g, b, a);
+        }
+    }
+    let tangents = reader.read_tangents();
+    let has_tangents = tangents.is_some();
+    if let Some(tangents) = tangents {
+        for (vertex, [x, y, z, w]) in vertices.iter_mut().zip(tangents) {
+            vertex.tangent = float4(x, y, -z, -w);
+        }
+    }
+
+    let indices: Vec<u32> = match reader.read_normals() {
+        Some(normals) => {
+            for (vertex, [x, y, z]) in vertices.iter_mut().zip(normals) {
+                vertex.normal = XMFLOAT3 { x, y, z: -z };
+            }
+            triangles.concat()
+        }
+        // The spec asks for flat shading, which needs a vertex per corner
+        None => {
+            let mut flat = Vec::with_capacity(triangles.len() * 3);
+            for triangle in &triangles {
+                let [a, b, c] = triangle.map(|i| vertices[i as usize].pos);
+                let e1 = (b.x - a.x, b.y - a.y, b.z - a.z);
+                let e2 = (c.x - a.x, c.y - a.y, c.z - a.z);
+                let normal = XMVector3Normalize(XMVectorSet(
+                    e1.1 * e2.2 - e1.2 * e2.1,
+                    e1.2 * e2.0 - e1.0 * e2.2,
+                    e1.0 * e2.1 - e1.1 * e2.0,
+                    0.0,
+                ));
+                for &i in triangle {
+                    let mut vertex = vertices[i as usize];
+                    XMStoreFloat3(&mut vertex.normal, normal);
+                    flat.push(vertex);
+                }
+            }
+            vertices = flat;
+            (0..vertices.len() as u32).collect()
+        }
+    };
+
+    let mut mesh = Mesh::new(vertices, indices);
+    if !has_tangents {
+        mesh.compute_tangents();
+    }
+    let indices = mesh.indices.iter().collect();
+    Ok(Some(Primitive {
+        vertices: mesh.vertices,
+        indices,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directx_math::XMVectorGetZ;
+
+    // Accumulates one binary buffer with its views and accessors, written out
+    // as glTF JSON by hand
+    #[derive(Default)]
+    struct Builder {
+        bin: Vec<u8>,
+        views: Vec<String>,
+        accessors: Vec<String>,
+    }
+
+    impl Builder {
+        fn view(&mut self, bytes: &[u8]) -> usize {
+            self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+            self.views.push(format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#,
+                self.bin.len(),
+                bytes.len()
+            ));
+            self.bin.extend_from_slice(bytes);
+            self.views.len() - 1
+        }
+
+        // `fields` is the rest of the accessor, type, componentType and so on
+        fn accessor(&mut self, bytes: &[u8], count: usize, fields: &str) -> usize {
+            let view = self.view(bytes);
+            self.push_accessor(format!(
+                r#"{{"bufferView":{},"count":{},{}}}"#,
+                view, count, fields
+            ))
+        }
+
+        fn push_accessor(&mut self, json: String) -> usize {
+            self.accessors.push(json);
+            self.accessors.len() - 1
+        }
+
+        // With the min and max positions and animation inputs need
+        fn floats(&mut self, values: &[f32], kind: &str) -> usize {
+            let width = match kind {
+                "SCALAR" => 1,
+                "VEC2" => 2,
+                "VEC3" => 3,
+                _ => 4,
+            };
+            let (min, max) = min_max(values, width);
+            let fields = format!(
+                r#""componentType":5126,"type":"{}","min":{:?},"max":{:?}"#,
+                kind, min, max
+            );
+            self.accessor(&float_bytes(values), values.len() / width, &fields)
+        }
+
+        // `uri` None for GLB, `rest` is more top level properties, starting
+        // with a comma
+        fn json(&self, uri: Option<&str>, rest: &str) -> String {
+            let uri = uri.map_or(String::new(), |uri| format!(r#","uri":"{}""#, uri));
+            format!(
+                r#"{{"asset":{{"version":"2.0"}},"buffers":[{{"byteLength":{}{}}}],"bufferViews":[{}],"accessors":[{}]{}}}"#,
+                self.bin.len(),
+                uri,
+                self.views.join(","),
+                self.accessors.join(","),
+                rest
+            )
+        }
+    }
+
+    fn float_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn min_max(values: &[f32], width: usize) -> (Vec<f32>, Vec<f32>) {
+        let mut min = vec![f32::MAX; width];
+        let mut max = vec![f32::MIN; width];
+        for element in values.chunks(width) {
+            for (i, &v) in element.iter().enumerate() {
+                min[i] = min[i].min(v);
+                max[i] = max[i].max(v);
+            }
+        }
+        (min, max)
+    }
+
+    fn glb(json: &str, bin: Option<&[u8]>) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut chunks = Vec::new();
+        chunks.extend((json.len() as u32).to_le_bytes());
+        chunks.extend(b"JSON");
+        chunks.extend(json);
+        if let Some(bin) = bin {
+            let mut bin = bin.to_vec();
+            bin.resize(bin.len().next_multiple_of(4), 0);
+            chunks.extend((bin.len() as u32).to_le_bytes());
+            chunks.extend(b"BIN\0");
+            chunks.extend(bin);
+        }
+        let mut file = b"glTF".to_vec();
+        file.extend(2u32.to_le_bytes());
+        file.extend((12 + chunks.len() as u32).to_le_bytes());
+        file.extend(chunks);
+        file
+    }
+
+    fn import(builder: &Builder, rest: &str) -> GltfScene {
+        let json = builder.json(Some("mesh.bin"), rest);
+        GltfScene::from_slice(json.as_bytes(), |uri| {
+            assert_eq!(uri, "mesh.bin");
+            Ok(builder.bin.clone())
+        })
+        .unwrap()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    fn cross(a: XMFLOAT3, b: XMFLOAT3) -> XMFLOAT3 {
+        XMFLOAT3 {
+            x: a.y * b.z - a.z * b.y,
+            y: a.z * b.x - a.x * b.z,
+            z: a.x * b.y - a.y * b.x,
+        }
+    }
+
+    // A counter-clockwise triangle facing +z, and its normals
+    fn triangle(builder: &mut Builder) -> (usize, usize) {
+        let positions = builder.floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], "VEC3");
+        let normals = builder.floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0], "VEC3");
+        (positions, normals)
+    }
+
+    fn triangle_mesh(builder: &mut Builder) -> String {
+        let (positions, normals) = triangle(builder);
+        format!(
+            r#","meshes":[{{"primitives":[{{"attributes":{{"POSITION":{},"NORMAL":{}}}}}]}}]"#,
+            positions, normals
+        )
+    }
+
+    #[test]
+    fn reads_every_component_type() {
+        let mut b = Builder::default();
+        let (positions, normals) = triangle(&mut b);
+        let scalar = |component: u32| format!(r#""componentType":{},"type":"SCALAR""#, component);
+        let normalized = |component: u32, kind: &str| {
+            format!(
+                r#""componentType":{},"type":"{}","normalized":true"#,
+                component, kind
+            )
+        };
+        let u8_indices = b.accessor(&[0, 1, 2], 3, &scalar(5121));
+        let u16_indices = b.accessor(&[0, 0, 1, 0, 2, 0], 3, &scalar(5123));
+        let u32_bytes: Vec<u8> = [0u32, 1, 2].iter().flat_map(|i| i.to_le_bytes()).collect();
+        let u32_indices = b.accessor(&u32_bytes, 3, &scalar(5125));
+
+        let u8_uvs = b.accessor(&[0, 0, 255, 0, 0, 255], 3, &normalized(5121, "VEC2"));
+        let u16_uv_bytes: Vec<u8> = [0u16, 0, 65535, 0, 0, 65535]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let u16_uvs = b.accessor(&u16_uv_bytes, 3, &normalized(5123, "VEC2"));
+        let f32_uvs = b.floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0], "VEC2");
+
+        let u8_colors = b.accessor(&[255; 12], 3, &normalized(5121, "VEC4"));
+        let u16_colors = b.accessor(&[255; 18], 3, &normalized(5123, "VEC3"));
+        let f32_colors = b.floats(&[0.25, 0.5, 1.0].repeat(3), "VEC3");
+
+        // Quaternion outputs can be normalized signed integers
+        let time = b.floats(&[0.0], "SCALAR");
+        let i8_rotation = b.accessor(&[127, 0, 0, 0], 1, &normalized(5120, "VEC4"));
+        let i16_rotation = b.accessor(&[0, 0, 255, 127, 0, 0, 0, 0], 1, &normalized(5122, "VEC4"));
+
+        let primitive = |indices: usize, uvs: usize, colors: usize| {
+            format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"TEXCOORD_0":{},"COLOR_0":{}}},"indices":{}}}"#,
+                positions, normals, uvs, colors, indices
+            )
+        };
+        let rest = format!(
+            r#","meshes":[{{"primitives":[{},{},{}]}}],"nodes":[{{"mesh":0}}],
+            "animations":[{{"samplers":[{{"input":{},"output":{}}},{{"input":{},"output":{}}}],
+            "channels":[{{"sampler":0,"target":{{"node":0,"path":"rotation"}}}},
+            {{"sampler":1,"target":{{"node":0,"path":"rotation"}}}}]}}]"#,
+            primitive(u8_indices, u8_uvs, u8_colors),
+            primitive(u16_indices, u16_uvs, u16_colors),
+            primitive(u32_indices, f32_uvs, f32_colors),
+            time,
+            i8_rotation,
+            time,
+            i16_rotation
+        );
+        let scene = import(&b, &rest);
+
+        let mesh = &scene.meshes[0];
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), [0, 2, 1].repeat(3));
+        let bases: Vec<i32> = mesh.submeshes.iter().map(|s| s.base_vertex).collect();
+        assert_eq!(bases, [0, 3, 6]);
+        for part in mesh.vertices.chunks(3) {
+            let uvs: Vec<(f32, f32)> = part.iter().map(|v| (v.uv.x, v.uv.y)).collect();
+            assert_eq!(uvs, [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        }
+        let color = |i: usize| {
+            let c = mesh.vertices[i].color;
+            (c.x, c.y, c.z, c.w)
+        };
+        assert_eq!(color(0), (1.0, 1.0, 1.0, 1.0));
+        // Alpha is 1 for RGB colors
+        assert_eq!(color(3), (1.0, 1.0, 1.0, 1.0));
+        assert_eq!(color(6), (0.25, 0.5, 1.0, 1.0));
+
+        // x and y negated by the mirror
+        let channels = &scene.animations[0].channels;
+        let rotation = |c: &Channel| {
+            let v = c.values[0];
+            (v.x, v.y, v.z, v.w)
+        };
+        assert_eq!(rotation(&channels[0]), (-1.0, -0.0, 0.0, 0.0));
+        assert_eq!(rotation(&channels[1]), (-0.0, -1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn applies_sparse_accessors() {
+        let mut b = Builder::default();
+        let (_, normals) = triangle(&mut b);
+        let bounds = r#""min":[0,0,0],"max":[1,1,0]"#;
+
+        // Zeroes from a buffer view, with the last position replaced
+        let base = b.view(&[0; 36]);
+        let indices = b.view(&[2]);
+        let values = b.view(&float_bytes(&[0.0, 1.0, 0.0]));
+        let with_view = b.push_accessor(format!(
+            r#"{{"bufferView":{},"count":3,"componentType":5126,"type":"VEC3",{},
+            "sparse":{{"count":1,"indices":{{"bufferView":{},"componentType":5121}},
+            "values":{{"bufferView":{}}}}}}}"#,
+            base, bounds, indices, values
+        ));
+
+        // No buffer view at all, everything not replaced is zero
+        let indices = b.view(&[1, 0, 2, 0]);
+        let values = b.view(&float_bytes(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0]));
+        let without_view = b.push_accessor(format!(
+            r#"{{"count":3,"componentType":5126,"type":"VEC3",{},
+            "sparse":{{"count":2,"indices":{{"bufferView":{},"componentType":5123}},
+            "values":{{"bufferView":{}}}}}}}"#,
+            bounds, indices, values
+        ));
+
+        let primitive = |positions: usize| {
+            format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{}}}}}"#,
+                positions, normals
+            )
+        };
+        let rest = format!(
+            r#","meshes":[{{"primitives":[{},{}]}}]"#,
+            primitive(with_view),
+            primitive(without_view)
+        );
+        let scene = import(&b, &rest);
+        let positions: Vec<(f32, f32, f32)> = scene.meshes[0]
+            .vertices
+            .iter()
+            .map(|v| (v.pos.x, v.pos.y, v.pos.z))
+            .collect();
+        assert_eq!(
+            positions,
+            [
+                (0.0, 0.0, 0.0),
+                (0.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+                (0.0, 0.0, 0.0),
+                (1.0, 0.0, 0.0),
+                (0.0, 1.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_data_uris_and_external_files() {
+        let mut b = Builder::default();
+        let rest = triangle_mesh(&mut b);
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(&b.bin)
+        );
+        let embedded = GltfScene::from_slice(b.json(Some(&uri), &rest).as_bytes(), |uri| {
+            panic!("read {}", uri)
+        })
+        .unwrap();
+        assert_eq!(embedded.meshes[0].vertices.len(), 3);
+        assert_eq!(embedded.meshes[0].vertices[1].pos.x, 1.0);
+
+        // Relative URIs are percent decoded before they're read
+        let mut read = Vec::new();
+        let external =
+            GltfScene::from_slice(b.json(Some("my%20mesh.bin"), &rest).as_bytes(), |uri| {
+                read.push(uri.to_string());
+                Ok(b.bin.clone())
+            })
+            .unwrap();
+        assert_eq!(read, ["my mesh.bin"]);
+        assert_eq!(external.meshes[0].vertices.len(), 3);
+
+        for bad in [
+            "data:application/octet-stream,plain",
+            "data:application/octet-stream;base64,!!!",
+            "data:application/octet-stream;base64",
+        ] {
+            let result =
+                GltfScene::from_slice(b.json(Some(bad), &rest).as_bytes(), |_| unreachable!());
+            assert_eq!(
+                result.unwrap_err().kind(),
+                ErrorKind::InvalidData,
+                "{}",
+                bad
+            );
+        }
+
+        // Shorter than the buffer says
+        let truncated = b.bin[..b.bin.len() - 4].to_vec();
+        let result = GltfScene::from_slice(b.json(Some("mesh.bin"), &rest).as_bytes(), |_| {
+            Ok(truncated.clone())
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_glb_binary_chunk() {
+        let mut b = Builder::default();
+        let rest = triangle_mesh(&mut b);
+        let png = b"\x89PNG not really";
+        let image = b.view(png);
+        let rest = format!(
+            r#"{},"images":[{{"name":"logo","bufferView":{},"mimeType":"image/png"}}]"#,
+            rest, image
+        );
+        let file = glb(&b.json(None, &rest), Some(&b.bin));
+        let scene = GltfScene::from_slice(&file, |uri| panic!("read {}", uri)).unwrap();
+        assert_eq!(scene.meshes[0].vertices.len(), 3);
+        assert_eq!(scene.meshes[0].vertices[2].pos.y, 1.0);
+        assert_eq!(scene.images[0].name, "logo");
+        assert_eq!(scene.images[0].mime_type.as_deref(), Some("image/png"));
+        assert_eq!(scene.images[0].data, png);
+
+        let missing = glb(&b.json(None, &rest), None);
+        let result = GltfScene::from_slice(&missing, |uri| panic!("read {}", uri));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn mirror_keeps_bitangents() {
+        let mut b = Builder::default();
+        let positions = b.floats(&[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0], "VEC3");
+        let normals = b.floats(&[1.0, 0.0, 0.0].repeat(3), "VEC3");
+        let tangents = b.floats(&[0.0, 0.6, 0.8, 1.0].repeat(3), "VEC4");
+        let rest = format!(
+            r#","meshes":[{{"primitives":[{{"attributes":{{"POSITION":{},"NORMAL":{},"TANGENT":{}}}}}]}}]"#,
+            positions, normals, tangents
+        );
+        let scene = import(&b, &rest);
+
+        let vertex = scene.meshes[0].vertices[0];
+        let tangent = XMFLOAT3 {
+            x: vertex.tangent.x,
+            y: vertex.tangent.y,
+            z: vertex.tangent.z,
+        };
+        assert_eq!((tangent.x, tangent.y, tangent.z), (0.0, 0.6, -0.8));
+        assert_eq!(vertex.tangent.w, -1.0);
+
+        // In glTF the bitangent is cross((1, 0, 0), (0, 0.6, 0.8)) = (0, -0.8,
+        // 0.6), mirrored that's (0, -0.8, -0.6)
+        let bitangent = cross(vertex.normal, tangent);
+        let w = vertex.tangent.w;
+        assert!(close(bitangent.x * w, 0.0));
+        assert!(close(bitangent.y * w, -0.8));
+        assert!(close(bitangent.z * w, -0.6));
+    }
+
+    #[test]
+    fn samples_cubic_splines() {
+        let mut b = Builder::default();
+        let time = b.floats(&[0.0, 2.0], "SCALAR");
+        // In tangent, value, out tangent per key, z mirrored on the way in
+        #[rustfmt::skip]
+        let keys = [
+            0.0, 0.0, 0.0,   0.0, 0.0, 0.0,    0.0, 0.0, -0.5,
+            0.0, 0.0, 0.0,   0.0, 0.0, -1.0,   0.0, 0.0, 0.0,
+        ];
+        let translations = b.floats(&keys, "VEC3");
+        let short = b.floats(&[0.0, 0.0, 0.0, 0.0, 0.0, 1.0], "VEC3");
+        let animation = |output: usize| {
+            format!(
+                r#"{{"samplers":[{{"input":{},"output":{},"interpolation":"CUBICSPLINE"}}],
+                "channels":[{{"sampler":0,"target":{{"node":0,"path":"translation"}}}}]}}"#,
+                time, output
+            )
+        };
+        let rest = format!(
+            r#","nodes":[{{}}],"animations":[{}]"#,
+            animation(translations)
+        );
+        let scene = import(&b, &rest);
+        let channel = &scene.animations[0].channels[0];
+        assert_eq!(channel.interpolation, Interpolation::CubicSpline);
+        assert_eq!(scene.animations[0].duration(), 2.0);
+
+        let z = |time: f32| XMVectorGetZ(channel.sample(time));
+        // Hermite from 0 to 1 with an out tangent of 0.5 per second, which is
+        // 1 over the two second span, and a flat in tangent
+        assert_eq!(z(0.0), 0.0);
+        assert!(close(z(0.5), 0.296875), "{}", z(0.5));
+        assert!(close(z(1.0), 0.625), "{}", z(1.0));
+        assert_eq!(z(2.0), 1.0);
+        // Held outside the keys
+        assert_eq!(z(-1.0), 0.0);
+        assert_eq!(z(5.0), 1.0);
+
+        // Two keys need six values
+        let rest = format!(r#","nodes":[{{}}],"animations":[{}]"#, animation(short));
+        let json = b.json(Some("mesh.bin"), &rest);
+        let result = GltfScene::from_slice(json.as_bytes(), |_| Ok(b.bin.clone()));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,243 @@
+// What to reload when files change: a graph of what depends on what, and a
+// watcher that notices changed files by polling their versions.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::file_system::FileSystem;
+
+// Edges point both ways so either side can be walked. Ordered, so walks come
+// out the same every time.
+pub struct DependencyGraph<K> {
+    dependencies: BTreeMap<K, BTreeSet<K>>,
+    dependents: BTreeMap<K, BTreeSet<K>>,
+}
+
+impl<K> Default for DependencyGraph<K> {
+    fn default() -> Self {
+        DependencyGraph {
+            dependencies: BTreeMap::new(),
+            dependents: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Clone + Ord> DependencyGraph<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, dependent: K, dependency: K) {
+        self.dependents
+            .entry(dependency.clone())
+            .or_default()
+            .insert(dependent.clone());
+        self.dependencies
+            .entry(dependent)
+            .or_default()
+            .insert(dependency);
+    }
+
+    // Replaces everything `dependent` depends on, for when it has been
+    // reloaded and may read different things now
+    pub fn set_dependencies<I: IntoIterator<Item = K>>(&mut self, dependent: &K, dependencies: I) {
+        self.clear_dependencies(dependent);
+        for dependency in dependencies {
+            self.add(dependent.clone(), dependency);
+        }
+    }
+
+    fn clear_dependencies(&mut self, dependent: &K) {
+        for dependency in self.dependencies.remove(dependent).unwrap_or_default() {
+            if let Some(dependents) = self.dependents.get_mut(&dependency) {
+                dependents.remove(dependent);
+                if dependents.is_empty() {
+                    self.dependents.remove(&dependency);
+                }
+            }
+        }
+    }
+
+    // Drops the node and its edges both ways
+    pub fn remove(&mut self, node: &K) {
+        self.clear_dependencies(node);
+        for dependent in self.dependents.remove(node).unwrap_or_default() {
+            if let Some(dependencies) = self.dependencies.get_mut(&dependent) {
+                dependencies.remove(node);
+                if dependencies.is_empty() {
+                    self.dependencies.remove(&dependent);
+                }
+            }
+        }
+    }
+
+    pub fn dependencies(&self, node: &K) -> impl Iterator<Item = &K> {
+        self.dependencies.get(node).into_iter().flatten()
+    }
+
+    pub fn dependents(&self, node: &K) -> impl Iterator<Item = &K> {
+        self.dependents.get(node).into_iter().flatten()
+    }
+
+    pub fn contains(&self, node: &K) -> bool {
+        self.dependencies.contains_key(node) || self.dependents.contains_key(node)
+    }
+
+    // Everything depending on the changed nodes, directly or through others,
+    // each after whatever it depends on among them so it can be rebuilt from
+    // fresh parts. The changed nodes themselves are left out unless they
+    // depend on another one. Cycles are broken somewhere rather than looping.
+    pub fn invalidated<I: IntoIterator<Item = K>>(&self, changed: I) -> Vec<K> {
+        let mut found = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut pending: Vec<K> = changed.into_iter().collect();
+        while let Some(node) = pending.pop() {
+            for dependent in self.dependents(&node) {
+                if seen.insert(dependent.clone()) {
+                    found.push(dependent.clone());
+                    pending.push(dependent.clone());
+                }
+            }
+        }
+
+        let mut ordered = Vec::with_capacity(found.len());
+        let mut done = BTreeSet::new();
+        while ordered.len() < found.len() {
+            let waiting = |node: &K| {
+                self.dependencies(node)
+                    .any(|dependency| seen.contains(dependency) && !done.contains(dependency))
+            };
+            let next = found
+                .iter()
+                .find(|&node| !done.contains(node) && !waiting(node))
+                .or_else(|| found.iter().find(|&node| !done.contains(node)))
+                .unwrap()
+                .clone();
+            done.insert(next.clone());
+            ordered.push(next);
+        }
+        ordered
+    }
+}
+
+// The version each watched file had when it was last read
+#[derive(Default)]
+pub struct FileWatcher {
+    versions: HashMap<PathBuf, Option<u64>>,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A version of None means the file was missing, so creating it counts as
+    // a change
+    pub fn watch(&mut self, path: PathBuf, version: Option<u64>) {
+        self.versions.insert(path, version);
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.versions.remove(path);
+    }
+
+    pub fn is_watching(&self, path: &Path) -> bool {
+        self.versions.contains_key(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    // Files whose version changed since they were watched or last polled
+    pub fn poll(&mut self, files: &dyn FileSystem) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (path, version) in self.versions.iter_mut() {
+            let current = files.version(path);
+            if current != *version {
+                *version = current;
+                changed.push(path.clone());
+            }
+        }
+        changed.sort();
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_system::MemoryFileSystem;
+
+    #[test]
+    fn invalidated_reaches_transitive_dependents() {
+        // b reads a, c is made from b, d from both a and c
+        let mut graph = DependencyGraph::new();
+        graph.add("b", "a");
+        graph.add("c", "b");
+        graph.add("d", "a");
+        graph.add("d", "c");
+        graph.add("x", "y");
+        assert_eq!(graph.invalidated(["a"]), ["b", "c", "d"]);
+        assert_eq!(graph.invalidated(["b"]), ["c", "d"]);
+        assert_eq!(graph.invalidated(["a", "y"]), ["x", "b", "c", "d"]);
+        assert!(graph.invalidated(["d"]).is_empty());
+
+        // Changed nodes come back when they depend on another changed one
+        assert_eq!(graph.invalidated(["a", "b"]), ["b", "c", "d"]);
+    }
+
+    #[test]
+    fn invalidated_breaks_cycles() {
+        let mut graph = DependencyGraph::new();
+        graph.add("a", "b");
+        graph.add("b", "c");
+        graph.add("c", "a");
+        graph.add("d", "c");
+        let mut invalidated = graph.invalidated(["a"]);
+        invalidated.sort_unstable();
+        assert_eq!(invalidated, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn edges_are_replaced_and_removed() {
+        let mut graph = DependencyGraph::new();
+        graph.add("b", "a");
+        graph.add("c", "b");
+        graph.set_dependencies(&"b", ["z"]);
+        assert!(graph.invalidated(["a"]).is_empty());
+        assert_eq!(graph.invalidated(["z"]), ["b", "c"]);
+        assert!(!graph.contains(&"a"));
+
+        graph.remove(&"b");
+        assert!(graph.invalidated(["z"]).is_empty());
+        assert!(!graph.contains(&"c") && !graph.contains(&"z"));
+    }
+
+    #[test]
+    fn watcher_reports_each_change_once() {
+        let files = MemoryFileSystem::new();
+        files.write("a.txt", "a");
+        files.write("b.txt", "b");
+        let mut watcher = FileWatcher::new();
+        for path in ["a.txt", "b.txt", "c.txt"] {
+            watcher.watch(PathBuf::from(path), files.version(Path::new(path)));
+        }
+        assert!(watcher.poll(&files).is_empty());
+
+        files.write("b.txt", "b2");
+        files.write("c.txt", "created");
+        files.remove("a.txt");
+        let changed = watcher.poll(&files);
+        assert_eq!(changed, ["a.txt", "b.txt", "c.txt"].map(PathBuf::from));
+        assert!(watcher.poll(&files).is_empty());
+
+        watcher.unwatch(Path::new("b.txt"));
+        files.write("b.txt", "b3");
+        assert!(watcher.poll(&files).is_empty());
+        assert_eq!(watcher.len(), 2);
+    }
+}
@@ -0,0 +1,305 @@
+// Keyboard and mouse state built from window events. Call begin_frame before
+// feeding the frame's events, then query from the update code.
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::platform::{Event, Key, Modifiers, MouseButton};
+
+// Repeats and duplicate downs/ups don't count as a new press/release
+fn press<T: Copy + Eq + Hash>(held: &mut HashSet<T>, pressed: &mut HashSet<T>, value: T) {
+    if held.insert(value) {
+        pressed.insert(value);
+    }
+}
+
+fn release<T: Copy + Eq + Hash>(held: &mut HashSet<T>, released: &mut HashSet<T>, value: T) {
+    if held.remove(&value) {
+        released.insert(value);
+    }
+}
+
+#[derive(Default)]
+pub struct Input {
+    keys_held: HashSet<Key>,
+    keys_pressed: HashSet<Key>,
+    keys_released: HashSet<Key>,
+
+    buttons_held: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+
+    mouse_position: Option<(i32, i32)>,
+    mouse_delta: (i32, i32),
+    wheel_delta: (f32, f32),
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Forget everything that only lasts one frame
+    pub fn begin_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.mouse_delta = (0, 0);
+        self.wheel_delta = (0.0, 0.0);
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyDown {
+                key, repeat: false, ..
+            } => press(&mut self.keys_held, &mut self.keys_pressed, key),
+            Event::KeyUp { key, .. } => release(&mut self.keys_held, &mut self.keys_released, key),
+            Event::MouseDown { button, x, y } => {
+                self.move_mouse(x, y);
+                press(&mut self.buttons_held, &mut self.buttons_pressed, button);
+            }
+            Event::MouseUp { button, x, y } => {
+                self.move_mouse(x, y);
+                release(&mut self.buttons_held, &mut self.buttons_released, button);
+            }
+            Event::MouseMove { x, y } => self.move_mouse(x, y),
+            Event::MouseWheel { delta_x, delta_y } => {
+                self.wheel_delta.0 += delta_x;
+                self.wheel_delta.1 += delta_y;
+            }
+            // We won't see the key and button ups, so release everything now
+            Event::Focused(false) => {
+                self.keys_released.extend(self.keys_held.drain());
+                self.buttons_released.extend(self.buttons_held.drain());
+            }
+            _ => {}
+        }
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32) {
+        // The first position we see has nothing to be relative to
+        if let Some((old_x, old_y)) = self.mouse_position {
+            self.mouse_delta.0 += x - old_x;
+            self.mouse_delta.1 += y - old_y;
+        }
+        self.mouse_position = Some((x, y));
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.keys_held.contains(&key)
+    }
+
+    // Went down this frame
+    pub fn was_key_pressed(&self, key: Key) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    // Went up this frame
+    pub fn was_key_released(&self, key: Key) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.is_key_down(Key::Shift),
+            control: self.is_key_down(Key::Control),
+            alt: self.is_key_down(Key::Alt),
+        }
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_held.contains(&button)
+    }
+
+    pub fn was_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn was_button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    // Client coordinates, None until the mouse has been over the window
+    pub fn mouse_position(&self) -> Option<(i32, i32)> {
+        self.mouse_position
+    }
+
+    // Movement in pixels this frame
+    pub fn mouse_delta(&self) -> (i32, i32) {
+        self.mouse_delta
+    }
+
+    // Wheel notches this frame, (horizontal, vertical)
+    pub fn wheel_delta(&self) -> (f32, f32) {
+        self.wheel_delta
+    }
+
+    // -1, 0 or 1 depending on which of the two keys are held
+    pub fn key_axis(&self, negative: Key, positive: Key) -> f32 {
+        let mut axis = 0.0;
+        if self.is_key_down(negative) {
+            axis -= 1.0;
+        }
+        if self.is_key_down(positive) {
+            axis += 1.0;
+        }
+        axis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_down(key: Key, repeat: bool) -> Event {
+        Event::KeyDown {
+            key,
+            modifiers: Modifiers::default(),
+            repeat,
+        }
+    }
+
+    fn key_up(key: Key) -> Event {
+        Event::KeyUp {
+            key,
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    fn feed(input: &mut Input, events: &[Event]) {
+        input.begin_frame();
+        for event in events {
+            input.handle_event(event);
+        }
+    }
+
+    #[test]
+    fn presses_and_releases_last_one_frame() {
+        let a = Key::Letter('A');
+        let mut input = Input::new();
+        feed(&mut input, &[key_down(a, false)]);
+        assert!(input.is_key_down(a));
+        assert!(input.was_key_pressed(a));
+        assert!(!input.was_key_released(a));
+
+        feed(&mut input, &[]);
+        assert!(input.is_key_down(a));
+        assert!(!input.was_key_pressed(a));
+
+        feed(&mut input, &[key_up(a)]);
+        assert!(!input.is_key_down(a));
+        assert!(input.was_key_released(a));
+        feed(&mut input, &[]);
+        assert!(!input.was_key_released(a));
+
+        // Down and up within one frame still counts as both
+        feed(&mut input, &[key_down(a, false), key_up(a)]);
+        assert!(input.was_key_pressed(a) && input.was_key_released(a));
+        assert!(!input.is_key_down(a));
+    }
+
+    #[test]
+    fn repeats_and_duplicates_dont_trigger() {
+        let a = Key::Letter('A');
+        let mut input = Input::new();
+        feed(&mut input, &[key_down(a, false)]);
+        feed(&mut input, &[key_down(a, true), key_down(a, false)]);
+        assert!(input.is_key_down(a));
+        assert!(!input.was_key_pressed(a));
+
+        // A repeat alone doesn't press a key that isn't held
+        let b = Key::Letter('B');
+        feed(&mut input, &[key_down(b, true)]);
+        assert!(!input.is_key_down(b) && !input.was_key_pressed(b));
+
+        feed(&mut input, &[key_up(a)]);
+        feed(&mut input, &[key_up(a), key_up(b)]);
+        assert!(!input.was_key_released(a));
+        assert!(!input.was_key_released(b));
+
+        let (x, y) = (10, 20);
+        let left = MouseButton::Left;
+        feed(&mut input, &[Event::MouseDown { button: left, x, y }]);
+        feed(&mut input, &[Event::MouseDown { button: left, x, y }]);
+        assert!(input.is_button_down(left));
+        assert!(!input.was_button_pressed(left));
+        feed(&mut input, &[Event::MouseUp { button: left, x, y }]);
+        assert!(input.was_button_released(left));
+        feed(&mut input, &[Event::MouseUp { button: left, x, y }]);
+        assert!(!input.was_button_released(left));
+    }
+
+    #[test]
+    fn mouse_moves_add_up() {
+        let mut input = Input::new();
+        assert_eq!(input.mouse_position(), None);
+        feed(&mut input, &[Event::MouseMove { x: 100, y: 50 }]);
+        assert_eq!(input.mouse_position(), Some((100, 50)));
+        assert_eq!(input.mouse_delta(), (0, 0));
+
+        feed(
+            &mut input,
+            &[
+                Event::MouseMove { x: 110, y: 45 },
+                Event::MouseDown {
+                    button: MouseButton::Right,
+                    x: 115,
+                    y: 40,
+                },
+                Event::MouseMove { x: 112, y: 60 },
+            ],
+        );
+        assert_eq!(input.mouse_position(), Some((112, 60)));
+        assert_eq!(input.mouse_delta(), (12, 10));
+
+        feed(&mut input, &[]);
+        assert_eq!(input.mouse_delta(), (0, 0));
+        assert_eq!(input.mouse_position(), Some((112, 60)));
+    }
+
+    #[test]
+    fn wheel_deltas_add_up_within_a_frame() {
+        let mut input = Input::new();
+        let wheel = |delta_x, delta_y| Event::MouseWheel { delta_x, delta_y };
+        feed(
+            &mut input,
+            &[wheel(0.0, 1.0), wheel(0.5, 2.0), wheel(0.0, -0.5)],
+        );
+        assert_eq!(input.wheel_delta(), (0.5, 2.5));
+        feed(&mut input, &[]);
+        assert_eq!(input.wheel_delta(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let a = Key::Letter('A');
+        let mut input = Input::new();
+        feed(
+            &mut input,
+            &[
+                key_down(a, false),
+                key_down(Key::Shift, false),
+                Event::MouseDown {
+                    button: MouseButton::Middle,
+                    x: 0,
+                    y: 0,
+                },
+            ],
+        );
+        assert!(input.modifiers().shift);
+
+        feed(&mut input, &[Event::Focused(false)]);
+        for key in [a, Key::Shift] {
+            assert!(!input.is_key_down(key));
+            assert!(input.was_key_released(key));
+        }
+        assert!(!input.is_button_down(MouseButton::Middle));
+        assert!(input.was_button_released(MouseButton::Middle));
+        assert_eq!(input.modifiers(), Modifiers::default());
+
+        // Gaining it back doesn't press anything
+        feed(&mut input, &[Event::Focused(true)]);
+        assert!(!input.was_key_pressed(a));
+        assert!(!input.was_key_released(a));
+    }
+}
@@ -0,0 +1,32 @@
+// Everything apart from the windowed D3D11 renderer in main, so it builds and
+// its tests run on any platform
+pub mod actions;
+pub mod adapter;
+pub mod app;
+pub mod archive;
+pub mod assets;
+pub mod bcn;
+pub mod camera;
+pub mod camera_controller;
+pub mod dds;
+pub mod display;
+pub mod draw;
+pub mod file_system;
+pub mod gamepad;
+pub mod gltf_import;
+pub mod hot_reload;
+pub mod input;
+pub mod material;
+pub mod mesh;
+pub mod obj;
+pub mod pipeline;
+pub mod platform;
+pub mod primitives;
+pub mod recovery;
+pub mod replay;
+pub mod scene_graph;
+pub mod texture;
+pub mod thread_pool;
+pub mod time;
+pub mod transform;
+pub mod vertex;
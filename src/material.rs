@@ -0,0 +1,891 @@
+// Materials: a shader permutation, the textures it samples and the values of
+// its parameters. What a shader expects is read from its source: the fields of
+// its cbMaterial constant buffer and its Texture2D registers. Materials are
+// checked against that before they're used, and their parameters are laid out
+// into the constant buffer the way HLSL packs it.
+//
+// Material files are one setting per line:
+//
+//   # comment
+//   shader = shaders.hlsl
+//   define TEXTURED
+//   color base_color = 0.4 0.8 1.0
+//   float2 uv_scale = 4 4
+//   texture base_color_texture = bricks.png
+//   cull = none
+//   blend = alpha
+//
+// Parameters are `float`, `float2`, `float3`, `float4` or `color`, which is an
+// RGB or RGBA float3 or float4. Defines pick the shader permutation. `cull`,
+// `fill`, `blend` and `depth` set the fixed-function state, see
+// pipeline::RenderState for the values.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::pipeline::{BlendMode, CullMode, DepthStencilDesc, FillMode, RenderState};
+
+// The constant buffer materials fill in, and where it's bound
+pub const MATERIAL_CBUFFER: &str = "cbMaterial";
+pub const MATERIAL_CBUFFER_SLOT: u32 = 2;
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    Float,
+    Float2,
+    Float3,
+    Float4,
+}
+
+impl ParamType {
+    pub fn from_name(name: &str) -> Option<ParamType> {
+        match name {
+            "float" | "float1" => Some(ParamType::Float),
+            "float2" => Some(ParamType::Float2),
+            "float3" => Some(ParamType::Float3),
+            "float4" => Some(ParamType::Float4),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ParamType::Float => "float",
+            ParamType::Float2 => "float2",
+            ParamType::Float3 => "float3",
+            ParamType::Float4 => "float4",
+        }
+    }
+
+    pub fn components(self) -> usize {
+        match self {
+            ParamType::Float => 1,
+            ParamType::Float2 => 2,
+            ParamType::Float3 => 3,
+            ParamType::Float4 => 4,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        self.components() * 4
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamValue {
+    Float(f32),
+    Float2([f32; 2]),
+    Float3([f32; 3]),
+    Float4([f32; 4]),
+    // RGBA, alpha is 1 unless given
+    Color([f32; 4]),
+}
+
+impl ParamValue {
+    pub fn color(r: f32, g: f32, b: f32, a: f32) -> Self {
+        ParamValue::Color([r, g, b, a])
+    }
+
+    // `kind` is a parameter type name or "color"
+    pub fn parse(kind: &str, text: &str) -> Result<ParamValue, Error> {
+        let values = text
+            .split_whitespace()
+            .map(|value| {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| invalid_data(format!("bad number \"{}\"", value)))
+            })
+            .collect::<Result<Vec<f32>, Error>>()?;
+        let expect = |count: usize| {
+            if values.len() == count {
+                Ok(())
+            } else {
+                Err(invalid_data(format!(
+                    "{} needs {} values, got {}",
+                    kind,
+                    count,
+                    values.len()
+                )))
+            }
+        };
+        match kind {
+            "color" => match values[..] {
+                [r, g, b] => Ok(ParamValue::color(r, g, b, 1.0)),
+                [r, g, b, a] => Ok(ParamValue::color(r, g, b, a)),
+                _ => Err(invalid_data(format!(
+                    "color needs 3 or 4 values, got {}",
+                    values.len()
+                ))),
+            },
+            _ => match ParamType::from_name(kind) {
+                Some(ty) => {
+                    expect(ty.components())?;
+                    Ok(ParamValue::from_components(ty, &values))
+                }
+                None => Err(invalid_data(format!("unknown parameter type {}", kind))),
+            },
+        }
+    }
+
+    fn from_components(ty: ParamType, values: &[f32]) -> ParamValue {
+        match ty {
+            ParamType::Float => ParamValue::Float(values[0]),
+            ParamType::Float2 => ParamValue::Float2([values[0], values[1]]),
+            ParamType::Float3 => ParamValue::Float3([values[0], values[1], values[2]]),
+            ParamType::Float4 => ParamValue::Float4([values[0], values[1], values[2], values[3]]),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParamValue::Float(_) => "float",
+            ParamValue::Float2(_) => "float2",
+            ParamValue::Float3(_) => "float3",
+            ParamValue::Float4(_) => "float4",
+            ParamValue::Color(_) => "color",
+        }
+    }
+
+    pub fn components(&self) -> &[f32] {
+        match self {
+            ParamValue::Float(value) => std::slice::from_ref(value),
+            ParamValue::Float2(values) => values,
+            ParamValue::Float3(values) => values,
+            ParamValue::Float4(values) | ParamValue::Color(values) => values,
+        }
+    }
+
+    // Colors go into float3s too, dropping alpha
+    pub fn fits(&self, ty: ParamType) -> bool {
+        match self {
+            ParamValue::Color(_) => ty == ParamType::Float3 || ty == ParamType::Float4,
+            _ => self.components().len() == ty.components(),
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self.components().iter().map(|v| v.to_string()).collect();
+        write!(f, "{}", values.join(" "))
+    }
+}
+
+// A field of the material constant buffer
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderParam {
+    pub name: String,
+    pub ty: ParamType,
+    // Bytes from the start of the buffer
+    pub offset: usize,
+    // From an initializer in the source, zero without one
+    pub default: [f32; 4],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderTexture {
+    pub name: String,
+    pub slot: u32,
+}
+
+// What materials of one shader permutation have to provide
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShaderInterface {
+    pub params: Vec<ShaderParam>,
+    // Of the constant buffer, a multiple of 16. 0 when there's none.
+    pub size: usize,
+    pub textures: Vec<ShaderTexture>,
+}
+
+// Keeps the lines an #ifdef/#ifndef/#else/#endif block leaves in for the
+// defines. Other #if conditions can't be evaluated here and count as true.
+fn preprocess(source: &str, defines: &[String]) -> Result<String, Error> {
+    let mut output = String::with_capacity(source.len());
+    // Whether each open block is taking its lines, and whether its parent is
+    let mut stack: Vec<(bool, bool)> = Vec::new();
+    let active = |stack: &[(bool, bool)]| stack.last().is_none_or(|&(taking, _)| taking);
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        let mut words = trimmed.split_whitespace();
+        let directive = if trimmed.starts_with('#') {
+            words.next()
+        } else {
+            None
+        };
+        let defined =
+            |name: Option<&str>| name.is_some_and(|name| defines.iter().any(|d| d == name));
+        match directive {
+            Some("#ifdef") | Some("#ifndef") | Some("#if") => {
+                let outer = active(&stack);
+                let condition = match directive {
+                    Some("#ifdef") => defined(words.next()),
+                    Some("#ifndef") => !defined(words.next()),
+                    _ => true,
+                };
+                stack.push((outer && condition, outer));
+            }
+            Some("#else") => match stack.last_mut() {
+                Some((taking, outer)) => *taking = *outer && !*taking,
+                None => return Err(invalid_data(String::from("#else without #if"))),
+            },
+            Some("#endif") if stack.pop().is_none() => {
+                return Err(invalid_data(String::from("#endif without #if")));
+            }
+            Some("#endif") => {}
+            _ if active(&stack) => {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+            _ => {}
+        }
+        // Keeps line numbers the same
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut rest = source;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.find('\n').map_or("", |end| &after[end..]);
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.find("*/").map_or("", |end| &after[end + 2..]);
+            output.push(' ');
+        } else {
+            let c = rest.chars().next().unwrap();
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    output
+}
+
+// Numbers in an initializer like `float4(1, 1, 1, 1)`, `{0.5, 0.5}` or `2`
+fn parse_initializer(text: &str, ty: ParamType) -> Result<[f32; 4], Error> {
+    let inner = match text.find('(') {
+        Some(open) => text[open + 1..].trim_end().trim_end_matches(')'),
+        None => text.trim_matches(|c: char| c == '{' || c == '}' || c.is_whitespace()),
+    };
+    let values = inner
+        .split(',')
+        .map(|value| {
+            let value = value.trim().trim_end_matches(['f', 'F']);
+            value
+                .parse::<f32>()
+                .map_err(|_| invalid_data(format!("can't read initializer \"{}\"", text.trim())))
+        })
+        .collect::<Result<Vec<f32>, Error>>()?;
+    let mut default = [0.0; 4];
+    match values.len() {
+        // Scalars fill every component
+        1 => default[..ty.components()].fill(values[0]),
+        n if n == ty.components() => default[..n].copy_from_slice(&values),
+        _ => {
+            return Err(invalid_data(format!(
+                "{} initializer has {} values",
+                ty.name(),
+                values.len()
+            )))
+        }
+    }
+    Ok(default)
+}
+
+impl ShaderInterface {
+    // The cbMaterial fields and Texture2D registers the source has with the
+    // defines set
+    pub fn parse(source: &str, defines: &[String]) -> Result<ShaderInterface, Error> {
+        let source = strip_comments(&preprocess(source, defines)?);
+        let mut interface = ShaderInterface::default();
+        let mut end = 0;
+
+        if let Some(start) = find_word(&source, MATERIAL_CBUFFER) {
+            let open = source[start..]
+                .find('{')
+                .map(|i| start + i)
+                .ok_or_else(|| invalid_data(format!("{} has no body", MATERIAL_CBUFFER)))?;
+            // Initializers can have braces of their own
+            let mut depth = 0;
+            let close = source[open..]
+                .find(|c| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                })
+                .map(|i| open + i)
+                .ok_or_else(|| invalid_data(format!("{} isn't closed", MATERIAL_CBUFFER)))?;
+            for declaration in source[open + 1..close].split(';') {
+                let declaration = declaration.trim();
+                if declaration.is_empty() {
+                    continue;
+                }
+                let (declaration, initializer) = match declaration.find('=') {
+                    Some(equals) => (&declaration[..equals], Some(&declaration[equals + 1..])),
+                    None => (declaration, None),
+                };
+                if declaration.contains(':') || declaration.contains('[') {
+                    return Err(invalid_data(format!(
+                        "{}: packoffset and arrays aren't supported",
+                        declaration.trim()
+                    )));
+                }
+                let words: Vec<&str> = declaration.split_whitespace().collect();
+                let (type_name, name) = match words[..] {
+                    [type_name, name] => (type_name, name),
+                    _ => {
+                        return Err(invalid_data(format!(
+                            "can't read \"{}\" in {}",
+                            declaration.trim(),
+                            MATERIAL_CBUFFER
+                        )))
+                    }
+                };
+                let ty = ParamType::from_name(type_name).ok_or_else(|| {
+                    invalid_data(format!("{} has unsupported type {}", name, type_name))
+                })?;
+                // A field can't straddle a 16 byte register
+                let mut offset = end;
+                if offset / 16 != (offset + ty.size() - 1) / 16 {
+                    offset = offset.next_multiple_of(16);
+                }
+                end = offset + ty.size();
+                interface.params.push(ShaderParam {
+                    name: String::from(name),
+                    ty,
+                    offset,
+                    default: match initializer {
+                        Some(text) => parse_initializer(text, ty)
+                            .map_err(|e| invalid_data(format!("{}: {}", name, e)))?,
+                        None => [0.0; 4],
+                    },
+                });
+            }
+        }
+        interface.size = end.next_multiple_of(16);
+
+        // Texture2D name : register(tN);
+        let mut rest = source.as_str();
+        while let Some(start) = find_word(rest, "Texture2D") {
+            let statement = rest[start + "Texture2D".len()..].trim_start();
+            let statement = &statement[..statement.find(';').unwrap_or(statement.len())];
+            // Texture2D<float4> name
+            let statement = match statement.strip_prefix('<') {
+                Some(templated) => &templated[templated.find('>').map_or(0, |i| i + 1)..],
+                None => statement,
+            };
+            let (name, register) = match statement.find(':') {
+                Some(colon) => (statement[..colon].trim(), &statement[colon + 1..]),
+                None => (statement.trim(), ""),
+            };
+            let slot = register
+                .trim()
+                .strip_prefix("register")
+                .map(|r| {
+                    r.trim()
+                        .trim_start_matches('(')
+                        .trim_end_matches(')')
+                        .trim()
+                })
+                .and_then(|r| r.strip_prefix('t'))
+                .and_then(|slot| slot.parse::<u32>().ok())
+                .ok_or_else(|| invalid_data(format!("Texture {} needs a register(tN)", name)))?;
+            interface.textures.push(ShaderTexture {
+                name: String::from(name),
+                slot,
+            });
+            rest = &rest[start + "Texture2D".len()..];
+        }
+        Ok(interface)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&ShaderParam> {
+        self.params.iter().find(|param| param.name == name)
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&ShaderTexture> {
+        self.textures.iter().find(|texture| texture.name == name)
+    }
+}
+
+// Where `word` first appears on its own rather than inside another name
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    let mut from = 0;
+    while let Some(i) = text[from..].find(word) {
+        let start = from + i;
+        let end = start + word.len();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        if !before.is_some_and(is_name) && !after.is_some_and(is_name) {
+            return Some(start);
+        }
+        from = end;
+    }
+    None
+}
+
+// A shader file compiled with a set of defines
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderPermutation {
+    pub shader: PathBuf,
+    // Sorted, without duplicates, so equal sets compare equal
+    pub defines: Vec<String>,
+}
+
+impl ShaderPermutation {
+    pub fn new<P: AsRef<Path>>(shader: P, defines: &[&str]) -> Self {
+        let defines: BTreeSet<String> = defines.iter().map(|d| d.to_string()).collect();
+        ShaderPermutation {
+            shader: shader.as_ref().to_path_buf(),
+            defines: defines.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for ShaderPermutation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.shader.display())?;
+        if !self.defines.is_empty() {
+            write!(f, " [{}]", self.defines.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+// A material as written in a file, before its textures are loaded
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialDesc {
+    pub permutation: ShaderPermutation,
+    pub params: BTreeMap<String, ParamValue>,
+    // Texture name in the shader to the texture's path
+    pub textures: BTreeMap<String, String>,
+    pub state: RenderState,
+}
+
+impl MaterialDesc {
+    pub fn new<P: AsRef<Path>>(shader: P) -> Self {
+        MaterialDesc {
+            permutation: ShaderPermutation::new(shader, &[]),
+            params: BTreeMap::new(),
+            textures: BTreeMap::new(),
+            state: RenderState::default(),
+        }
+    }
+
+    pub fn with_define(mut self, define: &str) -> Self {
+        let mut defines: Vec<&str> = self
+            .permutation
+            .defines
+            .iter()
+            .map(|d| d.as_str())
+            .collect();
+        defines.push(define);
+        self.permutation = ShaderPermutation::new(&self.permutation.shader, &defines);
+        self
+    }
+
+    pub fn with_param(mut self, name: &str, value: ParamValue) -> Self {
+        self.params.insert(String::from(name), value);
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str, path: &str) -> Self {
+        self.textures.insert(String::from(name), String::from(path));
+        self
+    }
+
+    pub fn with_state(mut self, state: RenderState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn parse(text: &str) -> Result<MaterialDesc, Error> {
+        let mut shader = None;
+        let mut defines = Vec::new();
+        let mut params = BTreeMap::new();
+        let mut textures = BTreeMap::new();
+        let mut state = RenderState::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| invalid_data(format!("Line {}: {}", number + 1, message));
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words[0] == "define" {
+                match words[..] {
+                    [_, define] => defines.push(define),
+                    _ => return Err(error("expected one define name")),
+                }
+                continue;
+            }
+            let equals = line.find('=').ok_or_else(|| error("expected '='"))?;
+            let value = line[equals + 1..].trim();
+            if value.is_empty() {
+                return Err(error("missing value"));
+            }
+            let words: Vec<&str> = line[..equals].split_whitespace().collect();
+            match words[..] {
+                ["shader"] => shader = Some(value),
+                ["cull"] => {
+                    state.rasterizer.cull =
+                        CullMode::from_name(value).ok_or_else(|| error("unknown cull mode"))?
+                }
+                ["fill"] => {
+                    state.rasterizer.fill =
+                        FillMode::from_name(value).ok_or_else(|| error("unknown fill mode"))?
+                }
+                ["blend"] => {
+                    state.blend =
+                        BlendMode::from_name(value).ok_or_else(|| error("unknown blend mode"))?
+                }
+                ["depth"] => {
+                    state.depth_stencil = DepthStencilDesc::from_name(value)
+                        .ok_or_else(|| error("unknown depth mode"))?
+                }
+                ["texture", name] => {
+                    textures.insert(String::from(name), String::from(value));
+                }
+                [kind, name] => {
+                    let value =
+                        ParamValue::parse(kind, value).map_err(|e| error(&e.to_string()))?;
+                    params.insert(String::from(name), value);
+                }
+                _ => return Err(error("expected a type and a name before '='")),
+            }
+        }
+        let shader = shader.ok_or_else(|| invalid_data(String::from("No shader given")))?;
+        Ok(MaterialDesc {
+            permutation: ShaderPermutation::new(shader, &defines),
+            params,
+            textures,
+            state,
+        })
+    }
+
+    // Every problem at once, so a material file can be fixed in one go
+    pub fn validate(&self, interface: &ShaderInterface) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        for (name, value) in &self.params {
+            match interface.param(name) {
+                None => problems.push(format!("{} has no parameter {}", self.permutation, name)),
+                Some(param) if !value.fits(param.ty) => problems.push(format!(
+                    "{} is a {}, not a {}",
+                    name,
+                    param.ty.name(),
+                    value.kind()
+                )),
+                Some(_) => {}
+            }
+        }
+        for name in self.textures.keys() {
+            if interface.texture(name).is_none() {
+                problems.push(format!("{} has no texture {}", self.permutation, name));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_data(problems.join(", ")))
+        }
+    }
+
+    // The material constant buffer's contents. Parameters the material
+    // doesn't set keep the shader's defaults.
+    pub fn constants(&self, interface: &ShaderInterface) -> Result<Vec<u8>, Error> {
+        self.validate(interface)?;
+        let mut bytes = vec![0; interface.size];
+        for param in &interface.params {
+            let components = param.ty.components();
+            let values = match self.params.get(&param.name) {
+                Some(value) => &value.components()[..components],
+                None => &param.default[..components],
+            };
+            for (i, value) in values.iter().enumerate() {
+                let at = param.offset + i * 4;
+                bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for MaterialDesc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "shader = {}", self.permutation.shader.display())?;
+        for define in &self.permutation.defines {
+            writeln!(f, "define {}", define)?;
+        }
+        for (name, value) in &self.params {
+            writeln!(f, "{} {} = {}", value.kind(), name, value)?;
+        }
+        for (name, path) in &self.textures {
+            writeln!(f, "texture {} = {}", name, path)?;
+        }
+        // Only what differs from the defaults
+        let defaults = RenderState::default();
+        if self.state.rasterizer.cull != defaults.rasterizer.cull {
+            writeln!(f, "cull = {}", self.state.rasterizer.cull.name())?;
+        }
+        if self.state.rasterizer.fill != defaults.rasterizer.fill {
+            writeln!(f, "fill = {}", self.state.rasterizer.fill.name())?;
+        }
+        if self.state.blend != defaults.blend {
+            writeln!(f, "blend = {}", self.state.blend.name())?;
+        }
+        if self.state.depth_stencil != defaults.depth_stencil {
+            writeln!(f, "depth = {}", self.state.depth_stencil.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "\
+cbuffer cbMaterial : register(b2)
+{
+    float roughness;
+    float3 base_color = float3(1, 0.5, 0.25);
+    float2 uv_scale = {2, 2};
+    float3 emissive; // after a float2, so it can't start at 24
+    float metallic = 0.5f;
+    float4 tint = 1;
+#ifdef TEXTURED
+    float2 uv_offset;
+#endif
+};
+
+Texture2D base_color_texture : register(t0);
+#ifdef NORMAL_MAP
+Texture2D<float4> normal_texture : register(t1);
+#else
+/* Texture2D unused : register(t5); */
+#endif
+";
+
+    fn interface(defines: &[&str]) -> ShaderInterface {
+        let defines: Vec<String> = defines.iter().map(|d| d.to_string()).collect();
+        ShaderInterface::parse(SHADER, &defines).unwrap()
+    }
+
+    fn offsets(interface: &ShaderInterface) -> Vec<(&str, usize)> {
+        interface
+            .params
+            .iter()
+            .map(|param| (param.name.as_str(), param.offset))
+            .collect()
+    }
+
+    #[test]
+    fn cbuffer_packing_respects_registers() {
+        let plain = interface(&[]);
+        assert_eq!(
+            offsets(&plain),
+            [
+                ("roughness", 0),
+                ("base_color", 4),
+                ("uv_scale", 16),
+                ("emissive", 32),
+                ("metallic", 44),
+                ("tint", 48),
+            ]
+        );
+        assert_eq!(plain.size, 64);
+        assert_eq!(
+            plain.param("base_color").unwrap().default,
+            [1.0, 0.5, 0.25, 0.0]
+        );
+        assert_eq!(
+            plain.param("uv_scale").unwrap().default,
+            [2.0, 2.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            plain.param("metallic").unwrap().default,
+            [0.5, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(plain.param("tint").unwrap().default, [1.0; 4]);
+
+        // A float2 after a float4 starts a register, the size rounds up to one
+        let textured = interface(&["TEXTURED"]);
+        assert_eq!(textured.param("uv_offset").unwrap().offset, 64);
+        assert_eq!(textured.size, 80);
+
+        let empty = ShaderInterface::parse("float4 main() : SV_Target { return 0; }", &[]);
+        assert_eq!(empty.unwrap(), ShaderInterface::default());
+        let unsupported = "cbuffer cbMaterial { float4x4 transform; };";
+        assert!(ShaderInterface::parse(unsupported, &[]).is_err());
+        let array = "cbuffer cbMaterial { float weights[4]; };";
+        assert!(ShaderInterface::parse(array, &[]).is_err());
+    }
+
+    #[test]
+    fn permutations_pick_textures() {
+        let textures = |defines: &[&str]| -> Vec<(String, u32)> {
+            interface(defines)
+                .textures
+                .into_iter()
+                .map(|texture| (texture.name, texture.slot))
+                .collect()
+        };
+        assert_eq!(textures(&[]), [(String::from("base_color_texture"), 0)]);
+        assert_eq!(
+            textures(&["NORMAL_MAP"]),
+            [
+                (String::from("base_color_texture"), 0),
+                (String::from("normal_texture"), 1)
+            ]
+        );
+        let missing_register = "Texture2D albedo;";
+        assert!(ShaderInterface::parse(missing_register, &[]).is_err());
+    }
+
+    #[test]
+    fn preprocess_keeps_active_lines() {
+        let source = "\
+a
+#ifdef A
+b
+#ifndef B
+c
+#else
+d
+#endif
+#else
+e
+#ifdef B
+f
+#endif
+#endif
+#if SOMETHING
+g
+#endif
+";
+        let kept = |defines: &[&str]| -> Vec<String> {
+            let defines: Vec<String> = defines.iter().map(|d| d.to_string()).collect();
+            let output = preprocess(source, &defines).unwrap();
+            // Line numbers stay the same
+            assert_eq!(output.lines().count(), source.lines().count());
+            output.split_whitespace().map(String::from).collect()
+        };
+        assert_eq!(kept(&[]), ["a", "e", "g"]);
+        assert_eq!(kept(&["A"]), ["a", "b", "c", "g"]);
+        assert_eq!(kept(&["A", "B"]), ["a", "b", "d", "g"]);
+        // An inactive block's #else doesn't turn its nested lines on
+        assert_eq!(kept(&["B"]), ["a", "e", "f", "g"]);
+
+        for unbalanced in ["#else\n", "#endif\n", "#ifdef A\n#endif\n#endif\n"] {
+            let error = preprocess(unbalanced, &[]).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let normal_mapped = interface(&["NORMAL_MAP"]);
+        let interface = interface(&[]);
+        let good = MaterialDesc::new("shader.hlsl")
+            .with_param("roughness", ParamValue::Float(0.25))
+            .with_param("base_color", ParamValue::color(1.0, 0.0, 0.0, 0.5))
+            .with_param("tint", ParamValue::color(1.0, 1.0, 1.0, 0.5))
+            .with_texture("base_color_texture", "bricks.png");
+        good.validate(&interface).unwrap();
+
+        let missing = good
+            .clone()
+            .with_param("glow", ParamValue::Float(1.0))
+            .validate(&interface)
+            .unwrap_err();
+        assert_eq!(missing.kind(), ErrorKind::InvalidData);
+        assert!(
+            missing.to_string().contains("no parameter glow"),
+            "{}",
+            missing
+        );
+
+        let mismatched = good
+            .clone()
+            .with_param("uv_scale", ParamValue::color(1.0, 1.0, 1.0, 1.0))
+            .validate(&interface)
+            .unwrap_err();
+        assert!(
+            mismatched
+                .to_string()
+                .contains("uv_scale is a float2, not a color"),
+            "{}",
+            mismatched
+        );
+
+        let unknown = good
+            .clone()
+            .with_texture("normal_texture", "normals.png")
+            .validate(&interface)
+            .unwrap_err();
+        assert!(
+            unknown.to_string().contains("no texture normal_texture"),
+            "{}",
+            unknown
+        );
+        // The permutation that has it takes it
+        good.clone()
+            .with_define("NORMAL_MAP")
+            .with_texture("normal_texture", "normals.png")
+            .validate(&normal_mapped)
+            .unwrap();
+
+        let all = good
+            .with_param("glow", ParamValue::Float(1.0))
+            .with_param("metallic", ParamValue::Float2([0.0, 1.0]))
+            .with_texture("normal_texture", "normals.png")
+            .validate(&interface)
+            .unwrap_err();
+        for problem in [
+            "no parameter glow",
+            "metallic is a float, not a float2",
+            "no texture normal_texture",
+        ] {
+            assert!(all.to_string().contains(problem), "{}", all);
+        }
+    }
+
+    #[test]
+    fn constants_fill_in_defaults() {
+        let interface = interface(&[]);
+        let bytes = MaterialDesc::new("shader.hlsl")
+            .with_param("roughness", ParamValue::Float(0.25))
+            .with_param("base_color", ParamValue::color(0.1, 0.2, 0.3, 0.5))
+            .constants(&interface)
+            .unwrap();
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        #[rustfmt::skip]
+        assert_eq!(
+            floats,
+            [
+                0.25, 0.1, 0.2, 0.3,
+                2.0, 2.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.5,
+                1.0, 1.0, 1.0, 1.0,
+            ]
+        );
+
+        let bad = MaterialDesc::new("shader.hlsl").with_param("tint", ParamValue::Float(1.0));
+        assert!(bad.constants(&interface).is_err());
+    }
+}
@@ -0,0 +1,601 @@
+// CPU side mesh: vertices, indices in the smallest format that fits, the
+// primitive topology and ranges of indices (submeshes) that can be drawn on
+// their own, e.g. with different materials. GpuMesh uploads one to D3D11.
+use std::io::{Error, ErrorKind};
+
+use directx_math::{
+    XMLoadFloat3, XMStoreFloat4, XMVector3Cross, XMVector3Dot, XMVector3LengthSq,
+    XMVector3Normalize, XMVectorAdd, XMVectorGetX, XMVectorScale, XMVectorSet, XMVectorSetW,
+    XMVectorSubtract, XMVectorZero, XMFLOAT3, XMVECTOR,
+};
+
+use crate::vertex::Vertex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+impl IndexFormat {
+    pub fn size(self) -> usize {
+        match self {
+            IndexFormat::U16 => 2,
+            IndexFormat::U32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    // 16 bit when every index fits, halves the index buffer for small meshes.
+    // 0xFFFF is left out, strips read it as a cut.
+    pub fn from_u32(indices: Vec<u32>) -> Self {
+        if indices.iter().all(|&i| i < u16::MAX as u32) {
+            Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            Indices::U16(_) => IndexFormat::U16,
+            Indices::U32(_) => IndexFormat::U32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<u32> {
+        match self {
+            Indices::U16(indices) => indices.get(i).map(|&i| i as u32),
+            Indices::U32(indices) => indices.get(i).copied(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.len()).map(move |i| self.get(i).unwrap())
+    }
+
+    // Raw bytes for the index buffer
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            match self {
+                Indices::U16(indices) => {
+                    std::slice::from_raw_parts(indices.as_ptr() as *const u8, indices.len() * 2)
+                }
+                Indices::U32(indices) => {
+                    std::slice::from_raw_parts(indices.as_ptr() as *const u8, indices.len() * 4)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Topology {
+    PointList,
+    LineList,
+    LineStrip,
+    #[default]
+    TriangleList,
+    TriangleStrip,
+}
+
+impl Topology {
+    // D3D_PRIMITIVE_TOPOLOGY value
+    pub fn to_raw(self) -> u32 {
+        match self {
+            Topology::PointList => 1,
+            Topology::LineList => 2,
+            Topology::LineStrip => 3,
+            Topology::TriangleList => 4,
+            Topology::TriangleStrip => 5,
+        }
+    }
+
+    // Lists need whole primitives, strips take any count
+    pub fn indices_per_primitive(self) -> u32 {
+        match self {
+            Topology::LineList => 2,
+            Topology::TriangleList => 3,
+            _ => 1,
+        }
+    }
+}
+
+// A range of the index buffer drawn with one draw call
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Submesh {
+    pub name: String,
+    pub index_start: u32,
+    pub index_count: u32,
+    // Added to every index of the range before fetching the vertex
+    pub base_vertex: i32,
+    // Which of the mesh's materials the range uses
+    pub material: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Indices,
+    pub topology: Topology,
+    pub submeshes: Vec<Submesh>,
+}
+
+impl Mesh {
+    // Triangle list with a single submesh covering all indices
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        let indices = Indices::from_u32(indices);
+        let submeshes = vec![Submesh {
+            name: String::new(),
+            index_start: 0,
+            index_count: indices.len() as u32,
+            base_vertex: 0,
+            material: 0,
+        }];
+        Self {
+            vertices,
+            indices,
+            topology: Topology::TriangleList,
+            submeshes,
+        }
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    // Replaces the default submesh covering everything
+    pub fn with_submeshes(mut self, submeshes: Vec<Submesh>) -> Self {
+        self.submeshes = submeshes;
+        self
+    }
+
+    pub fn index_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    // Checks that every submesh lies inside the index buffer and only refers
+    // to existing vertices. Bad indices would otherwise only show up as
+    // garbage on screen, or a removed device.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        if self.vertices.is_empty() || self.indices.is_empty() {
+            return Err(invalid(String::from("Mesh is empty")));
+        }
+
+        for submesh in &self.submeshes {
+            let start = submesh.index_start as usize;
+            let end = start + submesh.index_count as usize;
+            if end > self.indices.len() {
+                return Err(invalid(format!(
+                    "Submesh \"{}\" indices {}..{} past the end of {} indices",
+                    submesh.name,
+                    start,
+                    end,
+                    self.indices.len()
+                )));
+            }
+            if submesh.index_count % self.topology.indices_per_primitive() != 0 {
+                return Err(invalid(format!(
+                    "Submesh \"{}\" has {} indices, not a whole number of {:?} primitives",
+                    submesh.name, submesh.index_count, self.topology
+                )));
+            }
+            for i in start..end {
+                let vertex = self.indices.get(i).unwrap() as i64 + submesh.base_vertex as i64;
+                if vertex < 0 || vertex >= self.vertices.len() as i64 {
+                    return Err(invalid(format!(
+                        "Submesh \"{}\" refers to vertex {} of {}",
+                        submesh.name,
+                        vertex,
+                        self.vertices.len()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Smallest and largest x, y and z of the vertices
+    pub fn bounds(&self) -> Option<(XMFLOAT3, XMFLOAT3)> {
+        let first = self.vertices.first()?.pos;
+        Some(self.vertices.iter().fold((first, first), |(min, max), v| {
+            let p = v.pos;
+            (
+                XMFLOAT3 {
+                    x: min.x.min(p.x),
+                    y: min.y.min(p.y),
+                    z: min.z.min(p.z),
+                },
+                XMFLOAT3 {
+                    x: max.x.max(p.x),
+                    y: max.y.max(p.y),
+                    z: max.z.max(p.z),
+                },
+            )
+        }))
+    }
+
+    // Moves and scales the vertices so the bounding box is centered on the
+    // origin and its longest side is `size` long. Handy for models of
+    // unknown scale.
+    pub fn fit_to_size(&mut self, size: f32) {
+        let (min, max) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let longest = (max.x - min.x).max(max.y - min.y).max(max.z - min.z);
+        let scale = if longest > 0.0 { size / longest } else { 1.0 };
+        let center = (
+            (min.x + max.x) / 2.0,
+            (min.y + max.y) / 2.0,
+            (min.z + max.z) / 2.0,
+        );
+        for vertex in self.vertices.iter_mut() {
+            vertex.pos.x = (vertex.pos.x - center.0) * scale;
+            vertex.pos.y = (vertex.pos.y - center.1) * scale;
+            vertex.pos.z = (vertex.pos.z - center.2) * scale;
+        }
+    }
+
+    // Vertex indices of every triangle the submeshes draw, base_vertex
+    // applied. Triangle lists only.
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::new();
+        if self.topology != Topology::TriangleList {
+            return triangles;
+        }
+        for submesh in &self.submeshes {
+            let start = submesh.index_start as usize;
+            let end = (start + submesh.index_count as usize).min(self.indices.len());
+            let corners: Vec<usize> = (start..end)
+                .map(|i| {
+                    (self.indices.get(i).unwrap() as i64 + submesh.base_vertex as i64) as usize
+                })
+                .collect();
+            triangles.extend(corners.chunks_exact(3).map(|t| [t[0], t[1], t[2]]));
+        }
+        triangles
+    }
+
+    // Fills in Vertex::tangent from the positions, normals and UVs of the
+    // triangles around each vertex. Vertices without usable UVs get some
+    // tangent perpendicular to the normal. Triangle lists only.
+    pub fn compute_tangents(&mut self) {
+        if self.topology != Topology::TriangleList {
+            return;
+        }
+        let zero = XMVectorZero();
+        let mut tangents = vec![zero; self.vertices.len()];
+        let mut bitangents = vec![zero; self.vertices.len()];
+
+        for triangle in self.triangles() {
+            let [a, b, c] = triangle;
+            if a.max(b).max(c) >= self.vertices.len() {
+                continue;
+            }
+            let (va, vb, vc) = (&self.vertices[a], &self.vertices[b], &self.vertices[c]);
+            let e1 = XMVectorSubtract(XMLoadFloat3(&vb.pos), XMLoadFloat3(&va.pos));
+            let e2 = XMVectorSubtract(XMLoadFloat3(&vc.pos), XMLoadFloat3(&va.pos));
+            let (du1, dv1) = (vb.uv.x - va.uv.x, vb.uv.y - va.uv.y);
+            let (du2, dv2) = (vc.uv.x - va.uv.x, vc.uv.y - va.uv.y);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let r = 1.0 / det;
+            let t = XMVectorScale(
+                XMVectorSubtract(XMVectorScale(e1, dv2), XMVectorScale(e2, dv1)),
+                r,
+            );
+            let b = XMVectorScale(
+                XMVectorSubtract(XMVectorScale(e2, du1), XMVectorScale(e1, du2)),
+                r,
+            );
+            for i in triangle {
+                tangents[i] = XMVectorAdd(tangents[i], t);
+                bitangents[i] = XMVectorAdd(bitangents[i], b);
+            }
+        }
+
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let n = XMVector3Normalize(XMLoadFloat3(&vertex.normal));
+            // Gram-Schmidt, so the tangent ends up perpendicular to the normal
+            let mut t = XMVectorSubtract(tangents[i], XMVectorScale(n, dot3(n, tangents[i])));
+            if XMVectorGetX(XMVector3LengthSq(t)) < 1e-12 {
+                t = any_perpendicular(n);
+            }
+            let t = XMVector3Normalize(t);
+            let w = if dot3(XMVector3Cross(n, t), bitangents[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            XMStoreFloat4(&mut vertex.tangent, XMVectorSetW(t, w));
+        }
+    }
+}
+
+fn dot3(a: XMVECTOR, b: XMVECTOR) -> f32 {
+    XMVectorGetX(XMVector3Dot(a, b))
+}
+
+// Crossed with whichever axis is least parallel to `n`
+fn any_perpendicular(n: XMVECTOR) -> XMVECTOR {
+    let axis = if XMVectorGetX(n).abs() < 0.9 {
+        XMVectorSet(1.0, 0.0, 0.0, 0.0)
+    } else {
+        XMVectorSet(0.0, 1.0, 0.0, 0.0)
+    };
+    XMVector3Cross(n, axis)
+}
+
+#[cfg(windows)]
+pub use self::win32::GpuMesh;
+
+#[cfg(windows)]
+mod win32 {
+    use std::mem;
+    use std::ptr::null_mut;
+
+    use winapi::shared::dxgiformat::{DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32_UINT};
+    use winapi::shared::winerror::FAILED;
+    use winapi::um::d3d11::*;
+    use winapi::um::winnt::HRESULT;
+
+    use super::{IndexFormat, Mesh, Submesh};
+    use crate::vertex::Vertex;
+
+    // Immutable vertex and index buffers for a Mesh
+    pub struct GpuMesh {
+        vertex_buffer: *mut ID3D11Buffer,
+        index_buffer: *mut ID3D11Buffer,
+        index_format: IndexFormat,
+        submeshes: Vec<Submesh>,
+    }
+
+    impl GpuMesh {
+        pub unsafe fn new(device: &ID3D11Device, mesh: &Mesh) -> Result<Self, HRESULT> {
+            let mut gpu_mesh = Self {
+                vertex_buffer: null_mut(),
+                index_buffer: null_mut(),
+                index_format: mesh.indices.format(),
+                submeshes: mesh.submeshes.clone(),
+            };
+
+            let vertex_bytes = mem::size_of::<Vertex>() * mesh.vertices.len();
+            gpu_mesh.vertex_buffer = create_buffer(
+                device,
+                mesh.vertices.as_ptr() as _,
+                vertex_bytes,
+                D3D11_BIND_VERTEX_BUFFER,
+            )?;
+            let index_bytes = mesh.indices.as_bytes();
+            gpu_mesh.index_buffer = create_buffer(
+                device,
+                index_bytes.as_ptr() as _,
+                index_bytes.len(),
+                D3D11_BIND_INDEX_BUFFER,
+            )?;
+            Ok(gpu_mesh)
+        }
+
+        pub fn submeshes(&self) -> &[Submesh] {
+            &self.submeshes
+        }
+
+        // Buffers, once before drawing any of the submeshes. The topology is
+        // part of the pipeline.
+        pub unsafe fn bind(&self, context: &ID3D11DeviceContext) {
+            let stride = mem::size_of::<Vertex>() as u32;
+            let offset = 0;
+            context.IASetVertexBuffers(0, 1, &self.vertex_buffer, &stride, &offset);
+            let format = match self.index_format {
+                IndexFormat::U16 => DXGI_FORMAT_R16_UINT,
+                IndexFormat::U32 => DXGI_FORMAT_R32_UINT,
+            };
+            context.IASetIndexBuffer(self.index_buffer, format, 0);
+        }
+
+        pub unsafe fn draw_submesh(&self, context: &ID3D11DeviceContext, index: usize) {
+            let submesh = &self.submeshes[index];
+            context.DrawIndexed(
+                submesh.index_count,
+                submesh.index_start,
+                submesh.base_vertex,
+            );
+        }
+    }
+
+    unsafe fn create_buffer(
+        device: &ID3D11Device,
+        data: *const std::ffi::c_void,
+        size: usize,
+        bind_flags: D3D11_BIND_FLAG,
+    ) -> Result<*mut ID3D11Buffer, HRESULT> {
+        let desc = D3D11_BUFFER_DESC {
+            Usage: D3D11_USAGE_IMMUTABLE,
+            ByteWidth: size as u32,
+            BindFlags: bind_flags,
+            CPUAccessFlags: 0,
+            MiscFlags: 0,
+            StructureByteStride: 0,
+        };
+        let init_data = D3D11_SUBRESOURCE_DATA {
+            pSysMem: data as _,
+            SysMemPitch: 0,
+            SysMemSlicePitch: 0,
+        };
+        let mut buffer = null_mut();
+        let res = device.CreateBuffer(&desc, &init_data, &mut buffer);
+        if FAILED(res) {
+            return Err(res);
+        }
+        Ok(buffer)
+    }
+
+    impl Drop for GpuMesh {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some(buffer) = self.index_buffer.as_ref() {
+                    buffer.Release();
+                }
+                if let Some(buffer) = self.vertex_buffer.as_ref() {
+                    buffer.Release();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        Vertex::new((x, y, 0.0), (0.0, 0.0, -1.0), (u, v))
+    }
+
+    // Facing -z, u going right and v going down like the texture
+    fn quad(uvs: [(f32, f32); 4]) -> Mesh {
+        let corners = [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)];
+        let vertices = corners
+            .iter()
+            .zip(uvs)
+            .map(|(&(x, y), (u, v))| vertex(x, y, u, v))
+            .collect();
+        Mesh::new(vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    fn tangent(mesh: &Mesh, i: usize) -> (f32, f32, f32, f32) {
+        let t = mesh.vertices[i].tangent;
+        (t.x, t.y, t.z, t.w)
+    }
+
+    #[test]
+    fn indices_narrow_only_when_all_fit() {
+        let small = Indices::from_u32(vec![0, 1, u16::MAX as u32 - 1]);
+        assert_eq!(small.format(), IndexFormat::U16);
+        assert_eq!(small.as_bytes().len(), 6);
+        assert_eq!(small.iter().collect::<Vec<_>>(), [0, 1, 65534]);
+
+        // 0xFFFF would cut a strip
+        let cut = Indices::from_u32(vec![0, 1, u16::MAX as u32]);
+        assert_eq!(cut.format(), IndexFormat::U32);
+        assert_eq!(cut.iter().collect::<Vec<_>>(), [0, 1, 65535]);
+
+        let large = Indices::from_u32(vec![0, 1, u16::MAX as u32 + 1]);
+        assert_eq!(large.format(), IndexFormat::U32);
+        assert_eq!(large.as_bytes().len(), 12);
+        assert_eq!(large.get(2), Some(65536));
+        assert_eq!(large.get(3), None);
+
+        assert_eq!(Indices::from_u32(Vec::new()).format(), IndexFormat::U16);
+        assert_eq!(IndexFormat::U16.size(), 2);
+        assert_eq!(IndexFormat::U32.size(), 4);
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_indices() {
+        let uvs = [(0.0, 0.0); 4];
+        let mut mesh = quad(uvs);
+        mesh.validate().unwrap();
+
+        mesh.indices = Indices::from_u32(vec![0, 1, 2, 0, 2, 4]);
+        assert_eq!(mesh.validate().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // base_vertex counts too, both ways
+        let mut mesh = quad(uvs);
+        mesh.submeshes[0].base_vertex = 1;
+        assert!(mesh.validate().is_err());
+        mesh.submeshes[0].base_vertex = -1;
+        assert!(mesh.validate().is_err());
+
+        // A range past the end of the index buffer
+        let mut mesh = quad(uvs);
+        mesh.submeshes[0].index_start = 3;
+        assert!(mesh.validate().is_err());
+
+        assert!(Mesh::new(Vec::new(), Vec::new()).validate().is_err());
+        assert!(Mesh::new(quad(uvs).vertices, Vec::new())
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn validate_wants_whole_primitives() {
+        let vertices = quad([(0.0, 0.0); 4]).vertices;
+        let mesh = Mesh::new(vertices.clone(), vec![0, 1, 2, 3]);
+        assert_eq!(mesh.validate().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Each submesh on its own, not just the total
+        let split = Mesh::new(vertices.clone(), vec![0, 1, 2, 0, 2, 3]).with_submeshes(vec![
+            Submesh {
+                name: String::from("a"),
+                index_start: 0,
+                index_count: 4,
+                base_vertex: 0,
+                material: 0,
+            },
+            Submesh {
+                name: String::from("b"),
+                index_start: 4,
+                index_count: 2,
+                base_vertex: 0,
+                material: 0,
+            },
+        ]);
+        assert!(split.validate().is_err());
+
+        let lines = Mesh::new(vertices.clone(), vec![0, 1, 2]).with_topology(Topology::LineList);
+        assert!(lines.validate().is_err());
+        let strip = Mesh::new(vertices, vec![0, 1, 3, 2]).with_topology(Topology::TriangleStrip);
+        strip.validate().unwrap();
+    }
+
+    #[test]
+    fn tangents_follow_uvs() {
+        let mut mesh = quad([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        mesh.compute_tangents();
+        // u goes along +x, v along -y, which is cross(normal, tangent)
+        for i in 0..4 {
+            assert_eq!(tangent(&mesh, i), (1.0, 0.0, 0.0, 1.0));
+        }
+
+        // Mirrored texture, u goes the other way and the bitangent with it
+        let mut mirrored = quad([(1.0, 0.0), (0.0, 0.0), (0.0, 1.0), (1.0, 1.0)]);
+        mirrored.compute_tangents();
+        for i in 0..4 {
+            assert_eq!(tangent(&mirrored, i), (-1.0, 0.0, 0.0, -1.0));
+        }
+
+        // No usable UVs, any unit tangent perpendicular to the normal
+        let mut flat = quad([(0.5, 0.5); 4]);
+        flat.compute_tangents();
+        for i in 0..4 {
+            let (x, y, z, w) = tangent(&flat, i);
+            assert!(((x * x + y * y + z * z).sqrt() - 1.0).abs() < 1e-6);
+            assert_eq!(z, 0.0);
+            assert_eq!(w.abs(), 1.0);
+        }
+    }
+}
@@ -0,0 +1,605 @@
+// Wavefront OBJ models and their MTL material libraries. Faces are
+// triangulated as fans, each distinct position/uv/normal combination becomes
+// one vertex, and every run of faces with the same object, group and material
+// becomes a submesh.
+//
+// OBJ is right handed with v going up the texture, so z is flipped, windings
+// reversed and v flipped on the way in. Vertices without a normal get the
+// average of the faces around them.
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use crate::file_system::{DiskFileSystem, FileSystem};
+use crate::mesh::{Mesh, Submesh};
+use crate::vertex::Vertex;
+
+type Color = (f32, f32, f32);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    // Ka, Kd, Ks and Ke
+    pub ambient: Color,
+    pub diffuse: Color,
+    pub specular: Color,
+    pub emissive: Color,
+    // Ns, the specular exponent
+    pub shininess: f32,
+    // d, or 1 - Tr
+    pub opacity: f32,
+    // Texture file names as written in the library, relative to it
+    pub diffuse_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+}
+
+impl ObjMaterial {
+    // The defaults the MTL spec gives unset values
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ambient: (0.2, 0.2, 0.2),
+            diffuse: (0.8, 0.8, 0.8),
+            specular: (1.0, 1.0, 1.0),
+            emissive: (0.0, 0.0, 0.0),
+            shininess: 0.0,
+            opacity: 1.0,
+            diffuse_map: None,
+            specular_map: None,
+            normal_map: None,
+        }
+    }
+}
+
+pub fn parse_mtl(text: &str) -> Result<Vec<ObjMaterial>, Error> {
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| invalid_data(format!("Line {}: {}", number + 1, message));
+
+        let mut words = line.split_whitespace();
+        let kind = words.next().unwrap_or("");
+        if kind == "newmtl" {
+            let name = rest(line, kind);
+            if name.is_empty() {
+                return Err(error("missing material name"));
+            }
+            materials.push(ObjMaterial::new(name));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(error("expected \"newmtl\"")),
+        };
+        match kind {
+            "Ka" => material.ambient = parse_color(&mut words).map_err(|e| error(&e))?,
+            "Kd" => material.diffuse = parse_color(&mut words).map_err(|e| error(&e))?,
+            "Ks" => material.specular = parse_color(&mut words).map_err(|e| error(&e))?,
+            "Ke" => material.emissive = parse_color(&mut words).map_err(|e| error(&e))?,
+            "Ns" => material.shininess = parse_next(&mut words).map_err(|e| error(&e))?,
+            "d" => material.opacity = parse_next(&mut words).map_err(|e| error(&e))?,
+            "Tr" => {
+                let transparency: f32 = parse_next(&mut words).map_err(|e| error(&e))?;
+                material.opacity = 1.0 - transparency;
+            }
+            "map_Kd" => material.diffuse_map = Some(map_file(line).map_err(|e| error(&e))?),
+            "map_Ks" => material.specular_map = Some(map_file(line).map_err(|e| error(&e))?),
+            "norm" | "bump" | "map_Bump" | "map_bump" => {
+                material.normal_map = Some(map_file(line).map_err(|e| error(&e))?)
+            }
+            // Illumination models, other maps, etc.
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+pub struct ObjModel {
+    // Submesh::material indexes into materials
+    pub mesh: Mesh,
+    pub materials: Vec<ObjMaterial>,
+}
+
+// Indices into the position, uv and normal lists
+type Corner = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct Builder {
+    positions: Vec<(f32, f32, f32)>,
+    colors: Vec<Option<Color>>,
+    uvs: Vec<(f32, f32)>,
+    normals: Vec<(f32, f32, f32)>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    corners: HashMap<Corner, u32>,
+    // Vertices whose normal is computed from the faces
+    missing_normals: Vec<bool>,
+    submeshes: Vec<Submesh>,
+    materials: Vec<ObjMaterial>,
+    object: String,
+    group: String,
+    material: Option<usize>,
+}
+
+impl Builder {
+    fn vertex(&mut self, corner: Corner) -> u32 {
+        if let Some(&index) = self.corners.get(&corner) {
+            return index;
+        }
+        let (position, uv, normal) = corner;
+        let (x, y, z) = self.positions[position];
+        let (u, v) = uv.map_or((0.0, 0.0), |uv| self.uvs[uv]);
+        let (nx, ny, nz) = normal.map_or((0.0, 0.0, 0.0), |normal| self.normals[normal]);
+        let mut vertex = Vertex::new((x, y, -z), (nx, ny, -nz), (u, 1.0 - v));
+        if let Some((r, g, b)) = self.colors[position] {
+            vertex = vertex.with_color(r, g, b, 1.0);
+        }
+        self.vertices.push(vertex);
+        self.missing_normals.push(normal.is_none());
+        let index = (self.vertices.len() - 1) as u32;
+        self.corners.insert(corner, index);
+        index
+    }
+
+    // Faces from here on go into a new submesh, unless nothing was added to
+    // the current one yet
+    fn start_submesh(&mut self) {
+        let name = match (self.object.is_empty(), self.group.is_empty()) {
+            (false, false) if self.object != self.group => {
+                format!("{}/{}", self.object, self.group)
+            }
+            (false, _) => self.object.clone(),
+            _ => self.group.clone(),
+        };
+        let submesh = Submesh {
+            name,
+            index_start: self.indices.len() as u32,
+            index_count: 0,
+            base_vertex: 0,
+            material: self.material.unwrap_or(0),
+        };
+        match self.submeshes.last_mut() {
+            Some(last) if last.index_count == 0 => *last = submesh,
+            _ => self.submeshes.push(submesh),
+        }
+    }
+
+    fn material_index(&mut self, name: &str) -> usize {
+        match self.materials.iter().position(|m| m.name == name) {
+            Some(index) => index,
+            None => {
+                if name != DEFAULT_MATERIAL {
+                    println!("Material {} not found, using defaults", name);
+                }
+                self.materials.push(ObjMaterial::new(name));
+                self.materials.len() - 1
+            }
+        }
+    }
+
+    fn face(&mut self, corners: &[Corner]) {
+        if self.submeshes.is_empty() || self.material.is_none() {
+            if self.material.is_none() {
+                self.material = Some(self.material_index(DEFAULT_MATERIAL));
+            }
+            self.start_submesh();
+        }
+        let indices: Vec<u32> = corners.iter().map(|&c| self.vertex(c)).collect();
+        for i in 1..indices.len() - 1 {
+            // Reversed, clockwise is the front in the renderer
+            self.indices
+                .extend_from_slice(&[indices[0], indices[i + 1], indices[i]]);
+            self.submeshes.last_mut().unwrap().index_count += 3;
+        }
+    }
+
+    // Area weighted average of the faces around each vertex that came
+    // without a normal
+    fn compute_missing_normals(&mut self) {
+        if !self.missing_normals.contains(&true) {
+            return;
+        }
+        let mut sums = vec![(0.0f32, 0.0f32, 0.0f32); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].pos);
+            let (e1, e2) = (
+                (b.x - a.x, b.y - a.y, b.z - a.z),
+                (c.x - a.x, c.y - a.y, c.z - a.z),
+            );
+            let normal = (
+                e1.1 * e2.2 - e1.2 * e2.1,
+                e1.2 * e2.0 - e1.0 * e2.2,
+                e1.0 * e2.1 - e1.1 * e2.0,
+            );
+            for &index in triangle {
+                let sum = &mut sums[index as usize];
+                *sum = (sum.0 + normal.0, sum.1 + normal.1, sum.2 + normal.2);
+            }
+        }
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            if !self.missing_normals[i] {
+                continue;
+            }
+            let (x, y, z) = sums[i];
+            let length = (x * x + y * y + z * z).sqrt();
+            if length > 0.0 {
+                vertex.normal.x = x / length;
+                vertex.normal.y = y / length;
+                vertex.normal.z = z / length;
+            }
+        }
+    }
+}
+
+// Used for faces before the first usemtl
+const DEFAULT_MATERIAL: &str = "default";
+
+impl ObjModel {
+    // `read_mtl` gets each library named by mtllib and returns its text, so
+    // the caller decides where files come from
+    pub fn parse<F>(text: &str, mut read_mtl: F) -> Result<ObjModel, Error>
+    where
+        F: FnMut(&str) -> Result<String, Error>,
+    {
+        let mut builder = Builder::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| invalid_data(format!("Line {}: {}", number + 1, message));
+
+            let mut words = line.split_whitespace();
+            let kind = words.next().unwrap_or("");
+            match kind {
+                "v" => {
+                    let values = parse_floats(&mut words).map_err(|e| error(&e))?;
+                    let (position, color) = match values.len() {
+                        3 | 4 => ((values[0], values[1], values[2]), None),
+                        // Vertex colors, a common extension
+                        6 | 7 => (
+                            (values[0], values[1], values[2]),
+                            Some((values[3], values[4], values[5])),
+                        ),
+                        _ => return Err(error("expected 3 coordinates")),
+                    };
+                    builder.positions.push(position);
+                    builder.colors.push(color);
+                }
+                "vt" => {
+                    let values = parse_floats(&mut words).map_err(|e| error(&e))?;
+                    match values.len() {
+                        1 => builder.uvs.push((values[0], 0.0)),
+                        2 | 3 => builder.uvs.push((values[0], values[1])),
+                        _ => return Err(error("expected 2 texture coordinates")),
+                    }
+                }
+                "vn" => {
+                    let values = parse_floats(&mut words).map_err(|e| error(&e))?;
+                    if values.len() != 3 {
+                        return Err(error("expected 3 normal coordinates"));
+                    }
+                    builder.normals.push((values[0], values[1], values[2]));
+                }
+                "f" => {
+                    let corners = words
+                        .map(|word| parse_corner(word, &builder))
+                        .collect::<Result<Vec<Corner>, String>>()
+                        .map_err(|e| error(&e))?;
+                    if corners.len() < 3 {
+                        return Err(error("faces need at least 3 vertices"));
+                    }
+                    builder.face(&corners);
+                }
+                "o" => {
+                    builder.object = rest(line, kind).to_string();
+                    builder.group.clear();
+                    builder.start_submesh();
+                }
+                "g" => {
+                    builder.group = rest(line, kind).to_string();
+                    builder.start_submesh();
+                }
+                "usemtl" => {
+                    let index = builder.material_index(rest(line, kind));
+                    builder.material = Some(index);
+                    builder.start_submesh();
+                }
+                "mtllib" => {
+                    for name in words {
+                        let text = read_mtl(name)?;
+                        let materials = parse_mtl(&text)
+                            .map_err(|e| invalid_data(format!("{}: {}", name, e)))?;
+                        builder.materials.extend(materials);
+                    }
+                }
+                // Smoothing groups, lines, points, curves, etc.
+                _ => {}
+            }
+        }
+
+        builder.submeshes.retain(|s| s.index_count > 0);
+        if builder.indices.is_empty() {
+            return Err(invalid_data(String::from("No faces")));
+        }
+        builder.compute_missing_normals();
+        let mut mesh =
+            Mesh::new(builder.vertices, builder.indices).with_submeshes(builder.submeshes);
+        mesh.compute_tangents();
+        Ok(ObjModel {
+            mesh,
+            materials: builder.materials,
+        })
+    }
+
+    // Material libraries are looked up next to the model. Missing ones are
+    // reported and the materials get defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ObjModel, Error> {
+        Self::load_from(&DiskFileSystem, path.as_ref())
+    }
+
+    pub fn load_from(files: &dyn FileSystem, path: &Path) -> Result<ObjModel, Error> {
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::parse(&files.read_to_string(path)?, |name| {
+            match files.read_to_string(&directory.join(name)) {
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    println!("Error loading {}: {}", name, e);
+                    Ok(String::new())
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+// Everything after the keyword, for names that may contain spaces
+fn rest<'a>(line: &'a str, kind: &str) -> &'a str {
+    line[kind.len()..].trim()
+}
+
+fn parse_next<T: std::str::FromStr>(words: &mut SplitWhitespace) -> Result<T, String> {
+    let word = words.next().ok_or_else(|| String::from("missing value"))?;
+    word.parse()
+        .map_err(|_| format!("invalid value \"{}\"", word))
+}
+
+fn parse_floats(words: &mut SplitWhitespace) -> Result<Vec<f32>, String> {
+    words
+        .map(|word| {
+            word.parse()
+                .map_err(|_| format!("invalid value \"{}\"", word))
+        })
+        .collect()
+}
+
+fn parse_color(words: &mut SplitWhitespace) -> Result<Color, String> {
+    let values = parse_floats(words)?;
+    match values.len() {
+        // A single value is grey
+        1 => Ok((values[0], values[0], values[0])),
+        3 => Ok((values[0], values[1], values[2])),
+        _ => Err(String::from("expected 3 color components")),
+    }
+}
+
+// Texture statements may have options like "-bm 0.5" before the file name,
+// which is taken to be the last word
+fn map_file(line: &str) -> Result<String, String> {
+    line.split_whitespace()
+        .skip(1)
+        .last()
+        .map(String::from)
+        .ok_or_else(|| String::from("missing file name"))
+}
+
+// 1 based, negative counts back from the last one defined so far
+fn resolve_index(word: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = word
+        .parse()
+        .map_err(|_| format!("invalid index \"{}\"", word))?;
+    let resolved = match index {
+        0 => None,
+        i if i > 0 => Some(i - 1),
+        i => Some(count as i64 + i),
+    };
+    match resolved {
+        Some(i) if i >= 0 && (i as usize) < count => Ok(i as usize),
+        _ => Err(format!("index {} out of range", index)),
+    }
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn parse_corner(word: &str, builder: &Builder) -> Result<Corner, String> {
+    let mut parts = word.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), builder.positions.len())?;
+    let uv = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, builder.uvs.len())?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(part) if !part.is_empty() => Some(resolve_index(part, builder.normals.len())?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(format!("invalid face vertex \"{}\"", word));
+    }
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> ObjModel {
+        ObjModel::parse(text, |_| Ok(String::new())).unwrap()
+    }
+
+    fn indices(model: &ObjModel) -> Vec<u32> {
+        model.mesh.indices.iter().collect()
+    }
+
+    fn positions(model: &ObjModel) -> Vec<(f32, f32, f32)> {
+        model
+            .mesh
+            .vertices
+            .iter()
+            .map(|v| (v.pos.x, v.pos.y, v.pos.z))
+            .collect()
+    }
+
+    fn cross(a: (f32, f32, f32), b: (f32, f32, f32)) -> (f32, f32, f32) {
+        (
+            a.1 * b.2 - a.2 * b.1,
+            a.2 * b.0 - a.0 * b.2,
+            a.0 * b.1 - a.1 * b.0,
+        )
+    }
+
+    const SQUARE: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+    ";
+
+    #[test]
+    fn negative_indices_count_back() {
+        let absolute = parse(&format!("{}f 1 2 3 4\n", SQUARE));
+        let relative = parse(&format!("{}f -4 -3 -2 -1\n", SQUARE));
+        assert_eq!(indices(&relative), indices(&absolute));
+        assert_eq!(positions(&relative), positions(&absolute));
+
+        // Relative to what's defined so far, not the whole file
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\n");
+        assert_eq!(model.mesh.vertices.len(), 3);
+        assert!(!positions(&model).contains(&(5.0, 5.0, -5.0)));
+
+        let uvs = parse(&format!(
+            "{}vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\nf -4/-3/-1 -3/-2/-1 -2/-1/-1\n",
+            SQUARE
+        ));
+        let uv: Vec<(f32, f32)> = uvs.mesh.vertices.iter().map(|v| (v.uv.x, v.uv.y)).collect();
+        assert_eq!(uv, [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0)]);
+
+        for face in ["f 0 1 2", "f -5 1 2", "f 1 2 5", "f 1/2 2 3", "f 1 2"] {
+            let error = ObjModel::parse(&format!("{}{}\n", SQUARE, face), |_| Ok(String::new()))
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", face);
+        }
+    }
+
+    #[test]
+    fn polygons_become_fans() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n");
+        // Every triangle shares the first corner, winding reversed
+        assert_eq!(indices(&model), [0, 2, 1, 0, 3, 2, 0, 4, 3]);
+        assert_eq!(model.mesh.submeshes[0].index_count, 9);
+    }
+
+    #[test]
+    fn groups_and_materials_split_submeshes() {
+        let mtl = "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n";
+        let text = format!(
+            "mtllib colors.mtl\n{}
+            f 1 2 3
+            g lid
+            usemtl red
+            f 1 3 4
+            f 1 2 4
+            # Nothing drawn before the next change, so no empty submesh
+            usemtl blue
+            g base
+            usemtl red
+            f 2 3 4
+            o box
+            f 1 2 3
+            ",
+            SQUARE
+        );
+        let mut requested = Vec::new();
+        let model = ObjModel::parse(&text, |name| {
+            requested.push(name.to_string());
+            Ok(String::from(mtl))
+        })
+        .unwrap();
+        assert_eq!(requested, ["colors.mtl"]);
+
+        let names: Vec<&str> = model.materials.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["red", "blue", "default"]);
+        assert_eq!(model.materials[0].diffuse, (1.0, 0.0, 0.0));
+
+        let submeshes: Vec<(&str, u32, u32, usize)> = model
+            .mesh
+            .submeshes
+            .iter()
+            .map(|s| (s.name.as_str(), s.index_start, s.index_count, s.material))
+            .collect();
+        assert_eq!(
+            submeshes,
+            [
+                ("", 0, 3, 2),
+                ("lid", 3, 6, 0),
+                ("base", 9, 3, 0),
+                ("box", 12, 3, 0),
+            ]
+        );
+        model.mesh.validate().unwrap();
+    }
+
+    #[test]
+    fn shared_corners_are_deduplicated() {
+        let model = parse(&format!(
+            "{}vt 0 0\nvt 1 1\nf 1/1 2/1 3/1\nf 1/1 3/1 4/1\nf 1/2 2/2 3/2\n",
+            SQUARE
+        ));
+        // Same position and uv is the same vertex, a different uv isn't
+        assert_eq!(model.mesh.vertices.len(), 7);
+        assert_eq!(indices(&model)[..6], [0, 2, 1, 0, 3, 2]);
+        assert_eq!(indices(&model)[6..], [4, 6, 5]);
+    }
+
+    #[test]
+    fn handedness_is_flipped() {
+        // Counter-clockwise facing +z in OBJ's right handed space
+        let model = parse(&format!(
+            "{}vt 0.25 0.75\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n",
+            SQUARE
+        ));
+        let vertex = &model.mesh.vertices[1];
+        assert_eq!((vertex.pos.x, vertex.pos.y, vertex.pos.z), (1.0, 0.0, -0.0));
+        assert_eq!(
+            (vertex.normal.x, vertex.normal.y, vertex.normal.z),
+            (0.0, 0.0, -1.0)
+        );
+        assert_eq!((vertex.uv.x, vertex.uv.y), (0.25, 0.25));
+
+        // The reversed winding still agrees with the mirrored normal
+        let p = positions(&model);
+        let i = indices(&model);
+        let [a, b, c] = [p[i[0] as usize], p[i[1] as usize], p[i[2] as usize]];
+        let face = cross(
+            (b.0 - a.0, b.1 - a.1, b.2 - a.2),
+            (c.0 - a.0, c.1 - a.1, c.2 - a.2),
+        );
+        assert!(face.2 < 0.0 && face.0 == 0.0 && face.1 == 0.0);
+
+        // And computed normals come out the same way
+        let computed = parse(&format!("{}f 1 2 3\n", SQUARE));
+        for vertex in &computed.mesh.vertices {
+            assert_eq!(
+                (vertex.normal.x, vertex.normal.y, vertex.normal.z),
+                (0.0, 0.0, -1.0)
+            );
+        }
+    }
+}
@@ -0,0 +1,873 @@
+// Pipeline state: the shaders, input layout, topology and fixed-function
+// state a draw needs, described by value. PipelineCache hands out one id per
+// distinct description and shares layouts and state objects between
+// pipelines that have them in common, PipelineObjects holds the D3D11
+// objects for them.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::mesh::Topology;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ElementFormat {
+    Float2,
+    Float3,
+    Float4,
+}
+
+// One vertex attribute. Attributes follow each other in the order given, in
+// the first vertex buffer, with semantic index 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InputElement {
+    pub semantic: &'static str,
+    pub format: ElementFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FillMode {
+    #[default]
+    Solid,
+    Wireframe,
+}
+
+impl FillMode {
+    pub fn from_name(name: &str) -> Option<FillMode> {
+        match name {
+            "solid" => Some(FillMode::Solid),
+            "wireframe" => Some(FillMode::Wireframe),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FillMode::Solid => "solid",
+            FillMode::Wireframe => "wireframe",
+        }
+    }
+}
+
+// Which triangles are dropped. Clockwise triangles face the camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CullMode {
+    None,
+    Front,
+    #[default]
+    Back,
+}
+
+impl CullMode {
+    pub fn from_name(name: &str) -> Option<CullMode> {
+        match name {
+            "none" => Some(CullMode::None),
+            "front" => Some(CullMode::Front),
+            "back" => Some(CullMode::Back),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CullMode::None => "none",
+            CullMode::Front => "front",
+            CullMode::Back => "back",
+        }
+    }
+}
+
+// The defaults are D3D11's, what the sample drew with before it set any
+// state
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RasterizerDesc {
+    pub fill: FillMode,
+    pub cull: CullMode,
+}
+
+// How the pixel shader's output is combined with the render target
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    // color * alpha + target * (1 - alpha)
+    Alpha,
+    // color + target * (1 - alpha), for colors already multiplied by alpha
+    Premultiplied,
+    // color * alpha + target
+    Additive,
+}
+
+impl BlendMode {
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        match name {
+            "opaque" => Some(BlendMode::Opaque),
+            "alpha" => Some(BlendMode::Alpha),
+            "premultiplied" => Some(BlendMode::Premultiplied),
+            "additive" => Some(BlendMode::Additive),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BlendMode::Opaque => "opaque",
+            BlendMode::Alpha => "alpha",
+            BlendMode::Premultiplied => "premultiplied",
+            BlendMode::Additive => "additive",
+        }
+    }
+}
+
+// When a pixel passes the depth test, comparing its depth to the buffer's
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DepthCompare {
+    #[default]
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+}
+
+impl DepthCompare {
+    // The same test with near and far swapped, for reverse-Z
+    pub fn reversed(self) -> DepthCompare {
+        match self {
+            DepthCompare::Less => DepthCompare::Greater,
+            DepthCompare::LessEqual => DepthCompare::GreaterEqual,
+            DepthCompare::Greater => DepthCompare::Less,
+            DepthCompare::GreaterEqual => DepthCompare::LessEqual,
+            DepthCompare::Always => DepthCompare::Always,
+        }
+    }
+}
+
+// Depth testing, less than by default as in D3D11 and greater than with
+// reverse-Z. Writes only happen with the test on, also as in D3D11. No
+// stencil, nothing uses it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DepthStencilDesc {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub compare: DepthCompare,
+}
+
+impl DepthStencilDesc {
+    // "off", "test" or "write", which tests too
+    pub fn from_name(name: &str) -> Option<DepthStencilDesc> {
+        let (depth_test, depth_write) = match name {
+            "off" => (false, false),
+            "test" => (true, false),
+            "write" => (true, true),
+            _ => return None,
+        };
+        Some(DepthStencilDesc {
+            depth_test,
+            depth_write,
+            compare: DepthCompare::default(),
+        })
+    }
+
+    // For a camera with near at depth 1 and far at 0
+    pub fn reverse_z(self) -> DepthStencilDesc {
+        DepthStencilDesc {
+            compare: self.compare.reversed(),
+            ..self
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match (self.depth_test, self.depth_write) {
+            (false, _) => "off",
+            (true, false) => "test",
+            (true, true) => "write",
+        }
+    }
+}
+
+// The fixed-function state a material picks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RenderState {
+    pub rasterizer: RasterizerDesc,
+    pub blend: BlendMode,
+    pub depth_stencil: DepthStencilDesc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    // Index into the renderer's shader programs
+    pub shader: usize,
+    pub input_layout: &'static [InputElement],
+    pub topology: Topology,
+    pub rasterizer: RasterizerDesc,
+    pub blend: BlendMode,
+    pub depth_stencil: DepthStencilDesc,
+}
+
+impl PipelineDesc {
+    pub fn new(
+        shader: usize,
+        input_layout: &'static [InputElement],
+        topology: Topology,
+        state: RenderState,
+    ) -> Self {
+        Self {
+            shader,
+            input_layout,
+            topology,
+            rasterizer: state.rasterizer,
+            blend: state.blend,
+            depth_stencil: state.depth_stencil,
+        }
+    }
+}
+
+// A pipeline's description and where its shared parts are in the cache's
+// tables. Input layouts are made against the vertex shader, so they're
+// shared by pipelines with the same layout and shader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipelineStates {
+    pub desc: PipelineDesc,
+    pub input_layout: usize,
+    pub rasterizer: usize,
+    pub blend: usize,
+    pub depth_stencil: usize,
+}
+
+// Values in the order they were first seen, each once
+struct Interned<T> {
+    values: Vec<T>,
+    indices: HashMap<T, usize>,
+}
+
+impl<T> Default for Interned<T> {
+    fn default() -> Self {
+        Interned {
+            values: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> Interned<T> {
+    fn get(&mut self, value: T) -> usize {
+        let values = &mut self.values;
+        *self.indices.entry(value).or_insert_with(|| {
+            values.push(value);
+            values.len() - 1
+        })
+    }
+}
+
+// Only grows. Ids stay valid for the cache's lifetime, including across
+// device loss, PipelineObjects are rebuilt from the tables.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: Vec<PipelineStates>,
+    ids: HashMap<PipelineDesc, usize>,
+    // Shader program index and layout
+    input_layouts: Interned<(usize, &'static [InputElement])>,
+    rasterizers: Interned<RasterizerDesc>,
+    blends: Interned<BlendMode>,
+    depth_stencils: Interned<DepthStencilDesc>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The id of the pipeline for `desc`, adding it if it's new
+    pub fn get(&mut self, desc: &PipelineDesc) -> usize {
+        if let Some(&id) = self.ids.get(desc) {
+            return id;
+        }
+        let states = PipelineStates {
+            desc: *desc,
+            input_layout: self.input_layouts.get((desc.shader, desc.input_layout)),
+            rasterizer: self.rasterizers.get(desc.rasterizer),
+            blend: self.blends.get(desc.blend),
+            depth_stencil: self.depth_stencils.get(desc.depth_stencil),
+        };
+        self.pipelines.push(states);
+        self.ids.insert(*desc, self.pipelines.len() - 1);
+        self.pipelines.len() - 1
+    }
+
+    pub fn pipeline(&self, id: usize) -> &PipelineStates {
+        &self.pipelines[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    pub fn input_layouts(&self) -> &[(usize, &'static [InputElement])] {
+        &self.input_layouts.values
+    }
+
+    pub fn rasterizers(&self) -> &[RasterizerDesc] {
+        &self.rasterizers.values
+    }
+
+    pub fn blends(&self) -> &[BlendMode] {
+        &self.blends.values
+    }
+
+    pub fn depth_stencils(&self) -> &[DepthStencilDesc] {
+        &self.depth_stencils.values
+    }
+}
+
+// Which parts of a pipeline have to be set on the context
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateChanges {
+    pub shader: bool,
+    pub input_layout: bool,
+    pub topology: bool,
+    pub rasterizer: bool,
+    pub blend: bool,
+    pub depth_stencil: bool,
+}
+
+impl StateChanges {
+    pub fn count(&self) -> usize {
+        [
+            self.shader,
+            self.input_layout,
+            self.topology,
+            self.rasterizer,
+            self.blend,
+            self.depth_stencil,
+        ]
+        .iter()
+        .filter(|&&changed| changed)
+        .count()
+    }
+}
+
+// What was last bound, so switching pipelines only sets the parts that
+// differ. Start a new one whenever the context's state may have been reset.
+#[derive(Default)]
+pub struct BoundPipeline {
+    shader: Option<usize>,
+    input_layout: Option<usize>,
+    topology: Option<Topology>,
+    rasterizer: Option<usize>,
+    blend: Option<usize>,
+    depth_stencil: Option<usize>,
+}
+
+impl BoundPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, states: &PipelineStates) -> StateChanges {
+        fn set<T: PartialEq>(bound: &mut Option<T>, value: T) -> bool {
+            let changed = bound.as_ref() != Some(&value);
+            *bound = Some(value);
+            changed
+        }
+        StateChanges {
+            shader: set(&mut self.shader, states.desc.shader),
+            input_layout: set(&mut self.input_layout, states.input_layout),
+            topology: set(&mut self.topology, states.desc.topology),
+            rasterizer: set(&mut self.rasterizer, states.rasterizer),
+            blend: set(&mut self.blend, states.blend),
+            depth_stencil: set(&mut self.depth_stencil, states.depth_stencil),
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win32::{PipelineObjects, ShaderProgram};
+
+#[cfg(windows)]
+mod win32 {
+    use std::ffi::CString;
+    use std::ptr::null_mut;
+
+    use winapi::shared::dxgiformat::*;
+    use winapi::shared::minwindef::{FALSE, TRUE};
+    use winapi::shared::winerror::FAILED;
+    use winapi::um::d3d11::*;
+    use winapi::um::winnt::HRESULT;
+
+    use super::{
+        BlendMode, CullMode, DepthCompare, DepthStencilDesc, ElementFormat, FillMode, InputElement,
+        PipelineCache, PipelineStates, RasterizerDesc, StateChanges,
+    };
+
+    // A vertex and pixel shader pair. Keeps the vertex shader's bytecode for
+    // making input layouts against it. Empty when its shader didn't compile,
+    // so it can be fixed and reloaded without restarting.
+    pub struct ShaderProgram {
+        vertex_shader: *mut ID3D11VertexShader,
+        pixel_shader: *mut ID3D11PixelShader,
+        vertex_code: Vec<u8>,
+    }
+
+    impl ShaderProgram {
+        pub fn empty() -> Self {
+            Self {
+                vertex_shader: null_mut(),
+                pixel_shader: null_mut(),
+                vertex_code: Vec::new(),
+            }
+        }
+
+        pub unsafe fn new(
+            device: &ID3D11Device,
+            vertex_code: Vec<u8>,
+            pixel_code: &[u8],
+        ) -> Result<Self, HRESULT> {
+            let mut program = Self::empty();
+            let res = device.CreateVertexShader(
+                vertex_code.as_ptr() as _,
+                vertex_code.len(),
+                null_mut(),
+                &mut program.vertex_shader,
+            );
+            if FAILED(res) {
+                return Err(res);
+            }
+            let res = device.CreatePixelShader(
+                pixel_code.as_ptr() as _,
+                pixel_code.len(),
+                null_mut(),
+                &mut program.pixel_shader,
+            );
+            if FAILED(res) {
+                return Err(res);
+            }
+            program.vertex_code = vertex_code;
+            Ok(program)
+        }
+    }
+
+    impl Drop for ShaderProgram {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some(shader) = self.pixel_shader.as_ref() {
+                    shader.Release();
+                }
+                if let Some(shader) = self.vertex_shader.as_ref() {
+                    shader.Release();
+                }
+            }
+        }
+    }
+
+    // The D3D11 objects for a PipelineCache's tables, in the same order.
+    // Objects that fail to be created are null, which D3D11 takes as its
+    // defaults.
+    #[derive(Default)]
+    pub struct PipelineObjects {
+        input_layouts: Vec<*mut ID3D11InputLayout>,
+        rasterizers: Vec<*mut ID3D11RasterizerState>,
+        blends: Vec<*mut ID3D11BlendState>,
+        depth_stencils: Vec<*mut ID3D11DepthStencilState>,
+    }
+
+    impl PipelineObjects {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Creates objects for what was added to the cache since the last
+        // call
+        pub unsafe fn update(
+            &mut self,
+            device: &ID3D11Device,
+            cache: &PipelineCache,
+            programs: &[ShaderProgram],
+        ) {
+            for &(shader, layout) in &cache.input_layouts()[self.input_layouts.len()..] {
+                let input_layout = create_input_layout(device, layout, &programs[shader]);
+                self.input_layouts.push(input_layout);
+            }
+            for desc in &cache.rasterizers()[self.rasterizers.len()..] {
+                self.rasterizers.push(create_rasterizer(device, desc));
+            }
+            for &mode in &cache.blends()[self.blends.len()..] {
+                self.blends.push(create_blend(device, mode));
+            }
+            for desc in &cache.depth_stencils()[self.depth_stencils.len()..] {
+                self.depth_stencils.push(create_depth_stencil(device, desc));
+            }
+        }
+
+        // Remakes the input layouts made against a program that was replaced
+        pub unsafe fn program_changed(
+            &mut self,
+            device: &ID3D11Device,
+            cache: &PipelineCache,
+            programs: &[ShaderProgram],
+            shader: usize,
+        ) {
+            for (i, &(layout_shader, layout)) in cache.input_layouts().iter().enumerate() {
+                if layout_shader != shader || i >= self.input_layouts.len() {
+                    continue;
+                }
+                if let Some(input_layout) = self.input_layouts[i].as_ref() {
+                    input_layout.Release();
+                }
+                self.input_layouts[i] = create_input_layout(device, layout, &programs[shader]);
+            }
+        }
+
+        // Sets the parts of the pipeline in `changes`, from BoundPipeline::bind
+        pub unsafe fn bind(
+            &self,
+            context: &ID3D11DeviceContext,
+            programs: &[ShaderProgram],
+            states: &PipelineStates,
+            changes: StateChanges,
+        ) {
+            if changes.shader {
+                let program = &programs[states.desc.shader];
+                context.VSSetShader(program.vertex_shader, null_mut(), 0);
+                context.PSSetShader(program.pixel_shader, null_mut(), 0);
+            }
+            if changes.input_layout {
+                context.IASetInputLayout(self.input_layouts[states.input_layout]);
+            }
+            if changes.topology {
+                context.IASetPrimitiveTopology(states.desc.topology.to_raw());
+            }
+            if changes.rasterizer {
+                context.RSSetState(self.rasterizers[states.rasterizer]);
+            }
+            if changes.blend {
+                context.OMSetBlendState(self.blends[states.blend], &[1.0; 4], 0xffff_ffff);
+            }
+            if changes.depth_stencil {
+                context.OMSetDepthStencilState(self.depth_stencils[states.depth_stencil], 0);
+            }
+        }
+    }
+
+    impl Drop for PipelineObjects {
+        fn drop(&mut self) {
+            unsafe {
+                for input_layout in &self.input_layouts {
+                    if let Some(input_layout) = input_layout.as_ref() {
+                        input_layout.Release();
+                    }
+                }
+                for rasterizer in &self.rasterizers {
+                    if let Some(rasterizer) = rasterizer.as_ref() {
+                        rasterizer.Release();
+                    }
+                }
+                for blend in &self.blends {
+                    if let Some(blend) = blend.as_ref() {
+                        blend.Release();
+                    }
+                }
+                for depth_stencil in &self.depth_stencils {
+                    if let Some(depth_stencil) = depth_stencil.as_ref() {
+                        depth_stencil.Release();
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn create_input_layout(
+        device: &ID3D11Device,
+        layout: &[InputElement],
+        program: &ShaderProgram,
+    ) -> *mut ID3D11InputLayout {
+        let mut input_layout = null_mut();
+        // Nothing to check the layout against
+        if program.vertex_code.is_empty() {
+            return input_layout;
+        }
+        // The descs point into these, so they have to outlive the call
+        let semantics: Vec<CString> = layout
+            .iter()
+            .map(|element| CString::new(element.semantic).unwrap())
+            .collect();
+        let descs: Vec<D3D11_INPUT_ELEMENT_DESC> = layout
+            .iter()
+            .zip(&semantics)
+            .map(|(element, semantic)| D3D11_INPUT_ELEMENT_DESC {
+                SemanticName: semantic.as_ptr(),
+                SemanticIndex: 0,
+                Format: match element.format {
+                    ElementFormat::Float2 => DXGI_FORMAT_R32G32_FLOAT,
+                    ElementFormat::Float3 => DXGI_FORMAT_R32G32B32_FLOAT,
+                    ElementFormat::Float4 => DXGI_FORMAT_R32G32B32A32_FLOAT,
+                },
+                InputSlot: 0,
+                AlignedByteOffset: D3D11_APPEND_ALIGNED_ELEMENT,
+                InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
+                InstanceDataStepRate: 0,
+            })
+            .collect();
+        let res = device.CreateInputLayout(
+            descs.as_ptr(),
+            descs.len() as u32,
+            program.vertex_code.as_ptr() as _,
+            program.vertex_code.len(),
+            &mut input_layout,
+        );
+        if FAILED(res) {
+            println!("Error creating Input Layout: {}", res);
+        }
+        input_layout
+    }
+
+    unsafe fn create_rasterizer(
+        device: &ID3D11Device,
+        desc: &RasterizerDesc,
+    ) -> *mut ID3D11RasterizerState {
+        let desc = D3D11_RASTERIZER_DESC {
+            FillMode: match desc.fill {
+                FillMode::Solid => D3D11_FILL_SOLID,
+                FillMode::Wireframe => D3D11_FILL_WIREFRAME,
+            },
+            CullMode: match desc.cull {
+                CullMode::None => D3D11_CULL_NONE,
+                CullMode::Front => D3D11_CULL_FRONT,
+                CullMode::Back => D3D11_CULL_BACK,
+            },
+            FrontCounterClockwise: FALSE,
+            DepthBias: 0,
+            DepthBiasClamp: 0.0,
+            SlopeScaledDepthBias: 0.0,
+            DepthClipEnable: TRUE,
+            ScissorEnable: FALSE,
+            MultisampleEnable: FALSE,
+            AntialiasedLineEnable: FALSE,
+        };
+        let mut state = null_mut();
+        let res = device.CreateRasterizerState(&desc, &mut state);
+        if FAILED(res) {
+            println!("Error creating rasterizer state: {}", res);
+        }
+        state
+    }
+
+    unsafe fn create_blend(device: &ID3D11Device, mode: BlendMode) -> *mut ID3D11BlendState {
+        let (enable, source, destination) = match mode {
+            BlendMode::Opaque => (FALSE, D3D11_BLEND_ONE, D3D11_BLEND_ZERO),
+            BlendMode::Alpha => (TRUE, D3D11_BLEND_SRC_ALPHA, D3D11_BLEND_INV_SRC_ALPHA),
+            BlendMode::Premultiplied => (TRUE, D3D11_BLEND_ONE, D3D11_BLEND_INV_SRC_ALPHA),
+            BlendMode::Additive => (TRUE, D3D11_BLEND_SRC_ALPHA, D3D11_BLEND_ONE),
+        };
+        let target = D3D11_RENDER_TARGET_BLEND_DESC {
+            BlendEnable: enable,
+            SrcBlend: source,
+            DestBlend: destination,
+            BlendOp: D3D11_BLEND_OP_ADD,
+            SrcBlendAlpha: D3D11_BLEND_ONE,
+            DestBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
+            BlendOpAlpha: D3D11_BLEND_OP_ADD,
+            RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL as u8,
+        };
+        let desc = D3D11_BLEND_DESC {
+            AlphaToCoverageEnable: FALSE,
+            IndependentBlendEnable: FALSE,
+            RenderTarget: [target; 8],
+        };
+        let mut state = null_mut();
+        let res = device.CreateBlendState(&desc, &mut state);
+        if FAILED(res) {
+            println!("Error creating blend state: {}", res);
+        }
+        state
+    }
+
+    unsafe fn create_depth_stencil(
+        device: &ID3D11Device,
+        desc: &DepthStencilDesc,
+    ) -> *mut ID3D11DepthStencilState {
+        let stencil_op = D3D11_DEPTH_STENCILOP_DESC {
+            StencilFailOp: D3D11_STENCIL_OP_KEEP,
+            StencilDepthFailOp: D3D11_STENCIL_OP_KEEP,
+            StencilPassOp: D3D11_STENCIL_OP_KEEP,
+            StencilFunc: D3D11_COMPARISON_ALWAYS,
+        };
+        let desc = D3D11_DEPTH_STENCIL_DESC {
+            DepthEnable: if desc.depth_test { TRUE } else { FALSE },
+            DepthWriteMask: if desc.depth_write {
+                D3D11_DEPTH_WRITE_MASK_ALL
+            } else {
+                D3D11_DEPTH_WRITE_MASK_ZERO
+            },
+            DepthFunc: match desc.compare {
+                DepthCompare::Less => D3D11_COMPARISON_LESS,
+                DepthCompare::LessEqual => D3D11_COMPARISON_LESS_EQUAL,
+                DepthCompare::Greater => D3D11_COMPARISON_GREATER,
+                DepthCompare::GreaterEqual => D3D11_COMPARISON_GREATER_EQUAL,
+                DepthCompare::Always => D3D11_COMPARISON_ALWAYS,
+            },
+            StencilEnable: FALSE,
+            StencilReadMask: D3D11_DEFAULT_STENCIL_READ_MASK as u8,
+            StencilWriteMask: D3D11_DEFAULT_STENCIL_WRITE_MASK as u8,
+            FrontFace: stencil_op,
+            BackFace: stencil_op,
+        };
+        let mut state = null_mut();
+        let res = device.CreateDepthStencilState(&desc, &mut state);
+        if FAILED(res) {
+            println!("Error creating depth stencil state: {}", res);
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITION_ONLY: &[InputElement] = &[InputElement {
+        semantic: "POSITION",
+        format: ElementFormat::Float3,
+    }];
+    const POSITION_UV: &[InputElement] = &[
+        InputElement {
+            semantic: "POSITION",
+            format: ElementFormat::Float3,
+        },
+        InputElement {
+            semantic: "TEXCOORD",
+            format: ElementFormat::Float2,
+        },
+    ];
+
+    fn desc(shader: usize, state: RenderState) -> PipelineDesc {
+        PipelineDesc::new(shader, POSITION_ONLY, Topology::TriangleList, state)
+    }
+
+    fn blended(blend: BlendMode) -> RenderState {
+        RenderState {
+            blend,
+            ..RenderState::default()
+        }
+    }
+
+    #[test]
+    fn same_desc_gets_same_id() {
+        let mut cache = PipelineCache::new();
+        let a = cache.get(&desc(0, RenderState::default()));
+        let b = cache.get(&desc(0, blended(BlendMode::Alpha)));
+        assert_ne!(a, b);
+        assert_eq!(cache.get(&desc(0, RenderState::default())), a);
+        assert_eq!(cache.get(&desc(0, blended(BlendMode::Alpha))), b);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.pipeline(b).desc.blend, BlendMode::Alpha);
+    }
+
+    #[test]
+    fn shares_states_between_pipelines() {
+        let mut cache = PipelineCache::new();
+        let opaque = cache.get(&desc(0, RenderState::default()));
+        let alpha = cache.get(&desc(0, blended(BlendMode::Alpha)));
+        let other_shader = cache.get(&desc(1, blended(BlendMode::Alpha)));
+        let strip = cache.get(&PipelineDesc::new(
+            0,
+            POSITION_ONLY,
+            Topology::TriangleStrip,
+            RenderState::default(),
+        ));
+        let uv = cache.get(&PipelineDesc::new(
+            0,
+            POSITION_UV,
+            Topology::TriangleList,
+            RenderState::default(),
+        ));
+        assert_eq!(cache.len(), 5);
+
+        // One rasterizer and depth state for all of them, two blend states
+        assert_eq!(cache.rasterizers().len(), 1);
+        assert_eq!(cache.depth_stencils().len(), 1);
+        assert_eq!(cache.blends(), &[BlendMode::Opaque, BlendMode::Alpha]);
+        assert_eq!(
+            cache.pipeline(alpha).blend,
+            cache.pipeline(other_shader).blend
+        );
+
+        // Layouts are per shader and layout, topology doesn't need one
+        assert_eq!(cache.input_layouts().len(), 3);
+        let layout = |id| cache.pipeline(id).input_layout;
+        assert_eq!(layout(opaque), layout(alpha));
+        assert_eq!(layout(opaque), layout(strip));
+        assert_ne!(layout(opaque), layout(other_shader));
+        assert_ne!(layout(opaque), layout(uv));
+    }
+
+    #[test]
+    fn bind_sets_only_what_changed() {
+        let mut cache = PipelineCache::new();
+        let opaque = cache.get(&desc(0, RenderState::default()));
+        let alpha = cache.get(&desc(0, blended(BlendMode::Alpha)));
+        let other_shader = cache.get(&desc(1, blended(BlendMode::Alpha)));
+        let mut bound = BoundPipeline::new();
+
+        // Everything the first time
+        let changes = bound.bind(cache.pipeline(opaque));
+        assert_eq!(changes.count(), 6);
+        // Nothing when it's already bound
+        assert_eq!(bound.bind(cache.pipeline(opaque)), StateChanges::default());
+        assert_eq!(
+            bound.bind(cache.pipeline(alpha)),
+            StateChanges {
+                blend: true,
+                ..StateChanges::default()
+            }
+        );
+        assert_eq!(
+            bound.bind(cache.pipeline(other_shader)),
+            StateChanges {
+                shader: true,
+                input_layout: true,
+                ..StateChanges::default()
+            }
+        );
+        // A fresh one after the context was reset binds everything again
+        assert_eq!(BoundPipeline::new().bind(cache.pipeline(alpha)).count(), 6);
+    }
+
+    #[test]
+    fn parses_depth_names() {
+        for name in &["off", "test", "write"] {
+            assert_eq!(DepthStencilDesc::from_name(name).unwrap().name(), *name);
+        }
+        assert_eq!(DepthStencilDesc::from_name("read"), None);
+        let write = DepthStencilDesc::from_name("write").unwrap();
+        assert_eq!(write.compare, DepthCompare::Less);
+    }
+
+    #[test]
+    fn reverse_z_flips_the_comparison() {
+        let write = DepthStencilDesc::from_name("write").unwrap();
+        let reversed = write.reverse_z();
+        assert_eq!(reversed.compare, DepthCompare::Greater);
+        assert!(reversed.depth_test && reversed.depth_write);
+        assert_eq!(reversed.reverse_z(), write);
+        assert_eq!(DepthCompare::Always.reversed(), DepthCompare::Always);
+
+        // A separate state object from the forward one
+        let mut cache = PipelineCache::new();
+        let state = RenderState {
+            depth_stencil: write,
+            ..RenderState::default()
+        };
+        let reversed_state = RenderState {
+            depth_stencil: reversed,
+            ..state
+        };
+        assert_ne!(
+            cache.get(&desc(0, state)),
+            cache.get(&desc(0, reversed_state))
+        );
+        assert_eq!(cache.depth_stencils().len(), 2);
+    }
+}
@@ -0,0 +1,557 @@
+// Typed window events and the table that builds them from Win32 messages.
+// The translation only looks at message numbers and parameters, so it works
+// the same on any platform.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    // 'A'..='Z'
+    Letter(char),
+    // 0..=9, the number row
+    Digit(u8),
+    // 1..=24
+    F(u8),
+    Left,
+    Right,
+    Up,
+    Down,
+    Space,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Shift,
+    Control,
+    Alt,
+    // Any other virtual key code
+    Other(u32),
+}
+
+impl Key {
+    pub fn from_vk(vk: u32) -> Key {
+        match vk {
+            0x41..=0x5A => Key::Letter(vk as u8 as char),
+            0x30..=0x39 => Key::Digit((vk - 0x30) as u8),
+            0x70..=0x87 => Key::F((vk - 0x70 + 1) as u8),
+            0x25 => Key::Left,
+            0x26 => Key::Up,
+            0x27 => Key::Right,
+            0x28 => Key::Down,
+            0x20 => Key::Space,
+            0x0D => Key::Enter,
+            0x1B => Key::Escape,
+            0x09 => Key::Tab,
+            0x08 => Key::Backspace,
+            0x2D => Key::Insert,
+            0x2E => Key::Delete,
+            0x24 => Key::Home,
+            0x23 => Key::End,
+            0x21 => Key::PageUp,
+            0x22 => Key::PageDown,
+            0x10 => Key::Shift,
+            0x11 => Key::Control,
+            0x12 => Key::Alt,
+            vk => Key::Other(vk),
+        }
+    }
+
+    // Names used in config files: "A", "7", "F5", "Left", "Vk186", ...
+    pub fn name(&self) -> String {
+        match *self {
+            Key::Letter(c) => c.to_string(),
+            Key::Digit(d) => d.to_string(),
+            Key::F(n) => format!("F{}", n),
+            Key::Other(vk) => format!("Vk{}", vk),
+            key => format!("{:?}", key),
+        }
+    }
+
+    // Inverse of name(), case insensitive
+    pub fn from_name(name: &str) -> Option<Key> {
+        let upper = name.to_uppercase();
+        let mut chars = upper.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return match c {
+                'A'..='Z' => Some(Key::Letter(c)),
+                '0'..='9' => Some(Key::Digit(c as u8 - b'0')),
+                _ => None,
+            };
+        }
+
+        let named = [
+            Key::Left,
+            Key::Right,
+            Key::Up,
+            Key::Down,
+            Key::Space,
+            Key::Enter,
+            Key::Escape,
+            Key::Tab,
+            Key::Backspace,
+            Key::Insert,
+            Key::Delete,
+            Key::Home,
+            Key::End,
+            Key::PageUp,
+            Key::PageDown,
+            Key::Shift,
+            Key::Control,
+            Key::Alt,
+        ];
+        if let Some(key) = named.iter().find(|k| k.name().to_uppercase() == upper) {
+            return Some(*key);
+        }
+
+        if let Some(n) = upper.strip_prefix('F') {
+            return match n.parse() {
+                Ok(n @ 1..=24) => Some(Key::F(n)),
+                _ => None,
+            };
+        }
+        upper.strip_prefix("VK")?.parse().ok().map(Key::Other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    X1,
+    X2,
+}
+
+impl MouseButton {
+    pub const ALL: [MouseButton; 5] = [
+        MouseButton::Left,
+        MouseButton::Right,
+        MouseButton::Middle,
+        MouseButton::X1,
+        MouseButton::X2,
+    ];
+
+    // "MouseLeft", "MouseX1", ...
+    pub fn name(&self) -> String {
+        format!("Mouse{:?}", self)
+    }
+
+    pub fn from_name(name: &str) -> Option<MouseButton> {
+        MouseButton::ALL
+            .iter()
+            .copied()
+            .find(|b| b.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    // The user asked to close the window. The window stays open until dropped.
+    CloseRequested,
+    // New client area size in pixels
+    Resized {
+        width: u32,
+        height: u32,
+    },
+    Focused(bool),
+    KeyDown {
+        key: Key,
+        modifiers: Modifiers,
+        // Auto repeat from holding the key down
+        repeat: bool,
+    },
+    KeyUp {
+        key: Key,
+        modifiers: Modifiers,
+    },
+    // Text input, after keyboard layout and dead keys are applied
+    Char(char),
+    // Client area coordinates in pixels
+    MouseMove {
+        x: i32,
+        y: i32,
+    },
+    MouseDown {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+    },
+    MouseUp {
+        button: MouseButton,
+        x: i32,
+        y: i32,
+    },
+    // In notches, positive is away from the user / to the right
+    MouseWheel {
+        delta_x: f32,
+        delta_y: f32,
+    },
+    DpiChanged {
+        dpi: u32,
+    },
+}
+
+// Message numbers from winuser.h
+pub const WM_SIZE: u32 = 0x0005;
+pub const WM_SETFOCUS: u32 = 0x0007;
+pub const WM_KILLFOCUS: u32 = 0x0008;
+pub const WM_CLOSE: u32 = 0x0010;
+pub const WM_KEYDOWN: u32 = 0x0100;
+pub const WM_KEYUP: u32 = 0x0101;
+pub const WM_CHAR: u32 = 0x0102;
+pub const WM_SYSKEYDOWN: u32 = 0x0104;
+pub const WM_SYSKEYUP: u32 = 0x0105;
+pub const WM_MOUSEMOVE: u32 = 0x0200;
+pub const WM_LBUTTONDOWN: u32 = 0x0201;
+pub const WM_LBUTTONUP: u32 = 0x0202;
+pub const WM_RBUTTONDOWN: u32 = 0x0204;
+pub const WM_RBUTTONUP: u32 = 0x0205;
+pub const WM_MBUTTONDOWN: u32 = 0x0207;
+pub const WM_MBUTTONUP: u32 = 0x0208;
+pub const WM_MOUSEWHEEL: u32 = 0x020A;
+pub const WM_XBUTTONDOWN: u32 = 0x020B;
+pub const WM_XBUTTONUP: u32 = 0x020C;
+pub const WM_MOUSEHWHEEL: u32 = 0x020E;
+pub const WM_DPICHANGED: u32 = 0x02E0;
+
+const SIZE_MINIMIZED: usize = 1;
+const WHEEL_DELTA: f32 = 120.0;
+const XBUTTON1: u16 = 0x0001;
+
+fn low_word(value: usize) -> u16 {
+    (value & 0xffff) as u16
+}
+
+fn high_word(value: usize) -> u16 {
+    ((value >> 16) & 0xffff) as u16
+}
+
+// Signed client coordinates packed into lParam (GET_X_LPARAM/GET_Y_LPARAM)
+fn cursor_position(l_param: isize) -> (i32, i32) {
+    let x = low_word(l_param as usize) as i16 as i32;
+    let y = high_word(l_param as usize) as i16 as i32;
+    (x, y)
+}
+
+// Modifier state is tracked from the key messages themselves, and characters
+// outside the BMP arrive as two WM_CHAR surrogates, so translation keeps a
+// little state between messages.
+#[derive(Default)]
+pub struct MessageTranslator {
+    modifiers: Modifiers,
+    high_surrogate: Option<u16>,
+}
+
+impl MessageTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    fn set_modifier(&mut self, key: Key, down: bool) {
+        match key {
+            Key::Shift => self.modifiers.shift = down,
+            Key::Control => self.modifiers.control = down,
+            Key::Alt => self.modifiers.alt = down,
+            _ => {}
+        }
+    }
+
+    pub fn translate(&mut self, message: u32, w_param: usize, l_param: isize) -> Option<Event> {
+        match message {
+            WM_CLOSE => Some(Event::CloseRequested),
+            WM_SIZE if w_param != SIZE_MINIMIZED => Some(Event::Resized {
+                width: low_word(l_param as usize) as u32,
+                height: high_word(l_param as usize) as u32,
+            }),
+            WM_SETFOCUS => Some(Event::Focused(true)),
+            WM_KILLFOCUS => {
+                // Key ups for held keys go to whoever has focus now
+                self.modifiers = Modifiers::default();
+                Some(Event::Focused(false))
+            }
+            WM_KEYDOWN | WM_SYSKEYDOWN => {
+                let key = Key::from_vk(w_param as u32);
+                self.set_modifier(key, true);
+                // Bit 29 is set while Alt is held for WM_SYSKEYDOWN
+                if message == WM_SYSKEYDOWN && l_param & (1 << 29) != 0 {
+                    self.modifiers.alt = true;
+                }
+                Some(Event::KeyDown {
+                    key,
+                    modifiers: self.modifiers,
+                    // Bit 30 is the previous key state
+                    repeat: l_param & (1 << 30) != 0,
+                })
+            }
+            WM_KEYUP | WM_SYSKEYUP => {
+                let key = Key::from_vk(w_param as u32);
+                self.set_modifier(key, false);
+                Some(Event::KeyUp {
+                    key,
+                    modifiers: self.modifiers,
+                })
+            }
+            WM_CHAR => {
+                let unit = w_param as u16;
+                match unit {
+                    0xD800..=0xDBFF => {
+                        self.high_surrogate = Some(unit);
+                        None
+                    }
+                    0xDC00..=0xDFFF => {
+                        let high = self.high_surrogate.take()?;
+                        std::char::decode_utf16([high, unit].iter().copied())
+                            .next()?
+                            .ok()
+                            .map(Event::Char)
+                    }
+                    _ => std::char::from_u32(unit as u32).map(Event::Char),
+                }
+            }
+            WM_MOUSEMOVE => {
+                let (x, y) = cursor_position(l_param);
+                Some(Event::MouseMove { x, y })
+            }
+            WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN | WM_XBUTTONDOWN => {
+                let (x, y) = cursor_position(l_param);
+                Some(Event::MouseDown {
+                    button: mouse_button(message, w_param),
+                    x,
+                    y,
+                })
+            }
+            WM_LBUTTONUP | WM_RBUTTONUP | WM_MBUTTONUP | WM_XBUTTONUP => {
+                let (x, y) = cursor_position(l_param);
+                Some(Event::MouseUp {
+                    button: mouse_button(message, w_param),
+                    x,
+                    y,
+                })
+            }
+            WM_MOUSEWHEEL | WM_MOUSEHWHEEL => {
+                let delta = high_word(w_param) as i16 as f32 / WHEEL_DELTA;
+                Some(if message == WM_MOUSEWHEEL {
+                    Event::MouseWheel {
+                        delta_x: 0.0,
+                        delta_y: delta,
+                    }
+                } else {
+                    Event::MouseWheel {
+                        delta_x: delta,
+                        delta_y: 0.0,
+                    }
+                })
+            }
+            WM_DPICHANGED => Some(Event::DpiChanged {
+                dpi: low_word(w_param) as u32,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn mouse_button(message: u32, w_param: usize) -> MouseButton {
+    match message {
+        WM_LBUTTONDOWN | WM_LBUTTONUP => MouseButton::Left,
+        WM_RBUTTONDOWN | WM_RBUTTONUP => MouseButton::Right,
+        WM_MBUTTONDOWN | WM_MBUTTONUP => MouseButton::Middle,
+        // XBUTTON messages say which one in the high word of wParam
+        _ if high_word(w_param) == XBUTTON1 => MouseButton::X1,
+        _ => MouseButton::X2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs two words into an lParam or wParam, low word first
+    fn words(low: u16, high: u16) -> usize {
+        (high as usize) << 16 | low as usize
+    }
+
+    const VK_F4: usize = 0x73;
+    const VK_MENU: usize = 0x12;
+    const SIZE_RESTORED: usize = 0;
+    const SIZE_MAXIMIZED: usize = 2;
+
+    #[test]
+    fn resizes_unless_minimized() {
+        let mut translator = MessageTranslator::new();
+        assert_eq!(
+            translator.translate(WM_SIZE, SIZE_RESTORED, words(800, 600) as isize),
+            Some(Event::Resized {
+                width: 800,
+                height: 600
+            })
+        );
+        assert_eq!(
+            translator.translate(WM_SIZE, SIZE_MAXIMIZED, words(1920, 1017) as isize),
+            Some(Event::Resized {
+                width: 1920,
+                height: 1017
+            })
+        );
+        // Minimizing reports 0x0, which isn't a size to render at
+        assert_eq!(translator.translate(WM_SIZE, SIZE_MINIMIZED, 0), None);
+    }
+
+    #[test]
+    fn key_repeat_bit() {
+        let mut translator = MessageTranslator::new();
+        let first = translator.translate(WM_KEYDOWN, 0x41, 1);
+        let held = translator.translate(WM_KEYDOWN, 0x41, 1 | 1 << 30);
+        let key_down = |repeat| {
+            Some(Event::KeyDown {
+                key: Key::Letter('A'),
+                modifiers: Modifiers::default(),
+                repeat,
+            })
+        };
+        assert_eq!(first, key_down(false));
+        assert_eq!(held, key_down(true));
+    }
+
+    #[test]
+    fn alt_keys_come_as_system_keys() {
+        let mut translator = MessageTranslator::new();
+        // Alt itself, then F4 while it's held, context bit 29 set on both
+        let context = 1 << 29 | 1;
+        translator.translate(WM_SYSKEYDOWN, VK_MENU, context);
+        let alt = Modifiers {
+            alt: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(translator.modifiers(), alt);
+        assert_eq!(
+            translator.translate(WM_SYSKEYDOWN, VK_F4, context),
+            Some(Event::KeyDown {
+                key: Key::F(4),
+                modifiers: alt,
+                repeat: false
+            })
+        );
+        assert_eq!(
+            translator.translate(WM_SYSKEYUP, VK_MENU, 0),
+            Some(Event::KeyUp {
+                key: Key::Alt,
+                modifiers: Modifiers::default()
+            })
+        );
+
+        // F10 also comes as a system key, but without Alt
+        let mut translator = MessageTranslator::new();
+        assert_eq!(
+            translator.translate(WM_SYSKEYDOWN, 0x79, 1),
+            Some(Event::KeyDown {
+                key: Key::F(10),
+                modifiers: Modifiers::default(),
+                repeat: false
+            })
+        );
+    }
+
+    #[test]
+    fn focus_loss_clears_modifiers() {
+        let mut translator = MessageTranslator::new();
+        translator.translate(WM_KEYDOWN, 0x10, 1);
+        assert!(translator.modifiers().shift);
+        assert_eq!(
+            translator.translate(WM_KILLFOCUS, 0, 0),
+            Some(Event::Focused(false))
+        );
+        assert_eq!(translator.modifiers(), Modifiers::default());
+    }
+
+    #[test]
+    fn joins_surrogate_pairs() {
+        let mut translator = MessageTranslator::new();
+        assert_eq!(
+            translator.translate(WM_CHAR, 'é' as usize, 1),
+            Some(Event::Char('é'))
+        );
+        // U+1F600 is D83D DE00 in UTF-16
+        assert_eq!(translator.translate(WM_CHAR, 0xD83D, 1), None);
+        assert_eq!(
+            translator.translate(WM_CHAR, 0xDE00, 1),
+            Some(Event::Char('\u{1F600}'))
+        );
+        // A low surrogate on its own is dropped
+        assert_eq!(translator.translate(WM_CHAR, 0xDE00, 1), None);
+    }
+
+    #[test]
+    fn extra_mouse_buttons() {
+        let mut translator = MessageTranslator::new();
+        let position = words(10, 20) as isize;
+        assert_eq!(
+            translator.translate(WM_XBUTTONDOWN, words(0, 1), position),
+            Some(Event::MouseDown {
+                button: MouseButton::X1,
+                x: 10,
+                y: 20
+            })
+        );
+        assert_eq!(
+            translator.translate(WM_XBUTTONUP, words(0, 2), position),
+            Some(Event::MouseUp {
+                button: MouseButton::X2,
+                x: 10,
+                y: 20
+            })
+        );
+    }
+
+    #[test]
+    fn negative_cursor_positions() {
+        // Captured drags outside the client area go negative
+        let mut translator = MessageTranslator::new();
+        assert_eq!(
+            translator.translate(WM_MOUSEMOVE, 0, words(-5i16 as u16, -1i16 as u16) as isize),
+            Some(Event::MouseMove { x: -5, y: -1 })
+        );
+    }
+
+    #[test]
+    fn wheel_delta_signs() {
+        let mut translator = MessageTranslator::new();
+        // Away from the user is positive, towards is negative
+        assert_eq!(
+            translator.translate(WM_MOUSEWHEEL, words(0, 120), 0),
+            Some(Event::MouseWheel {
+                delta_x: 0.0,
+                delta_y: 1.0
+            })
+        );
+        assert_eq!(
+            translator.translate(WM_MOUSEWHEEL, words(0, -240i16 as u16), 0),
+            Some(Event::MouseWheel {
+                delta_x: 0.0,
+                delta_y: -2.0
+            })
+        );
+        // Tilting right is positive
+        assert_eq!(
+            translator.translate(WM_MOUSEHWHEEL, words(0, 60), 0),
+            Some(Event::MouseWheel {
+                delta_x: 0.5,
+                delta_y: 0.0
+            })
+        );
+    }
+}
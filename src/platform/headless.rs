@@ -0,0 +1,86 @@
+// Window without a screen. Each poll_events call hands out the next frame of a
+// script, so the app loop can run without Win32.
+use std::collections::VecDeque;
+use std::io::Error;
+
+use super::{Event, Platform, Window, WindowDesc, DEFAULT_DPI};
+
+#[derive(Default)]
+pub struct HeadlessPlatform {
+    script: Vec<Vec<Event>>,
+}
+
+impl HeadlessPlatform {
+    // One entry per frame. Once the script runs out the window asks to close,
+    // which ends any loop that honours close requests.
+    pub fn new(script: Vec<Vec<Event>>) -> Self {
+        Self { script }
+    }
+}
+
+impl Platform for HeadlessPlatform {
+    type Window = HeadlessWindow;
+
+    fn create_window(&mut self, desc: &WindowDesc) -> Result<HeadlessWindow, Error> {
+        Ok(HeadlessWindow {
+            frames: self.script.drain(..).collect(),
+            width: desc.rect.width().max(0) as u32,
+            height: desc.rect.height().max(0) as u32,
+            dpi: DEFAULT_DPI,
+            close_requested: false,
+            frame: 0,
+        })
+    }
+}
+
+pub struct HeadlessWindow {
+    frames: VecDeque<Vec<Event>>,
+    width: u32,
+    height: u32,
+    dpi: u32,
+    close_requested: bool,
+    frame: u64,
+}
+
+impl HeadlessWindow {
+    // Number of poll_events calls so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+impl Window for HeadlessWindow {
+    fn poll_events(&mut self, events: &mut Vec<Event>) {
+        self.frame += 1;
+        let frame = match self.frames.pop_front() {
+            Some(frame) => frame,
+            None if self.close_requested => return,
+            None => vec![Event::CloseRequested],
+        };
+
+        for event in frame {
+            match event {
+                Event::CloseRequested => self.close_requested = true,
+                Event::Resized { width, height } => {
+                    self.width = width;
+                    self.height = height;
+                }
+                Event::DpiChanged { dpi } => self.dpi = dpi,
+                _ => {}
+            }
+            events.push(event);
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn dpi(&self) -> u32 {
+        self.dpi
+    }
+
+    fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+}
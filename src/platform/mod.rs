@@ -0,0 +1,40 @@
+// Windowing behind a trait so the app loop doesn't care whether it runs on a
+// real Win32 window or a scripted headless one.
+use std::io::Error;
+
+pub mod event;
+pub mod headless;
+#[cfg(windows)]
+pub mod win32;
+
+pub use self::event::{Event, Key, Modifiers, MouseButton};
+
+// Windows' baseline DPI, dpi_scale() is relative to this
+pub const DEFAULT_DPI: u32 = 96;
+
+#[derive(Clone, Debug)]
+pub struct WindowDesc {
+    pub title: String,
+    // Outer window rect and whether it has a frame, see display::window_rect
+    pub rect: crate::display::Rect,
+    pub style: crate::display::WindowStyle,
+}
+
+pub trait Window {
+    // Appends the events that arrived since the last call
+    fn poll_events(&mut self, events: &mut Vec<Event>);
+    // Client area size in pixels
+    fn size(&self) -> (u32, u32);
+    fn dpi(&self) -> u32;
+    fn close_requested(&self) -> bool;
+
+    fn dpi_scale(&self) -> f32 {
+        self.dpi() as f32 / DEFAULT_DPI as f32
+    }
+}
+
+pub trait Platform {
+    type Window: Window;
+
+    fn create_window(&mut self, desc: &WindowDesc) -> Result<Self::Window, Error>;
+}
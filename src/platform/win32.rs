@@ -0,0 +1,268 @@
+use std::io::Error;
+use std::mem;
+use std::mem::size_of;
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::{DWORD, FALSE, LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::{HICON, HWND, RECT};
+use winapi::um::winuser::*;
+
+use super::event::MessageTranslator;
+use super::{Event, Platform, Window, WindowDesc, DEFAULT_DPI};
+use crate::display;
+
+pub fn win32_string(value: &str) -> Vec<u16> {
+    use std::ffi::OsStr;
+    use std::iter::once;
+    use std::os::windows::ffi::OsStrExt;
+    OsStr::new(value).encode_wide().chain(once(0)).collect()
+}
+
+pub fn window_style(style: display::WindowStyle) -> DWORD {
+    match style {
+        display::WindowStyle::Overlapped => WS_OVERLAPPEDWINDOW,
+        display::WindowStyle::Popup => WS_POPUP,
+    }
+}
+
+// Size of the overlapped window frame around the client area
+pub fn frame_insets() -> display::FrameInsets {
+    unsafe {
+        let mut rect: RECT = mem::zeroed();
+        AdjustWindowRect(&mut rect, WS_OVERLAPPEDWINDOW, FALSE);
+        display::FrameInsets {
+            left: -rect.left,
+            top: -rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+}
+
+// Written to by window_proc through GWLP_USERDATA
+struct WindowState {
+    translator: MessageTranslator,
+    events: Vec<Event>,
+    width: u32,
+    height: u32,
+    dpi: u32,
+    close_requested: bool,
+}
+
+pub struct Win32Platform {
+    class_name: Vec<u16>,
+}
+
+impl Win32Platform {
+    pub fn new(class_name: &str) -> Result<Self, Error> {
+        //Convert strings to correct format
+        let class_name = win32_string(class_name);
+
+        unsafe {
+            let hinstance = winapi::um::libloaderapi::GetModuleHandleW(null_mut());
+            let wnd_class = WNDCLASSEXW {
+                cbSize: size_of::<WNDCLASSEXW>() as u32,
+                hIconSm: 0 as HICON,
+                style: CS_HREDRAW | CS_VREDRAW,
+                lpfnWndProc: Some(window_proc),
+                hInstance: hinstance,
+                lpszClassName: class_name.as_ptr(),
+                cbClsExtra: 0,
+                cbWndExtra: 0,
+                hIcon: null_mut(),
+                hCursor: null_mut(),
+                hbrBackground: null_mut(),
+                lpszMenuName: null_mut(),
+            };
+
+            if RegisterClassExW(&wnd_class) == 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        Ok(Self { class_name })
+    }
+}
+
+impl Platform for Win32Platform {
+    type Window = Win32Window;
+
+    fn create_window(&mut self, desc: &WindowDesc) -> Result<Win32Window, Error> {
+        let title = win32_string(&desc.title);
+        let mut state = Box::new(WindowState {
+            translator: MessageTranslator::new(),
+            events: Vec::new(),
+            width: 0,
+            height: 0,
+            dpi: DEFAULT_DPI,
+            close_requested: false,
+        });
+
+        unsafe {
+            let hinstance = winapi::um::libloaderapi::GetModuleHandleW(null_mut());
+            let handle = CreateWindowExW(
+                0,
+                self.class_name.as_ptr(),
+                title.as_ptr(),
+                window_style(desc.style) | WS_VISIBLE,
+                desc.rect.left,
+                desc.rect.top,
+                desc.rect.width(),
+                desc.rect.height(),
+                null_mut(),
+                null_mut(),
+                hinstance,
+                // Picked up by window_proc in WM_NCCREATE
+                &mut *state as *mut WindowState as _,
+            );
+
+            if handle.is_null() {
+                return Err(Error::last_os_error());
+            }
+
+            let mut rect: RECT = mem::zeroed();
+            GetClientRect(handle, &mut rect);
+            state.width = (rect.right - rect.left) as u32;
+            state.height = (rect.bottom - rect.top) as u32;
+            state.dpi = GetDpiForWindow(handle);
+            // Creation messages aren't interesting to the app
+            state.events.clear();
+
+            Ok(Win32Window { handle, state })
+        }
+    }
+}
+
+pub struct Win32Window {
+    handle: HWND,
+    state: Box<WindowState>,
+}
+
+impl Win32Window {
+    pub fn handle(&self) -> HWND {
+        self.handle
+    }
+
+    // Change the frame style and outer rect, used when switching display modes
+    pub fn set_placement(&self, style: display::WindowStyle, rect: display::Rect) {
+        unsafe {
+            SetWindowLongPtrW(
+                self.handle,
+                GWL_STYLE,
+                (window_style(style) | WS_VISIBLE) as isize,
+            );
+            SetWindowPos(
+                self.handle,
+                null_mut(),
+                rect.left,
+                rect.top,
+                rect.width(),
+                rect.height(),
+                SWP_FRAMECHANGED | SWP_NOZORDER,
+            );
+        }
+    }
+}
+
+impl Drop for Win32Window {
+    fn drop(&mut self) {
+        unsafe {
+            // window_proc must not touch the state once it's freed
+            SetWindowLongPtrW(self.handle, GWLP_USERDATA, 0);
+            DestroyWindow(self.handle);
+        }
+    }
+}
+
+impl Window for Win32Window {
+    // Drains the whole message queue. window_proc turns the messages into
+    // events as they are dispatched.
+    fn poll_events(&mut self, events: &mut Vec<Event>) {
+        unsafe {
+            let mut message: MSG = mem::zeroed();
+            while PeekMessageW(&mut message as *mut MSG, null_mut(), 0, 0, PM_REMOVE) != 0 {
+                if message.message == WM_QUIT {
+                    self.state.close_requested = true;
+                    self.state.events.push(Event::CloseRequested);
+                    break;
+                }
+                TranslateMessage(&message as *const MSG);
+                DispatchMessageW(&message as *const MSG);
+            }
+        }
+        events.append(&mut self.state.events);
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.state.width, self.state.height)
+    }
+
+    fn dpi(&self) -> u32 {
+        self.state.dpi
+    }
+
+    fn close_requested(&self) -> bool {
+        self.state.close_requested
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    u_msg: UINT,
+    w_param: WPARAM,
+    l_param: LPARAM,
+) -> LRESULT {
+    if u_msg == WM_NCCREATE {
+        let create_struct = &*(l_param as *const CREATESTRUCTW);
+        SetWindowLongPtrW(hwnd, GWLP_USERDATA, create_struct.lpCreateParams as isize);
+    }
+
+    if let Some(state) = (GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut WindowState).as_mut() {
+        let event = state.translator.translate(u_msg, w_param, l_param);
+        match event {
+            // Leave closing to the app
+            Some(Event::CloseRequested) => {
+                state.close_requested = true;
+                state.events.push(Event::CloseRequested);
+                return 0;
+            }
+            Some(Event::Resized { width, height }) => {
+                if (width, height) != (state.width, state.height) {
+                    state.width = width;
+                    state.height = height;
+                    state.events.push(Event::Resized { width, height });
+                }
+            }
+            Some(Event::DpiChanged { dpi }) => {
+                state.dpi = dpi;
+                state.events.push(Event::DpiChanged { dpi });
+
+                // Windows suggests a rect that keeps the window the same physical size
+                let suggested = &*(l_param as *const RECT);
+                SetWindowPos(
+                    hwnd,
+                    null_mut(),
+                    suggested.left,
+                    suggested.top,
+                    suggested.right - suggested.left,
+                    suggested.bottom - suggested.top,
+                    SWP_NOZORDER | SWP_NOACTIVATE,
+                );
+                return 0;
+            }
+            Some(event) => state.events.push(event),
+            None => {}
+        }
+    }
+
+    match u_msg {
+        WM_DESTROY => {
+            PostQuitMessage(0);
+            return 0;
+        }
+        // Alt+Enter would otherwise beep as an unknown menu accelerator
+        WM_SYSCHAR if w_param == '\r' as WPARAM => return 0,
+        _ => 0,
+    };
+    DefWindowProcW(hwnd, u_msg, w_param, l_param)
+}
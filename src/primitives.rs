@@ -0,0 +1,599 @@
+// Meshes for common shapes, centered on the origin. Left handed like the rest
+// of the renderer: triangles are clockwise seen from the front, normals point
+// out, u runs to the right and v down when looking at the front of a face.
+// Flat 2D shapes lie in the XY plane facing -z, towards the default camera.
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use crate::mesh::Mesh;
+use crate::vertex::Vertex;
+
+type Vec3 = (f32, f32, f32);
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    (a.0 + b.0, a.1 + b.1, a.2 + b.2)
+}
+
+fn scale(a: Vec3, s: f32) -> Vec3 {
+    (a.0 * s, a.1 * s, a.2 * s)
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+fn normalize(a: Vec3) -> Vec3 {
+    let length = (a.0 * a.0 + a.1 * a.1 + a.2 * a.2).sqrt();
+    if length > 0.0 {
+        scale(a, 1.0 / length)
+    } else {
+        a
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl Builder {
+    fn vertex(&mut self, pos: Vec3, normal: Vec3, uv: (f32, f32)) -> u32 {
+        self.vertices.push(Vertex::new(pos, normal, uv));
+        (self.vertices.len() - 1) as u32
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    // Corners clockwise seen from the front
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    // Rows of vertices from `rows + 1` rings of `columns + 1` vertices each,
+    // as added by the caller, v going down the rows
+    fn grid_indices(&mut self, first: u32, columns: u32, rows: u32) {
+        for row in 0..rows {
+            for column in 0..columns {
+                let a = first + row * (columns + 1) + column;
+                let d = a + columns + 1;
+                self.quad(a, a + 1, d + 1, d);
+            }
+        }
+    }
+
+    // A flat rectangle split into cells. `down` is the direction of +v.
+    #[allow(clippy::too_many_arguments)]
+    fn face(
+        &mut self,
+        center: Vec3,
+        normal: Vec3,
+        down: Vec3,
+        width: f32,
+        height: f32,
+        columns: u32,
+        rows: u32,
+    ) {
+        let right = normalize(cross(down, normal));
+        let origin = add(
+            center,
+            add(scale(right, -width / 2.0), scale(down, -height / 2.0)),
+        );
+        let first = self.vertices.len() as u32;
+        for row in 0..=rows {
+            let v = row as f32 / rows as f32;
+            for column in 0..=columns {
+                let u = column as f32 / columns as f32;
+                let pos = add(
+                    origin,
+                    add(scale(right, u * width), scale(down, v * height)),
+                );
+                self.vertex(pos, normal, (u, v));
+            }
+        }
+        self.grid_indices(first, columns, rows);
+    }
+
+    // Fan around a center vertex. `outline` goes clockwise seen from the
+    // front, which faces -z, and is mapped into UV space by `extent`, the
+    // half width and height.
+    fn fan(&mut self, outline: &[(f32, f32)], extent: (f32, f32)) {
+        let normal = (0.0, 0.0, -1.0);
+        let uv = |x: f32, y: f32| (0.5 + x / (2.0 * extent.0), 0.5 - y / (2.0 * extent.1));
+        let center = self.vertex((0.0, 0.0, 0.0), normal, (0.5, 0.5));
+        let first = self.vertices.len() as u32;
+        for &(x, y) in outline {
+            self.vertex((x, y, 0.0), normal, uv(x, y));
+        }
+        let count = outline.len() as u32;
+        for i in 0..count {
+            self.triangle(center, first + i, first + (i + 1) % count);
+        }
+    }
+
+    // Disc at height `y` facing straight up or down
+    fn cap(&mut self, radius: f32, y: f32, segments: u32, up: bool) {
+        let normal = (0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let center = self.vertex((0.0, y, 0.0), normal, (0.5, 0.5));
+        let first = self.vertices.len() as u32;
+        for i in 0..segments {
+            let (sin, cos) = (TAU * i as f32 / segments as f32).sin_cos();
+            // Seen from above +z is up on the texture, from below it's down
+            let v = if up { 0.5 - sin / 2.0 } else { 0.5 + sin / 2.0 };
+            self.vertex(
+                (cos * radius, y, sin * radius),
+                normal,
+                (0.5 + cos / 2.0, v),
+            );
+        }
+        for i in 0..segments {
+            let (a, b) = (first + i, first + (i + 1) % segments);
+            if up {
+                self.triangle(center, b, a);
+            } else {
+                self.triangle(center, a, b);
+            }
+        }
+    }
+
+    fn finish(self) -> Mesh {
+        let mut mesh = Mesh::new(self.vertices, self.indices);
+        mesh.compute_tangents();
+        mesh
+    }
+}
+
+// Direction around the y axis for u, starting at +x and moving to +z, so that u
+// increases to the right on the side facing the camera
+fn around_y(u: f32) -> (f32, f32) {
+    let (sin, cos) = (TAU * u).sin_cos();
+    (cos, sin)
+}
+
+pub fn quad(width: f32, height: f32) -> Mesh {
+    let mut builder = Builder::default();
+    builder.face(
+        (0.0, 0.0, 0.0),
+        (0.0, 0.0, -1.0),
+        (0.0, -1.0, 0.0),
+        width,
+        height,
+        1,
+        1,
+    );
+    builder.finish()
+}
+
+// Flat ground in the XZ plane facing up, split into columns along x and rows
+// along z
+pub fn plane_grid(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh {
+    let mut builder = Builder::default();
+    builder.face(
+        (0.0, 0.0, 0.0),
+        (0.0, 1.0, 0.0),
+        (0.0, 0.0, -1.0),
+        width,
+        depth,
+        columns.max(1),
+        rows.max(1),
+    );
+    builder.finish()
+}
+
+// Polygon with `segments` sides, the first corner at the top
+pub fn circle(radius: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let outline: Vec<(f32, f32)> = (0..segments)
+        .map(|i| {
+            let (sin, cos) = (FRAC_PI_2 - TAU * i as f32 / segments as f32).sin_cos();
+            (cos * radius, sin * radius)
+        })
+        .collect();
+    let mut builder = Builder::default();
+    builder.fan(&outline, (radius, radius));
+    builder.finish()
+}
+
+// Rectangle with each corner rounded off by a quarter circle of
+// `corner_segments` pieces. The radius is limited to half the shorter side.
+pub fn rounded_rect(width: f32, height: f32, radius: f32, corner_segments: u32) -> Mesh {
+    let radius = radius.clamp(0.0, width.min(height) / 2.0);
+    let (half_width, half_height) = (width / 2.0, height / 2.0);
+    // Corner centers clockwise from the top right, with the angle each arc
+    // starts at
+    let corners = [
+        (half_width - radius, half_height - radius, FRAC_PI_2),
+        (half_width - radius, radius - half_height, 0.0),
+        (radius - half_width, radius - half_height, -FRAC_PI_2),
+        (radius - half_width, half_height - radius, -PI),
+    ];
+    let steps = if radius > 0.0 {
+        corner_segments.max(1)
+    } else {
+        0
+    };
+    let mut outline = Vec::new();
+    for &(cx, cy, start) in &corners {
+        for step in 0..=steps {
+            let angle = start - FRAC_PI_2 * step as f32 / steps.max(1) as f32;
+            let (sin, cos) = angle.sin_cos();
+            outline.push((cx + cos * radius, cy + sin * radius));
+        }
+    }
+    let mut builder = Builder::default();
+    builder.fan(&outline, (half_width, half_height));
+    builder.finish()
+}
+
+// Separate vertices per face, so the edges stay sharp
+pub fn cube(size: f32) -> Mesh {
+    let half = size / 2.0;
+    // Normal and the direction that's down on the face's texture
+    let faces = [
+        ((0.0, 0.0, -1.0), (0.0, -1.0, 0.0)),
+        ((1.0, 0.0, 0.0), (0.0, -1.0, 0.0)),
+        ((0.0, 0.0, 1.0), (0.0, -1.0, 0.0)),
+        ((-1.0, 0.0, 0.0), (0.0, -1.0, 0.0)),
+        ((0.0, 1.0, 0.0), (0.0, 0.0, -1.0)),
+        ((0.0, -1.0, 0.0), (0.0, 0.0, 1.0)),
+    ];
+    let mut builder = Builder::default();
+    for &(normal, down) in &faces {
+        builder.face(scale(normal, half), normal, down, size, size, 1, 1);
+    }
+    builder.finish()
+}
+
+// Latitude/longitude sphere. `segments` around, `rings` from pole to pole,
+// with a seam of duplicated vertices where u wraps.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let mut builder = Builder::default();
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let (sin_lat, cos_lat) = (PI * v).sin_cos();
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let (x, z) = around_y(u);
+            let normal = (x * sin_lat, cos_lat, z * sin_lat);
+            builder.vertex(scale(normal, radius), normal, (u, v));
+        }
+    }
+    // Like grid_indices, minus the triangles that collapse at the poles
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let a = ring * (segments + 1) + segment;
+            let d = a + segments + 1;
+            if ring != 0 {
+                builder.triangle(a, a + 1, d + 1);
+            }
+            if ring != rings - 1 {
+                builder.triangle(a, d + 1, d);
+            }
+        }
+    }
+    builder.finish()
+}
+
+// Subdivided icosahedron, evenly spread triangles unlike uv_sphere. Every
+// subdivision multiplies the triangle count by 4.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&p| normalize(p))
+    .collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                let (pa, pb) = (positions[a as usize], positions[b as usize]);
+                positions.push(normalize(scale(add(pa, pb), 0.5)));
+                (positions.len() - 1) as u32
+            })
+        };
+        let mut subdivided = Vec::with_capacity(triangles.len() * 4);
+        for &[a, b, c] in &triangles {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            subdivided.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+        }
+        triangles = subdivided;
+    }
+
+    // Spherical UVs as in uv_sphere. Triangles across the seam get the
+    // vertices on the u = 0 side moved to u = 1, and the poles take the u of
+    // the triangle they're in, so those vertices are split per distinct u.
+    let uv_of = |p: Vec3| {
+        let mut u = p.2.atan2(p.0) / TAU;
+        if u < 0.0 {
+            u += 1.0;
+        }
+        (u, p.1.clamp(-1.0, 1.0).acos() / PI)
+    };
+    let mut builder = Builder::default();
+    let mut split: HashMap<(u32, u32), u32> = HashMap::new();
+    for triangle in &triangles {
+        let mut uvs = triangle.map(|i| uv_of(positions[i as usize]));
+        let (min, max) = uvs.iter().fold((1.0f32, 0.0f32), |(min, max), uv| {
+            (min.min(uv.0), max.max(uv.0))
+        });
+        if max - min > 0.5 {
+            for uv in uvs.iter_mut().filter(|uv| uv.0 < 0.5) {
+                uv.0 += 1.0;
+            }
+        }
+        for corner in 0..3 {
+            if positions[triangle[corner] as usize].1.abs() > 0.9999 {
+                uvs[corner].0 = (uvs[(corner + 1) % 3].0 + uvs[(corner + 2) % 3].0) / 2.0;
+            }
+        }
+
+        let mut corners = [0; 3];
+        for corner in 0..3 {
+            let index = triangle[corner];
+            let uv = uvs[corner];
+            corners[corner] = *split.entry((index, uv.0.to_bits())).or_insert_with(|| {
+                let normal = positions[index as usize];
+                builder.vertex(scale(normal, radius), normal, uv)
+            });
+        }
+        builder.triangle(corners[0], corners[1], corners[2]);
+    }
+    builder.finish()
+}
+
+// Along the y axis, with caps
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let half = height / 2.0;
+    let mut builder = Builder::default();
+    let first = builder.vertices.len() as u32;
+    for (y, v) in [(half, 0.0), (-half, 1.0)] {
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let (x, z) = around_y(u);
+            builder.vertex((x * radius, y, z * radius), (x, 0.0, z), (u, v));
+        }
+    }
+    builder.grid_indices(first, segments, 1);
+    builder.cap(radius, half, segments, true);
+    builder.cap(radius, -half, segments, false);
+    builder.finish()
+}
+
+// Along the y axis, base down, with a separate tip vertex per segment so each
+// side gets its own normal there
+pub fn cone(radius: f32, height: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let half = height / 2.0;
+    let normal = |u: f32| {
+        let (x, z) = around_y(u);
+        normalize((x * height, radius, z * height))
+    };
+    let mut builder = Builder::default();
+    let tips = builder.vertices.len() as u32;
+    for segment in 0..segments {
+        let u = (segment as f32 + 0.5) / segments as f32;
+        builder.vertex((0.0, half, 0.0), normal(u), (u, 0.0));
+    }
+    let base = builder.vertices.len() as u32;
+    for segment in 0..=segments {
+        let u = segment as f32 / segments as f32;
+        let (x, z) = around_y(u);
+        builder.vertex((x * radius, -half, z * radius), normal(u), (u, 1.0));
+    }
+    for segment in 0..segments {
+        builder.triangle(tips + segment, base + segment + 1, base + segment);
+    }
+    builder.cap(radius, -half, segments, false);
+    builder.finish()
+}
+
+// Ring around the y axis. `major_radius` to the middle of the tube,
+// `minor_radius` of the tube itself.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> Mesh {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let mut builder = Builder::default();
+    // Starts at the top of the tube, v going down its outside
+    for minor in 0..=minor_segments {
+        let v = minor as f32 / minor_segments as f32;
+        let (sin, cos) = (FRAC_PI_2 - TAU * v).sin_cos();
+        for major in 0..=major_segments {
+            let u = major as f32 / major_segments as f32;
+            let (x, z) = around_y(u);
+            let normal = (x * cos, sin, z * cos);
+            let center = (x * major_radius, 0.0, z * major_radius);
+            builder.vertex(add(center, scale(normal, minor_radius)), normal, (u, v));
+        }
+    }
+    builder.grid_indices(0, major_segments, minor_segments);
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(mesh: &Mesh) -> (usize, usize) {
+        (mesh.vertices.len(), mesh.index_count())
+    }
+
+    #[test]
+    fn vertex_and_index_counts() {
+        assert_eq!(counts(&quad(2.0, 1.0)), (4, 6));
+        assert_eq!(counts(&plane_grid(1.0, 1.0, 3, 2)), (12, 36));
+        assert_eq!(counts(&circle(1.0, 8)), (9, 24));
+        // Three outline points per corner, or just the corners when square
+        assert_eq!(counts(&rounded_rect(2.0, 1.0, 0.25, 2)), (13, 36));
+        assert_eq!(counts(&rounded_rect(2.0, 1.0, 0.0, 2)), (5, 12));
+        assert_eq!(counts(&cube(1.0)), (24, 36));
+        // 5 rings of 9, minus one triangle per segment at each pole
+        assert_eq!(counts(&uv_sphere(1.0, 8, 4)), (45, 144));
+        assert_eq!(counts(&cylinder(1.0, 2.0, 8)), (36, 96));
+        assert_eq!(counts(&cone(1.0, 2.0, 8)), (26, 48));
+        assert_eq!(counts(&torus(1.0, 0.25, 8, 4)), (45, 192));
+        for subdivisions in 0..3 {
+            let mesh = icosphere(1.0, subdivisions);
+            assert_eq!(mesh.index_count(), 60 * 4usize.pow(subdivisions));
+        }
+        // Too few segments are raised to the minimum
+        assert_eq!(counts(&circle(1.0, 1)), (4, 9));
+    }
+
+    // Positions rounded so vertices split along seams and hard edges meet
+    fn key(mesh: &Mesh, index: usize) -> (i32, i32, i32) {
+        let p = mesh.vertices[index].pos;
+        let round = |x: f32| (x * 1e4).round() as i32;
+        (round(p.x), round(p.y), round(p.z))
+    }
+
+    // Every edge of a closed surface with consistent winding is walked once
+    // in each direction, by the two triangles it separates
+    fn assert_closed(name: &str, mesh: &Mesh) {
+        let mut edges: HashMap<_, u32> = HashMap::new();
+        for triangle in mesh.triangles() {
+            for i in 0..3 {
+                let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+                let edge = (key(mesh, a), key(mesh, b));
+                assert_ne!(edge.0, edge.1, "{} has a degenerate edge", name);
+                *edges.entry(edge).or_default() += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(
+                count, 1,
+                "{} walks {:?} -> {:?} {} times",
+                name, a, b, count
+            );
+            assert_eq!(
+                edges.get(&(b, a)),
+                Some(&1),
+                "{} has an open or flipped edge {:?} -> {:?}",
+                name,
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn solids_are_closed() {
+        assert_closed("cube", &cube(1.0));
+        assert_closed("uv_sphere", &uv_sphere(1.0, 12, 6));
+        assert_closed("icosphere", &icosphere(1.0, 2));
+        assert_closed("cylinder", &cylinder(0.5, 2.0, 10));
+        assert_closed("cone", &cone(0.5, 1.0, 10));
+        assert_closed("torus", &torus(1.0, 0.3, 12, 8));
+    }
+
+    fn sub(a: Vec3, b: Vec3) -> Vec3 {
+        (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+    }
+
+    fn dot(a: Vec3, b: Vec3) -> f32 {
+        a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+    }
+
+    // Clockwise seen from the front means cross(b - a, c - a) points out of
+    // the front, the same way as the vertex normals
+    fn assert_winding_matches_normals(name: &str, mesh: &Mesh) {
+        let position = |i: usize| {
+            let p = mesh.vertices[i].pos;
+            (p.x, p.y, p.z)
+        };
+        let normal = |i: usize| {
+            let n = mesh.vertices[i].normal;
+            (n.x, n.y, n.z)
+        };
+        for [a, b, c] in mesh.triangles() {
+            let face = cross(sub(position(b), position(a)), sub(position(c), position(a)));
+            let vertex_normals = add(add(normal(a), normal(b)), normal(c));
+            assert!(
+                dot(face, vertex_normals) > 0.0,
+                "{} triangle {:?} faces away from its normals",
+                name,
+                [a, b, c]
+            );
+        }
+    }
+
+    #[test]
+    fn normals_agree_with_winding() {
+        assert_winding_matches_normals("quad", &quad(2.0, 1.0));
+        assert_winding_matches_normals("plane_grid", &plane_grid(2.0, 2.0, 3, 3));
+        assert_winding_matches_normals("circle", &circle(1.0, 7));
+        assert_winding_matches_normals("rounded_rect", &rounded_rect(2.0, 1.0, 0.3, 3));
+        assert_winding_matches_normals("cube", &cube(1.0));
+        assert_winding_matches_normals("uv_sphere", &uv_sphere(1.0, 12, 6));
+        assert_winding_matches_normals("icosphere", &icosphere(1.0, 2));
+        assert_winding_matches_normals("cylinder", &cylinder(0.5, 2.0, 10));
+        assert_winding_matches_normals("cone", &cone(0.5, 1.0, 10));
+        assert_winding_matches_normals("torus", &torus(1.0, 0.3, 12, 8));
+    }
+
+    #[test]
+    fn flat_shapes_face_the_camera() {
+        for mesh in &[
+            quad(1.0, 1.0),
+            circle(1.0, 6),
+            rounded_rect(1.0, 1.0, 0.2, 2),
+        ] {
+            for vertex in &mesh.vertices {
+                assert_eq!(
+                    (vertex.normal.x, vertex.normal.y, vertex.normal.z),
+                    (0.0, 0.0, -1.0)
+                );
+                assert_eq!(vertex.pos.z, 0.0);
+            }
+        }
+    }
+}
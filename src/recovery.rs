@@ -0,0 +1,263 @@
+// Device lost handling. The backend owns every device dependent resource and
+// knows how to recreate them from their descriptions; DeviceRecovery decides
+// when to tear down and rebuild.
+
+// Same values as in winerror.h, kept here so classification doesn't need winapi
+pub const DXGI_STATUS_OCCLUDED: i32 = 0x087A_0001;
+pub const DXGI_ERROR_DEVICE_REMOVED: i32 = 0x887A_0005_u32 as i32;
+pub const DXGI_ERROR_DEVICE_HUNG: i32 = 0x887A_0006_u32 as i32;
+pub const DXGI_ERROR_DEVICE_RESET: i32 = 0x887A_0007_u32 as i32;
+pub const DXGI_ERROR_DRIVER_INTERNAL_ERROR: i32 = 0x887A_0020_u32 as i32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceLostReason {
+    Removed,
+    Reset,
+    Hung,
+    DriverInternalError,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentResult {
+    Ok,
+    Occluded,
+    DeviceLost(DeviceLostReason),
+    // Any other failure. The device is still usable.
+    Failed(i32),
+}
+
+impl PresentResult {
+    pub fn from_hresult(hr: i32) -> Self {
+        match hr {
+            DXGI_STATUS_OCCLUDED => PresentResult::Occluded,
+            DXGI_ERROR_DEVICE_REMOVED => PresentResult::DeviceLost(DeviceLostReason::Removed),
+            DXGI_ERROR_DEVICE_RESET => PresentResult::DeviceLost(DeviceLostReason::Reset),
+            DXGI_ERROR_DEVICE_HUNG => PresentResult::DeviceLost(DeviceLostReason::Hung),
+            DXGI_ERROR_DRIVER_INTERNAL_ERROR => {
+                PresentResult::DeviceLost(DeviceLostReason::DriverInternalError)
+            }
+            hr if hr < 0 => PresentResult::Failed(hr),
+            _ => PresentResult::Ok,
+        }
+    }
+}
+
+pub trait GraphicsBackend {
+    // Create the device and everything that depends on it, and bind the
+    // pipeline state. Called once at startup and again after device loss.
+    fn create_resources(&mut self) -> Result<(), i32>;
+    // Release everything create_resources made. Must be safe to call on a
+    // partially created backend.
+    fn release_resources(&mut self);
+    fn present(&mut self) -> PresentResult;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryState {
+    Running,
+    // Device is gone, recreation has failed `attempts` times so far
+    Lost { attempts: u32 },
+    Failed,
+}
+
+pub struct DeviceRecovery {
+    state: RecoveryState,
+    max_attempts: u32,
+    recoveries: u32,
+}
+
+impl DeviceRecovery {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            state: RecoveryState::Running,
+            max_attempts,
+            recoveries: 0,
+        }
+    }
+
+    pub fn state(&self) -> RecoveryState {
+        self.state
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == RecoveryState::Running
+    }
+
+    // Times the device has been recreated. Anything the backend can't restore
+    // on its own, like exclusive fullscreen, is reapplied when this changes.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+
+    // Presents while running. Once the device is lost every call makes one
+    // attempt at recreating it until it succeeds or max_attempts is reached.
+    pub fn present<B: GraphicsBackend>(&mut self, backend: &mut B) -> RecoveryState {
+        match self.state {
+            RecoveryState::Running => {
+                if let PresentResult::DeviceLost(reason) = backend.present() {
+                    println!("Device lost: {:?}", reason);
+                    backend.release_resources();
+                    self.state = RecoveryState::Lost { attempts: 0 };
+                    self.recreate(backend);
+                }
+            }
+            RecoveryState::Lost { .. } => self.recreate(backend),
+            RecoveryState::Failed => {}
+        }
+        self.state
+    }
+
+    fn recreate<B: GraphicsBackend>(&mut self, backend: &mut B) {
+        let attempts = match self.state {
+            RecoveryState::Lost { attempts } => attempts,
+            _ => return,
+        };
+
+        match backend.create_resources() {
+            Ok(()) => {
+                println!("Device recreated after {} failed attempts", attempts);
+                self.state = RecoveryState::Running;
+                self.recoveries += 1;
+            }
+            Err(res) => {
+                println!("Error recreating device: {}", res);
+                backend.release_resources();
+                let attempts = attempts + 1;
+                self.state = if attempts >= self.max_attempts {
+                    RecoveryState::Failed
+                } else {
+                    RecoveryState::Lost { attempts }
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Plays back scripted present and create results, counting calls
+    #[derive(Default)]
+    struct FakeBackend {
+        presents: VecDeque<PresentResult>,
+        creates: VecDeque<Result<(), i32>>,
+        created: u32,
+        released: u32,
+    }
+
+    impl GraphicsBackend for FakeBackend {
+        fn create_resources(&mut self) -> Result<(), i32> {
+            self.created += 1;
+            self.creates.pop_front().unwrap_or(Ok(()))
+        }
+
+        fn release_resources(&mut self) {
+            self.released += 1;
+        }
+
+        fn present(&mut self) -> PresentResult {
+            self.presents.pop_front().unwrap_or(PresentResult::Ok)
+        }
+    }
+
+    const E_FAIL: i32 = 0x8000_4005_u32 as i32;
+
+    #[test]
+    fn classifies_hresults() {
+        assert_eq!(PresentResult::from_hresult(0), PresentResult::Ok);
+        assert_eq!(
+            PresentResult::from_hresult(DXGI_STATUS_OCCLUDED),
+            PresentResult::Occluded
+        );
+        assert_eq!(
+            PresentResult::from_hresult(DXGI_ERROR_DEVICE_REMOVED),
+            PresentResult::DeviceLost(DeviceLostReason::Removed)
+        );
+        assert_eq!(
+            PresentResult::from_hresult(DXGI_ERROR_DEVICE_HUNG),
+            PresentResult::DeviceLost(DeviceLostReason::Hung)
+        );
+        assert_eq!(
+            PresentResult::from_hresult(E_FAIL),
+            PresentResult::Failed(E_FAIL)
+        );
+    }
+
+    #[test]
+    fn recovers_after_successful_recreate() {
+        let mut backend = FakeBackend::default();
+        backend
+            .presents
+            .push_back(PresentResult::DeviceLost(DeviceLostReason::Reset));
+        let mut recovery = DeviceRecovery::new(3);
+
+        assert_eq!(recovery.present(&mut backend), RecoveryState::Running);
+        assert_eq!(backend.released, 1);
+        assert_eq!(backend.created, 1);
+        assert!(recovery.is_running());
+        assert_eq!(recovery.recoveries(), 1);
+    }
+
+    #[test]
+    fn retries_then_recovers() {
+        let mut backend = FakeBackend::default();
+        backend
+            .presents
+            .push_back(PresentResult::DeviceLost(DeviceLostReason::Removed));
+        backend.creates.extend(vec![Err(E_FAIL), Ok(())]);
+        let mut recovery = DeviceRecovery::new(3);
+
+        assert_eq!(
+            recovery.present(&mut backend),
+            RecoveryState::Lost { attempts: 1 }
+        );
+        assert!(!recovery.is_running());
+        assert_eq!(recovery.present(&mut backend), RecoveryState::Running);
+        assert_eq!(backend.created, 2);
+        // Once for the loss, once after the failed attempt
+        assert_eq!(backend.released, 2);
+    }
+
+    #[test]
+    fn fails_after_max_attempts() {
+        let mut backend = FakeBackend::default();
+        backend
+            .presents
+            .push_back(PresentResult::DeviceLost(DeviceLostReason::Hung));
+        backend.creates.extend(vec![Err(E_FAIL); 5]);
+        let mut recovery = DeviceRecovery::new(3);
+
+        assert_eq!(
+            recovery.present(&mut backend),
+            RecoveryState::Lost { attempts: 1 }
+        );
+        assert_eq!(
+            recovery.present(&mut backend),
+            RecoveryState::Lost { attempts: 2 }
+        );
+        assert_eq!(recovery.present(&mut backend), RecoveryState::Failed);
+        // No more attempts once failed
+        assert_eq!(recovery.present(&mut backend), RecoveryState::Failed);
+        assert_eq!(backend.created, 3);
+    }
+
+    #[test]
+    fn occluded_and_failed_presents_keep_running() {
+        let mut backend = FakeBackend::default();
+        backend.presents.extend(vec![
+            PresentResult::Occluded,
+            PresentResult::Failed(E_FAIL),
+            PresentResult::Ok,
+        ]);
+        let mut recovery = DeviceRecovery::new(3);
+
+        for _ in 0..3 {
+            assert_eq!(recovery.present(&mut backend), RecoveryState::Running);
+        }
+        assert_eq!(backend.created, 0);
+        assert_eq!(backend.released, 0);
+        assert_eq!(recovery.recoveries(), 0);
+    }
+}
@@ -0,0 +1,1606 @@
+// The D3D11 renderer and the windowed app loop around it. Windows only, the
+// rest of the app is in the library.
+use std::ffi::CString;
+use std::mem;
+use std::ptr::null_mut;
+
+use rust_dx::platform::{Event, Platform, Window as _};
+use rust_dx::recovery::GraphicsBackend;
+use winapi::shared::dxgi::*;
+use winapi::shared::dxgiformat::*;
+use winapi::shared::dxgitype::*;
+use winapi::shared::minwindef::{FALSE, LPCVOID, LPVOID, TRUE, UINT};
+use winapi::shared::windef::{HWND, RECT};
+use winapi::shared::winerror::{E_INVALIDARG, FAILED};
+use winapi::um::d3d11::*;
+use winapi::um::d3dcommon::{
+    ID3DBlob, D3D_DRIVER_TYPE_UNKNOWN, D3D_DRIVER_TYPE_WARP, D3D_FEATURE_LEVEL_11_0,
+    D3D_FEATURE_LEVEL_11_1, D3D_SHADER_MACRO,
+};
+use winapi::um::d3dcompiler::*;
+use winapi::um::unknwnbase::IUnknown;
+use winapi::um::winnt::HRESULT;
+use winapi::um::winuser::GetClientRect;
+use winapi::Interface;
+
+use rust_dx::{
+    actions, adapter, app, archive, assets, display, draw, gamepad, input, material, mesh,
+    pipeline, platform, primitives, recovery, replay, texture, thread_pool, time, vertex,
+};
+
+#[derive(Clone, Copy)]
+struct Window {
+    handle: HWND,
+    width: i32,
+    height: i32,
+}
+
+struct Buffers {
+    // One per entry in Renderer::geometry
+    meshes: Vec<mesh::GpuMesh>,
+    // One per entry in Renderer::textures
+    textures: Vec<texture::Texture>,
+    sampler: Option<texture::Sampler>,
+    // One per entry in Renderer::permutations
+    programs: Vec<pipeline::ShaderProgram>,
+    // For Renderer::pipelines
+    pipeline_objects: pipeline::PipelineObjects,
+    frame_constants: Option<draw::ConstantRingBuffer>,
+    material_constants: Option<draw::ConstantRingBuffer>,
+    object_constants: Option<draw::ConstantRingBuffer>,
+}
+
+struct D11Devices {
+    _device: *mut ID3D11Device,
+    _device_context: *mut ID3D11DeviceContext,
+    _swap_chain: *mut IDXGISwapChain,
+    _back_buffer: *mut ID3D11Texture2D,
+    _render_target: *mut ID3D11RenderTargetView,
+    // Same size as the back buffer
+    _depth_buffer: *mut ID3D11Texture2D,
+    _depth_stencil_view: *mut ID3D11DepthStencilView,
+}
+
+// A shader compiled with a set of defines, and what materials using it have
+// to provide
+struct Permutation {
+    // Index into Renderer::shaders
+    shader: usize,
+    defines: Vec<String>,
+    interface: material::ShaderInterface,
+}
+
+// Everything that has to be rebuilt when the device is lost
+struct Renderer {
+    window: Window,
+    adapter_preference: adapter::AdapterPreference,
+    devices: D11Devices,
+    buffers: Buffers,
+    // CPU side copies so the buffers can be recreated. Draw items refer to
+    // meshes by index into this.
+    geometry: Vec<mesh::Mesh>,
+    // Same for textures, materials refer to them by index
+    textures: Vec<texture::TextureData>,
+    shaders: Vec<assets::Shader>,
+    permutations: Vec<Permutation>,
+    // Draw items refer to these by index
+    materials: Vec<draw::Material>,
+    // And to pipelines by id. Kept when the device is lost, the objects are
+    // made again from it.
+    pipelines: pipeline::PipelineCache,
+}
+
+// Release a COM object and null the pointer. Does nothing for null pointers.
+unsafe fn release<T>(ptr: &mut *mut T) {
+    if let Some(unknown) = (*ptr as *mut IUnknown).as_ref() {
+        unknown.Release();
+    }
+    *ptr = null_mut();
+}
+
+impl recovery::GraphicsBackend for Renderer {
+    // 1. Create Device and context
+    // 2. Create Swap Chain
+    // 3. Set viewport
+    // 4. Create shader programs and pipeline state
+    // 5. Init Graphics
+    // 6. Init Constant buffers
+    fn create_resources(&mut self) -> Result<(), HRESULT> {
+        create_device(&mut self.devices, &self.adapter_preference)?;
+        create_swap_chain(&self.window, &mut self.devices)?;
+        set_viewport(&self.window, &self.devices);
+        self.buffers.programs = self
+            .permutations
+            .iter()
+            .map(|permutation| {
+                create_program(
+                    &self.devices,
+                    &self.shaders[permutation.shader],
+                    &permutation.defines,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        self.buffers.pipeline_objects = pipeline::PipelineObjects::new();
+        unsafe {
+            self.buffers.pipeline_objects.update(
+                self.devices._device.as_ref().unwrap(),
+                &self.pipelines,
+                &self.buffers.programs,
+            );
+        }
+        init_graphics(
+            &self.devices,
+            &mut self.buffers,
+            &self.geometry,
+            &self.textures,
+        )?;
+        init_constant_buffers(&self.devices, &mut self.buffers)?;
+        Ok(())
+    }
+
+    fn release_resources(&mut self) {
+        unsafe {
+            if let Some(context) = self.devices._device_context.as_ref() {
+                context.ClearState();
+                context.Flush();
+            }
+            self.buffers.object_constants = None;
+            self.buffers.material_constants = None;
+            self.buffers.frame_constants = None;
+            self.buffers.meshes.clear();
+            self.buffers.textures.clear();
+            self.buffers.sampler = None;
+            self.buffers.pipeline_objects = pipeline::PipelineObjects::new();
+            self.buffers.programs.clear();
+            // Swap chains can't be released while in exclusive fullscreen
+            if let Some(swap_chain) = self.devices._swap_chain.as_ref() {
+                swap_chain.SetFullscreenState(FALSE, null_mut());
+            }
+            release(&mut self.devices._depth_stencil_view);
+            release(&mut self.devices._depth_buffer);
+            release(&mut self.devices._render_target);
+            release(&mut self.devices._back_buffer);
+            release(&mut self.devices._swap_chain);
+            release(&mut self.devices._device_context);
+            release(&mut self.devices._device);
+        }
+    }
+
+    fn present(&mut self) -> recovery::PresentResult {
+        unsafe {
+            let res = self.devices._swap_chain.as_ref().unwrap().Present(0, 0);
+            let result = recovery::PresentResult::from_hresult(res);
+            if let recovery::PresentResult::DeviceLost(_) = result {
+                let reason = self
+                    .devices
+                    ._device
+                    .as_ref()
+                    .unwrap()
+                    .GetDeviceRemovedReason();
+                println!("Present failed: {:#x}, removed reason: {:#x}", res, reason);
+            }
+            result
+        }
+    }
+}
+
+fn create_device(
+    devices: &mut D11Devices,
+    preference: &adapter::AdapterPreference,
+) -> Result<(), HRESULT> {
+    #[cfg(debug_assertions)]
+    let creation_flags = D3D11_CREATE_DEVICE_DEBUG;
+
+    #[cfg(not(debug_assertions))]
+    let creation_flags = 0;
+
+    // Pick an adapter
+    let adapters = adapter::enumerate_adapters();
+    println!("Adapters:");
+    for adapter in &adapters {
+        println!("  {}", adapter.desc);
+    }
+    let descs: Vec<adapter::AdapterDesc> = adapters.iter().map(|a| a.desc.clone()).collect();
+    let choice = adapter::select_adapter(&descs, preference, adapter::FeatureLevel::Level11_0);
+
+    // A specific adapter requires D3D_DRIVER_TYPE_UNKNOWN
+    let (p_adapter, driver_type, adapter_name) = match choice {
+        adapter::DeviceChoice::Adapter(i) => (
+            adapters[i].raw as *mut IDXGIAdapter,
+            D3D_DRIVER_TYPE_UNKNOWN,
+            adapters[i].desc.name.clone(),
+        ),
+        adapter::DeviceChoice::Warp => (null_mut(), D3D_DRIVER_TYPE_WARP, String::from("WARP")),
+    };
+
+    let feature_levels = [D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0];
+    let mut feature_level = 0;
+
+    // Create Device and context
+    unsafe {
+        let mut res = D3D11CreateDevice(
+            p_adapter,
+            driver_type,
+            null_mut(),
+            creation_flags,
+            feature_levels.as_ptr(),
+            feature_levels.len() as UINT,
+            D3D11_SDK_VERSION,
+            &mut devices._device,
+            &mut feature_level,
+            &mut devices._device_context,
+        );
+        // The 11.0 runtime doesn't know about 11_1
+        if res == E_INVALIDARG {
+            res = D3D11CreateDevice(
+                p_adapter,
+                driver_type,
+                null_mut(),
+                creation_flags,
+                feature_levels[1..].as_ptr(),
+                1,
+                D3D11_SDK_VERSION,
+                &mut devices._device,
+                &mut feature_level,
+                &mut devices._device_context,
+            );
+        }
+        if FAILED(res) {
+            println!("Error creating device on {}: {}", adapter_name, res);
+            return Err(res);
+        }
+    }
+
+    match adapter::FeatureLevel::from_raw(feature_level) {
+        Some(level) => println!("Using {} (feature level {})", adapter_name, level),
+        None => println!(
+            "Using {} (feature level {:#x})",
+            adapter_name, feature_level
+        ),
+    }
+    Ok(())
+}
+
+fn create_swap_chain(window: &Window, devices: &mut D11Devices) -> Result<(), HRESULT> {
+    unsafe {
+        // Describe the swap chain
+        let mut swap_chain_desc: DXGI_SWAP_CHAIN_DESC = mem::zeroed();
+        swap_chain_desc.BufferDesc.Width = window.width as u32;
+        swap_chain_desc.BufferDesc.Height = window.height as u32;
+        swap_chain_desc.BufferCount = 1;
+        swap_chain_desc.Windowed = 1;
+        swap_chain_desc.BufferDesc.Format = DXGI_FORMAT_R8G8B8A8_UNORM;
+        swap_chain_desc.BufferUsage = DXGI_USAGE_RENDER_TARGET_OUTPUT;
+        swap_chain_desc.SampleDesc.Count = 1;
+        swap_chain_desc.SampleDesc.Quality = 0;
+        swap_chain_desc.SwapEffect = DXGI_SWAP_EFFECT_DISCARD; // TODO: Change this. DXGI_SWAP_EFFECT_FLIP_DISCARD and use BufferCount = 2
+        swap_chain_desc.OutputWindow = window.handle;
+
+        let mut dxgi_device: *mut IDXGIDevice = null_mut();
+        let mut dxgi_adapter: *mut IDXGIAdapter = null_mut();
+        let mut dxgi_factory: *mut IDXGIFactory1 = null_mut();
+
+        // get dxgi device
+        let mut res = devices._device.as_ref().unwrap().QueryInterface(
+            &IDXGIDevice::uuidof(),
+            &mut dxgi_device as *mut *mut IDXGIDevice as *mut *mut winapi::ctypes::c_void,
+        );
+
+        // Get dxgi adapter
+        if !FAILED(res) {
+            res = dxgi_device.as_ref().unwrap().GetAdapter(&mut dxgi_adapter);
+        }
+
+        // Get dxgi factory
+        if !FAILED(res) {
+            res = dxgi_adapter.as_ref().unwrap().GetParent(
+                &IDXGIFactory1::uuidof(),
+                &mut dxgi_factory as *mut *mut IDXGIFactory1 as *mut *mut winapi::ctypes::c_void,
+            );
+        }
+
+        // Create SwapChain
+        if !FAILED(res) {
+            res = dxgi_factory.as_ref().unwrap().CreateSwapChain(
+                devices._device as *mut IUnknown,
+                &mut swap_chain_desc,
+                &mut devices._swap_chain,
+            );
+        }
+
+        // Alt+Enter is handled by us, DXGI would only toggle exclusive fullscreen
+        if !FAILED(res) {
+            dxgi_factory
+                .as_ref()
+                .unwrap()
+                .MakeWindowAssociation(window.handle, DXGI_MWA_NO_ALT_ENTER);
+        }
+
+        release(&mut dxgi_factory);
+        release(&mut dxgi_adapter);
+        release(&mut dxgi_device);
+
+        if FAILED(res) {
+            println!("Error creating swap chain: {}", res);
+            return Err(res);
+        }
+    }
+    create_render_target(devices)
+}
+
+// Back buffer render target view and a depth buffer to go with it, recreated
+// whenever the swap chain is resized
+fn create_render_target(devices: &mut D11Devices) -> Result<(), HRESULT> {
+    unsafe {
+        // Get swap chain’s back buffer
+        let res = devices._swap_chain.as_ref().unwrap().GetBuffer(
+            0,
+            &IID_ID3D11Texture2D,
+            &mut devices._back_buffer as *mut _ as *mut LPVOID,
+        );
+        if FAILED(res) {
+            println!("Error getting back buffer: {}", res);
+            return Err(res);
+        }
+        //  Create the render target view
+        let res = devices._device.as_ref().unwrap().CreateRenderTargetView(
+            devices._back_buffer as *mut _,
+            null_mut(),
+            &mut devices._render_target as *mut _ as *mut _,
+        );
+        if FAILED(res) {
+            println!("Error creating render target view: {}", res);
+            return Err(res);
+        }
+
+        // Depth buffer the size of the back buffer. 32 bit float depth, which
+        // reverse-Z gets the most out of.
+        let mut back_buffer_desc: D3D11_TEXTURE2D_DESC = mem::zeroed();
+        devices
+            ._back_buffer
+            .as_ref()
+            .unwrap()
+            .GetDesc(&mut back_buffer_desc);
+        let mut depth_desc: D3D11_TEXTURE2D_DESC = mem::zeroed();
+        depth_desc.Width = back_buffer_desc.Width;
+        depth_desc.Height = back_buffer_desc.Height;
+        depth_desc.MipLevels = 1;
+        depth_desc.ArraySize = 1;
+        depth_desc.Format = DXGI_FORMAT_D32_FLOAT;
+        depth_desc.SampleDesc = back_buffer_desc.SampleDesc;
+        depth_desc.Usage = D3D11_USAGE_DEFAULT;
+        depth_desc.BindFlags = D3D11_BIND_DEPTH_STENCIL;
+        let device = devices._device.as_ref().unwrap();
+        let res = device.CreateTexture2D(&depth_desc, null_mut(), &mut devices._depth_buffer);
+        if FAILED(res) {
+            println!("Error creating depth buffer: {}", res);
+            return Err(res);
+        }
+        let res = device.CreateDepthStencilView(
+            devices._depth_buffer as *mut _,
+            null_mut(),
+            &mut devices._depth_stencil_view,
+        );
+        if FAILED(res) {
+            println!("Error creating depth stencil view: {}", res);
+            return Err(res);
+        }
+
+        // Bind views.
+        devices
+            ._device_context
+            .as_ref()
+            .unwrap()
+            .OMSetRenderTargets(
+                1,
+                &mut devices._render_target as _,
+                devices._depth_stencil_view,
+            );
+    }
+    Ok(())
+}
+
+// Match the swap chain to the window's current client area
+fn resize_swap_chain(window: &mut Window, devices: &mut D11Devices) {
+    unsafe {
+        let mut rect: RECT = mem::zeroed();
+        GetClientRect(window.handle, &mut rect);
+        window.width = rect.right - rect.left;
+        window.height = rect.bottom - rect.top;
+
+        // All references to the back buffer have to be gone before ResizeBuffers
+        devices
+            ._device_context
+            .as_ref()
+            .unwrap()
+            .OMSetRenderTargets(0, null_mut(), null_mut());
+        release(&mut devices._depth_stencil_view);
+        release(&mut devices._depth_buffer);
+        release(&mut devices._render_target);
+        release(&mut devices._back_buffer);
+
+        let res = devices._swap_chain.as_ref().unwrap().ResizeBuffers(
+            0,
+            window.width as UINT,
+            window.height as UINT,
+            DXGI_FORMAT_UNKNOWN,
+            0,
+        );
+        if FAILED(res) {
+            println!("Error resizing swap chain: {}", res)
+        }
+    }
+    // Present reports the lost device if this failed because of one
+    create_render_target(devices).ok();
+    set_viewport(window, devices);
+}
+
+fn apply_display_mode(
+    platform_window: &platform::win32::Win32Window,
+    window: &mut Window,
+    devices: &mut D11Devices,
+    mode: &display::DisplayMode,
+    monitors: &[display::Monitor],
+) {
+    let rect = display::window_rect(mode, monitors, platform::win32::frame_insets());
+    unsafe {
+        let swap_chain = devices._swap_chain.as_ref().unwrap();
+
+        // Leave exclusive fullscreen before touching the window
+        swap_chain.SetFullscreenState(FALSE, null_mut());
+
+        platform_window.set_placement(mode.window_style(), rect);
+
+        if let display::DisplayMode::Exclusive { monitor, mode } = *mode {
+            let video_mode = display::find_monitor(monitors, monitor)
+                .and_then(|m| display::select_video_mode(&m.modes, mode));
+            if let Some(video_mode) = video_mode {
+                let mut mode_desc: DXGI_MODE_DESC = mem::zeroed();
+                mode_desc.Width = video_mode.width;
+                mode_desc.Height = video_mode.height;
+                mode_desc.RefreshRate.Numerator = video_mode.refresh_rate;
+                mode_desc.RefreshRate.Denominator = 1;
+                mode_desc.Format = DXGI_FORMAT_R8G8B8A8_UNORM;
+                swap_chain.ResizeTarget(&mode_desc);
+                println!(
+                    "Exclusive fullscreen {}x{}@{}",
+                    video_mode.width, video_mode.height, video_mode.refresh_rate
+                );
+            }
+            // Goes fullscreen on the output the window is on
+            let res = swap_chain.SetFullscreenState(TRUE, null_mut());
+            if FAILED(res) {
+                println!("Error entering exclusive fullscreen: {}", res)
+            }
+        }
+    }
+    resize_swap_chain(window, devices);
+}
+
+fn set_viewport(window: &Window, devices: &D11Devices) {
+    unsafe {
+        let mut viewport: D3D11_VIEWPORT = mem::zeroed();
+        viewport.TopLeftX = 0.0;
+        viewport.TopLeftY = 0.0;
+        viewport.Width = window.width as f32;
+        viewport.Height = window.height as f32;
+        viewport.MinDepth = 0.0;
+        viewport.MaxDepth = 1.0;
+
+        devices
+            ._device_context
+            .as_ref()
+            .unwrap()
+            .RSSetViewports(1, &viewport);
+    }
+}
+
+// Compiles one entry point of the shader to bytecode, with each of the
+// defines set to 1
+fn compile_shader(
+    shader: &assets::Shader,
+    defines: &[String],
+    entry_point: &str,
+    target: &str,
+    flags: UINT,
+) -> Result<Vec<u8>, HRESULT> {
+    unsafe {
+        let mut blob: *mut ID3DBlob = mem::zeroed();
+        // Used for error messages and finding includes
+        let source_name = CString::new(shader.path.to_string_lossy().as_bytes()).unwrap();
+        let entry_point = CString::new(entry_point).unwrap();
+        let target = CString::new(target).unwrap();
+        let names: Vec<CString> = defines
+            .iter()
+            .map(|define| CString::new(define.as_str()).unwrap())
+            .collect();
+        let one = CString::new("1").unwrap();
+        let mut macros: Vec<D3D_SHADER_MACRO> = names
+            .iter()
+            .map(|name| D3D_SHADER_MACRO {
+                Name: name.as_ptr(),
+                Definition: one.as_ptr(),
+            })
+            .collect();
+        // The list ends with an empty entry
+        macros.push(D3D_SHADER_MACRO {
+            Name: std::ptr::null(),
+            Definition: std::ptr::null(),
+        });
+        let res: HRESULT = D3DCompile(
+            shader.source.as_ptr() as LPCVOID,
+            shader.source.len(),
+            source_name.as_ptr(),
+            macros.as_ptr(),
+            D3D_COMPILE_STANDARD_FILE_INCLUDE,
+            entry_point.as_ptr(),
+            target.as_ptr(),
+            flags,
+            0,
+            &mut blob,
+            null_mut(),
+        );
+        if FAILED(res) {
+            return Err(res);
+        }
+        let code = std::slice::from_raw_parts(
+            blob.as_ref().unwrap().GetBufferPointer() as *const u8,
+            blob.as_ref().unwrap().GetBufferSize(),
+        )
+        .to_vec();
+        release(&mut blob);
+        Ok(code)
+    }
+}
+
+// Flags for shaders compiled at startup, ones packed ahead of time are
+// optimized
+const SHADER_COMPILE_FLAGS: UINT = D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION;
+
+// Leaves the program empty when the shader doesn't compile, so it can be
+// fixed and reloaded without restarting. Failing to create the shader objects
+// is an error, the device may be gone.
+fn create_program(
+    devices: &D11Devices,
+    shader: &assets::Shader,
+    defines: &[String],
+) -> Result<pipeline::ShaderProgram, HRESULT> {
+    // Compile Vertex and Pixel Shaders, unless they come precompiled
+    let (vertex_code, pixel_code) = match shader.bytecode.get(defines) {
+        Some(bytecode) => (bytecode.vertex.clone(), bytecode.pixel.clone()),
+        None => {
+            let vertex_code =
+                match compile_shader(shader, defines, "VSMain", "vs_5_0", SHADER_COMPILE_FLAGS) {
+                    Ok(code) => code,
+                    Err(res) => {
+                        println!("Error Compiling Vertex Shader: {}", res);
+                        return Ok(pipeline::ShaderProgram::empty());
+                    }
+                };
+            let pixel_code =
+                match compile_shader(shader, defines, "PSMain", "ps_5_0", SHADER_COMPILE_FLAGS) {
+                    Ok(code) => code,
+                    Err(res) => {
+                        println!("Error Compiling Pixel Shader: {}", res);
+                        return Ok(pipeline::ShaderProgram::empty());
+                    }
+                };
+            (vertex_code, pixel_code)
+        }
+    };
+    let device = unsafe { devices._device.as_ref().unwrap() };
+    unsafe { pipeline::ShaderProgram::new(device, vertex_code, &pixel_code) }.map_err(|res| {
+        println!("Error creating shaders: {}", res);
+        res
+    })
+}
+
+// The sample's quad, with a different color in each corner
+fn quad_geometry() -> mesh::Mesh {
+    let mut mesh = primitives::quad(2.0, 2.0);
+    for vertex in mesh.vertices.iter_mut() {
+        let (r, g, b) = match (vertex.pos.x < 0.0, vertex.pos.y < 0.0) {
+            (true, false) => (1.0, 0.0, 0.0),
+            (false, true) => (0.0, 1.0, 0.0),
+            (true, true) => (0.0, 0.0, 1.0),
+            (false, false) => (1.0, 1.0, 0.0),
+        };
+        *vertex = vertex.with_color(r, g, b, 1.0);
+    }
+    mesh
+}
+
+fn triangle_geometry() -> mesh::Mesh {
+    let corner = |x, y, shade| {
+        vertex::Vertex::new(
+            (x, y, 0.0),
+            (0.0, 0.0, -1.0),
+            (0.5 + x / 2.0, 0.5 - y / 2.0),
+        )
+        .with_color(shade, shade, shade, 1.0)
+    };
+    let mut mesh = mesh::Mesh::new(
+        vec![
+            corner(0.0, 1.0, 1.0),
+            corner(0.866, -0.5, 0.6),
+            corner(-0.866, -0.5, 0.6),
+        ],
+        vec![0, 1, 2],
+    );
+    mesh.compute_tangents();
+    mesh
+}
+
+// A copy of an asset loading in the background, once it's done
+fn wait_for<T: assets::Asset + Clone>(
+    assets: &mut assets::AssetManager,
+    handle: &assets::Handle<T>,
+) -> T {
+    let path = assets.path(handle).display().to_string();
+    match assets.wait(handle) {
+        Ok(asset) => asset.clone(),
+        Err(e) => panic!("Error loading {}: {}", path, e),
+    }
+}
+
+// What the renderer's meshes, shaders, textures and materials were loaded
+// from, so reloads know what to patch
+struct LoadedAssets {
+    model: Option<assets::Handle<mesh::Mesh>>,
+    // Parallel to Renderer::shaders
+    shaders: Vec<assets::Handle<assets::Shader>>,
+    // With their index into Renderer::textures
+    textures: Vec<(assets::Handle<texture::TextureData>, usize)>,
+    // Parallel to Renderer::materials
+    materials: Vec<assets::Handle<material::MaterialDesc>>,
+    // --texture=<file>, put on the quad's material
+    quad_texture: Option<String>,
+    controls: assets::Handle<actions::ActionMap>,
+}
+
+fn load_error(path: &std::path::Path, e: &std::io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+// Index into Renderer::shaders, loading the shader if it's new
+fn add_shader(
+    assets: &mut assets::AssetManager,
+    renderer: &mut Renderer,
+    loaded: &mut LoadedAssets,
+    path: &std::path::Path,
+) -> Result<usize, std::io::Error> {
+    let handle = assets.load::<assets::Shader, _>(path);
+    if let Some(index) = loaded.shaders.iter().position(|h| *h == handle) {
+        return Ok(index);
+    }
+    let shader = assets
+        .wait(&handle)
+        .map_err(|e| load_error(path, e))?
+        .clone();
+    renderer.shaders.push(shader);
+    loaded.shaders.push(handle);
+    Ok(renderer.shaders.len() - 1)
+}
+
+// Index into Renderer::permutations, compiling the permutation if it's new.
+// Pipelines are only created while there's a device, recovery creates the
+// rest.
+fn add_permutation(
+    assets: &mut assets::AssetManager,
+    renderer: &mut Renderer,
+    loaded: &mut LoadedAssets,
+    has_device: bool,
+    permutation: &material::ShaderPermutation,
+) -> Result<usize, std::io::Error> {
+    let shader = add_shader(assets, renderer, loaded, &permutation.shader)?;
+    if let Some(index) = renderer
+        .permutations
+        .iter()
+        .position(|p| p.shader == shader && p.defines == permutation.defines)
+    {
+        return Ok(index);
+    }
+    let interface =
+        material::ShaderInterface::parse(&renderer.shaders[shader].source, &permutation.defines)
+            .map_err(|e| load_error(&permutation.shader, &e))?;
+    if has_device {
+        let program = create_program(
+            &renderer.devices,
+            &renderer.shaders[shader],
+            &permutation.defines,
+        );
+        renderer
+            .buffers
+            .programs
+            .push(program.unwrap_or_else(|_| pipeline::ShaderProgram::empty()));
+    }
+    renderer.permutations.push(Permutation {
+        shader,
+        defines: permutation.defines.clone(),
+        interface,
+    });
+    Ok(renderer.permutations.len() - 1)
+}
+
+// Textures materials can use without a file
+const BUILTIN_TEXTURES: [(&str, usize); 2] =
+    [("white", draw::WHITE_TEXTURE), ("checker", CHECKER_TEXTURE)];
+
+// Index into Renderer::textures, loading the texture if it's new
+fn add_texture(
+    assets: &mut assets::AssetManager,
+    renderer: &mut Renderer,
+    loaded: &mut LoadedAssets,
+    has_device: bool,
+    name: &str,
+) -> Result<usize, std::io::Error> {
+    if let Some(&(_, index)) = BUILTIN_TEXTURES
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+    {
+        return Ok(index);
+    }
+    let path = std::path::Path::new(name);
+    let handle = assets.load::<texture::TextureData, _>(path);
+    if let Some((_, index)) = loaded.textures.iter().find(|(h, _)| *h == handle) {
+        return Ok(*index);
+    }
+    let data = assets
+        .wait(&handle)
+        .map_err(|e| load_error(path, e))?
+        .clone();
+    data.validate().map_err(|e| load_error(path, &e))?;
+    if has_device {
+        let device = unsafe { renderer.devices._device.as_ref().unwrap() };
+        let texture = unsafe { texture::Texture::new(device, &data) }.map_err(|res| {
+            std::io::Error::other(format!("Error creating texture {}: {}", name, res))
+        })?;
+        renderer.buffers.textures.push(texture);
+    }
+    renderer.textures.push(data);
+    loaded.textures.push((handle, renderer.textures.len() - 1));
+    Ok(renderer.textures.len() - 1)
+}
+
+// The name the shader gives the quad's texture, for --texture=<file>
+const BASE_COLOR_TEXTURE: &str = "base_color_texture";
+
+// The material's file, with --texture=<file> applied to the quad's
+fn material_desc(
+    assets: &assets::AssetManager,
+    loaded: &LoadedAssets,
+    index: usize,
+) -> Result<material::MaterialDesc, std::io::Error> {
+    let handle = &loaded.materials[index];
+    let mut desc = match assets.get(handle) {
+        Some(desc) => desc.clone(),
+        None => {
+            let e = match assets.error(handle) {
+                Some(e) => load_error(assets.path(handle), e),
+                None => std::io::Error::other(format!(
+                    "{} hasn't loaded",
+                    assets.path(handle).display()
+                )),
+            };
+            return Err(e);
+        }
+    };
+    if let (app::QUAD_MATERIAL, Some(path)) = (index, &loaded.quad_texture) {
+        desc = desc.with_texture(BASE_COLOR_TEXTURE, path);
+    }
+    Ok(desc)
+}
+
+// Checks the material against its shader permutation and loads what it
+// refers to. Textures the shader samples but the material leaves out are
+// white.
+fn resolve_material(
+    assets: &mut assets::AssetManager,
+    renderer: &mut Renderer,
+    loaded: &mut LoadedAssets,
+    has_device: bool,
+    desc: &material::MaterialDesc,
+) -> Result<draw::Material, std::io::Error> {
+    let permutation = add_permutation(assets, renderer, loaded, has_device, &desc.permutation)?;
+    let interface = renderer.permutations[permutation].interface.clone();
+    let constants = desc.constants(&interface)?;
+    let mut textures = Vec::with_capacity(interface.textures.len());
+    for slot in &interface.textures {
+        let texture = match desc.textures.get(&slot.name) {
+            Some(name) => add_texture(assets, renderer, loaded, has_device, name)?,
+            None => draw::WHITE_TEXTURE,
+        };
+        textures.push((slot.slot, texture));
+    }
+    Ok(draw::Material {
+        permutation,
+        constants,
+        textures,
+        state: desc.state,
+    })
+}
+
+// Says why a reload failed, if it did. The asset keeps its old contents.
+fn reload_failed<T: assets::Asset>(
+    assets: &assets::AssetManager,
+    handle: &assets::Handle<T>,
+) -> bool {
+    match assets.error(handle) {
+        Some(e) => {
+            println!("Error reloading {}: {}", assets.path(handle).display(), e);
+            true
+        }
+        None => false,
+    }
+}
+
+// Swaps in assets whose files changed on disk. Draw items and materials refer
+// to meshes, textures and materials by index, so replacing the entry patches
+// them all. GPU copies are only remade while there's a device, otherwise
+// recovery builds them from the new CPU copies.
+fn apply_reloads(
+    assets: &mut assets::AssetManager,
+    renderer: &mut Renderer,
+    loaded: &mut LoadedAssets,
+    has_device: bool,
+    action_map: &mut actions::ActionMap,
+) {
+    for handle in assets.take_reloaded::<mesh::Mesh>() {
+        if reload_failed(assets, &handle) || Some(&handle) != loaded.model.as_ref() {
+            continue;
+        }
+        let mut mesh = assets.get(&handle).unwrap().clone();
+        if let Err(e) = mesh.validate() {
+            println!("Invalid mesh {}: {}", assets.path(&handle).display(), e);
+            continue;
+        }
+        mesh.fit_to_size(2.0);
+        if has_device {
+            let device = unsafe { renderer.devices._device.as_ref().unwrap() };
+            match unsafe { mesh::GpuMesh::new(device, &mesh) } {
+                Ok(gpu_mesh) => renderer.buffers.meshes[app::QUAD_MESH] = gpu_mesh,
+                Err(res) => println!("Error creating mesh buffers: {}", res),
+            }
+        }
+        renderer.geometry[app::QUAD_MESH] = mesh;
+        println!("Reloaded {}", assets.path(&handle).display());
+    }
+    for handle in assets.take_reloaded::<texture::TextureData>() {
+        if reload_failed(assets, &handle) {
+            continue;
+        }
+        let index = match loaded.textures.iter().find(|(h, _)| *h == handle) {
+            Some(&(_, index)) => index,
+            None => continue,
+        };
+        let data = assets.get(&handle).unwrap().clone();
+        if let Err(e) = data.validate() {
+            println!("Invalid texture {}: {}", assets.path(&handle).display(), e);
+            continue;
+        }
+        if has_device {
+            let device = unsafe { renderer.devices._device.as_ref().unwrap() };
+            match unsafe { texture::Texture::new(device, &data) } {
+                Ok(texture) => renderer.buffers.textures[index] = texture,
+                Err(res) => println!("Error creating texture: {}", res),
+            }
+        }
+        renderer.textures[index] = data;
+        println!("Reloaded {}", assets.path(&handle).display());
+    }
+
+    // A shader's parameters may have moved, so every material is laid out
+    // again after one changes
+    let mut stale_materials = Vec::new();
+    for handle in assets.take_reloaded::<assets::Shader>() {
+        if reload_failed(assets, &handle) {
+            continue;
+        }
+        let shader = match loaded.shaders.iter().position(|h| *h == handle) {
+            Some(shader) => shader,
+            None => continue,
+        };
+        let source = assets.get(&handle).unwrap();
+        let interfaces: Result<Vec<_>, _> = renderer
+            .permutations
+            .iter()
+            .filter(|permutation| permutation.shader == shader)
+            .map(|permutation| {
+                material::ShaderInterface::parse(&source.source, &permutation.defines)
+            })
+            .collect();
+        let mut interfaces = match interfaces {
+            Ok(interfaces) => interfaces.into_iter(),
+            Err(e) => {
+                println!("Error reloading {}: {}", assets.path(&handle).display(), e);
+                continue;
+            }
+        };
+        renderer.shaders[shader] = source.clone();
+        for (index, permutation) in renderer.permutations.iter_mut().enumerate() {
+            if permutation.shader != shader {
+                continue;
+            }
+            permutation.interface = interfaces.next().unwrap();
+            if has_device {
+                renderer.buffers.programs[index] = create_program(
+                    &renderer.devices,
+                    &renderer.shaders[shader],
+                    &permutation.defines,
+                )
+                .unwrap_or_else(|_| pipeline::ShaderProgram::empty());
+                unsafe {
+                    renderer.buffers.pipeline_objects.program_changed(
+                        renderer.devices._device.as_ref().unwrap(),
+                        &renderer.pipelines,
+                        &renderer.buffers.programs,
+                        index,
+                    );
+                }
+            }
+        }
+        stale_materials = (0..loaded.materials.len()).collect();
+        println!("Reloaded {}", assets.path(&handle).display());
+    }
+    for handle in assets.take_reloaded::<material::MaterialDesc>() {
+        if reload_failed(assets, &handle) {
+            continue;
+        }
+        if let Some(index) = loaded.materials.iter().position(|h| *h == handle) {
+            stale_materials.push(index);
+        }
+    }
+    stale_materials.sort_unstable();
+    stale_materials.dedup();
+    for index in stale_materials {
+        let path = assets.path(&loaded.materials[index]).to_path_buf();
+        let material = material_desc(assets, loaded, index)
+            .and_then(|desc| resolve_material(assets, renderer, loaded, has_device, &desc));
+        match material {
+            Ok(material) => {
+                renderer.materials[index] = material;
+                println!("Reloaded {}", path.display());
+            }
+            Err(e) => println!("Error reloading {}: {}", path.display(), e),
+        }
+    }
+
+    for handle in assets.take_reloaded::<actions::ActionMap>() {
+        if reload_failed(assets, &handle) || handle != loaded.controls {
+            continue;
+        }
+        *action_map = assets.get(&handle).unwrap().clone();
+        println!("Reloaded {}", assets.path(&handle).display());
+    }
+}
+
+// --pack=<archive> tool mode: packs files and directories into an archive.
+// Shaders are packed with bytecode for the permutations the packed materials
+// use.
+pub fn pack_archive(path: &str, inputs: &[String], compression: archive::Compression) {
+    if let Err(e) = pack(path, inputs, compression) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn pack(path: &str, inputs: &[String], compression: archive::Compression) -> Result<(), String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let found =
+            archive::list_files(input).map_err(|e| format!("Error reading {}: {}", input, e))?;
+        files.extend(found);
+    }
+    let has_extension =
+        |file: &std::path::Path, extension: &str| file.extension().is_some_and(|e| e == extension);
+
+    // Permutations by the shader's name in the archive
+    let mut permutations: std::collections::BTreeMap<String, Vec<Vec<String>>> =
+        std::collections::BTreeMap::new();
+    for file in files.iter().filter(|file| has_extension(file, "mat")) {
+        let desc = std::fs::read_to_string(file)
+            .and_then(|text| material::MaterialDesc::parse(&text))
+            .map_err(|e| format!("Error reading {}: {}", file.display(), e))?;
+        if let Some(shader) = archive::entry_name(&desc.permutation.shader) {
+            let defines = permutations.entry(shader).or_default();
+            if !defines.contains(&desc.permutation.defines) {
+                defines.push(desc.permutation.defines);
+            }
+        }
+    }
+
+    let mut builder = archive::ArchiveBuilder::new();
+    for file in &files {
+        let added = if has_extension(file, "hlsl") {
+            let defines = archive::entry_name(file)
+                .and_then(|name| permutations.get(&name))
+                .map_or(&[][..], |defines| defines.as_slice());
+            pack_shader(&mut builder, file, defines, compression)
+        } else {
+            builder.add_file(file, compression)
+        };
+        added.map_err(|e| format!("Error packing {}: {}", file.display(), e))?;
+    }
+    let bytes = builder
+        .build()
+        .map_err(|e| format!("Error building {}: {}", path, e))?;
+    std::fs::write(path, &bytes).map_err(|e| format!("Error writing {}: {}", path, e))?;
+    println!(
+        "Packed {} files ({} bytes) into {} ({} bytes)",
+        builder.len(),
+        builder.data_size(),
+        path,
+        bytes.len()
+    );
+    Ok(())
+}
+
+// Adds the source, which materials are checked against, and the bytecode of
+// each permutation under the names assets::Shader looks for
+fn pack_shader(
+    builder: &mut archive::ArchiveBuilder,
+    path: &std::path::Path,
+    permutations: &[Vec<String>],
+    compression: archive::Compression,
+) -> Result<(), std::io::Error> {
+    let shader = assets::Shader {
+        path: path.to_path_buf(),
+        source: std::fs::read_to_string(path)?,
+        bytecode: std::collections::BTreeMap::new(),
+    };
+    builder.add(path, shader.source.clone().into_bytes(), compression)?;
+    if permutations.is_empty() {
+        return Ok(());
+    }
+    let mut list = String::new();
+    for (index, defines) in permutations.iter().enumerate() {
+        for (stage, entry_point, target) in [("vs", "VSMain", "vs_5_0"), ("ps", "PSMain", "ps_5_0")]
+        {
+            let code = compile_shader(
+                &shader,
+                defines,
+                entry_point,
+                target,
+                D3DCOMPILE_OPTIMIZATION_LEVEL3,
+            )
+            .map_err(|res| {
+                std::io::Error::other(format!(
+                    "{} [{}] doesn't compile: {:#x}",
+                    entry_point,
+                    defines.join(" "),
+                    res
+                ))
+            })?;
+            builder.add(assets::bytecode_path(path, index, stage), code, compression)?;
+        }
+        list.push_str(&defines.join(" "));
+        list.push('\n');
+    }
+    builder.add(
+        assets::permutations_path(path),
+        list.into_bytes(),
+        compression,
+    )
+}
+
+// Index into Renderer::textures, after the white one at draw::WHITE_TEXTURE.
+// Textures materials load go after these.
+const CHECKER_TEXTURE: usize = 1;
+
+fn builtin_textures() -> Vec<texture::TextureData> {
+    let from_image = |image: &texture::Image| {
+        texture::TextureData::from_image(image, texture::ColorSpace::Srgb, true)
+    };
+    let white = from_image(&texture::Image::solid(1, 1, [255, 255, 255, 255]));
+    let checker = from_image(&texture::Image::checkerboard(
+        64,
+        8,
+        [255, 255, 255, 255],
+        [96, 96, 96, 255],
+    ));
+    vec![white, checker]
+}
+
+fn init_graphics(
+    devices: &D11Devices,
+    buffers: &mut Buffers,
+    geometry: &[mesh::Mesh],
+    textures: &[texture::TextureData],
+) -> Result<(), HRESULT> {
+    let device = unsafe { devices._device.as_ref().unwrap() };
+    for mesh in geometry {
+        if let Err(e) = mesh.validate() {
+            panic!("Invalid mesh: {}", e)
+        }
+        buffers
+            .meshes
+            .push(unsafe { mesh::GpuMesh::new(device, mesh)? });
+    }
+    for data in textures {
+        if let Err(e) = data.validate() {
+            panic!("Invalid texture: {}", e)
+        }
+        buffers
+            .textures
+            .push(unsafe { texture::Texture::new(device, data)? });
+    }
+    buffers.sampler =
+        Some(unsafe { texture::Sampler::new(device, &texture::SamplerDesc::default())? });
+    Ok(())
+}
+
+fn init_constant_buffers(devices: &D11Devices, buffers: &mut Buffers) -> Result<(), HRESULT> {
+    unsafe {
+        let device = devices._device.as_ref().unwrap();
+        let context = devices._device_context.as_ref().unwrap();
+        buffers.frame_constants = Some(draw::ConstantRingBuffer::new(
+            device,
+            context,
+            draw::FRAME_RING_SIZE,
+        )?);
+        buffers.object_constants = Some(draw::ConstantRingBuffer::new(
+            device,
+            context,
+            draw::OBJECT_RING_SIZE,
+        )?);
+        buffers.material_constants = Some(draw::ConstantRingBuffer::new(
+            device,
+            context,
+            draw::MATERIAL_RING_SIZE,
+        )?);
+    }
+    Ok(())
+}
+
+// Uploads the frame constants once, then per object constants and a draw
+// call for each item. Pipeline state, materials and meshes are only rebound
+// when they change, so sort the list by pipeline first.
+fn draw_items(
+    devices: &D11Devices,
+    buffers: &mut Buffers,
+    pipelines: &pipeline::PipelineCache,
+    materials: &[draw::Material],
+    frame: &draw::FrameConstants,
+    list: &draw::DrawList,
+) {
+    unsafe {
+        let context = devices._device_context.as_ref().unwrap();
+        // Objects for pipelines first used this frame
+        buffers.pipeline_objects.update(
+            devices._device.as_ref().unwrap(),
+            pipelines,
+            &buffers.programs,
+        );
+        let frame_constants = buffers.frame_constants.as_mut().unwrap();
+        let object_constants = buffers.object_constants.as_mut().unwrap();
+        let material_constants = buffers.material_constants.as_mut().unwrap();
+
+        match frame_constants.push(context, frame) {
+            Some(allocation) => frame_constants.bind_vs(context, 0, allocation),
+            None => return,
+        }
+        buffers.sampler.as_ref().unwrap().bind(context, 0);
+
+        let mut bound_pipeline = pipeline::BoundPipeline::new();
+        let mut bound_mesh = None;
+        let mut bound_material = None;
+        for item in list.items() {
+            let changes = bound_pipeline.bind(pipelines.pipeline(item.pipeline));
+            buffers.pipeline_objects.bind(
+                context,
+                &buffers.programs,
+                pipelines.pipeline(item.pipeline),
+                changes,
+            );
+            let mesh = &buffers.meshes[item.mesh];
+            if bound_mesh != Some(item.mesh) {
+                mesh.bind(context);
+                bound_mesh = Some(item.mesh);
+            }
+            if bound_material != Some(item.material) {
+                let material = &materials[item.material];
+                for &(slot, texture) in &material.textures {
+                    buffers.textures[texture].bind(context, slot);
+                }
+                // Shaders without a cbMaterial have nothing to upload
+                if !material.constants.is_empty() {
+                    match material_constants.push_bytes(context, &material.constants) {
+                        Some(allocation) => {
+                            material_constants.bind_vs(
+                                context,
+                                material::MATERIAL_CBUFFER_SLOT,
+                                allocation,
+                            );
+                            material_constants.bind_ps(
+                                context,
+                                material::MATERIAL_CBUFFER_SLOT,
+                                allocation,
+                            );
+                        }
+                        None => {
+                            println!(
+                                "Error uploading constants for material {}, skipping draw",
+                                item.material
+                            );
+                            continue;
+                        }
+                    }
+                }
+                bound_material = Some(item.material);
+            }
+
+            match object_constants.push(context, &item.constants()) {
+                Some(allocation) => {
+                    object_constants.bind_vs(context, 1, allocation);
+                    // draw every part of the mesh to the back buffer
+                    for submesh in 0..mesh.submeshes().len() {
+                        mesh.draw_submesh(context, submesh);
+                    }
+                }
+                None => println!(
+                    "Error uploading constants for mesh {}, skipping draw",
+                    item.mesh
+                ),
+            }
+        }
+    }
+}
+
+// Give up after this many consecutive failed attempts at recreating the device
+const MAX_RECOVERY_ATTEMPTS: u32 = 10;
+
+// Not in winapi
+const DXGI_MWA_NO_ALT_ENTER: UINT = 1 << 1;
+
+// What the quad and the satellite are drawn with. The quad's can be swapped
+// with --material=<file>.
+const QUAD_MATERIAL_FILE: &str = "materials/quad.mat";
+const SATELLITE_MATERIAL_FILE: &str = "materials/satellite.mat";
+
+// Seconds between checks for changed asset files
+const RELOAD_CHECK_INTERVAL: f64 = 0.5;
+
+// Opens the window and runs the app until it's closed, reading the rest of
+// the command line
+pub fn run(mut action_map: actions::ActionMap, playback: Option<replay::Recording>) {
+    let name = "winclass1";
+    let title = "win_title";
+
+    let adapter_preference = std::env::args()
+        .find_map(|arg| {
+            arg.strip_prefix("--adapter=")
+                .map(adapter::AdapterPreference::from_arg)
+        })
+        .unwrap_or_default();
+
+    let display_mode = std::env::args()
+        .find_map(|arg| display::DisplayMode::from_arg(&arg))
+        .unwrap_or_default();
+    let mut display_state = display::DisplayState::new(display_mode);
+
+    let record_path =
+        std::env::args().find_map(|arg| arg.strip_prefix("--record=").map(String::from));
+    let reverse_z = std::env::args().any(|arg| arg == "--reverse-z");
+    let model_path =
+        std::env::args().find_map(|arg| arg.strip_prefix("--model=").map(String::from));
+    let texture_path =
+        std::env::args().find_map(|arg| arg.strip_prefix("--texture=").map(String::from));
+    let material_path = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--material=").map(String::from))
+        .unwrap_or_else(|| String::from(QUAD_MATERIAL_FILE));
+    let archive_path =
+        std::env::args().find_map(|arg| arg.strip_prefix("--archive=").map(String::from));
+    // Files load in the background while the window and device are created,
+    // from the archive if there is one
+    let mut asset_manager = match &archive_path {
+        Some(path) => match archive::Archive::open(path) {
+            Ok(archive) => assets::AssetManager::new(
+                thread_pool::ThreadPool::with_available_parallelism(),
+                std::sync::Arc::new(archive),
+            ),
+            Err(e) => panic!("Error opening {}: {}", path, e),
+        },
+        None => assets::AssetManager::default(),
+    };
+    // In app::QUAD_MATERIAL, app::SATELLITE_MATERIAL order. Their shaders and textures
+    // load once the device exists.
+    let material_handles = [material_path.as_str(), SATELLITE_MATERIAL_FILE]
+        .iter()
+        .map(|path| asset_manager.load::<material::MaterialDesc, _>(path))
+        .collect();
+    let mut loaded = LoadedAssets {
+        model: model_path.map(|path| asset_manager.load::<mesh::Mesh, _>(path)),
+        shaders: Vec::new(),
+        textures: Vec::new(),
+        materials: material_handles,
+        quad_texture: texture_path,
+        // Already loaded above, this is only so edits to it are picked up
+        controls: asset_manager.load::<actions::ActionMap, _>(app::CONTROLS_FILE),
+    };
+
+    let monitors = display::enumerate_monitors();
+
+    // 1. Create window
+    // 2. Create device dependent resources
+    // 3. Switch to the requested display mode
+    // Fullscreen modes start out borderless, exclusive mode is entered once
+    // the swap chain exists
+    let mut platform = platform::win32::Win32Platform::new(name).unwrap();
+    let mut platform_window = platform
+        .create_window(&platform::WindowDesc {
+            title: String::from(title),
+            rect: display::window_rect(
+                &display_state.mode,
+                &monitors,
+                platform::win32::frame_insets(),
+            ),
+            style: display_state.mode.window_style(),
+        })
+        .unwrap();
+    let (width, height) = platform_window.size();
+
+    // A model takes the quad's place, scaled to the quad's size
+    let quad = match &loaded.model {
+        Some(handle) => {
+            let mut mesh = wait_for(&mut asset_manager, handle);
+            mesh.fit_to_size(2.0);
+            mesh
+        }
+        None => quad_geometry(),
+    };
+
+    let mut renderer = Renderer {
+        window: Window {
+            handle: platform_window.handle(),
+            width: width as i32,
+            height: height as i32,
+        },
+        adapter_preference,
+        devices: D11Devices {
+            _swap_chain: unsafe { mem::zeroed() },
+            _device: unsafe { mem::zeroed() },
+            _device_context: unsafe { mem::zeroed() },
+            _back_buffer: unsafe { mem::zeroed() },
+            _render_target: unsafe { mem::zeroed() },
+            _depth_buffer: null_mut(),
+            _depth_stencil_view: null_mut(),
+        },
+        buffers: Buffers {
+            meshes: Vec::new(),
+            textures: Vec::new(),
+            sampler: None,
+            programs: Vec::new(),
+            pipeline_objects: pipeline::PipelineObjects::new(),
+            frame_constants: None,
+            material_constants: None,
+            object_constants: None,
+        },
+        geometry: vec![quad, triangle_geometry()],
+        textures: builtin_textures(),
+        shaders: Vec::new(),
+        permutations: Vec::new(),
+        materials: Vec::new(),
+        pipelines: pipeline::PipelineCache::new(),
+    };
+    if let Err(res) = renderer.create_resources() {
+        panic!("Error creating device: {}", res)
+    }
+    for index in 0..loaded.materials.len() {
+        let path = asset_manager.path(&loaded.materials[index]).to_path_buf();
+        asset_manager.wait(&loaded.materials[index]).ok();
+        let material = material_desc(&asset_manager, &loaded, index).and_then(|desc| {
+            resolve_material(&mut asset_manager, &mut renderer, &mut loaded, true, &desc)
+        });
+        match material {
+            Ok(material) => renderer.materials.push(material),
+            Err(e) => panic!("Error loading {}: {}", path.display(), e),
+        }
+    }
+    apply_display_mode(
+        &platform_window,
+        &mut renderer.window,
+        &mut renderer.devices,
+        &display_state.mode,
+        &monitors,
+    );
+
+    let mut device_recovery = recovery::DeviceRecovery::new(MAX_RECOVERY_ATTEMPTS);
+    let mut timer = time::Time::new();
+    let mut scene = app::Scene::new();
+    scene.camera.reverse_z = reverse_z;
+    let mut draw_list = draw::DrawList::new();
+    let mut events = Vec::new();
+    let mut input = input::Input::new();
+    // What the scene sees as the client size. On replay this is the recorded
+    // size and its resizes, whatever the window really is.
+    let mut viewport = match &playback {
+        Some(recording) => recording.viewport,
+        None => (width, height),
+    };
+    let mut gamepads = gamepad::Gamepads::new(match playback {
+        Some(_) => replay::TapeBackend::replay(),
+        None => replay::TapeBackend::live(gamepad::XInputBackend),
+    });
+    let mut playback = playback.map(|recording| recording.frames.into_iter());
+    let mut recording = replay::Recording::new();
+    recording.viewport = (width, height);
+    let mut reload_timer = 0.0;
+
+    loop {
+        input.begin_frame();
+        platform_window.poll_events(&mut events);
+
+        // On replay the window only drives resizing and closing, input comes
+        // from the recording
+        let replayed = match &mut playback {
+            Some(frames) => match frames.next() {
+                Some(frame) => Some(frame),
+                None => break,
+            },
+            None => None,
+        };
+        let frame_events = match &replayed {
+            Some(frame) => frame.events.clone(),
+            None => events.clone(),
+        };
+        for event in &frame_events {
+            input.handle_event(event);
+            if let Event::Resized { width, height } = *event {
+                viewport = (width, height);
+            }
+        }
+
+        for event in events.drain(..) {
+            match event {
+                Event::Resized { width, height } => {
+                    display_state.windowed_resized(width, height);
+                    let resized = (width as i32, height as i32)
+                        != (renderer.window.width, renderer.window.height);
+                    if resized && device_recovery.is_running() {
+                        resize_swap_chain(&mut renderer.window, &mut renderer.devices);
+                    }
+                }
+                Event::DpiChanged { dpi } => println!("DPI changed to {}", dpi),
+                _ => {}
+            }
+        }
+        if platform_window.close_requested() {
+            break;
+        }
+
+        if action_map.was_triggered(&input, "toggle_fullscreen") && device_recovery.is_running() {
+            let mode = display_state.toggle_fullscreen();
+            apply_display_mode(
+                &platform_window,
+                &mut renderer.window,
+                &mut renderer.devices,
+                &mode,
+                &monitors,
+            );
+        }
+        match &replayed {
+            Some(frame) => {
+                timer.advance(frame.delta_time);
+                gamepads.backend_mut().set_frame(&frame.pads);
+            }
+            None => timer.tick(),
+        }
+
+        gamepads.update();
+        for event in gamepads.events() {
+            match event {
+                gamepad::GamepadEvent::Connected(i) => println!("Gamepad {} connected", i),
+                gamepad::GamepadEvent::Disconnected(i) => println!("Gamepad {} disconnected", i),
+            }
+        }
+        if record_path.is_some() {
+            recording.frames.push(replay::Frame {
+                delta_time: timer.delta_time,
+                events: frame_events,
+                pads: gamepads.backend_mut().take_polls(),
+            });
+        } else {
+            gamepads.backend_mut().take_polls();
+        }
+
+        reload_timer += timer.delta_time;
+        if reload_timer >= RELOAD_CHECK_INTERVAL {
+            reload_timer = 0.0;
+            asset_manager.reload_changed();
+        }
+        asset_manager.update();
+        apply_reloads(
+            &mut asset_manager,
+            &mut renderer,
+            &mut loaded,
+            device_recovery.is_running(),
+            &mut action_map,
+        );
+        app::update_scene(
+            &mut scene,
+            &input,
+            &action_map,
+            &gamepads,
+            viewport,
+            timer.delta_time,
+        );
+
+        // Nothing to draw with while the device is being recreated
+        if device_recovery.is_running() {
+            unsafe {
+                let devices = &renderer.devices;
+
+                // Clear Canvas
+                let array: [f32; 4] = [0.1, 0.0, 0.3, 1.0];
+                let context = devices._device_context.as_ref().unwrap();
+                context.ClearRenderTargetView(devices._render_target, &array);
+                // To the far plane, which is 0 with reverse-Z
+                context.ClearDepthStencilView(
+                    devices._depth_stencil_view,
+                    D3D11_CLEAR_DEPTH,
+                    scene.camera.far_depth(),
+                    0,
+                );
+            }
+
+            draw_list.clear();
+            for object in &scene.objects {
+                let material = &renderer.materials[object.material];
+                let mut state = material.state;
+                if scene.camera.reverse_z {
+                    state.depth_stencil = state.depth_stencil.reverse_z();
+                }
+                let pipeline = renderer.pipelines.get(&pipeline::PipelineDesc::new(
+                    material.permutation,
+                    vertex::Vertex::LAYOUT,
+                    renderer.geometry[object.mesh].topology,
+                    state,
+                ));
+                draw_list.push(
+                    object.mesh,
+                    scene.graph.world_matrix(object.node),
+                    object.material,
+                    pipeline,
+                );
+            }
+            draw_list.sort_by_pipeline();
+            let frame = draw::FrameConstants::new(
+                scene.camera.view_projection_matrix(),
+                timer.game_time as f32,
+            );
+            draw_items(
+                &renderer.devices,
+                &mut renderer.buffers,
+                &renderer.pipelines,
+                &renderer.materials,
+                &frame,
+                &draw_list,
+            );
+        }
+
+        // Switch back & front buffers, recreating the device if it was lost
+        let recoveries = device_recovery.recoveries();
+        if device_recovery.present(&mut renderer) == recovery::RecoveryState::Failed {
+            panic!("Could not recreate the device")
+        }
+        // A new swap chain starts out windowed, put it back in exclusive mode
+        if device_recovery.recoveries() != recoveries {
+            apply_display_mode(
+                &platform_window,
+                &mut renderer.window,
+                &mut renderer.devices,
+                &display_state.mode,
+                &monitors,
+            );
+        }
+    }
+
+    renderer.release_resources();
+
+    if let Some(path) = record_path {
+        match recording.save(&path) {
+            Ok(()) => println!("Recorded {} frames to {}", recording.frames.len(), path),
+            Err(e) => println!("Error saving {}: {}", path, e),
+        }
+    }
+}
@@ -0,0 +1,551 @@
+// Input recording and replay. Every frame stores the window events, the
+// gamepad polls and the frame's delta time, which is everything the scene
+// update reads, so feeding a recording back reproduces the session exactly.
+//
+// Recordings are plain text, one item per line:
+//
+//   version 1
+//   viewport 1280 720
+//   frame 0.016693
+//   key_down Left - 0
+//   mouse_down MouseLeft 12 40
+//   pad 0 0x1000 0 0 -3200 12000 0 0
+//   pad 1 none
+//   frame 0.016671
+//   ...
+//
+// Deltas are written with Rust's shortest round-trip formatting, so they read
+// back bit for bit.
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use crate::gamepad::{GamepadBackend, RawGamepad, MAX_GAMEPADS};
+use crate::platform::{Event, Key, Modifiers, MouseButton};
+
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    pub delta_time: f64,
+    pub events: Vec<Event>,
+    // Gamepad slots polled this frame and what they returned
+    pub pads: Vec<(usize, Option<RawGamepad>)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    // Client size when recording started, later sizes come in as events
+    pub viewport: (u32, u32),
+    pub frames: Vec<Frame>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Recording, Error> {
+        let mut recording = Recording::new();
+        let mut version = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| invalid_data(format!("Line {}: {}", number + 1, message));
+
+            let mut words = line.split_whitespace();
+            let kind = words.next().unwrap_or("");
+            if version.is_none() {
+                if kind != "version" {
+                    return Err(error("expected \"version\""));
+                }
+                let v: u32 = parse_next(&mut words).map_err(|e| error(&e))?;
+                if v != VERSION {
+                    return Err(error(&format!("unsupported version {}", v)));
+                }
+                version = Some(v);
+                continue;
+            }
+
+            if kind == "viewport" {
+                let width = parse_next(&mut words).map_err(|e| error(&e))?;
+                let height = parse_next(&mut words).map_err(|e| error(&e))?;
+                recording.viewport = (width, height);
+                continue;
+            }
+            if kind == "frame" {
+                let delta_time = parse_next(&mut words).map_err(|e| error(&e))?;
+                recording.frames.push(Frame {
+                    delta_time,
+                    ..Frame::default()
+                });
+                continue;
+            }
+            let frame = recording
+                .frames
+                .last_mut()
+                .ok_or_else(|| error("expected \"frame\""))?;
+            if kind == "pad" {
+                frame
+                    .pads
+                    .push(parse_pad(&mut words).map_err(|e| error(&e))?);
+            } else {
+                frame
+                    .events
+                    .push(parse_event(kind, &mut words).map_err(|e| error(&e))?);
+            }
+            if words.next().is_some() {
+                return Err(error("unexpected trailing text"));
+            }
+        }
+        Ok(recording)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_string())
+    }
+
+    // Total time covered by the recording, in seconds
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|f| f.delta_time).sum()
+    }
+
+    // The events on their own, in the shape HeadlessPlatform wants
+    pub fn event_script(&self) -> Vec<Vec<Event>> {
+        self.frames.iter().map(|f| f.events.clone()).collect()
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version {}", VERSION)?;
+        writeln!(f, "viewport {} {}", self.viewport.0, self.viewport.1)?;
+        for frame in &self.frames {
+            writeln!(f, "frame {}", frame.delta_time)?;
+            for event in &frame.events {
+                writeln!(f, "{}", EventLine(event))?;
+            }
+            for (index, pad) in &frame.pads {
+                match pad {
+                    Some(pad) => writeln!(
+                        f,
+                        "pad {} {:#06x} {} {} {} {} {} {}",
+                        index,
+                        pad.buttons,
+                        pad.left_trigger,
+                        pad.right_trigger,
+                        pad.thumb_lx,
+                        pad.thumb_ly,
+                        pad.thumb_rx,
+                        pad.thumb_ry
+                    )?,
+                    None => writeln!(f, "pad {} none", index)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn next_word<'a>(words: &mut SplitWhitespace<'a>) -> Result<&'a str, String> {
+    words.next().ok_or_else(|| String::from("missing value"))
+}
+
+fn parse_next<T: std::str::FromStr>(words: &mut SplitWhitespace) -> Result<T, String> {
+    let word = next_word(words)?;
+    word.parse()
+        .map_err(|_| format!("invalid value \"{}\"", word))
+}
+
+fn parse_bool(words: &mut SplitWhitespace) -> Result<bool, String> {
+    Ok(parse_next::<u8>(words)? != 0)
+}
+
+// "-" for none, otherwise "Ctrl+Shift+Alt" in any order
+fn format_modifiers(modifiers: Modifiers) -> String {
+    let mut names = Vec::new();
+    if modifiers.control {
+        names.push("Ctrl");
+    }
+    if modifiers.shift {
+        names.push("Shift");
+    }
+    if modifiers.alt {
+        names.push("Alt");
+    }
+    if names.is_empty() {
+        String::from("-")
+    } else {
+        names.join("+")
+    }
+}
+
+fn parse_modifiers(words: &mut SplitWhitespace) -> Result<Modifiers, String> {
+    let mut modifiers = Modifiers::default();
+    let word = next_word(words)?;
+    if word == "-" {
+        return Ok(modifiers);
+    }
+    for name in word.split('+') {
+        match name {
+            "Ctrl" => modifiers.control = true,
+            "Shift" => modifiers.shift = true,
+            "Alt" => modifiers.alt = true,
+            _ => return Err(format!("unknown modifier \"{}\"", name)),
+        }
+    }
+    Ok(modifiers)
+}
+
+fn parse_key(words: &mut SplitWhitespace) -> Result<Key, String> {
+    let word = next_word(words)?;
+    Key::from_name(word).ok_or_else(|| format!("unknown key \"{}\"", word))
+}
+
+fn parse_button(words: &mut SplitWhitespace) -> Result<MouseButton, String> {
+    let word = next_word(words)?;
+    MouseButton::from_name(word).ok_or_else(|| format!("unknown mouse button \"{}\"", word))
+}
+
+fn parse_event(kind: &str, words: &mut SplitWhitespace) -> Result<Event, String> {
+    Ok(match kind {
+        "close" => Event::CloseRequested,
+        "resized" => Event::Resized {
+            width: parse_next(words)?,
+            height: parse_next(words)?,
+        },
+        "focused" => Event::Focused(parse_bool(words)?),
+        "key_down" => Event::KeyDown {
+            key: parse_key(words)?,
+            modifiers: parse_modifiers(words)?,
+            repeat: parse_bool(words)?,
+        },
+        "key_up" => Event::KeyUp {
+            key: parse_key(words)?,
+            modifiers: parse_modifiers(words)?,
+        },
+        // As a code point, so whitespace survives
+        "char" => {
+            let code = parse_next(words)?;
+            Event::Char(std::char::from_u32(code).ok_or("invalid character")?)
+        }
+        "mouse_move" => Event::MouseMove {
+            x: parse_next(words)?,
+            y: parse_next(words)?,
+        },
+        "mouse_down" => Event::MouseDown {
+            button: parse_button(words)?,
+            x: parse_next(words)?,
+            y: parse_next(words)?,
+        },
+        "mouse_up" => Event::MouseUp {
+            button: parse_button(words)?,
+            x: parse_next(words)?,
+            y: parse_next(words)?,
+        },
+        "wheel" => Event::MouseWheel {
+            delta_x: parse_next(words)?,
+            delta_y: parse_next(words)?,
+        },
+        "dpi" => Event::DpiChanged {
+            dpi: parse_next(words)?,
+        },
+        _ => return Err(format!("unknown event \"{}\"", kind)),
+    })
+}
+
+fn parse_pad(words: &mut SplitWhitespace) -> Result<(usize, Option<RawGamepad>), String> {
+    let index: usize = parse_next(words)?;
+    if index >= MAX_GAMEPADS {
+        return Err(format!("invalid gamepad index {}", index));
+    }
+    let buttons = next_word(words)?;
+    if buttons == "none" {
+        return Ok((index, None));
+    }
+    let buttons = u16::from_str_radix(buttons.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid buttons \"{}\"", buttons))?;
+    Ok((
+        index,
+        Some(RawGamepad {
+            buttons,
+            left_trigger: parse_next(words)?,
+            right_trigger: parse_next(words)?,
+            thumb_lx: parse_next(words)?,
+            thumb_ly: parse_next(words)?,
+            thumb_rx: parse_next(words)?,
+            thumb_ry: parse_next(words)?,
+        }),
+    ))
+}
+
+struct EventLine<'a>(&'a Event);
+
+impl fmt::Display for EventLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Event::CloseRequested => write!(f, "close"),
+            Event::Resized { width, height } => write!(f, "resized {} {}", width, height),
+            Event::Focused(focused) => write!(f, "focused {}", focused as u8),
+            Event::KeyDown {
+                key,
+                modifiers,
+                repeat,
+            } => write!(
+                f,
+                "key_down {} {} {}",
+                key.name(),
+                format_modifiers(modifiers),
+                repeat as u8
+            ),
+            Event::KeyUp { key, modifiers } => {
+                write!(f, "key_up {} {}", key.name(), format_modifiers(modifiers))
+            }
+            Event::Char(c) => write!(f, "char {}", c as u32),
+            Event::MouseMove { x, y } => write!(f, "mouse_move {} {}", x, y),
+            Event::MouseDown { button, x, y } => {
+                write!(f, "mouse_down {} {} {}", button.name(), x, y)
+            }
+            Event::MouseUp { button, x, y } => write!(f, "mouse_up {} {} {}", button.name(), x, y),
+            Event::MouseWheel { delta_x, delta_y } => write!(f, "wheel {} {}", delta_x, delta_y),
+            Event::DpiChanged { dpi } => write!(f, "dpi {}", dpi),
+        }
+    }
+}
+
+// Sits between Gamepads and the device. Live it passes polls through to the
+// real backend and remembers them for the recording, on replay it answers
+// from the recorded frame instead.
+pub struct TapeBackend<B: GamepadBackend> {
+    live: Option<B>,
+    replayed: [Option<RawGamepad>; MAX_GAMEPADS],
+    polls: Vec<(usize, Option<RawGamepad>)>,
+}
+
+impl<B: GamepadBackend> TapeBackend<B> {
+    pub fn live(backend: B) -> Self {
+        Self {
+            live: Some(backend),
+            replayed: [None; MAX_GAMEPADS],
+            polls: Vec::new(),
+        }
+    }
+
+    pub fn replay() -> Self {
+        Self {
+            live: None,
+            replayed: [None; MAX_GAMEPADS],
+            polls: Vec::new(),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.live.is_none()
+    }
+
+    // Call before Gamepads::update with the frame being replayed
+    pub fn set_frame(&mut self, pads: &[(usize, Option<RawGamepad>)]) {
+        for &(index, pad) in pads {
+            self.replayed[index] = pad;
+        }
+    }
+
+    // Polls since the last call, for Frame::pads
+    pub fn take_polls(&mut self) -> Vec<(usize, Option<RawGamepad>)> {
+        std::mem::take(&mut self.polls)
+    }
+}
+
+impl<B: GamepadBackend> GamepadBackend for TapeBackend<B> {
+    fn poll(&mut self, index: usize) -> Option<RawGamepad> {
+        let pad = match &mut self.live {
+            Some(backend) => backend.poll(index),
+            None => self.replayed[index],
+        };
+        self.polls.push((index, pad));
+        pad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::{GamepadButton, GamepadState, Gamepads, VirtualGamepad};
+
+    fn every_event() -> Vec<Event> {
+        let ctrl_shift = Modifiers {
+            control: true,
+            shift: true,
+            alt: false,
+        };
+        vec![
+            Event::Resized {
+                width: 800,
+                height: 600,
+            },
+            Event::Focused(false),
+            Event::KeyDown {
+                key: Key::Letter('W'),
+                modifiers: ctrl_shift,
+                repeat: true,
+            },
+            Event::KeyUp {
+                key: Key::F(11),
+                modifiers: Modifiers::default(),
+            },
+            Event::Char(' '),
+            Event::Char('é'),
+            Event::MouseMove { x: -5, y: 7 },
+            Event::MouseDown {
+                button: MouseButton::X2,
+                x: 1,
+                y: 2,
+            },
+            Event::MouseUp {
+                button: MouseButton::Left,
+                x: 3,
+                y: 4,
+            },
+            Event::MouseWheel {
+                delta_x: -0.5,
+                delta_y: 1.0 / 3.0,
+            },
+            Event::DpiChanged { dpi: 144 },
+            Event::CloseRequested,
+        ]
+    }
+
+    fn pad() -> RawGamepad {
+        RawGamepad {
+            left_trigger: 200,
+            thumb_lx: -32768,
+            thumb_ry: 12000,
+            ..RawGamepad::default()
+        }
+        .with_buttons(&[GamepadButton::A, GamepadButton::DPadLeft])
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let recording = Recording {
+            viewport: (1280, 720),
+            frames: vec![
+                Frame {
+                    // Needs all 17 digits to come back the same
+                    delta_time: 1.0 / 60.0,
+                    events: every_event(),
+                    pads: vec![(0, Some(pad())), (3, None)],
+                },
+                Frame {
+                    delta_time: 0.1 + 0.2,
+                    events: Vec::new(),
+                    pads: Vec::new(),
+                },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("replay-test-{}.txt", std::process::id()));
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path);
+        fs::remove_file(&path).ok();
+        assert_eq!(loaded.unwrap(), recording);
+        assert_eq!(Recording::parse(&recording.to_string()).unwrap(), recording);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        let cases = [
+            "frame 0.1\n",
+            "version 2\n",
+            "version 1\nkey_down Left - 0\n",
+            "version 1\nframe 0.1\nkey_down Nope - 0\n",
+            "version 1\nframe 0.1\nkey_down Left Meta 0\n",
+            "version 1\nframe 0.1\nclose now\n",
+            "version 1\nframe 0.1\npad 4 none\n",
+            "version 1\nframe 0.1\npad 0 0x1000 0 0\n",
+            "version 1\nframe 0.1\nchar 55296\n",
+            "version 1\nframe fast\n",
+        ];
+        for text in &cases {
+            let error = Recording::parse(text).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", text);
+        }
+        // Blank lines and comments are fine
+        let recording = Recording::parse("# hi\n\nversion 1\n  \nframe 0.5\n").unwrap();
+        assert_eq!(recording.frames.len(), 1);
+        assert_eq!(recording.duration(), 0.5);
+    }
+
+    // Runs the pads through Gamepads for every frame and returns what they
+    // read as, plus the polls the tape saw
+    fn play<B: GamepadBackend>(
+        backend: TapeBackend<B>,
+        frames: &[Frame],
+    ) -> (Vec<[GamepadState; MAX_GAMEPADS]>, Vec<Frame>) {
+        let mut gamepads = Gamepads::new(backend);
+        let mut states = Vec::new();
+        let mut taped = Vec::new();
+        for frame in frames {
+            gamepads.backend_mut().set_frame(&frame.pads);
+            gamepads.update();
+            states.push([0, 1, 2, 3].map(|i| *gamepads.state(i)));
+            taped.push(Frame {
+                delta_time: frame.delta_time,
+                events: frame.events.clone(),
+                pads: gamepads.backend_mut().take_polls(),
+            });
+        }
+        (states, taped)
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        // Record a live session where pad 1 is there from the start, moves and
+        // drops out. Pad 0 never shows up, so after the first frame it's only
+        // polled again on the reconnect interval.
+        let mut virtual_pads = VirtualGamepad::new();
+        let moved = RawGamepad {
+            thumb_lx: 20000,
+            ..pad()
+        };
+        virtual_pads.script(1, vec![Some(pad()), Some(moved), Some(moved), None]);
+        let frames: Vec<Frame> = (0..6)
+            .map(|i| Frame {
+                delta_time: 0.016 + i as f64 * 0.001,
+                events: every_event(),
+                pads: Vec::new(),
+            })
+            .collect();
+        let (live_states, taped) = play(TapeBackend::live(virtual_pads), &frames);
+        assert!(live_states.iter().any(|s| s[1].connected));
+
+        let recording = Recording::parse(
+            &Recording {
+                viewport: (640, 480),
+                frames: taped,
+            }
+            .to_string(),
+        )
+        .unwrap();
+        let (first, first_tape) = play(TapeBackend::<VirtualGamepad>::replay(), &recording.frames);
+        let (second, second_tape) =
+            play(TapeBackend::<VirtualGamepad>::replay(), &recording.frames);
+        assert_eq!(first, live_states);
+        assert_eq!(second, first);
+        // Replaying polls the same slots in the same order, so a replay can
+        // itself be recorded
+        assert_eq!(first_tape, recording.frames);
+        assert_eq!(second_tape, first_tape);
+    }
+}
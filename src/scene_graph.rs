@@ -0,0 +1,422 @@
+// Nodes with a local Transform and optional parent. World matrices are cached
+// and only recomputed for nodes whose transform, or some ancestor's, changed
+// since the last update_world_matrices.
+use directx_math::{XMLoadFloat4x4, XMMatrixMultiply, XMStoreFloat4x4, XMFLOAT4X4, XMMATRIX};
+
+use crate::transform::Transform;
+
+// Generation counted, so a handle to a removed node doesn't silently point at
+// whatever reused its slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+pub struct Node {
+    pub name: String,
+    transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: XMFLOAT4X4,
+    dirty: bool,
+}
+
+impl Node {
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    // As of the last update_world_matrices
+    pub fn world_matrix(&self) -> XMMATRIX {
+        XMLoadFloat4x4(&self.world)
+    }
+}
+
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    roots: Vec<NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, transform: Transform, parent: Option<NodeId>) -> NodeId {
+        let node = Node {
+            name: name.to_string(),
+            transform,
+            parent: None,
+            children: Vec::new(),
+            world: XMFLOAT4X4::default(),
+            dirty: true,
+        };
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        };
+
+        match parent.filter(|&p| self.contains(p)) {
+            Some(parent) => {
+                self.node_mut(id).parent = Some(parent);
+                self.node_mut(parent).children.push(id);
+            }
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    // Removes the node and everything below it
+    pub fn remove(&mut self, id: NodeId) {
+        if !self.contains(id) {
+            return;
+        }
+        self.detach(id);
+        self.roots.retain(|&r| r != id);
+
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let slot = &mut self.slots[id.index];
+            if let Some(node) = slot.node.take() {
+                pending.extend(node.children);
+            }
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(id.index);
+        }
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+            .expect("stale NodeId")
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter()
+            .find(|(_, node)| node.name == name)
+            .map(|(id, _)| id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    // Every node, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.node.as_ref().map(|node| {
+                (
+                    NodeId {
+                        index,
+                        generation: slot.generation,
+                    },
+                    node,
+                )
+            })
+        })
+    }
+
+    pub fn transform(&self, id: NodeId) -> &Transform {
+        &self.get(id).expect("stale NodeId").transform
+    }
+
+    // Marks the node dirty, so assume it changed
+    pub fn transform_mut(&mut self, id: NodeId) -> &mut Transform {
+        let node = self.node_mut(id);
+        node.dirty = true;
+        &mut node.transform
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        *self.transform_mut(id) = transform;
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        if let Some(parent) = self.node_mut(id).parent.take() {
+            self.node_mut(parent).children.retain(|&c| c != id);
+        }
+    }
+
+    // Moves a node (and its subtree) under a new parent, or to the top level.
+    // Keeps the local transform, so the node moves with its new parent.
+    // Returns false if that would make the node its own ancestor, or either
+    // node is gone.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        if let Some(parent) = parent {
+            if !self.contains(parent) || self.is_ancestor(id, parent) {
+                return false;
+            }
+        }
+
+        self.detach(id);
+        self.roots.retain(|&r| r != id);
+        match parent {
+            Some(parent) => {
+                self.node_mut(parent).children.push(id);
+                self.node_mut(id).parent = Some(parent);
+            }
+            None => self.roots.push(id),
+        }
+        self.node_mut(id).dirty = true;
+        true
+    }
+
+    // True if `ancestor` is `id` or above it
+    pub fn is_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current {
+            if node == ancestor {
+                return true;
+            }
+            current = self.get(node).and_then(|n| n.parent);
+        }
+        false
+    }
+
+    // Recomputes the world matrix of dirty nodes and everything below them
+    pub fn update_world_matrices(&mut self) {
+        let mut pending: Vec<(NodeId, Option<XMFLOAT4X4>, bool)> =
+            self.roots.iter().rev().map(|&r| (r, None, false)).collect();
+        while let Some((id, parent_world, parent_changed)) = pending.pop() {
+            let node = self.node_mut(id);
+            let changed = node.dirty || parent_changed;
+            if changed {
+                let local = node.transform.matrix();
+                let world = match &parent_world {
+                    Some(parent) => XMMatrixMultiply(local, &XMLoadFloat4x4(parent)),
+                    None => local,
+                };
+                XMStoreFloat4x4(&mut node.world, world);
+                node.dirty = false;
+            }
+            let world = node.world;
+            pending.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, Some(world), changed)),
+            );
+        }
+    }
+
+    pub fn world_matrix(&self, id: NodeId) -> XMMATRIX {
+        self.get(id).expect("stale NodeId").world_matrix()
+    }
+
+    // Depth first, parents before children, children in the order added.
+    // The callback gets the depth, 0 for roots.
+    pub fn traverse<F: FnMut(NodeId, &Node, usize)>(&self, mut visit: F) {
+        let mut pending: Vec<(NodeId, usize)> = self.roots.iter().rev().map(|&r| (r, 0)).collect();
+        while let Some((id, depth)) = pending.pop() {
+            if let Some(node) = self.get(id) {
+                visit(id, node, depth);
+                pending.extend(node.children.iter().rev().map(|&c| (c, depth + 1)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directx_math::{XMStoreFloat3, XMVector3TransformCoord, XMVectorSet, XMFLOAT3};
+
+    fn origin(graph: &SceneGraph, id: NodeId) -> (f32, f32, f32) {
+        let mut stored = XMFLOAT3::default();
+        XMStoreFloat3(
+            &mut stored,
+            XMVector3TransformCoord(XMVectorSet(0.0, 0.0, 0.0, 1.0), graph.world_matrix(id)),
+        );
+        (stored.x, stored.y, stored.z)
+    }
+
+    fn dirty(graph: &SceneGraph) -> Vec<String> {
+        let mut names: Vec<String> = graph
+            .iter()
+            .filter(|(_, node)| node.dirty)
+            .map(|(_, node)| node.name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // root -> child -> grandchild, plus a separate root
+    fn family() -> (SceneGraph, [NodeId; 4]) {
+        let mut graph = SceneGraph::new();
+        let root = graph.add("root", Transform::from_translation(1.0, 0.0, 0.0), None);
+        let child = graph.add(
+            "child",
+            Transform::from_translation(0.0, 1.0, 0.0).with_uniform_scale(2.0),
+            Some(root),
+        );
+        let grandchild = graph.add(
+            "grandchild",
+            Transform::from_translation(0.0, 0.0, 1.0),
+            Some(child),
+        );
+        let other = graph.add("other", Transform::from_translation(5.0, 0.0, 0.0), None);
+        graph.update_world_matrices();
+        (graph, [root, child, grandchild, other])
+    }
+
+    #[test]
+    fn world_matrices_compose_parents() {
+        let (graph, [root, child, grandchild, other]) = family();
+        assert_eq!(origin(&graph, root), (1.0, 0.0, 0.0));
+        assert_eq!(origin(&graph, child), (1.0, 1.0, 0.0));
+        // The child's scale applies to its children's offsets
+        assert_eq!(origin(&graph, grandchild), (1.0, 1.0, 2.0));
+        assert_eq!(origin(&graph, other), (5.0, 0.0, 0.0));
+        assert!(dirty(&graph).is_empty());
+    }
+
+    #[test]
+    fn changes_propagate_to_descendants() {
+        let (mut graph, [root, child, grandchild, other]) = family();
+        graph.transform_mut(child).translation.y = 3.0;
+        assert_eq!(dirty(&graph), ["child"]);
+        // Cached until the next update
+        assert_eq!(origin(&graph, grandchild), (1.0, 1.0, 2.0));
+
+        graph.update_world_matrices();
+        assert!(dirty(&graph).is_empty());
+        assert_eq!(origin(&graph, root), (1.0, 0.0, 0.0));
+        assert_eq!(origin(&graph, child), (1.0, 3.0, 0.0));
+        assert_eq!(origin(&graph, grandchild), (1.0, 3.0, 2.0));
+        assert_eq!(origin(&graph, other), (5.0, 0.0, 0.0));
+
+        graph.set_transform(root, Transform::identity());
+        graph.update_world_matrices();
+        assert_eq!(origin(&graph, grandchild), (0.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let (mut graph, [root, child, grandchild, other]) = family();
+        assert!(!graph.set_parent(root, Some(grandchild)));
+        assert!(!graph.set_parent(child, Some(child)));
+        assert!(!graph.set_parent(root, Some(child)));
+        // Nothing moved
+        assert_eq!(graph.roots(), [root, other]);
+        assert_eq!(graph.get(root).unwrap().children(), [child]);
+        assert_eq!(graph.get(root).unwrap().parent(), None);
+        assert!(dirty(&graph).is_empty());
+
+        // Moving a subtree elsewhere is fine and keeps its local transform
+        assert!(graph.set_parent(child, Some(other)));
+        assert!(graph.get(root).unwrap().children().is_empty());
+        assert_eq!(graph.get(other).unwrap().children(), [child]);
+        graph.update_world_matrices();
+        assert_eq!(origin(&graph, grandchild), (5.0, 1.0, 2.0));
+
+        assert!(graph.set_parent(child, None));
+        assert_eq!(graph.roots(), [root, other, child]);
+        assert!(graph.is_ancestor(child, grandchild));
+        assert!(!graph.is_ancestor(other, grandchild));
+
+        // Removed nodes can't be moved
+        graph.remove(other);
+        assert!(!graph.set_parent(other, None));
+        assert!(!graph.set_parent(other, Some(root)));
+        assert!(!graph.set_parent(root, Some(other)));
+        assert_eq!(graph.roots(), [root, child]);
+    }
+
+    #[test]
+    fn stale_ids_fail_lookup_after_reuse() {
+        let (mut graph, [root, child, grandchild, other]) = family();
+        graph.remove(child);
+        assert_eq!(graph.len(), 2);
+        assert!(!graph.contains(child));
+        assert!(graph.get(grandchild).is_none());
+        assert!(graph.get(root).unwrap().children().is_empty());
+        assert_eq!(graph.find("grandchild"), None);
+
+        // Both freed slots get reused, neither old id sees the new nodes
+        let a = graph.add("a", Transform::identity(), Some(root));
+        let b = graph.add("b", Transform::identity(), Some(other));
+        let reused = [a.index, b.index];
+        assert!(reused.contains(&child.index) && reused.contains(&grandchild.index));
+        assert_ne!(a, child);
+        assert_ne!(b, grandchild);
+        assert!(graph.get(child).is_none());
+        assert!(graph.get(grandchild).is_none());
+        assert_eq!(graph.len(), 4);
+
+        // Stale ids are ignored rather than touching the new nodes
+        graph.remove(child);
+        graph.remove(grandchild);
+        assert_eq!(graph.len(), 4);
+        let orphan = graph.add("orphan", Transform::identity(), Some(child));
+        assert_eq!(graph.get(orphan).unwrap().parent(), None);
+        assert!(graph.roots().contains(&orphan));
+    }
+
+    #[test]
+    fn traverse_visits_parents_first() {
+        let (graph, _) = family();
+        let mut visited = Vec::new();
+        graph.traverse(|_, node, depth| visited.push((node.name.clone(), depth)));
+        let expected = [("root", 0), ("child", 1), ("grandchild", 2), ("other", 0)];
+        assert_eq!(
+            visited,
+            expected.map(|(name, depth)| (String::from(name), depth))
+        );
+    }
+}
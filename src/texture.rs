@@ -0,0 +1,742 @@
+// Images decoded to 8 bit RGBA, their mip chains and the texture data and
+// sampler settings the GPU side is created from. Texture and Sampler wrap the
+// D3D11 objects.
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::file_system::{DiskFileSystem, FileSystem};
+
+// Bytes per pixel of an Image
+const RGBA: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    // Rows top to bottom, 4 bytes per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize * RGBA);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn solid(width: u32, height: u32, color: [u8; 4]) -> Self {
+        Self::new(
+            width,
+            height,
+            color.repeat(width as usize * height as usize),
+        )
+    }
+
+    // Alternating `a` and `b` squares of `cell` pixels, `a` in the top left
+    pub fn checkerboard(size: u32, cell: u32, a: [u8; 4], b: [u8; 4]) -> Self {
+        let cell = cell.max(1);
+        let mut pixels = Vec::with_capacity(size as usize * size as usize * RGBA);
+        for y in 0..size {
+            for x in 0..size {
+                let odd = (x / cell + y / cell) % 2 == 1;
+                pixels.extend_from_slice(if odd { &b } else { &a });
+            }
+        }
+        Self::new(size, size, pixels)
+    }
+
+    // PNG, JPEG and BMP are recognized by their signature. TGA has none, so
+    // anything else is tried as TGA.
+    pub fn decode(bytes: &[u8]) -> Result<Image, Error> {
+        let format = image::guess_format(bytes).unwrap_or(image::ImageFormat::Tga);
+        Self::decode_format(bytes, format)
+    }
+
+    fn decode_format(bytes: &[u8], format: image::ImageFormat) -> Result<Image, Error> {
+        let decoded = image::load_from_memory_with_format(bytes, format)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?
+            .into_rgba8();
+        let (width, height) = decoded.dimensions();
+        Ok(Self::new(width, height, decoded.into_raw()))
+    }
+
+    // The extension picks the format, falling back to the signature
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, Error> {
+        Self::load_from(&DiskFileSystem, path.as_ref())
+    }
+
+    pub fn load_from(files: &dyn FileSystem, path: &Path) -> Result<Image, Error> {
+        let bytes = files.read(path)?;
+        match image::ImageFormat::from_path(path) {
+            Ok(format) => Self::decode_format(&bytes, format),
+            Err(_) => Self::decode(&bytes),
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y as usize * self.width as usize + x as usize) * RGBA;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    // Half the size, at least 1x1. Each pixel is the average of the 2x2
+    // block above it, edge pixels are repeated for odd sizes. sRGB colors are
+    // averaged in linear space, alpha always is.
+    pub fn downsample(&self, color_space: ColorSpace) -> Image {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * RGBA);
+        for y in 0..height {
+            let rows = [
+                (y * 2).min(self.height - 1),
+                (y * 2 + 1).min(self.height - 1),
+            ];
+            for x in 0..width {
+                let columns = [(x * 2).min(self.width - 1), (x * 2 + 1).min(self.width - 1)];
+                let mut sum = [0.0f32; 4];
+                for &row in &rows {
+                    for &column in &columns {
+                        let pixel = self.pixel(column, row);
+                        for c in 0..4 {
+                            sum[c] += match (c, color_space) {
+                                (0..=2, ColorSpace::Srgb) => srgb_to_linear(pixel[c]),
+                                _ => pixel[c] as f32 / 255.0,
+                            };
+                        }
+                    }
+                }
+                for (c, &total) in sum.iter().enumerate() {
+                    let average = total / 4.0;
+                    pixels.push(match (c, color_space) {
+                        (0..=2, ColorSpace::Srgb) => linear_to_srgb(average),
+                        _ => (average * 255.0).round() as u8,
+                    });
+                }
+            }
+        }
+        Image::new(width, height, pixels)
+    }
+
+    // This image followed by every smaller level down to 1x1
+    pub fn mip_chain(&self, color_space: ColorSpace) -> Vec<Image> {
+        let mut mips = vec![self.clone()];
+        while let Some(last) = mips.last().filter(|m| m.width > 1 || m.height > 1) {
+            let next = last.downsample(color_space);
+            mips.push(next);
+        }
+        mips
+    }
+}
+
+// How the color channels of an image are encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    // Colors meant to be seen, like albedo. Sampled with the hardware
+    // converting to linear.
+    Srgb,
+    // Data like normals, roughness or masks
+    Linear,
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let c = value.clamp(0.0, 1.0);
+    let c = if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+// Levels in a full mip chain of the given size
+pub fn mip_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8,
+    Rgba8Srgb,
+    Bgra8,
+    Bgra8Srgb,
+    R8,
+    Rg8,
+    Rgba16Float,
+    Rgba32Float,
+    // Block compressed, 4x4 pixels per block
+    Bc1,
+    Bc1Srgb,
+    Bc2,
+    Bc2Srgb,
+    Bc3,
+    Bc3Srgb,
+    Bc4,
+    Bc4Snorm,
+    Bc5,
+    Bc5Snorm,
+    Bc6hUfloat,
+    Bc6hSfloat,
+    Bc7,
+    Bc7Srgb,
+}
+
+// Each format and its DXGI_FORMAT value
+const DXGI_FORMATS: [(TextureFormat, u32); 22] = [
+    (TextureFormat::Rgba32Float, 2),
+    (TextureFormat::Rgba16Float, 10),
+    (TextureFormat::Rgba8, 28),
+    (TextureFormat::Rgba8Srgb, 29),
+    (TextureFormat::Rg8, 49),
+    (TextureFormat::R8, 61),
+    (TextureFormat::Bc1, 71),
+    (TextureFormat::Bc1Srgb, 72),
+    (TextureFormat::Bc2, 74),
+    (TextureFormat::Bc2Srgb, 75),
+    (TextureFormat::Bc3, 77),
+    (TextureFormat::Bc3Srgb, 78),
+    (TextureFormat::Bc4, 80),
+    (TextureFormat::Bc4Snorm, 81),
+    (TextureFormat::Bc5, 83),
+    (TextureFormat::Bc5Snorm, 84),
+    (TextureFormat::Bgra8, 87),
+    (TextureFormat::Bgra8Srgb, 91),
+    (TextureFormat::Bc6hUfloat, 95),
+    (TextureFormat::Bc6hSfloat, 96),
+    (TextureFormat::Bc7, 98),
+    (TextureFormat::Bc7Srgb, 99),
+];
+
+impl TextureFormat {
+    pub fn to_dxgi(self) -> u32 {
+        DXGI_FORMATS.iter().find(|(f, _)| *f == self).unwrap().1
+    }
+
+    pub fn from_dxgi(value: u32) -> Option<Self> {
+        DXGI_FORMATS
+            .iter()
+            .find(|(_, v)| *v == value)
+            .map(|(f, _)| *f)
+    }
+
+    // Bytes per 4x4 block for block compressed formats, None for the others
+    pub fn block_size(self) -> Option<usize> {
+        match self {
+            TextureFormat::Bc1
+            | TextureFormat::Bc1Srgb
+            | TextureFormat::Bc4
+            | TextureFormat::Bc4Snorm => Some(8),
+            TextureFormat::Bc2
+            | TextureFormat::Bc2Srgb
+            | TextureFormat::Bc3
+            | TextureFormat::Bc3Srgb
+            | TextureFormat::Bc5
+            | TextureFormat::Bc5Snorm
+            | TextureFormat::Bc6hUfloat
+            | TextureFormat::Bc6hSfloat
+            | TextureFormat::Bc7
+            | TextureFormat::Bc7Srgb => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self.block_size().is_some()
+    }
+
+    // Bytes per pixel of uncompressed formats
+    pub fn pixel_size(self) -> Option<usize> {
+        match self {
+            TextureFormat::R8 => Some(1),
+            TextureFormat::Rg8 => Some(2),
+            TextureFormat::Rgba8
+            | TextureFormat::Rgba8Srgb
+            | TextureFormat::Bgra8
+            | TextureFormat::Bgra8Srgb => Some(4),
+            TextureFormat::Rgba16Float => Some(8),
+            TextureFormat::Rgba32Float => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn is_srgb(self) -> bool {
+        matches!(
+            self,
+            TextureFormat::Rgba8Srgb
+                | TextureFormat::Bgra8Srgb
+                | TextureFormat::Bc1Srgb
+                | TextureFormat::Bc2Srgb
+                | TextureFormat::Bc3Srgb
+                | TextureFormat::Bc7Srgb
+        )
+    }
+
+    // Bytes per row of a level `width` pixels wide. A row of blocks for
+    // compressed formats. None if that doesn't fit in a usize.
+    pub fn row_pitch(self, width: u32) -> Option<usize> {
+        match self.block_size() {
+            Some(block) => blocks(width).checked_mul(block),
+            None => (width as usize).checked_mul(self.pixel_size().unwrap()),
+        }
+    }
+
+    // Rows of pitch bytes in a level
+    pub fn row_count(self, height: u32) -> usize {
+        if self.is_compressed() {
+            blocks(height)
+        } else {
+            height as usize
+        }
+    }
+
+    // Bytes in a whole level, None if that doesn't fit in a usize
+    pub fn level_size(self, width: u32, height: u32) -> Option<usize> {
+        self.row_pitch(width)?.checked_mul(self.row_count(height))
+    }
+}
+
+// Blocks needed to cover `pixels`, partial blocks included
+fn blocks(pixels: u32) -> usize {
+    (pixels as usize).div_ceil(4).max(1)
+}
+
+// Everything needed to create a texture: the size of the top level and the
+// bytes of each subresource. Subresources are in D3D order, every mip level
+// of the first array slice largest first, then the next slice.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub mip_levels: u32,
+    // Slices in the array, six per cube for cubemaps (+x, -x, +y, -y, +z, -z)
+    pub array_size: u32,
+    pub cubemap: bool,
+    pub subresources: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn from_image(image: &Image, color_space: ColorSpace, mipmaps: bool) -> Self {
+        let format = match color_space {
+            ColorSpace::Srgb => TextureFormat::Rgba8Srgb,
+            ColorSpace::Linear => TextureFormat::Rgba8,
+        };
+        let mips: Vec<Vec<u8>> = if mipmaps {
+            image
+                .mip_chain(color_space)
+                .into_iter()
+                .map(|mip| mip.pixels)
+                .collect()
+        } else {
+            vec![image.pixels.clone()]
+        };
+        Self {
+            width: image.width,
+            height: image.height,
+            format,
+            mip_levels: mips.len() as u32,
+            array_size: 1,
+            cubemap: false,
+            subresources: mips,
+        }
+    }
+
+    // Size of mip `level`
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    pub fn subresource(&self, slice: usize, level: usize) -> &[u8] {
+        &self.subresources[slice * self.mip_levels as usize + level]
+    }
+
+    // Every subresource has the size the chain says it should
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::new(ErrorKind::InvalidData, message));
+        if self.width == 0 || self.height == 0 || self.mip_levels == 0 || self.array_size == 0 {
+            return invalid(String::from("Empty texture"));
+        }
+        if self.mip_levels > mip_count(self.width, self.height) {
+            return invalid(String::from("Too many mip levels"));
+        }
+        if self.cubemap && (!self.array_size.is_multiple_of(6) || self.width != self.height) {
+            return invalid(String::from(
+                "Cubemaps need square faces and six slices per cube",
+            ));
+        }
+        // D3D11 wants whole blocks at the top level
+        if self.format.is_compressed()
+            && (!self.width.is_multiple_of(4) || !self.height.is_multiple_of(4))
+        {
+            return invalid(format!(
+                "{:?} needs a size in multiples of 4, not {}x{}",
+                self.format, self.width, self.height
+            ));
+        }
+        let expected_count = (self.mip_levels * self.array_size) as usize;
+        if self.subresources.len() != expected_count {
+            return invalid(format!(
+                "{} subresources, expected {}",
+                self.subresources.len(),
+                expected_count
+            ));
+        }
+        for (i, data) in self.subresources.iter().enumerate() {
+            let level = i % self.mip_levels as usize;
+            let (width, height) = self.level_size(level);
+            let expected = self
+                .format
+                .level_size(width, height)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Texture too large"))?;
+            if data.len() != expected {
+                return invalid(format!(
+                    "Slice {} mip {} has {} bytes, expected {}",
+                    i / self.mip_levels as usize,
+                    level,
+                    data.len(),
+                    expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Point,
+    // Linear within a level, nearest level
+    Bilinear,
+    Trilinear,
+    // Up to this many samples, 1 to 16
+    Anisotropic(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressMode {
+    Wrap,
+    Mirror,
+    Clamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerDesc {
+    pub filter: Filter,
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::new(Filter::Trilinear, AddressMode::Wrap)
+    }
+}
+
+impl SamplerDesc {
+    pub fn new(filter: Filter, address: AddressMode) -> Self {
+        Self {
+            filter,
+            address_u: address,
+            address_v: address,
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use self::win32::{Sampler, Texture};
+
+#[cfg(windows)]
+mod win32 {
+    use std::mem;
+    use std::ptr::null_mut;
+
+    use winapi::shared::dxgitype::DXGI_SAMPLE_DESC;
+    use winapi::shared::winerror::FAILED;
+    use winapi::um::d3d11::*;
+    use winapi::um::d3dcommon::{
+        D3D11_SRV_DIMENSION_TEXTURECUBE, D3D11_SRV_DIMENSION_TEXTURECUBEARRAY,
+    };
+    use winapi::um::winnt::HRESULT;
+
+    use super::{AddressMode, Filter, SamplerDesc, TextureData};
+
+    // An immutable 2D texture, texture array or cubemap and a view of all of
+    // it
+    pub struct Texture {
+        texture: *mut ID3D11Texture2D,
+        view: *mut ID3D11ShaderResourceView,
+    }
+
+    impl Texture {
+        pub unsafe fn new(device: &ID3D11Device, data: &TextureData) -> Result<Self, HRESULT> {
+            let desc = D3D11_TEXTURE2D_DESC {
+                Width: data.width,
+                Height: data.height,
+                MipLevels: data.mip_levels,
+                ArraySize: data.array_size,
+                Format: data.format.to_dxgi(),
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_IMMUTABLE,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                CPUAccessFlags: 0,
+                MiscFlags: if data.cubemap {
+                    D3D11_RESOURCE_MISC_TEXTURECUBE
+                } else {
+                    0
+                },
+            };
+            let init_data: Vec<D3D11_SUBRESOURCE_DATA> = data
+                .subresources
+                .iter()
+                .enumerate()
+                .map(|(i, subresource)| {
+                    let level = i % data.mip_levels as usize;
+                    D3D11_SUBRESOURCE_DATA {
+                        pSysMem: subresource.as_ptr() as _,
+                        // Validated above, so the pitch fits
+                        SysMemPitch: data.format.row_pitch(data.level_size(level).0).unwrap()
+                            as u32,
+                        SysMemSlicePitch: 0,
+                    }
+                })
+                .collect();
+
+            let mut texture = Self {
+                texture: null_mut(),
+                view: null_mut(),
+            };
+            let res = device.CreateTexture2D(&desc, init_data.as_ptr(), &mut texture.texture);
+            if FAILED(res) {
+                return Err(res);
+            }
+            // Without a description the view covers the whole texture as a
+            // 2D texture or array. Cubemaps have to ask to be sampled as cubes.
+            let mut view_desc: D3D11_SHADER_RESOURCE_VIEW_DESC = mem::zeroed();
+            let view_desc_ptr = if data.cubemap {
+                view_desc.Format = desc.Format;
+                if data.array_size == 6 {
+                    view_desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURECUBE;
+                    let cube = view_desc.u.TextureCube_mut();
+                    cube.MipLevels = data.mip_levels;
+                } else {
+                    view_desc.ViewDimension = D3D11_SRV_DIMENSION_TEXTURECUBEARRAY;
+                    let cubes = view_desc.u.TextureCubeArray_mut();
+                    cubes.MipLevels = data.mip_levels;
+                    cubes.NumCubes = data.array_size / 6;
+                }
+                &view_desc as *const _
+            } else {
+                std::ptr::null()
+            };
+            let res = device.CreateShaderResourceView(
+                texture.texture as *mut ID3D11Resource,
+                view_desc_ptr,
+                &mut texture.view,
+            );
+            if FAILED(res) {
+                return Err(res);
+            }
+            Ok(texture)
+        }
+
+        pub unsafe fn bind(&self, context: &ID3D11DeviceContext, slot: u32) {
+            context.PSSetShaderResources(slot, 1, &self.view);
+        }
+    }
+
+    impl Drop for Texture {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some(view) = self.view.as_ref() {
+                    view.Release();
+                }
+                if let Some(texture) = self.texture.as_ref() {
+                    texture.Release();
+                }
+            }
+        }
+    }
+
+    pub struct Sampler {
+        state: *mut ID3D11SamplerState,
+    }
+
+    impl Sampler {
+        pub unsafe fn new(device: &ID3D11Device, desc: &SamplerDesc) -> Result<Self, HRESULT> {
+            let (filter, max_anisotropy) = match desc.filter {
+                Filter::Point => (D3D11_FILTER_MIN_MAG_MIP_POINT, 1),
+                Filter::Bilinear => (D3D11_FILTER_MIN_MAG_LINEAR_MIP_POINT, 1),
+                Filter::Trilinear => (D3D11_FILTER_MIN_MAG_MIP_LINEAR, 1),
+                Filter::Anisotropic(samples) => (D3D11_FILTER_ANISOTROPIC, samples.clamp(1, 16)),
+            };
+            let address = |mode| match mode {
+                AddressMode::Wrap => D3D11_TEXTURE_ADDRESS_WRAP,
+                AddressMode::Mirror => D3D11_TEXTURE_ADDRESS_MIRROR,
+                AddressMode::Clamp => D3D11_TEXTURE_ADDRESS_CLAMP,
+            };
+            let desc = D3D11_SAMPLER_DESC {
+                Filter: filter,
+                AddressU: address(desc.address_u),
+                AddressV: address(desc.address_v),
+                AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                MipLODBias: 0.0,
+                MaxAnisotropy: max_anisotropy,
+                ComparisonFunc: D3D11_COMPARISON_NEVER,
+                BorderColor: [0.0; 4],
+                MinLOD: 0.0,
+                MaxLOD: D3D11_FLOAT32_MAX,
+            };
+            let mut sampler = Self { state: null_mut() };
+            let res = device.CreateSamplerState(&desc, &mut sampler.state);
+            if FAILED(res) {
+                return Err(res);
+            }
+            Ok(sampler)
+        }
+
+        pub unsafe fn bind(&self, context: &ID3D11DeviceContext, slot: u32) {
+            context.PSSetSamplers(slot, 1, &self.state);
+        }
+    }
+
+    impl Drop for Sampler {
+        fn drop(&mut self) {
+            unsafe {
+                if let Some(state) = self.state.as_ref() {
+                    state.Release();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    // 2x2, red and green over blue and half transparent white
+    const PIXELS: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255, 255, 255, 128],
+    ];
+
+    #[rustfmt::skip]
+    const PNG: [u8; 76] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x72,
+        0xb6, 0x0d, 0x24, 0x00, 0x00, 0x00, 0x13, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xf8,
+        0xcf, 0xc0, 0xf0, 0x1f, 0x0c, 0x81, 0x34, 0x08, 0x34, 0x00, 0x00, 0x49, 0x49, 0x09, 0x78,
+        0x9c, 0x51, 0x17, 0x92, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60,
+        0x82,
+    ];
+
+    // Uncompressed true color, BGRA rows stored bottom up
+    #[rustfmt::skip]
+    const TGA: [u8; 34] = [
+        0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02,
+        0x00, 0x20, 0x08,
+        0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x80,
+        0x00, 0x00, 0xff, 0xff, 0x00, 0xff, 0x00, 0xff,
+    ];
+
+    fn sizes(mips: &[Image]) -> Vec<(u32, u32)> {
+        mips.iter().map(|m| (m.width, m.height)).collect()
+    }
+
+    #[test]
+    fn mip_chains_halve_down_to_one() {
+        assert_eq!(mip_count(1, 1), 1);
+        assert_eq!(mip_count(256, 256), 9);
+        assert_eq!(mip_count(256, 1), 9);
+        assert_eq!(mip_count(5, 3), 3);
+        assert_eq!(mip_count(0, 0), 1);
+
+        let odd = Image::solid(5, 3, WHITE).mip_chain(ColorSpace::Srgb);
+        assert_eq!(sizes(&odd), [(5, 3), (2, 1), (1, 1)]);
+        let thin = Image::solid(1, 6, WHITE).mip_chain(ColorSpace::Linear);
+        assert_eq!(sizes(&thin), [(1, 6), (1, 3), (1, 1)]);
+        let npot = Image::solid(100, 60, WHITE).mip_chain(ColorSpace::Srgb);
+        assert_eq!(npot.len() as u32, mip_count(100, 60));
+        assert_eq!(
+            sizes(&npot),
+            [
+                (100, 60),
+                (50, 30),
+                (25, 15),
+                (12, 7),
+                (6, 3),
+                (3, 1),
+                (1, 1)
+            ]
+        );
+        // A solid color stays that color all the way down
+        assert!(npot.iter().all(|m| m.pixel(0, 0) == WHITE));
+
+        let data = TextureData::from_image(&Image::solid(7, 5, BLACK), ColorSpace::Srgb, true);
+        assert_eq!(data.mip_levels, 3);
+        assert_eq!(data.level_size(1), (3, 2));
+        assert_eq!(data.subresource(0, 1).len(), 3 * 2 * 4);
+        data.validate().unwrap();
+        let single = TextureData::from_image(&Image::solid(7, 5, BLACK), ColorSpace::Srgb, false);
+        assert_eq!(single.mip_levels, 1);
+        single.validate().unwrap();
+    }
+
+    #[test]
+    fn srgb_averages_in_linear_space() {
+        let checker = Image::checkerboard(2, 1, BLACK, WHITE);
+        // Half the light, not half the encoded value
+        let srgb = checker.downsample(ColorSpace::Srgb);
+        assert_eq!(srgb.pixel(0, 0), [188, 188, 188, 255]);
+        let linear = checker.downsample(ColorSpace::Linear);
+        assert_eq!(linear.pixel(0, 0), [128, 128, 128, 255]);
+
+        // Alpha is never sRGB
+        let faded = Image::checkerboard(2, 1, [255, 255, 255, 0], WHITE);
+        assert_eq!(faded.downsample(ColorSpace::Srgb).pixel(0, 0)[3], 128);
+
+        // Whole 2x2 blocks, so a 2 pixel checker keeps its cells one level down
+        let big = Image::checkerboard(8, 2, BLACK, WHITE).downsample(ColorSpace::Srgb);
+        assert_eq!(big.pixel(0, 0), BLACK);
+        assert_eq!(big.pixel(1, 0), WHITE);
+
+        for value in [0, 1, 10, 128, 200, 255] {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn decodes_png_and_tga() {
+        let expected = Image::new(2, 2, PIXELS.concat());
+        assert_eq!(Image::decode(&PNG).unwrap(), expected);
+        // No signature, so it's taken to be TGA, and flipped to top down
+        assert_eq!(Image::decode(&TGA).unwrap(), expected);
+
+        let error = Image::decode(&PNG[..40]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(Image::decode(b"not an image").is_err());
+    }
+}
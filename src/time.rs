@@ -1,6 +1,44 @@
-use std::mem;
-use winapi::shared::ntdef::LARGE_INTEGER;
-use winapi::um::profileapi::{QueryPerformanceCounter, QueryPerformanceFrequency};
+// Ticks of a monotonic clock, and how many there are per second
+#[cfg(windows)]
+fn counter() -> i64 {
+    use std::mem;
+    use winapi::shared::ntdef::LARGE_INTEGER;
+    use winapi::um::profileapi::QueryPerformanceCounter;
+
+    unsafe {
+        let mut count: LARGE_INTEGER = mem::zeroed();
+        QueryPerformanceCounter(&mut count);
+        *count.QuadPart()
+    }
+}
+
+#[cfg(windows)]
+fn counts_per_second() -> i64 {
+    use std::mem;
+    use winapi::shared::ntdef::LARGE_INTEGER;
+    use winapi::um::profileapi::QueryPerformanceFrequency;
+
+    unsafe {
+        let mut frequency: LARGE_INTEGER = mem::zeroed();
+        QueryPerformanceFrequency(&mut frequency);
+        *frequency.QuadPart()
+    }
+}
+
+// Nanoseconds since the first call
+#[cfg(not(windows))]
+fn counter() -> i64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as i64
+}
+
+#[cfg(not(windows))]
+fn counts_per_second() -> i64 {
+    1_000_000_000
+}
 
 pub struct Time {
     pub game_time: f64,
@@ -25,41 +63,49 @@ impl Default for Time {
 
 impl Time {
     pub fn new() -> Self {
-        unsafe {
-            let mut counts_per_sec: LARGE_INTEGER = mem::zeroed();
-            QueryPerformanceFrequency(&mut counts_per_sec);
-
-            let mut curr_time: LARGE_INTEGER = mem::zeroed();
-            QueryPerformanceCounter(&mut curr_time);
-
-            Self {
-                game_time: 0.0,
-                delta_time: 0.0,
-                m_seconds_per_count: 1.0 / *counts_per_sec.QuadPart() as f64,
-                m_base_time: 0,
-                m_paused_time: 0,
-                m_stop_time: 0,
-                m_prev_time: *curr_time.QuadPart(),
-                m_curr_time: 0,
-                m_stopped: false,
-            }
+        Self {
+            game_time: 0.0,
+            delta_time: 0.0,
+            m_seconds_per_count: 1.0 / counts_per_second() as f64,
+            m_base_time: 0,
+            m_paused_time: 0,
+            m_stop_time: 0,
+            m_prev_time: counter(),
+            m_curr_time: 0,
+            m_stopped: false,
+        }
+    }
+    pub fn reset(&mut self) {
+        let curr_time = counter();
+        self.m_base_time = curr_time;
+        self.m_prev_time = curr_time;
+        self.m_stop_time = 0;
+        self.m_stopped = false;
+    }
+    pub fn start(&mut self) {
+        if self.m_stopped {
+            // Accumulate the time spent stopped and carry on from now
+            let start_time = counter();
+            self.m_paused_time += start_time - self.m_stop_time;
+            self.m_prev_time = start_time;
+            self.m_stop_time = 0;
+            self.m_stopped = false;
+        }
+    }
+    pub fn stop(&mut self) {
+        if !self.m_stopped {
+            self.m_stop_time = counter();
+            self.m_stopped = true;
         }
     }
-    pub fn reset(&mut self) {}
-    pub fn start(&mut self) {}
-    pub fn stop(&mut self) {}
     pub fn tick(&mut self) {
         if self.m_stopped {
             self.delta_time = 0.0;
             return;
         }
 
-        unsafe {
-            // Get the time this frame
-            let mut curr_time: LARGE_INTEGER = mem::zeroed();
-            QueryPerformanceCounter(&mut curr_time);
-            self.m_curr_time = *curr_time.QuadPart();
-        }
+        // Get the time this frame
+        self.m_curr_time = counter();
 
         // Time difference between this frame and the previous.
         self.delta_time = (self.m_curr_time - self.m_prev_time) as f64 * self.m_seconds_per_count;
@@ -77,4 +123,10 @@ impl Time {
 
         // println!("delta time: {}", self.delta_time);
     }
+    // Step by a known amount instead of reading the clock, used when replaying
+    // a recording
+    pub fn advance(&mut self, delta_time: f64) {
+        self.delta_time = delta_time;
+        self.game_time += delta_time;
+    }
 }
@@ -0,0 +1,191 @@
+// Translation, rotation and scale of an object relative to its parent.
+// Applied scale first, then rotation, then translation, like the sample's
+// hand written model matrix.
+use directx_math::{
+    XMLoadFloat3, XMLoadFloat4, XMMatrixDecompose, XMMatrixMultiply, XMMatrixRotationQuaternion,
+    XMMatrixScalingFromVector, XMMatrixTranslationFromVector, XMQuaternionIdentity,
+    XMQuaternionMultiply, XMQuaternionNormalize, XMQuaternionRotationRollPitchYaw, XMStoreFloat3,
+    XMStoreFloat4, XMVector3Rotate, XMVectorSet, XMFLOAT3, XMFLOAT4, XMMATRIX, XMVECTOR,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: XMFLOAT3,
+    // Unit quaternion
+    pub rotation: XMFLOAT4,
+    pub scale: XMFLOAT3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        let mut rotation = XMFLOAT4::default();
+        XMStoreFloat4(&mut rotation, XMQuaternionIdentity());
+        Self {
+            translation: XMFLOAT3::default(),
+            rotation,
+            scale: XMFLOAT3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        }
+    }
+
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self {
+        Self::identity().with_translation(x, y, z)
+    }
+
+    pub fn with_translation(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.translation = XMFLOAT3 { x, y, z };
+        self
+    }
+
+    // Angles in radians, applied roll, then pitch, then yaw
+    pub fn with_rotation(mut self, pitch: f32, yaw: f32, roll: f32) -> Self {
+        self.set_rotation(XMQuaternionRotationRollPitchYaw(pitch, yaw, roll));
+        self
+    }
+
+    pub fn with_scale(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.scale = XMFLOAT3 { x, y, z };
+        self
+    }
+
+    pub fn with_uniform_scale(self, scale: f32) -> Self {
+        self.with_scale(scale, scale, scale)
+    }
+
+    // Splits an affine matrix back up. None if it can't be, e.g. zero scale.
+    pub fn from_matrix(matrix: XMMATRIX) -> Option<Self> {
+        let mut scale = XMVectorSet(1.0, 1.0, 1.0, 0.0);
+        let mut rotation = XMQuaternionIdentity();
+        let mut translation = XMVectorSet(0.0, 0.0, 0.0, 0.0);
+        if !XMMatrixDecompose(&mut scale, &mut rotation, &mut translation, matrix) {
+            return None;
+        }
+        let mut transform = Self::identity();
+        XMStoreFloat3(&mut transform.scale, scale);
+        XMStoreFloat3(&mut transform.translation, translation);
+        transform.set_rotation(rotation);
+        Some(transform)
+    }
+
+    pub fn translation_vector(&self) -> XMVECTOR {
+        XMLoadFloat3(&self.translation)
+    }
+
+    pub fn rotation_vector(&self) -> XMVECTOR {
+        XMLoadFloat4(&self.rotation)
+    }
+
+    pub fn scale_vector(&self) -> XMVECTOR {
+        XMLoadFloat3(&self.scale)
+    }
+
+    pub fn set_rotation(&mut self, rotation: XMVECTOR) {
+        XMStoreFloat4(&mut self.rotation, XMQuaternionNormalize(rotation));
+    }
+
+    // Rotate further by `rotation`, applied after the current rotation
+    pub fn rotate(&mut self, rotation: XMVECTOR) {
+        self.set_rotation(XMQuaternionMultiply(self.rotation_vector(), rotation));
+    }
+
+    // Move along the object's own axes
+    pub fn translate_local(&mut self, x: f32, y: f32, z: f32) {
+        let offset = XMVector3Rotate(XMVectorSet(x, y, z, 0.0), self.rotation_vector());
+        let mut moved = XMFLOAT3::default();
+        XMStoreFloat3(&mut moved, offset);
+        self.translation.x += moved.x;
+        self.translation.y += moved.y;
+        self.translation.z += moved.z;
+    }
+
+    // Local to parent space
+    pub fn matrix(&self) -> XMMATRIX {
+        let scale = XMMatrixScalingFromVector(self.scale_vector());
+        let rotation = XMMatrixRotationQuaternion(self.rotation_vector());
+        let translation = XMMatrixTranslationFromVector(self.translation_vector());
+        XMMatrixMultiply(XMMatrixMultiply(scale, &rotation), &translation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directx_math::{XMMatrixSet, XMVector3TransformCoord, XM_PIDIV2};
+
+    fn point(matrix: XMMATRIX, x: f32, y: f32, z: f32) -> XMFLOAT3 {
+        let mut stored = XMFLOAT3::default();
+        XMStoreFloat3(
+            &mut stored,
+            XMVector3TransformCoord(XMVectorSet(x, y, z, 1.0), matrix),
+        );
+        stored
+    }
+
+    fn assert_near(actual: XMFLOAT3, expected: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        assert!(
+            close(actual.x, expected.0)
+                && close(actual.y, expected.1)
+                && close(actual.z, expected.2),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn matrix_scales_then_rotates_then_translates() {
+        let transform = Transform::from_translation(0.0, 0.0, 5.0)
+            .with_rotation(0.0, XM_PIDIV2, 0.0)
+            .with_uniform_scale(2.0);
+        // Scaled to (2, 0, 0), a quarter turn of yaw takes +x to -z
+        assert_near(point(transform.matrix(), 1.0, 0.0, 0.0), (0.0, 0.0, 3.0));
+        assert_near(
+            point(Transform::identity().matrix(), 1.0, 2.0, 3.0),
+            (1.0, 2.0, 3.0),
+        );
+    }
+
+    #[test]
+    fn from_matrix_round_trips() {
+        let transform = Transform::from_translation(1.0, -2.0, 3.0)
+            .with_rotation(0.3, -1.2, 0.5)
+            .with_scale(2.0, 0.5, 3.0);
+        let back = Transform::from_matrix(transform.matrix()).unwrap();
+        for (x, y, z) in [(1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (0.3, -0.7, 2.0)] {
+            let expected = point(transform.matrix(), x, y, z);
+            assert_near(
+                point(back.matrix(), x, y, z),
+                (expected.x, expected.y, expected.z),
+            );
+        }
+        let collapsed = Transform::identity().with_uniform_scale(0.0);
+        assert!(Transform::from_matrix(collapsed.matrix()).is_none());
+        // y axis leaning over x
+        let sheared = XMMatrixSet(
+            1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+        );
+        assert!(Transform::from_matrix(sheared).is_none());
+    }
+
+    #[test]
+    fn translate_local_follows_rotation() {
+        let mut transform = Transform::identity().with_rotation(0.0, XM_PIDIV2, 0.0);
+        transform.translate_local(0.0, 0.0, 2.0);
+        assert_near(transform.translation, (2.0, 0.0, 0.0));
+
+        // Two quarter turns face back down -z
+        transform.rotate(XMQuaternionRotationRollPitchYaw(0.0, XM_PIDIV2, 0.0));
+        transform.translate_local(0.0, 1.0, 1.0);
+        assert_near(transform.translation, (2.0, 1.0, -1.0));
+    }
+}
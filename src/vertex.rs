@@ -1,4 +1,75 @@
+use directx_math::{XMFLOAT2, XMFLOAT3, XMFLOAT4};
+
+use crate::pipeline::{ElementFormat, InputElement};
+
+// Matches VertexIn in shaders.hlsl and Vertex::LAYOUT
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
 pub struct Vertex {
-    pub pos: directx_math::XMFLOAT2,
-    pub color: directx_math::XMFLOAT4,
+    pub pos: XMFLOAT3,
+    pub normal: XMFLOAT3,
+    // Direction of increasing u. w is 1 or -1, the bitangent (increasing v)
+    // is cross(normal, tangent) * w.
+    pub tangent: XMFLOAT4,
+    pub uv: XMFLOAT2,
+    pub color: XMFLOAT4,
+}
+
+impl Vertex {
+    pub const LAYOUT: &'static [InputElement] = &[
+        InputElement {
+            semantic: "POSITION",
+            format: ElementFormat::Float3,
+        },
+        InputElement {
+            semantic: "NORMAL",
+            format: ElementFormat::Float3,
+        },
+        InputElement {
+            semantic: "TANGENT",
+            format: ElementFormat::Float4,
+        },
+        InputElement {
+            semantic: "TEXCOORD",
+            format: ElementFormat::Float2,
+        },
+        InputElement {
+            semantic: "COLOR",
+            format: ElementFormat::Float4,
+        },
+    ];
+
+    // White, tangent left for Mesh::compute_tangents
+    pub fn new(pos: (f32, f32, f32), normal: (f32, f32, f32), uv: (f32, f32)) -> Self {
+        Self {
+            pos: XMFLOAT3 {
+                x: pos.0,
+                y: pos.1,
+                z: pos.2,
+            },
+            normal: XMFLOAT3 {
+                x: normal.0,
+                y: normal.1,
+                z: normal.2,
+            },
+            tangent: XMFLOAT4::default(),
+            uv: XMFLOAT2 { x: uv.0, y: uv.1 },
+            color: XMFLOAT4 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+                w: 1.0,
+            },
+        }
+    }
+
+    pub fn with_color(mut self, r: f32, g: f32, b: f32, a: f32) -> Self {
+        self.color = XMFLOAT4 {
+            x: r,
+            y: g,
+            z: b,
+            w: a,
+        };
+        self
+    }
 }